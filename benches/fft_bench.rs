@@ -0,0 +1,35 @@
+use std::hint::black_box;
+use criterion::{criterion_group, criterion_main, Criterion};
+
+extern crate octave;
+use octave::{audio::WindowFunction, fft::Fft};
+
+fn generate_noise(num_samples: usize) -> Vec<f32> {
+    let mut state = 0x1234_5678u32;
+    let mut samples = Vec::with_capacity(num_samples);
+    for _ in 0..num_samples {
+        state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+        samples.push((state >> 8) as f32 / (1 << 23) as f32 - 1.);
+    }
+    samples
+}
+
+// the full complex transform every real buffer used to go through, against the packed real one
+fn bench_fft_sizes(c: &mut Criterion) {
+    // a power of two spectrogram window, and the RTA buffer sizes
+    for size in [4096, 6000, 24000, 48000] {
+        let samples = generate_noise(size);
+        let fft = Fft::new(48000, size, WindowFunction::Hann);
+        let mut scratch = fft.scratch();
+
+        c.bench_function(&format!("complex fft {}", size), |b| {
+            b.iter(|| black_box(fft.forward_complex(black_box(&samples))))
+        });
+        c.bench_function(&format!("real fft {}", size), |b| {
+            b.iter(|| black_box(fft.forward_real(black_box(&samples), &mut scratch).len()))
+        });
+    }
+}
+
+criterion_group!(benches, bench_fft_sizes);
+criterion_main!(benches);
@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+
+use crate::audio_source::{audio_path, AudioFormat};
+use crate::file_io::{check_write_format, encode_samples, read_str, SpeakerPos, WavError, WavInfo, WavWriteInfo};
+
+// the only version of AIFF-C there is, stored in the FVER chunk
+const AIFC_VERSION_1: u32 = 0xA2805140;
+
+// Reads the COMM and SSND chunks of an AIFF or AIFF-C file into a WavInfo.
+// The "data" chunk entry points at the sample data inside SSND, so read_data
+// and WavReader work on AIFF files without knowing about them.
+pub fn read_aiff_meta<R: Read + Seek>(f: &mut R) -> Result<WavInfo, WavError> {
+    let file_len = f.seek(SeekFrom::End(0))?;
+    f.seek(SeekFrom::Start(0))?;
+
+    if file_len < 12 {
+        return Err(WavError::TruncatedChunk("FORM".to_string()));
+    }
+    let form_tag = read_str(f, 4)?;
+    let form_size = read_be_u32(f)? as u64;
+    let form_type = read_str(f, 4)?;
+    let is_aifc = form_type == "AIFC";
+    if form_tag != "FORM" || !(form_type == "AIFF" || is_aifc) {
+        return Err(WavError::InvalidHeader("not an AIFF/AIFF-C file"));
+    }
+
+    // build the chunk table first, COMM and SSND can come in any order
+    let form_end = u64::min(form_size.saturating_add(8), file_len);
+    let mut chunks: HashMap<String, (u64, u64)> = HashMap::new();
+    while f.stream_position()? + 8 <= form_end {
+        let title = read_str(f, 4)?;
+        let size = read_be_u32(f)? as u64;
+        let pos = f.stream_position()?;
+        if pos + size > file_len {
+            return Err(WavError::TruncatedChunk(title));
+        }
+        chunks.insert(title, (pos, size));
+        // chunks are word aligned, odd sized chunks have a padding byte
+        f.seek(SeekFrom::Start(pos + size + (size & 1)))?;
+    }
+
+    let (comm_pos, comm_size) = *chunks.get("COMM").ok_or(WavError::MissingChunk("COMM"))?;
+    let (ssnd_pos, ssnd_size) = *chunks.get("SSND").ok_or(WavError::MissingChunk("SSND"))?;
+    if comm_size < if is_aifc { 22 } else { 18 } {
+        return Err(WavError::TruncatedChunk("COMM".to_string()));
+    }
+
+    f.seek(SeekFrom::Start(comm_pos))?;
+    let channels = read_be_u16(f)? as u32;
+    let num_frames = read_be_u32(f)? as u64;
+    let sample_size = read_be_u16(f)? as u32;
+    let sample_rate = read_extended(f)?;
+    // plain AIFF is always uncompressed big endian PCM
+    let compression = if is_aifc { read_str(f, 4)? } else { "NONE".to_string() };
+
+    if channels == 0 || channels > u8::MAX as u32 {
+        return Err(WavError::InvalidHeader("invalid number of channels"));
+    }
+    if !(sample_rate >= 1. && sample_rate <= u32::MAX as f64) {
+        return Err(WavError::InvalidHeader("invalid sample rate"));
+    }
+
+    // PCM samples are stored left aligned in whole bytes, so 20-bit audio is read as 24-bit
+    let pcm_depth = sample_size.div_ceil(8) * 8;
+    // (sample type, bit depth, big endian, signed 8-bit)
+    let (sample_type, bit_depth, big_endian, signed_8bit) = match compression.as_str() {
+        "NONE" | "twos" => (1, pcm_depth, true, true),
+        "sowt" => (1, pcm_depth, false, true),
+        // offset binary only exists as 8-bit
+        "raw " if pcm_depth == 8 => (1, 8, false, false),
+        "raw " => return Err(WavError::UnsupportedBitDepth { sample_type: 1, bit_depth: sample_size }),
+        "fl32" | "FL32" => (3, 32, true, false),
+        "fl64" | "FL64" => (3, 64, true, false),
+        // the sample size of these is usually given as 16, but the data is always 8-bit
+        "alaw" | "ALAW" => (6, 8, false, false),
+        "ulaw" | "ULAW" => (7, 8, false, false),
+        _ => return Err(WavError::UnsupportedCompression(compression)),
+    };
+    if sample_type == 1 && !(1..=32).contains(&sample_size) {
+        return Err(WavError::UnsupportedBitDepth { sample_type, bit_depth: sample_size });
+    }
+
+    // the sample data starts after the offset and block size fields, plus the offset
+    if ssnd_size < 8 {
+        return Err(WavError::TruncatedChunk("SSND".to_string()));
+    }
+    f.seek(SeekFrom::Start(ssnd_pos))?;
+    let offset = read_be_u32(f)? as u64;
+    let _block_size = read_be_u32(f)?;
+    if 8 + offset > ssnd_size {
+        return Err(WavError::TruncatedChunk("SSND".to_string()));
+    }
+    let data_size = u64::min(ssnd_size - 8 - offset, num_frames * (bit_depth / 8 * channels) as u64);
+    chunks.insert("data".to_string(), (ssnd_pos + 8 + offset, data_size));
+
+    // AIFF has no channel mask, so channels map directly to the speakers in wav order
+    let channel_map = (0..channels as u8)
+        .map(|c| (c, SpeakerPos::from(1u32.checked_shl(c as u32).unwrap_or(0))))
+        .collect();
+
+    f.seek(SeekFrom::Start(0))?;
+    let mut info = WavInfo::new(sample_type as u8, channels as u8, sample_rate.round() as u32, bit_depth, form_size, chunks, channel_map);
+    info.sample_type_str = format!("{} {}", if is_aifc { "AIFF-C" } else { "AIFF" }, info.sample_type_str);
+    if sample_type == 1 {
+        info.valid_bits_per_sample = sample_size;
+    }
+    info.big_endian = big_endian;
+    info.signed_8bit = signed_8bit;
+    info.format = AudioFormat::Aiff;
+    Ok(info)
+}
+
+// Writes PCM as plain AIFF, and float/A-law/µ-law as AIFF-C. Metadata chunks are only written to wav files.
+pub fn write_aiff_file(target_file: String, target_wav_format: &WavWriteInfo, samples: &[Vec<f32>]) -> Result<(), WavError> {
+    let channels = target_wav_format.channels;
+    let sample_type = target_wav_format.sample_type;
+    let bit_depth = target_wav_format.bit_depth;
+
+    //check input validity
+    if channels == 0 || samples.len() != channels as usize {return Err(WavError::InvalidInput("Wav Format must match given samples!"));}
+    let samples_per_channel = samples[0].len();
+    if samples.iter().any(|ch| ch.len() != samples_per_channel) {
+        return Err(WavError::InvalidInput("All channels must have the same number of samples!"));
+    }
+    check_write_format(target_wav_format)?;
+
+    let is_aifc = sample_type != 1;
+    let (compression, compression_name, sample_size): (&[u8; 4], &str, u16) = match sample_type {
+        1 => (b"NONE", "not compressed", bit_depth),
+        3 if bit_depth == 32 => (b"fl32", "32-bit floating point", 32),
+        3 => (b"fl64", "64-bit floating point", 64),
+        // sample size is 16 here, since that is what the data decodes to
+        6 => (b"alaw", "ALaw 2:1", 16),
+        _ => (b"ulaw", "uLaw 2:1", 16),
+    };
+
+    let mut interleaved = Vec::with_capacity(samples_per_channel * samples.len());
+    for i in 0..samples_per_channel {
+        for ch in samples {
+            interleaved.push(ch[i]);
+        }
+    }
+    let mut data = vec![];
+    encode_samples(sample_type, bit_depth, &interleaved, &mut data);
+    // encode_samples gives the wav layout, AIFF wants big endian and signed 8-bit PCM
+    match (sample_type, bit_depth) {
+        (1, 8) => data.iter_mut().for_each(|b| *b ^= 0x80),
+        (6 | 7, _) => {}
+        _ => data.chunks_exact_mut((bit_depth / 8) as usize).for_each(|s| s.reverse()),
+    }
+
+    // compression name is a pascal string, padded to an even length
+    let mut pstring = vec![compression_name.len() as u8];
+    pstring.extend_from_slice(compression_name.as_bytes());
+    if pstring.len() & 1 == 1 {
+        pstring.push(0);
+    }
+
+    let comm_size: u32 = 18 + if is_aifc { 4 + pstring.len() as u32 } else { 0 };
+    let ssnd_size: u64 = 8 + data.len() as u64;
+    // form type + every chunk with its 8 byte header
+    let form_size: u64 = 4 + (if is_aifc { 12 } else { 0 }) + (8 + comm_size as u64) + (8 + ssnd_size + (ssnd_size & 1));
+    if form_size > u32::MAX as u64 {
+        return Err(WavError::InvalidInput("AIFF files can't be bigger than 4GiB!"));
+    }
+
+    let mut file = BufWriter::new(File::create_new(audio_path(&target_file))?);
+
+    file.write_all(b"FORM")?;
+    file.write_all(&(form_size as u32).to_be_bytes())?;
+    file.write_all(if is_aifc { b"AIFC" } else { b"AIFF" })?;
+
+    if is_aifc {
+        file.write_all(b"FVER")?;
+        file.write_all(&4u32.to_be_bytes())?;
+        file.write_all(&AIFC_VERSION_1.to_be_bytes())?;
+    }
+
+    file.write_all(b"COMM")?;
+    file.write_all(&comm_size.to_be_bytes())?;
+    file.write_all(&(channels as u16).to_be_bytes())?;
+    file.write_all(&(u64::min(samples_per_channel as u64, u32::MAX as u64) as u32).to_be_bytes())?;
+    file.write_all(&sample_size.to_be_bytes())?;
+    file.write_all(&write_extended(target_wav_format.sample_rate))?;
+    if is_aifc {
+        file.write_all(compression)?;
+        file.write_all(&pstring)?;
+    }
+
+    file.write_all(b"SSND")?;
+    file.write_all(&(ssnd_size as u32).to_be_bytes())?;
+    // no offset or block alignment
+    file.write_all(&0u32.to_be_bytes())?;
+    file.write_all(&0u32.to_be_bytes())?;
+    file.write_all(&data)?;
+    if ssnd_size & 1 == 1 {
+        file.write_all(&[0])?;
+    }
+
+    file.flush()?;
+    Ok(())
+}
+
+fn read_be_u16<R: Read>(f: &mut R) -> io::Result<u16> {
+    let mut buf = [0u8; 2];
+    f.read_exact(&mut buf)?;
+    Ok(u16::from_be_bytes(buf))
+}
+
+fn read_be_u32<R: Read>(f: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    f.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+// 80-bit IEEE 754 extended precision float, which is how AIFF stores the sample rate:
+// 1 sign bit, 15 exponent bits (bias 16383), and a 64-bit mantissa with an explicit integer bit
+fn read_extended<R: Read>(f: &mut R) -> io::Result<f64> {
+    let mut buf = [0u8; 10];
+    f.read_exact(&mut buf)?;
+    let sign_exp = u16::from_be_bytes([buf[0], buf[1]]);
+    let mantissa = u64::from_be_bytes(buf[2..10].try_into().unwrap());
+
+    let exponent = (sign_exp & 0x7FFF) as i32;
+    if exponent == 0x7FFF {
+        return Ok(f64::NAN);
+    }
+    let value = mantissa as f64 * 2f64.powi(exponent - 16383 - 63);
+    Ok(if sign_exp & 0x8000 != 0 { -value } else { value })
+}
+
+fn write_extended(value: u32) -> [u8; 10] {
+    let mut buf = [0u8; 10];
+    if value == 0 {
+        return buf;
+    }
+    // shift the integer bit to the top of the mantissa
+    let shift = (value as u64).leading_zeros();
+    let exponent = (16383 + 63 - shift) as u16;
+    buf[0..2].copy_from_slice(&exponent.to_be_bytes());
+    buf[2..10].copy_from_slice(&((value as u64) << shift).to_be_bytes());
+    buf
+}
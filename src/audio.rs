@@ -1,19 +1,67 @@
-use std::{fmt, mem::size_of_val};
+use std::{fmt, mem::size_of, num::NonZero, thread};
 
-use crate::fft::Fft;
+use crate::fft::{Complex, Fft, FftScratch};
+use crate::audio_source::AudioSource;
+use crate::file_io::WavError;
+
+// What an STFT keeps of every bin
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StftOutput {
+    Magnitude,
+    Complex,
+}
+
+// The bins of every window, one window after the other
+#[derive(Clone, Debug, PartialEq)]
+pub enum StftFrames {
+    // the same amplitudes Fft::process gives
+    Magnitude(Vec<f32>),
+    // the unscaled spectrum, as from Fft::forward_real, up to and including nyquist
+    Complex(Vec<Complex>),
+}
+
+impl StftFrames {
+    fn len(&self) -> usize {
+        match self {
+            StftFrames::Magnitude(frames) => frames.len(),
+            StftFrames::Complex(frames) => frames.len(),
+        }
+    }
+
+    fn value_size(&self) -> usize {
+        match self {
+            StftFrames::Magnitude(_) => size_of::<f32>(),
+            StftFrames::Complex(_) => size_of::<Complex>(),
+        }
+    }
+}
 
 pub struct ShortTimeDftData {
-    pub dft_data: Vec<Vec<FreqData>>,
+    pub frames: StftFrames,
     pub num_dfts: u32,
     pub num_freq: u32,
     pub sample_rate: u32,
+    pub frequency_step: f32,
     pub data_size: usize,
+    // how the windows were taken, for turning the frames back into samples
+    pub window_func: WindowFunction,
+    pub window_len: usize,
+    pub step_size: usize,
+    amplitude_scale: f32, // from a complex bin to its amplitude
 }
 
 impl ShortTimeDftData {
-    pub fn new(dft_data: Vec<Vec<FreqData>>, num_dfts: u32, num_freq: u32, sample_rate: u32) -> Self {
-        let data_size = (size_of_val(&dft_data[0][0]) as u32 * num_dfts * num_freq) as usize + (size_of::<u32>() * 4);
-        Self { dft_data, num_dfts, num_freq, sample_rate, data_size }
+    pub fn amplitude(&self, dft: usize, bin: usize) -> f32 {
+        let idx = dft * self.num_freq as usize + bin;
+        match &self.frames {
+            StftFrames::Magnitude(frames) => frames[idx],
+            StftFrames::Complex(frames) => (frames[idx] * self.amplitude_scale).magnitude(),
+        }
+    }
+
+    // every bin of one window
+    pub fn dft(&self, dft: usize) -> Vec<f32> {
+        (0..self.num_freq as usize).map(|bin| self.amplitude(dft, bin)).collect()
     }
 }
 
@@ -23,36 +71,278 @@ impl fmt::Display for ShortTimeDftData {
     }
 }
 
-pub fn do_short_time_fourier_transform(samples: &Vec<f32>, sample_rate: u32, window_size: f32, overlap: f32, window_func: WindowFunction) -> Vec<Vec<FreqData>> {
-    let samples_per_window = (window_size * sample_rate as f32).round() as usize;
-    let overlap_size = (samples_per_window as f32 * overlap).floor() as usize;
-    let step_size = samples_per_window - overlap_size;
-    let num_windows = samples.len() / (samples_per_window - overlap_size);
+// windows transformed at once for every thread when streaming, so short pushes don't each start threads for a few windows
+const WINDOWS_PER_THREAD: usize = 16;
+
+// Turns samples into an STFT as they come in, doing the windows they complete on all available threads
+pub struct Stft {
+    fft: Fft,
+    sample_rate: u32,
+    window_func: WindowFunction,
+    window_len: usize,
+    step_size: usize,
+    // samples that haven't been part of a full window yet
+    pending: Vec<f32>,
+    scratch: Vec<FftScratch>, // one per thread
+    frames: StftFrames,
+    num_dfts: usize,
+}
+
+impl Stft {
+    // window_size is in seconds, overlap is the fraction of each window shared with the next
+    pub fn new(sample_rate: u32, window_size: f32, overlap: f32, window_func: WindowFunction, output: StftOutput) -> Result<Self, WavError> {
+        let window_len = (window_size * sample_rate as f32).round() as usize;
+        let overlap_size = (window_len as f32 * overlap).floor() as usize;
+        Self::from_lengths(sample_rate, window_len, window_len.saturating_sub(overlap_size), window_func, output)
+    }
+
+    // the same, with the window length and the step between windows in samples
+    pub fn from_lengths(sample_rate: u32, window_len: usize, step_size: usize, window_func: WindowFunction, output: StftOutput) -> Result<Self, WavError> {
+        if window_len == 0 || step_size == 0 || step_size > window_len {
+            return Err(WavError::InvalidInput("Window size and overlap must leave a step of at least 1 sample!"));
+        }
+
+        let fft = Fft::new(sample_rate, window_len, window_func);
+        let threads = thread::available_parallelism().map_or(1, NonZero::get);
+        let scratch = (0..threads).map(|_| fft.scratch()).collect();
+        let frames = match output {
+            StftOutput::Magnitude => StftFrames::Magnitude(vec![]),
+            StftOutput::Complex => StftFrames::Complex(vec![]),
+        };
+        Ok(Self { fft, sample_rate, window_func, window_len, step_size, pending: vec![], scratch, frames, num_dfts: 0 })
+    }
+
+    // values kept of every window, the bins below nyquist for magnitudes, and nyquist as well for complex frames
+    pub fn num_bins(&self) -> usize {
+        match self.frames {
+            StftFrames::Magnitude(_) => self.window_len.div_ceil(2),
+            StftFrames::Complex(_) => self.window_len / 2 + 1,
+        }
+    }
+
+    pub fn num_dfts(&self) -> usize {
+        self.num_dfts
+    }
+
+    pub fn frequency_step(&self) -> f32 {
+        self.fft.frequency_step
+    }
+
+    // the frames of every window finished so far
+    pub fn frames(&self) -> &StftFrames {
+        &self.frames
+    }
+
+    // takes out the frames finished so far, so long signals don't have to be kept whole.
+    // num_dfts only counts the frames that are left, so it starts again from 0
+    pub fn take_frames(&mut self) -> StftFrames {
+        let empty = match self.frames {
+            StftFrames::Magnitude(_) => StftFrames::Magnitude(vec![]),
+            StftFrames::Complex(_) => StftFrames::Complex(vec![]),
+        };
+        self.num_dfts = 0;
+        std::mem::replace(&mut self.frames, empty)
+    }
+
+    // adds samples to the end of the signal. The windows they complete are done in batches, returns
+    // how many windows were added to the frames, 0 until there are enough for a batch
+    pub fn push(&mut self, samples: &[f32]) -> usize {
+        self.pending.extend_from_slice(samples);
+        if self.ready_windows() < WINDOWS_PER_THREAD * self.scratch.len() {
+            return 0;
+        }
+        self.flush()
+    }
+
+    // windows the samples so far complete, that aren't in the frames yet
+    fn ready_windows(&self) -> usize {
+        if self.pending.len() < self.window_len {
+            return 0;
+        }
+        (self.pending.len() - self.window_len) / self.step_size + 1
+    }
+
+    // adds every window completed so far to the frames, without waiting for a whole batch
+    pub fn flush(&mut self) -> usize {
+        let count = self.ready_windows();
+        if count == 0 {
+            return 0;
+        }
+
+        let num_bins = self.num_bins();
+        let windows = Windows { fft: &self.fft, samples: &self.pending, window_len: self.window_len, step_size: self.step_size, num_bins };
+        match &mut self.frames {
+            // the same operations as Fft::process, so the amplitudes come out identical
+            StftFrames::Magnitude(frames) => windows.transform(&mut self.scratch, count, frames, |bin| {
+                (*bin / (self.window_len as f32 * self.fft.coherent_gain)).magnitude() * 2.
+            }),
+            StftFrames::Complex(frames) => windows.transform(&mut self.scratch, count, frames, |bin| *bin),
+        }
+
+        self.pending.drain(..count * self.step_size);
+        self.num_dfts += count;
+        count
+    }
+
+    pub fn finish(mut self) -> ShortTimeDftData {
+        self.flush();
+        let num_freq = self.num_bins() as u32;
+        let data_size = self.frames.len() * self.frames.value_size() + size_of::<u32>() * 4;
+        ShortTimeDftData {
+            amplitude_scale: 2. / (self.window_len as f32 * self.fft.coherent_gain),
+            frequency_step: self.fft.frequency_step,
+            frames: self.frames,
+            num_dfts: self.num_dfts as u32,
+            num_freq,
+            sample_rate: self.sample_rate,
+            data_size,
+            window_func: self.window_func,
+            window_len: self.window_len,
+            step_size: self.step_size,
+        }
+    }
+}
+
+// the windows completed by the samples pushed into an Stft
+struct Windows<'a> {
+    fft: &'a Fft,
+    samples: &'a [f32],
+    window_len: usize,
+    step_size: usize,
+    num_bins: usize,
+}
+
+impl Windows<'_> {
+    // appends the bins of the first `count` windows to `out`, splitting the windows between threads
+    fn transform<T: Copy + Default + Send>(
+        &self,
+        scratch: &mut [FftScratch],
+        count: usize,
+        out: &mut Vec<T>,
+        convert: impl Fn(&Complex) -> T + Sync,
+    ) {
+        let start = out.len();
+        out.resize(start + count * self.num_bins, T::default());
+        let per_thread = count.div_ceil(scratch.len());
 
-    let dft = Fft::new(sample_rate, samples_per_window, window_func);
-    
-    let mut out: Vec<Vec<FreqData>> = vec![vec![]; num_windows];
-    let mut window_idx = 0;
-    let mut i: usize = 0;
-    while i + samples_per_window < samples.len() {
-        out[window_idx] = dft.process(&samples[i..i+samples_per_window]);
-        window_idx += 1;
-        i += step_size;
+        let do_windows = |first: usize, out: &mut [T], scratch: &mut FftScratch| {
+            for (i, out) in out.chunks_exact_mut(self.num_bins).enumerate() {
+                let offset = (first + i) * self.step_size;
+                let spectrum = self.fft.forward_real(&self.samples[offset..offset + self.window_len], scratch);
+                for (out, bin) in out.iter_mut().zip(spectrum) {
+                    *out = convert(bin);
+                }
+            }
+        };
+
+        let mut chunks = out[start..].chunks_mut(per_thread * self.num_bins).zip(scratch.iter_mut()).enumerate();
+        if count <= per_thread {
+            // not worth starting a thread for
+            if let Some((_, (out, scratch))) = chunks.next() {
+                do_windows(0, out, scratch);
+            }
+            return;
+        }
+        thread::scope(|s| {
+            for (t, (out, scratch)) in chunks {
+                let do_windows = &do_windows;
+                s.spawn(move || do_windows(t * per_thread, out, scratch));
+            }
+        });
+    }
+}
+
+// Overlap-adds the inverse FFTs of complex frames (window_len / 2 + 1 bins each), windowing
+// them again and dividing by the sum of the squared windows. Frames straight from an Stft give back its input,
+// except where the window is 0 at the very ends
+pub fn inverse_stft(frames: &[Complex], window_func: WindowFunction, window_len: usize, step_size: usize) -> Vec<f32> {
+    let num_bins = window_len / 2 + 1;
+    let num_frames = frames.len() / num_bins;
+    if num_frames == 0 {
+        return vec![];
     }
+    let fft = Fft::new(0, window_len, window_func);
+    let window = window_func.coefficients(window_len);
 
-    // remove the empty dft(s) at the end that are
-    // caused by the rounding of window size
-    while out[out.len()-1].len() == 0 {
-        out.pop();
+    let len = (num_frames - 1) * step_size + window_len;
+    let mut out = vec![0.; len];
+    let mut window_sum = vec![0.; len];
+    let mut spectrum = vec![Complex::zero(); window_len];
+    for (m, frame) in frames.chunks_exact(num_bins).enumerate() {
+        // a real signal's spectrum mirrors around nyquist
+        spectrum[..num_bins].copy_from_slice(frame);
+        for k in 1..window_len.div_ceil(2) {
+            spectrum[window_len - k] = frame[k].conj();
+        }
+        let start = m * step_size;
+        let samples = fft.inverse(&spectrum);
+        for (i, (sample, &w)) in samples.iter().zip(&window).enumerate() {
+            out[start + i] += sample.r * w;
+            window_sum[start + i] += w * w;
+        }
     }
 
+    for (out, &sum) in out.iter_mut().zip(&window_sum) {
+        *out = if sum > 1e-6 { *out / sum } else { 0. };
+    }
     out
 }
 
-#[derive(Eq, PartialEq, Clone, Copy)]
+pub fn do_inverse_short_time_fourier_transform(stdft: &ShortTimeDftData) -> Result<Vec<f32>, WavError> {
+    match &stdft.frames {
+        StftFrames::Complex(frames) => Ok(inverse_stft(frames, stdft.window_func, stdft.window_len, stdft.step_size)),
+        StftFrames::Magnitude(_) => Err(WavError::InvalidInput("Only complex frames can be turned back into samples!")),
+    }
+}
+
+// Transforms samples that are already in memory
+pub fn do_short_time_fourier_transform(
+    samples: &[f32],
+    sample_rate: u32,
+    window_size: f32,
+    overlap: f32,
+    window_func: WindowFunction,
+    output: StftOutput,
+) -> Result<ShortTimeDftData, WavError> {
+    let mut stft = Stft::new(sample_rate, window_size, overlap, window_func, output)?;
+    stft.push(samples);
+    Ok(stft.finish())
+}
+
+// Same as do_short_time_fourier_transform, but reads one channel of the file block by block
+// instead of needing all of its samples in memory
+pub fn do_short_time_fourier_transform_streaming(
+    reader: &mut dyn AudioSource,
+    channel: usize,
+    window_size: f32,
+    overlap: f32,
+    window_func: WindowFunction,
+    output: StftOutput,
+) -> Result<ShortTimeDftData, WavError> {
+    if channel >= reader.info().channels as usize {
+        return Err(WavError::InvalidInput("Channel does not exist in this file!"));
+    }
+    let mut stft = Stft::new(reader.info().sample_rate, window_size, overlap, window_func, output)?;
+
+    let mut block = vec![];
+    while reader.read_block(&mut block)? > 0 {
+        stft.push(&block[channel]);
+    }
+    Ok(stft.finish())
+}
+
+pub const DEFAULT_KAISER_BETA: f32 = 8.6;
+pub const DEFAULT_GAUSSIAN_SIGMA: f32 = 0.4;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
 pub enum WindowFunction {
     Square,
     Hann,
+    Hamming,
+    BlackmanHarris,
+    FlatTop,
+    Kaiser { beta: f32 },
+    Gaussian { sigma: f32 }, // relative to half the window length
 }
 
 impl WindowFunction {
@@ -60,9 +350,79 @@ impl WindowFunction {
         match str.to_lowercase().as_str() {
             "square" => Some(WindowFunction::Square),
             "hann" | "bellcurve" => Some(WindowFunction::Hann),
+            "hamming" => Some(WindowFunction::Hamming),
+            "blackman-harris" | "blackmanharris" => Some(WindowFunction::BlackmanHarris),
+            "flat top" | "flat-top" | "flattop" => Some(WindowFunction::FlatTop),
+            "kaiser" => Some(WindowFunction::Kaiser { beta: DEFAULT_KAISER_BETA }),
+            "gaussian" => Some(WindowFunction::Gaussian { sigma: DEFAULT_GAUSSIAN_SIGMA }),
             _ => None
         }
     }
+
+    // The window for an n point DFT. These are the periodic versions, which repeat every
+    // n samples instead of ending on the same value they start with
+    pub fn coefficients(&self, n: usize) -> Vec<f32> {
+        // sums of cosines, with the terms alternating in sign
+        let cosine_sum = |terms: &[f64]| -> Vec<f32> {
+            (0..n).map(|i| {
+                let phase = std::f64::consts::TAU * i as f64 / n as f64;
+                terms.iter().enumerate()
+                    .map(|(k, &a)| if k % 2 == 0 { a } else { -a } * (phase * k as f64).cos())
+                    .sum::<f64>() as f32
+            }).collect()
+        };
+
+        match *self {
+            WindowFunction::Square => vec![1.; n],
+            WindowFunction::Hann => cosine_sum(&[0.5, 0.5]),
+            WindowFunction::Hamming => cosine_sum(&[0.54, 0.46]),
+            WindowFunction::BlackmanHarris => cosine_sum(&[0.35875, 0.48829, 0.14128, 0.01168]),
+            WindowFunction::FlatTop => cosine_sum(&[0.21557895, 0.41663158, 0.277263158, 0.083578947, 0.006947368]),
+            WindowFunction::Kaiser { beta } => {
+                let beta = beta as f64;
+                (0..n).map(|i| {
+                    let x = 2. * i as f64 / n as f64 - 1.;
+                    (bessel_i0(beta * (1. - x * x).sqrt()) / bessel_i0(beta)) as f32
+                }).collect()
+            }
+            WindowFunction::Gaussian { sigma } => {
+                let half = n as f64 / 2.;
+                (0..n).map(|i| {
+                    let x = (i as f64 - half) / (sigma as f64 * half);
+                    (-0.5 * x * x).exp() as f32
+                }).collect()
+            }
+        }
+    }
+
+    // The average of the window, which is how much it scales the amplitude of a sine in its bin
+    pub fn coherent_gain(&self, n: usize) -> f32 {
+        let window = self.coefficients(n);
+        window.iter().sum::<f32>() / n as f32
+    }
+
+    // Equivalent noise bandwidth in bins, how much wider than a single bin the window lets noise through
+    pub fn enbw(&self, n: usize) -> f32 {
+        let window = self.coefficients(n);
+        let sum: f32 = window.iter().sum();
+        let sum_sq: f32 = window.iter().map(|w| w * w).sum();
+        n as f32 * sum_sq / (sum * sum)
+    }
+}
+
+// modified bessel function of the first kind, order 0
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.;
+    let mut term = 1.;
+    let quarter_x_sq = x * x / 4.;
+    for k in 1..200 {
+        term *= quarter_x_sq / (k * k) as f64;
+        sum += term;
+        if term < sum * 1e-17 {
+            break;
+        }
+    }
+    sum
 }
 
 impl From<usize> for WindowFunction {
@@ -70,6 +430,11 @@ impl From<usize> for WindowFunction {
         match value {
             0 => Self::Square,
             1 => Self::Hann,
+            2 => Self::Hamming,
+            3 => Self::BlackmanHarris,
+            4 => Self::FlatTop,
+            5 => Self::Kaiser { beta: DEFAULT_KAISER_BETA },
+            6 => Self::Gaussian { sigma: DEFAULT_GAUSSIAN_SIGMA },
             _ => Self::Square
         }
     }
@@ -0,0 +1,193 @@
+use std::fs::File;
+use std::io::{BufReader, Read, Seek};
+use std::path::Path;
+
+use crate::file_io::{read_format_meta, WavError, WavInfo, WavReader};
+use crate::flac::FlacReader;
+use crate::mmap::MmapReader;
+use crate::mp3::{is_mp3_start, read_mp3_sample_rate, Mp3Reader};
+use crate::ogg::{is_ogg_start, OggReader};
+use crate::raw_pcm::{RawPcmReader, RawPcmSpec};
+
+// where the app looks for audio files
+pub const AUDIO_DIR: &str = "./res/audio";
+// frames per read_block call until set_block_size is used
+pub const DEFAULT_BLOCK_SIZE: usize = 4096;
+
+pub fn audio_path(file: &str) -> String {
+    format!("{}/{}", AUDIO_DIR, file)
+}
+
+// Anything audio can be streamed from, in blocks of f32 samples. Every format is
+// described by a WavInfo, with the decoded layout in its bit depth and block size.
+pub trait AudioSource {
+    fn info(&self) -> &WavInfo;
+
+    fn num_frames(&self) -> u64;
+
+    // index of the next frame that will be read
+    fn position(&self) -> u64;
+
+    fn block_size(&self) -> usize;
+
+    fn set_block_size(&mut self, block_size: usize) -> Result<(), WavError>;
+
+    fn seek_frame(&mut self, frame: u64) -> Result<(), WavError>;
+
+    // fills as much of `out` with interleaved samples as possible, returns the number of frames read
+    fn read_interleaved(&mut self, out: &mut [f32]) -> Result<usize, WavError>;
+
+    // reads the next block into `block` (one Vec per channel), returns the number of frames read
+    fn read_block(&mut self, block: &mut Vec<Vec<f32>>) -> Result<usize, WavError>;
+}
+
+impl<R: Read + Seek> AudioSource for WavReader<R> {
+    fn info(&self) -> &WavInfo {
+        WavReader::info(self)
+    }
+
+    fn num_frames(&self) -> u64 {
+        WavReader::num_frames(self)
+    }
+
+    fn position(&self) -> u64 {
+        WavReader::position(self)
+    }
+
+    fn block_size(&self) -> usize {
+        WavReader::block_size(self)
+    }
+
+    fn set_block_size(&mut self, block_size: usize) -> Result<(), WavError> {
+        WavReader::set_block_size(self, block_size)
+    }
+
+    fn seek_frame(&mut self, frame: u64) -> Result<(), WavError> {
+        WavReader::seek_frame(self, frame)
+    }
+
+    fn read_interleaved(&mut self, out: &mut [f32]) -> Result<usize, WavError> {
+        WavReader::read_interleaved(self, out)
+    }
+
+    fn read_block(&mut self, block: &mut Vec<Vec<f32>>) -> Result<usize, WavError> {
+        WavReader::read_block(self, block)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AudioFormat {
+    #[default]
+    Wav, // RIFF/WAVE, RF64 and BW64
+    Aiff, // AIFF and AIFF-C
+    Flac,
+    Mp3, // MPEG-1/2/2.5 Layer III
+    Ogg, // Ogg Vorbis and Ogg Opus
+}
+
+impl AudioFormat {
+    // FLAC, MP3 and Ogg files can't be read straight from their data, they need a decoder
+    pub fn is_compressed(self) -> bool {
+        matches!(self, Self::Flac | Self::Mp3 | Self::Ogg)
+    }
+
+    // Detects the format from the first bytes of a file, falling back to the file extension
+    // so that a broken file still gets the error of the reader it was meant for
+    pub fn detect(header: &[u8], extension: Option<&str>) -> Option<Self> {
+        let magic = |pos: usize, tag: &[u8]| header.get(pos..pos + tag.len()) == Some(tag);
+
+        if (magic(0, b"RIFF") || magic(0, b"RF64") || magic(0, b"BW64")) && magic(8, b"WAVE") {
+            return Some(Self::Wav);
+        }
+        if magic(0, b"FORM") && (magic(8, b"AIFF") || magic(8, b"AIFC")) {
+            return Some(Self::Aiff);
+        }
+        if magic(0, b"fLaC") {
+            return Some(Self::Flac);
+        }
+        if is_mp3_start(header) {
+            return Some(Self::Mp3);
+        }
+        if is_ogg_start(header) {
+            return Some(Self::Ogg);
+        }
+
+        match extension?.to_ascii_lowercase().as_str() {
+            "wav" | "wave" | "bwf" | "rf64" => Some(Self::Wav),
+            "aif" | "aiff" | "aifc" => Some(Self::Aiff),
+            "flac" => Some(Self::Flac),
+            "mp3" => Some(Self::Mp3),
+            "ogg" | "oga" | "opus" => Some(Self::Ogg),
+            _ => None,
+        }
+    }
+
+    // detect() on the first bytes of a stream, which is rewound afterwards
+    pub fn detect_stream<R: Read + Seek>(f: &mut R, extension: Option<&str>) -> Result<Option<Self>, WavError> {
+        f.rewind()?;
+        // short files just leave the rest of the header empty
+        let mut header = [0u8; 12];
+        let mut len = 0;
+        while len < header.len() {
+            match f.read(&mut header[len..])? {
+                0 => break,
+                n => len += n,
+            }
+        }
+        f.rewind()?;
+        Ok(Self::detect(&header[..len], extension))
+    }
+}
+
+// the format of a file on disk, None for anything open_audio can't read (e.g. headerless PCM)
+pub fn detect_file_format<P: AsRef<Path>>(path: P) -> Result<Option<AudioFormat>, WavError> {
+    let path = path.as_ref();
+    let mut file = File::open(path)?;
+    detect_opened_format(&mut file, path)
+}
+
+fn detect_opened_format(file: &mut File, path: &Path) -> Result<Option<AudioFormat>, WavError> {
+    AudioFormat::detect_stream(file, path.extension().and_then(|e| e.to_str()))
+}
+
+// Opens any supported audio file, based on its contents rather than its name
+pub fn open_audio<P: AsRef<Path>>(path: P) -> Result<Box<dyn AudioSource + Send>, WavError> {
+    let path = path.as_ref();
+    let mut file = File::open(path)?;
+    let format = detect_opened_format(&mut file, path)?.ok_or(WavError::UnrecognizedFormat)?;
+    let reader = BufReader::new(file);
+    Ok(match format {
+        AudioFormat::Wav | AudioFormat::Aiff => Box::new(WavReader::with_format(reader, format, DEFAULT_BLOCK_SIZE)?),
+        AudioFormat::Flac => Box::new(FlacReader::new(reader, DEFAULT_BLOCK_SIZE)?),
+        AudioFormat::Mp3 => Box::new(Mp3Reader::new(reader, DEFAULT_BLOCK_SIZE)?),
+        AudioFormat::Ogg => Box::new(OggReader::new(reader, DEFAULT_BLOCK_SIZE)?),
+    })
+}
+
+// Just the sample rate of a file, read from its header without setting up a decoder. MP3 files
+// aren't indexed for this, only their first frame is read
+pub fn read_sample_rate<P: AsRef<Path>>(path: P) -> Result<u32, WavError> {
+    let path = path.as_ref();
+    let mut file = File::open(path)?;
+    let format = detect_opened_format(&mut file, path)?.ok_or(WavError::UnrecognizedFormat)?;
+    let mut reader = BufReader::new(file);
+    match format {
+        AudioFormat::Mp3 => read_mp3_sample_rate(&mut reader),
+        format => Ok(read_format_meta(&mut reader, format)?.sample_rate),
+    }
+}
+
+// Like open_audio, but uncompressed files are read from a memory map so seeking around them is free
+pub fn open_mapped_audio<P: AsRef<Path>>(path: P) -> Result<Box<dyn AudioSource + Send>, WavError> {
+    match MmapReader::open(path.as_ref(), DEFAULT_BLOCK_SIZE) {
+        Ok(reader) => Ok(Box::new(reader)),
+        // compressed files, or no mmap on this platform
+        Err(_) => open_audio(path),
+    }
+}
+
+// Opens a headerless file, whose format has to be given
+pub fn open_raw_pcm<P: AsRef<Path>>(path: P, spec: RawPcmSpec) -> Result<Box<dyn AudioSource + Send>, WavError> {
+    let file = File::open(path)?;
+    Ok(Box::new(RawPcmReader::new(BufReader::new(file), spec, DEFAULT_BLOCK_SIZE)?))
+}
@@ -1,141 +1,342 @@
-use std::{f32::consts::{PI, TAU}, num::NonZero, ops::{Add, AddAssign, Div, Mul, MulAssign, Sub}, thread};
+use std::{f32::consts::{PI, TAU}, num::NonZero, ops::{Add, AddAssign, Div, Mul, MulAssign, Neg, Sub, SubAssign}, sync::OnceLock, thread};
 
 use crate::audio::{FreqData, WindowFunction};
 
 pub struct Fft {
     pub frequency_step: f32,
     pub buffer_size: usize,
-    pub twiddle_factors: Vec<Complex>,
-    non_padded_buf_size: usize,
+    // of the window function, see WindowFunction::coherent_gain and WindowFunction::enbw
+    pub coherent_gain: f32,
+    pub enbw: f32,
+    // even sizes pack the real input into a half length transform
+    half_plan: Option<ComplexFft>,
+    real_twiddles: Vec<Complex>, // e^(-2πik/n) for k up to n/2
+    // only built once something needs the full length transform
+    full_plan: OnceLock<ComplexFft>,
     window_function: Vec<f32>,
 }
 
+// Working memory for Fft::forward_real, so repeated transforms don't allocate
+pub struct FftScratch {
+    packed: Vec<Complex>,
+    work: Vec<Complex>,
+    spectrum: Vec<Complex>,
+}
+
 impl Fft {
+    // any buffer size works, sizes made of 2, 3, 5 and 7 are the fastest
     pub fn new(sample_rate: u32, buffer_size: usize, window_function: WindowFunction) -> Self {
+        let frequency_step = sample_rate as f32 / buffer_size as f32;
 
-        let mut padded_buf_size = buffer_size;
-        if buffer_size & (buffer_size - 1) != 0 {
-            padded_buf_size = usize::pow(2, f32::log2(buffer_size as f32) as u32 + 1);
-        }
-
-        let frequency_step = sample_rate as f32 / padded_buf_size as f32;
-
-        let twiddle_factors = Self::compute_twiddles(padded_buf_size);
-        let window_function = Self::compute_window_func(padded_buf_size, window_function);
+        let (half_plan, real_twiddles) = if buffer_size > 0 && buffer_size.is_multiple_of(2) {
+            use std::f64::consts::TAU;
+            let twiddles = (0..=buffer_size / 2).map(|k| {
+                let angle = -TAU * k as f64 / buffer_size as f64;
+                Complex::new(angle.cos() as f32, angle.sin() as f32)
+            }).collect();
+            (Some(ComplexFft::new(buffer_size / 2)), twiddles)
+        } else {
+            (None, vec![])
+        };
+        let window_function = window_function.coefficients(buffer_size);
+        let window_sum: f32 = window_function.iter().sum();
+        let coherent_gain = window_sum / buffer_size as f32;
+        let enbw = buffer_size as f32 * window_function.iter().map(|w| w * w).sum::<f32>() / (window_sum * window_sum);
 
         Self{
             frequency_step,
-            buffer_size: padded_buf_size,
-            twiddle_factors,
+            buffer_size,
+            coherent_gain,
+            enbw,
+            half_plan,
+            real_twiddles,
+            full_plan: OnceLock::new(),
             window_function,
-            non_padded_buf_size: buffer_size
         }
     }
 
-    fn compute_twiddles(n: usize) -> Vec<Complex> {
-        use std::f64::consts::TAU;
-        let mut twiddles = Vec::with_capacity(n / 2);
+    fn full_plan(&self) -> &ComplexFft {
+        self.full_plan.get_or_init(|| ComplexFft::new(self.buffer_size))
+    }
 
-        let cos_base = f64::cos(TAU / n as f64);
-        let sin_base = f64::sin(TAU / n as f64);
+    pub fn scratch(&self) -> FftScratch {
+        let plan = self.half_plan.as_ref().unwrap_or_else(|| self.full_plan());
+        FftScratch {
+            packed: vec![Complex::zero(); plan.n],
+            work: vec![Complex::zero(); plan.scratch_len()],
+            spectrum: Vec::with_capacity(self.buffer_size / 2 + 1),
+        }
+    }
 
-        twiddles.push(Complex::new(1., 0.));
+    // Bins 0 to n/2 of the windowed buffer, the same as the start of forward_complex
+    pub fn forward_real<'a>(&self, buffer: &[f32], scratch: &'a mut FftScratch) -> &'a [Complex] {
+        let buffer = &buffer[..self.buffer_size];
+        let FftScratch { packed, work, spectrum } = scratch;
+        spectrum.clear();
+        match &self.half_plan {
+            Some(plan) => {
+                // even samples become the real parts, odd samples the imaginary ones
+                let (pairs, _) = buffer.as_chunks::<2>();
+                let (windows, _) = self.window_function.as_chunks::<2>();
+                for ((packed, x), w) in packed.iter_mut().zip(pairs).zip(windows) {
+                    *packed = Complex::new(x[0] * w[0], x[1] * w[1]);
+                }
+                plan.process_with_scratch(packed, work);
+
+                // split the result back into the spectra of the even and odd samples, and combine them
+                let half = plan.n;
+                for (k, &twiddle) in self.real_twiddles.iter().enumerate() {
+                    let z = packed[k % half];
+                    let z_mirror = packed[(half - k) % half].conj();
+                    let even = (z + z_mirror) * 0.5;
+                    let diff = z - z_mirror;
+                    // (z - z_mirror) / 2i
+                    let odd = Complex::new(diff.i, -diff.r) * 0.5;
+                    spectrum.push(even + twiddle * odd);
+                }
+            }
+            None => {
+                for ((packed, &x), &w) in packed.iter_mut().zip(buffer).zip(&self.window_function) {
+                    *packed = Complex::new(x * w, 0.);
+                }
+                self.full_plan().process_with_scratch(packed, work);
+                spectrum.extend_from_slice(&packed[..usize::min(self.buffer_size / 2 + 1, self.buffer_size)]);
+            }
+        }
+        spectrum
+    }
 
-        // use f64 to avoid rounding errors because of repeated operations
-        let mut real = 1f64;
-        let mut imag = 0f64;
+    // Every bin of the windowed buffer, without any scaling
+    pub fn forward_complex(&self, buffer: &[f32]) -> Vec<Complex> {
+        // convert real-valued inputs to complex inputs, and premultiply by the window function
+        let mut complex_buffer: Vec<Complex> = buffer[..self.buffer_size].iter()
+            .zip(&self.window_function)
+            .map(|(&x, &w)| Complex::new(x * w, 0.))
+            .collect();
+        //modifies the complex_buffer in-place to be the output of the fft
+        self.full_plan().process(complex_buffer.as_mut_slice());
+        complex_buffer
+    }
 
-        for _ in 1..(n/2) {
-            let next_r = real * cos_base - imag * sin_base;
-            let next_i = imag * cos_base + real * sin_base;
+    // Turns a full spectrum from forward_complex back into the (windowed) signal.
+    // The window isn't undone, and the imaginary parts are only 0 if the spectrum is conjugate symmetric
+    pub fn inverse(&self, spectrum: &[Complex]) -> Vec<Complex> {
+        assert_eq!(spectrum.len(), self.buffer_size, "spectrum must have one value per bin");
+        // the inverse transform is the forward one with the conjugate on both sides
+        let mut buffer: Vec<Complex> = spectrum.iter().map(Complex::conj).collect();
+        self.full_plan().process(&mut buffer);
+        for x in buffer.iter_mut() {
+            *x = x.conj() / self.buffer_size as f32;
+        }
+        buffer
+    }
 
-            real = next_r;
-            imag = next_i;
+    pub fn process(&self, buffer: &[f32]) -> Vec<FreqData> {
+        self.process_with_scratch(buffer, &mut self.scratch())
+    }
 
-            twiddles.push(Complex::new(real as f32, -imag as f32));
-        }
+    pub fn process_with_scratch(&self, buffer: &[f32], scratch: &mut FftScratch) -> Vec<FreqData> {
+        let spectrum = self.forward_real(buffer, scratch);
+
+        // every bin below nyquist
+        let num_bins = self.buffer_size.div_ceil(2);
+        let mut out: Vec<FreqData> = Vec::with_capacity(num_bins);
+        for (i, bin) in spectrum[..num_bins].iter().enumerate() {
+            // undo the window's gain, so a sine reads as its own amplitude whatever the window
+            let center = *bin / (self.buffer_size as f32 * self.coherent_gain);
 
-        twiddles
+            let freq = i as f32 * self.frequency_step;
+            let amp = center.magnitude() * 2.;
+            let phase = bin.phase();
+            let cur_data = FreqData::new(freq, amp, phase);
+            out.push(cur_data);
+        }
+        out
     }
+}
 
-    fn compute_window_func(n: usize, window_function: WindowFunction) -> Vec<f32> {
-        let mut out;
-        match window_function {
-            WindowFunction::Hann => {
-                out = Vec::with_capacity(n);
-                for i in 0..n {
-                    let cur_hann = 0.5 * (1. - f32::cos((TAU * i as f32) / n as f32));
-                    out.push(cur_hann);
-                }
-            },
-            WindowFunction::Square => {
-                out = vec![1f32; n];
+// An unnormalized forward FFT of any length. Lengths that split into factors of 2, 3, 5 and 7
+// use mixed-radix butterflies, anything with a larger prime factor goes through Bluestein's algorithm
+pub(crate) struct ComplexFft {
+    n: usize,
+    twiddles: Vec<Complex>, // e^(-2πik/n) for every k < n
+    factors: Vec<usize>,
+    bluestein: Option<Box<Bluestein>>,
+}
+
+// Turns the DFT into a convolution with a chirp, which is done with a power of two FFT
+struct Bluestein {
+    chirp: Vec<Complex>, // e^(-πik²/n)
+    chirp_spectrum: Vec<Complex>, // FFT of the conjugate chirp, wrapped around and divided by the FFT size
+    fft: ComplexFft,
+}
+
+impl ComplexFft {
+    pub(crate) fn new(n: usize) -> Self {
+        let mut factors = vec![];
+        let mut rest = n.max(1);
+        for radix in [4, 2, 3, 5, 7] {
+            while rest.is_multiple_of(radix) {
+                factors.push(radix);
+                rest /= radix;
             }
         }
-        out
+
+        if rest > 1 {
+            return Self { n, twiddles: vec![], factors: vec![], bluestein: Some(Box::new(Bluestein::new(n))) };
+        }
+        Self { n, twiddles: Self::compute_twiddles(n), factors, bluestein: None }
+    }
+
+    fn compute_twiddles(n: usize) -> Vec<Complex> {
+        use std::f64::consts::TAU;
+        // use f64 so the larger angles don't lose precision
+        (0..n).map(|k| {
+            let angle = -TAU * k as f64 / n as f64;
+            Complex::new(angle.cos() as f32, angle.sin() as f32)
+        }).collect()
     }
 
-    fn compute_fft_in_place(&self, buffer: &mut [Complex]) {
-        let n = buffer.len();
-        if n == 1 {
-            return;
+    // how much working memory process_with_scratch needs
+    pub(crate) fn scratch_len(&self) -> usize {
+        match &self.bluestein {
+            Some(bluestein) => 2 * bluestein.chirp_spectrum.len(),
+            None => self.n,
         }
+    }
 
-        //bit reversal to allow for iterative fft implementation
-        let mut i = 0;
-        for j in 1..n {
-            let mut bit = n >> 1;
-            while i & bit != 0 {
-                i &= !bit;
-                bit >>= 1;
-            }
-            i |= bit;
+    fn process(&self, buffer: &mut [Complex]) {
+        let mut scratch = vec![Complex::zero(); self.scratch_len()];
+        self.process_with_scratch(buffer, &mut scratch);
+    }
+
+    pub(crate) fn process_with_scratch(&self, buffer: &mut [Complex], scratch: &mut [Complex]) {
+        assert_eq!(buffer.len(), self.n);
+        if let Some(bluestein) = &self.bluestein {
+            bluestein.process(buffer, scratch);
+        } else if self.n > 1 {
+            let input = &mut scratch[..self.n];
+            input.copy_from_slice(buffer);
+            self.work(buffer, input, 1, &self.factors);
+        }
+    }
 
-            if j < i {
-                buffer.swap(j, i);
+    // decimation in time: does the `out.len() / radix` point FFTs of every radix-th input,
+    // then combines them with one pass of radix point butterflies
+    fn work(&self, out: &mut [Complex], input: &[Complex], stride: usize, factors: &[usize]) {
+        let radix = factors[0];
+        let m = out.len() / radix;
+        if m == 1 {
+            for (q, out) in out.iter_mut().enumerate() {
+                *out = input[q * stride];
+            }
+        } else {
+            for (q, out) in out.chunks_exact_mut(m).enumerate() {
+                self.work(out, &input[q * stride..], stride * radix, &factors[1..]);
             }
         }
 
-        let mut len = 2;
-        while len <= n {
-            for start in (0..n).step_by(len) {
-                for k in 0..(len/2) {
-                    let twiddle = self.twiddle_factors[k * (n / len)];
-                    let even = buffer[start + k];
-                    let odd = buffer[start + k + len / 2] * twiddle;
+        match radix {
+            2 => self.butterfly_2(out, stride, m),
+            4 => self.butterfly_4(out, stride, m),
+            _ => self.butterfly_generic(out, stride, m, radix),
+        }
+    }
 
-                    buffer[start + k] = even + odd;
-                    buffer[start + k + len / 2] = even - odd;
+    fn butterfly_2(&self, out: &mut [Complex], stride: usize, m: usize) {
+        let (even, odd) = out.split_at_mut(m);
+        for (k, (even, odd)) in even.iter_mut().zip(odd).enumerate() {
+            let t = *odd * self.twiddles[k * stride];
+            *odd = *even - t;
+            *even += t;
+        }
+    }
 
+    fn butterfly_4(&self, out: &mut [Complex], stride: usize, m: usize) {
+        for k in 0..m {
+            let s0 = out[k + m] * self.twiddles[k * stride];
+            let s1 = out[k + 2 * m] * self.twiddles[2 * k * stride];
+            let s2 = out[k + 3 * m] * self.twiddles[3 * k * stride];
+
+            let s5 = out[k] - s1;
+            let s4 = out[k] + s1;
+            let s3 = s0 + s2;
+            let s6 = s0 - s2;
+
+            out[k] = s4 + s3;
+            out[k + 2 * m] = s4 - s3;
+            // s6 times -i and +i
+            out[k + m] = Complex::new(s5.r + s6.i, s5.i - s6.r);
+            out[k + 3 * m] = Complex::new(s5.r - s6.i, s5.i + s6.r);
+        }
+    }
+
+    // the small odd radices, as a direct DFT over the radix inputs
+    fn butterfly_generic(&self, out: &mut [Complex], stride: usize, m: usize, radix: usize) {
+        let mut scratch = [Complex::zero(); 7];
+        for u in 0..m {
+            for (q, scratch) in scratch[..radix].iter_mut().enumerate() {
+                *scratch = out[u + q * m];
+            }
+            for q1 in 0..radix {
+                let k = u + q1 * m;
+                let mut sum = scratch[0];
+                let mut twiddle_idx = 0;
+                for &x in &scratch[1..radix] {
+                    // stride * k is below n, so one wrap is enough
+                    twiddle_idx += stride * k;
+                    if twiddle_idx >= self.n {
+                        twiddle_idx -= self.n;
+                    }
+                    sum += x * self.twiddles[twiddle_idx];
                 }
+                out[k] = sum;
             }
-            len *= 2;
         }
     }
+}
 
-    pub fn process(&self, buffer: &[f32]) -> Vec<FreqData> {
-        let mut complex_buffer = Vec::with_capacity(self.buffer_size);
-        // convert real-valued inputs to complex inputs, and premultiply by the window function
-        for i in 0..self.non_padded_buf_size {
-            complex_buffer.push(Complex::new(buffer[i] * self.window_function[i], 0.));
+impl Bluestein {
+    fn new(n: usize) -> Self {
+        use std::f64::consts::PI;
+        let fft_size = (2 * n - 1).next_power_of_two();
+        // k² wraps around every 2n, so reduce it before it gets too big for an accurate angle
+        let chirp: Vec<Complex> = (0..n as u64).map(|k| {
+            let angle = -PI * ((k * k) % (2 * n as u64)) as f64 / n as f64;
+            Complex::new(angle.cos() as f32, angle.sin() as f32)
+        }).collect();
+
+        let fft = ComplexFft::new(fft_size);
+        let mut chirp_spectrum = vec![Complex::zero(); fft_size];
+        chirp_spectrum[0] = chirp[0].conj();
+        for k in 1..n {
+            chirp_spectrum[k] = chirp[k].conj();
+            chirp_spectrum[fft_size - k] = chirp[k].conj();
         }
-        for _ in self.non_padded_buf_size..self.buffer_size {
-            complex_buffer.push(Complex::new(0., 0.));
+        fft.process(&mut chirp_spectrum);
+        for x in chirp_spectrum.iter_mut() {
+            *x = *x / fft_size as f32;
         }
-        //modifies the complex_buffer in-place to be the output of the fft
-        self.compute_fft_in_place(complex_buffer.as_mut_slice());
 
-        let mut out: Vec<FreqData> = Vec::with_capacity(self.buffer_size / 2);
-        for i in 0..self.buffer_size/2 {
-            let center = complex_buffer[i] / self.buffer_size as f32;
+        Self { chirp, chirp_spectrum, fft }
+    }
 
-            let freq = i as f32 * self.frequency_step;
-            let amp = center.magnitude() * 2.;
-            let phase = f32::atan2(complex_buffer[i].i, complex_buffer[i].r);
-            let cur_data = FreqData::new(freq, amp, phase);
-            out.push(cur_data);
+    // scratch holds the padded signal, and the scratch of the power of two FFT
+    fn process(&self, buffer: &mut [Complex], scratch: &mut [Complex]) {
+        let (work, fft_scratch) = scratch.split_at_mut(self.chirp_spectrum.len());
+        work.fill(Complex::zero());
+        for ((work, &x), &chirp) in work.iter_mut().zip(buffer.iter()).zip(&self.chirp) {
+            *work = x * chirp;
+        }
+        self.fft.process_with_scratch(work, fft_scratch);
+        // the inverse FFT of the product, done as a forward FFT of the conjugate
+        for (work, &chirp) in work.iter_mut().zip(&self.chirp_spectrum) {
+            *work = (*work * chirp).conj();
+        }
+        self.fft.process_with_scratch(work, fft_scratch);
+        for ((out, work), &chirp) in buffer.iter_mut().zip(work.iter()).zip(&self.chirp) {
+            *out = work.conj() * chirp;
         }
-        out
     }
 }
 
@@ -157,19 +358,7 @@ impl NaiveDft {
         let available_threads = thread::available_parallelism().unwrap();
         
         // pre-compute the values for the window function
-        let mut window_function_vec: Vec<f32>;
-        match window_function {
-            WindowFunction::Hann => {
-                window_function_vec = Vec::with_capacity(buffer_size);
-                for i in 0..buffer_size {
-                    let cur_hann = 0.5 * (1. - f32::cos((TAU * i as f32) / buffer_size as f32));
-                    window_function_vec.push(cur_hann);
-                }
-            },
-            WindowFunction::Square => {
-                window_function_vec = vec![1f32; buffer_size];
-            }
-        }
+        let window_function_vec = window_function.coefficients(buffer_size);
 
         Self {
             frequency_step,
@@ -196,7 +385,7 @@ impl NaiveDft {
                             let mut angle = (i as f32/self.buffer_size as f32) * TAU * f as f32;
                             angle = ((angle + PI) % TAU) - PI;
                             let test_pt = Complex::new(f32::cos(angle), f32::sin(angle));
-                            sum += &test_pt * (buffer[i] * self.window_function[i]);
+                            sum += test_pt * (buffer[i] * self.window_function[i]);
                         }
 
                         let sample_center = sum / self.buffer_size as f32;
@@ -215,24 +404,36 @@ impl NaiveDft {
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
 pub struct Complex {
     pub r: f32,
     pub i: f32,
 }
 
 impl Complex {
-    fn zero() -> Self {
+    pub fn zero() -> Self {
         Self { r: 0., i: 0. }
     }
     
-    fn new(r: f32, i: f32) -> Self {
+    pub fn new(r: f32, i: f32) -> Self {
         Self { r, i }
     }
 
-    fn magnitude(&self) -> f32 {
+    pub fn from_polar(magnitude: f32, phase: f32) -> Self {
+        Self { r: magnitude * phase.cos(), i: magnitude * phase.sin() }
+    }
+
+    pub fn magnitude(&self) -> f32 {
         f32::sqrt(self.r * self.r + self.i * self.i)
     }
+
+    pub fn phase(&self) -> f32 {
+        f32::atan2(self.i, self.r)
+    }
+
+    pub fn conj(&self) -> Self {
+        Self { r: self.r, i: -self.i }
+    }
 }
 
 impl AddAssign for Complex {
@@ -252,6 +453,23 @@ impl Add for Complex {
     }
 }
 
+impl SubAssign for Complex {
+    fn sub_assign(&mut self, rhs: Self) {
+        self.r -= rhs.r;
+        self.i -= rhs.i;
+    }
+}
+
+impl Neg for Complex {
+    type Output = Complex;
+    fn neg(self) -> Self::Output {
+        Complex {
+            r: -self.r,
+            i: -self.i,
+        }
+    }
+}
+
 impl Sub for Complex {
     type Output = Complex;
     fn sub(self, rhs: Self) -> Self::Output {
@@ -290,6 +508,16 @@ impl MulAssign for Complex {
     }
 }
 
+impl Mul<f32> for Complex {
+    type Output = Complex;
+    fn mul(self, rhs: f32) -> Self::Output {
+        Complex {
+            r: self.r * rhs,
+            i: self.i * rhs,
+        }
+    }
+}
+
 impl<'a> Mul<f32> for &'a Complex {
     type Output = Complex;
     fn mul(self, rhs: f32) -> Self::Output {
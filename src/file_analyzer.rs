@@ -1,9 +1,13 @@
-use std::{thread, sync::Arc, fs::File, io::BufReader};
+use std::{thread, sync::Arc};
 
-use crate::file_io::{read_data, read_wav_meta, WavInfo};
+use crate::audio::WindowFunction;
+use crate::audio_source::{open_audio, AudioSource};
+use crate::file_io::{WavError, WavInfo};
 use crate::fir_filter::FIRFilter;
-use crate::parametric_eq::{EqNode, FilterType, ParametricEq, Biquad};
+use crate::parametric_eq::{FilterType, Biquad};
 use crate::fir_filter_constants::*;
+use crate::psd::{Averaging, PowerSpectrum, Welch};
+use crate::room_acoustics::{room_acoustics, RoomAcoustics};
 
 #[derive(Debug)]
 pub struct FileResults {
@@ -12,194 +16,282 @@ pub struct FileResults {
     pub lkfs_s: f64, //LKFS
     pub lkfs_m: f64, //LKFS
     pub true_peaks: Vec<f32>, // dBTP (dB True-Peak)
+    pub ltas: Option<PowerSpectrum>, // long-term average spectrum of all channels, None if the file is too short
 }
 
-pub fn analyze_file(path: String) -> Option<FileResults> {
-    let file = File::open(path);
-    match file {
-        Ok(_) => {}
-        Err(_) => return None
-    }
-    let mut reader = BufReader::new(file.unwrap());
-    let metadata = read_wav_meta(&mut reader);
+// frames read from the file at a time, so long files never have to fit in memory
+const ANALYSIS_BLOCK_SIZE: usize = 1 << 16;
+// ~0.17s at 48kHz, about 6Hz between bins
+const LTAS_SEGMENT_LEN: usize = 8192;
+// the impulse response has to be in memory at once, longer files are rejected rather than read in
+const ROOM_IR_MAX_SECS: u64 = 30;
 
-    let samples = match read_data(&mut reader, &metadata, 0., metadata.audio_duration) {
-        Some(data) => data,
-        None => return None,
-    };
+pub fn analyze_file(path: String) -> Result<FileResults, WavError> {
+    analyze_source(open_audio(path)?.as_mut())
+}
 
-    let true_peaks = match calculate_true_peak(&samples, &metadata) {
-        Some(d) => d,
-        None => vec![]
-    };
-    let (lkfs_i, lkfs_m, lkfs_s) = calculate_file_loudness(&samples, &metadata);
+// measures whatever is left of `reader`, for sources that open_audio can't open on its own
+pub fn analyze_source(reader: &mut dyn AudioSource) -> Result<FileResults, WavError> {
+    reader.set_block_size(ANALYSIS_BLOCK_SIZE)?;
+    let metadata = reader.info().clone();
+
+    let mut true_peak_meter = TruePeakMeter::new(metadata.sample_rate, metadata.channels as usize);
+    let mut loudness_meter = LoudnessMeter::new(metadata.sample_rate, metadata.channels as usize);
+    // no spectrum for files without a sample rate, rather than no results at all
+    let mut ltas: Vec<Welch> = (0..metadata.channels)
+        .filter_map(|_| Welch::new(metadata.sample_rate, LTAS_SEGMENT_LEN, 0.5, WindowFunction::Hann, Averaging::Mean).ok())
+        .collect();
+
+    let mut block = vec![];
+    while reader.read_block(&mut block)? > 0 {
+        if let Some(meter) = true_peak_meter.as_mut() {
+            meter.process(&block);
+        }
+        loudness_meter.process(&block);
+        for (welch, samples) in ltas.iter_mut().zip(&block) {
+            welch.push(samples);
+        }
+    }
+
+    let true_peaks = true_peak_meter.and_then(|m| m.finish()).unwrap_or_default();
+    let (lkfs_i, lkfs_m, lkfs_s) = loudness_meter.finish();
+    let ltas = average_spectra(ltas);
 
-    Some(
+    Ok(
         FileResults {
             metadata,
             lkfs_i,
             lkfs_s,
             lkfs_m,
             true_peaks,
+            ltas,
         }
     )
 }
 
+// ISO 3382 parameters of the first channel, for files the user says are impulse responses
+pub fn analyze_impulse_response(path: String) -> Result<RoomAcoustics, WavError> {
+    analyze_impulse_response_source(open_audio(path)?.as_mut())
+}
 
-pub fn calculate_true_peak(samples: &Vec<Vec<f32>>, metadata: &WavInfo) -> Option<Vec<f32>> {
-    // first upsample to 192kHz
-    let upsampled = upsample(samples.clone(), metadata.sample_rate);
-
-    // now find highest absolute magnitude(s)
-    let mut ch_maxes = vec![];
-    for c in 0..upsampled.len() {
-        let mut ch_max = 0.;
-        for s in &upsampled[c] {
-            if s.abs() > ch_max {
-                ch_max = s.abs();
-            }
+pub fn analyze_impulse_response_source(reader: &mut dyn AudioSource) -> Result<RoomAcoustics, WavError> {
+    let sample_rate = reader.info().sample_rate;
+    if reader.num_frames() > ROOM_IR_MAX_SECS * sample_rate as u64 {
+        return Err(WavError::InvalidInput("Impulse responses can't be longer than 30 seconds!"));
+    }
+    reader.set_block_size(ANALYSIS_BLOCK_SIZE)?;
+
+    let mut ir = vec![];
+    let mut block = vec![];
+    while reader.read_block(&mut block)? > 0 {
+        if let Some(samples) = block.first() {
+            ir.extend_from_slice(samples);
         }
-        ch_maxes.push(20. * ch_max.log10());
     }
+    room_acoustics(&ir, sample_rate)
+}
 
-    Some(ch_maxes)
+// the mean of every channel's spectrum
+fn average_spectra(channels: Vec<Welch>) -> Option<PowerSpectrum> {
+    let num_channels = channels.len();
+    let mut spectra = channels.into_iter().map(|welch| welch.finish().ok());
+    let mut average = spectra.next()??;
+    for spectrum in spectra {
+        for (avg, p) in average.density.iter_mut().zip(spectrum?.density) {
+            *avg += p;
+        }
+    }
+    average.density.iter_mut().for_each(|p| *p /= num_channels as f32);
+    Some(average)
 }
 
-// LKFS Measurements are based on: 
-// Recommendation ITU-R BS.1770-5 (11/2023) Algorithms to measure audio programme loudness and true-peak audio level
-fn calculate_file_loudness(samples: &Vec<Vec<f32>>, metadata: &WavInfo) -> (f64, f64, f64) {
-    let mut samples = samples.clone();
 
-    if metadata.sample_rate == 48000 {
-        // with 48kHz audio, we can directly use the Biquad filters given in the ITU-R document
+pub fn calculate_true_peak(samples: &Vec<Vec<f32>>, metadata: &WavInfo) -> Option<Vec<f32>> {
+    let mut meter = TruePeakMeter::new(metadata.sample_rate, samples.len())?;
+    meter.process(samples);
+    meter.finish()
+}
+
+// Finds the true peak of every channel block by block, by upsampling to 4x the sample rate
+pub struct TruePeakMeter {
+    filters: Vec<FIRFilter>,
+    history: Vec<Vec<f32>>, // the last FIR_UPSAMPLING_DEG - 1 samples of the previous block
+    ch_maxes: Vec<f32>,
+    num_samples: usize,
+}
 
-        let mut stage1_filter = Biquad::with_coefficients(1.53512485958697, -2.69169618940638, 1.19839281085285, -1.69065929318241, 0.73248077421585, 48000);
-        let mut stage2_filter = Biquad::with_coefficients(1., -2., 1., -1.99004745483398, 0.99007225036621, 48000);
+impl TruePeakMeter {
+    // upsampling filters only exist for 48kHz, 44.1kHz and 8kHz
+    pub fn new(sample_rate: u32, channels: usize) -> Option<Self> {
+        Some(Self {
+            filters: upsampling_filters(sample_rate)?,
+            history: vec![vec![]; channels],
+            ch_maxes: vec![0.; channels],
+            num_samples: 0,
+        })
+    }
 
-        for c in 0..samples.len() {
-            for i in 0..samples[c].len() {
-                samples[c][i] = stage1_filter.process(samples[c][i]);
-                samples[c][i] = stage2_filter.process(samples[c][i]);
+    pub fn process(&mut self, block: &[Vec<f32>]) {
+        for ((history, ch_max), samples) in self.history.iter_mut().zip(self.ch_maxes.iter_mut()).zip(block) {
+            history.extend_from_slice(samples);
+            if history.len() < FIR_UPSAMPLING_DEG {
+                continue;
             }
-            stage1_filter.reset_mem();
-            stage2_filter.reset_mem();
-        }
-
-    } else {
-        // these are frequency, q, and gain based filters derived from the coefficients in the ITU-R recommendation,
-        // and are slightly less accurate, but work for any sample rate
-        let stage1_filter = EqNode { filter_type: FilterType::HIGHSHELF, freq: 1500.2, gain: 4., q: 1. };
-        let mut stage1 = ParametricEq::new(vec![stage1_filter], metadata.sample_rate);
-        let stage2_filter = EqNode { filter_type: FilterType::HPF, freq: 50.42, gain: 0., q: 1. };
-        let mut stage2 = ParametricEq::new(vec![stage2_filter], metadata.sample_rate);
-
-        // process all channels through 2-stage K-weighting filters
-        for c in 0..samples.len() {
-            stage1.process(&mut samples[c]);
-            stage2.process(&mut samples[c]);
-            
-            stage1.reset_filter_mem();
-            stage2.reset_filter_mem();
+            for i in 0..=(history.len() - FIR_UPSAMPLING_DEG) {
+                for filter in &self.filters {
+                    let s = filter.process(&history[i..(i+FIR_UPSAMPLING_DEG)]).abs();
+                    if s > *ch_max {
+                        *ch_max = s;
+                    }
+                }
+            }
+            // keep just enough samples for the first window of the next block
+            history.drain(..(history.len() + 1 - FIR_UPSAMPLING_DEG));
         }
+        self.num_samples += block.first().map_or(0, |ch| ch.len());
     }
 
-    let samples_squared = samples.iter().map(|v| v.iter().map(|&x| (x as f64).powf(2.)).collect::<Vec<f64>>()).collect::<Vec<Vec<f64>>>();
+    // dBTP of every channel, None if there weren't enough samples to upsample
+    pub fn finish(self) -> Option<Vec<f32>> {
+        if self.num_samples <= 2 * FIR_UPSAMPLING_DEG {
+            return None;
+        }
+        Some(self.ch_maxes.iter().map(|m| 20. * m.log10()).collect())
+    }
+}
 
-    let samples_per_block = (0.4 * metadata.sample_rate as f32).round() as usize;
-    let step_size = (0.25 * samples_per_block as f32).round() as usize;
+// LKFS Measurements are based on: 
+// Recommendation ITU-R BS.1770-5 (11/2023) Algorithms to measure audio programme loudness and true-peak audio level
+struct LoudnessMeter {
+    channels: usize,
+    k_filters: Vec<[Biquad; 2]>, // 2-stage K-weighting filters for every channel
+    step_size: usize, // 100ms, a quarter of a gating block
+    step_pos: usize,
+    step_acc: Vec<f64>,
+    step_sums: Vec<Vec<f64>>, // sum of squares of every 100ms step
+}
 
-    let mut mean_squares = vec![vec![]; metadata.channels as usize];
-    for c in 0..samples_squared.len() {
-        let mut i = 0;
-        while i + samples_per_block < samples_squared[c].len() {
-            let mean = samples_squared[c][i..i+samples_per_block].iter().sum::<f64>() / samples_per_block as f64;
-            mean_squares[c].push(mean);
-            i += step_size;
+impl LoudnessMeter {
+    fn new(sample_rate: u32, channels: usize) -> Self {
+        let k_filters = (0..channels).map(|_| {
+            if sample_rate == 48000 {
+                // with 48kHz audio, we can directly use the Biquad filters given in the ITU-R document
+                [
+                    Biquad::with_coefficients(1.53512485958697, -2.69169618940638, 1.19839281085285, -1.69065929318241, 0.73248077421585, 48000),
+                    Biquad::with_coefficients(1., -2., 1., -1.99004745483398, 0.99007225036621, 48000),
+                ]
+            } else {
+                // these are frequency, q, and gain based filters derived from the coefficients in the ITU-R recommendation,
+                // and are slightly less accurate, but work for any sample rate
+                [
+                    Biquad::new(FilterType::HIGHSHELF, 1500.2, 4., 1., sample_rate),
+                    Biquad::new(FilterType::HPF, 50.42, 0., 1., sample_rate),
+                ]
+            }
+        }).collect();
+
+        let samples_per_block = (0.4 * sample_rate as f32).round() as usize;
+        Self {
+            channels,
+            k_filters,
+            step_size: ((0.25 * samples_per_block as f32).round() as usize).max(1),
+            step_pos: 0,
+            step_acc: vec![0.; channels],
+            step_sums: vec![vec![]; channels],
         }
     }
 
-    // calculate LKFS-M
-    let mut lkfs_m = f64::MIN;
-    for i in 0..mean_squares[0].len() {
-        let mut sum = 0.;
-        for c in 0..metadata.channels as usize {
-            sum += mean_squares[c][i];
-        }
-        let block_loudness = loudness(sum);
-        if block_loudness > lkfs_m {
-            lkfs_m = block_loudness;
+    fn process(&mut self, block: &[Vec<f32>]) {
+        // every channel sees the same frames, so they all end up at the same step position
+        let step_size = self.step_size;
+        let mut step_pos = self.step_pos;
+        let channels = self.k_filters.iter_mut().zip(self.step_acc.iter_mut()).zip(self.step_sums.iter_mut());
+        for ((([stage1, stage2], acc), sums), samples) in channels.zip(block) {
+            step_pos = self.step_pos;
+            for &sample in samples {
+                let s = stage2.process(stage1.process(sample)) as f64;
+                *acc += s * s;
+                step_pos += 1;
+                if step_pos == step_size {
+                    sums.push(*acc);
+                    *acc = 0.;
+                    step_pos = 0;
+                }
+            }
         }
+        self.step_pos = step_pos;
     }
 
-    // calculate LKFS-S
-    let mut lkfs_s = f64::MIN;
-    if mean_squares[0].len() >= 30 {
-        for i in 0..(mean_squares[0].len() - 30) {
+    // (LKFS-I, LKFS-M, LKFS-S)
+    fn finish(self) -> (f64, f64, f64) {
+        // 400ms blocks with 75% overlap
+        let samples_per_block = 4 * self.step_size;
+        let mut mean_squares: Vec<Vec<f64>> = self.step_sums
+            .iter()
+            .map(|sums| sums.windows(4).map(|steps| steps.iter().sum::<f64>() / samples_per_block as f64).collect())
+            .collect();
+
+        // calculate LKFS-M
+        let mut lkfs_m = f64::MIN;
+        for i in 0..mean_squares[0].len() {
             let mut sum = 0.;
-            for c in 0..metadata.channels as usize {
-                sum += mean_squares[c][i..i+30].iter().sum::<f64>() / 30.;
+            for c in 0..self.channels {
+                sum += mean_squares[c][i];
             }
             let block_loudness = loudness(sum);
-            if block_loudness > lkfs_s {
-                lkfs_s = block_loudness;
+            if block_loudness > lkfs_m {
+                lkfs_m = block_loudness;
             }
         }
-    }
-
-    // first gating stage
-    let gamma_a = -70.;
-    mean_squares = filter_blocks(mean_squares, gamma_a);
 
-    let mut gamma_r = 0.;
-    for c in 0..metadata.channels as usize {
-        let ch_sum = mean_squares[c].iter().sum::<f64>();
-        gamma_r += ch_sum / mean_squares[c].len() as f64;
-    }
-    gamma_r = loudness(gamma_r) - 10.;
+        // calculate LKFS-S
+        let mut lkfs_s = f64::MIN;
+        if mean_squares[0].len() >= 30 {
+            for i in 0..(mean_squares[0].len() - 30) {
+                let mut sum = 0.;
+                for c in 0..self.channels {
+                    sum += mean_squares[c][i..i+30].iter().sum::<f64>() / 30.;
+                }
+                let block_loudness = loudness(sum);
+                if block_loudness > lkfs_s {
+                    lkfs_s = block_loudness;
+                }
+            }
+        }
 
-    // second gating stage
-    mean_squares = filter_blocks(mean_squares, gamma_r);
+        // first gating stage
+        let gamma_a = -70.;
+        mean_squares = filter_blocks(mean_squares, gamma_a);
 
-    let mut final_loudness_sum = 0.;
-    for c in 0..metadata.channels as usize {
-        let ch_sum = mean_squares[c].iter().sum::<f64>();
-        final_loudness_sum += ch_sum / mean_squares[c].len() as f64;
-    }
-    let lkfs_i = loudness(final_loudness_sum);
-    
-    //if there were not enough samples for a 3s measurement
-    if lkfs_s == f64::MIN {
-        lkfs_s = lkfs_i;
-    }
-
-    (lkfs_i, lkfs_m, lkfs_s)
-}
+        let mut gamma_r = 0.;
+        for c in 0..self.channels {
+            let ch_sum = mean_squares[c].iter().sum::<f64>();
+            gamma_r += ch_sum / mean_squares[c].len() as f64;
+        }
+        gamma_r = loudness(gamma_r) - 10.;
 
-// helpers for calculate_true_peak
-pub fn upsample(samples: Vec<Vec<f32>>, sample_rate: u32) -> Vec<Vec<f32>> {
-    //FIR Filters
-    let mut upsampling_filters = vec![];
+        // second gating stage
+        mean_squares = filter_blocks(mean_squares, gamma_r);
 
-    match sample_rate {
-        48000 => {
-            for f in FIR_COEFF_48K {
-                upsampling_filters.push(FIRFilter::new(FIR_UPSAMPLING_DEG, f.to_vec()));
-            }
-        },
-        44100 => {
-            for f in FIR_COEFF_44_1K {
-                upsampling_filters.push(FIRFilter::new(FIR_UPSAMPLING_DEG, f.to_vec()));
-            }
-        },
-        8000 => {
-            for f in FIR_COEFF_8K {
-                upsampling_filters.push(FIRFilter::new(FIR_UPSAMPLING_DEG, f.to_vec()));
-            }
+        let mut final_loudness_sum = 0.;
+        for c in 0..self.channels {
+            let ch_sum = mean_squares[c].iter().sum::<f64>();
+            final_loudness_sum += ch_sum / mean_squares[c].len() as f64;
         }
-        _ => {
-            panic!();
+        let lkfs_i = loudness(final_loudness_sum);
+        
+        //if there were not enough samples for a 3s measurement
+        if lkfs_s == f64::MIN {
+            lkfs_s = lkfs_i;
         }
+
+        (lkfs_i, lkfs_m, lkfs_s)
     }
+}
+
+// helpers for true peak measurement
+pub fn upsample(samples: Vec<Vec<f32>>, sample_rate: u32) -> Vec<Vec<f32>> {
+    let upsampling_filters = upsampling_filters(sample_rate).unwrap();
 
     let channels = samples.len();
 
@@ -231,7 +323,18 @@ pub fn upsample(samples: Vec<Vec<f32>>, sample_rate: u32) -> Vec<Vec<f32>> {
     upsampled
 }
 
-// helpers for calculate_file_loudness
+// one polyphase FIR filter per upsampled sample, None for unsupported sample rates
+fn upsampling_filters(sample_rate: u32) -> Option<Vec<FIRFilter>> {
+    let coefficients: &[[f32; FIR_UPSAMPLING_DEG]] = match sample_rate {
+        48000 => &FIR_COEFF_48K,
+        44100 => &FIR_COEFF_44_1K,
+        8000 => &FIR_COEFF_8K,
+        _ => return None,
+    };
+    Some(coefficients.iter().map(|f| FIRFilter::new(FIR_UPSAMPLING_DEG, f.to_vec())).collect())
+}
+
+// helpers for LoudnessMeter
 fn loudness(squared_mean: f64) -> f64 {
     -0.691 + 10. * f64::log10(squared_mean)
 }
@@ -1,9 +1,164 @@
 use std::collections::HashMap;
 use std::fmt;
 use std::fs::File;
-use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
 
+use crate::aiff::read_aiff_meta;
+use crate::audio_source::{audio_path, AudioFormat, AudioSource};
+use crate::flac::{read_flac_meta, FlacReader};
 use crate::lookup_tables::*;
+use crate::mp3::{read_mp3_meta, Mp3Reader};
+use crate::ogg::{read_ogg_meta, OggReader};
+use crate::wav_metadata::{BextChunk, CuePoint, IxmlChunk, WavTags};
+
+#[derive(Debug)]
+pub enum WavError {
+    Io(io::Error),
+    InvalidHeader(&'static str),
+    TruncatedChunk(String), // chunk claims to be larger than what is left of the file
+    MissingChunk(&'static str),
+    UnsupportedFormat(u16),
+    UnsupportedSubFormat(Guid),
+    UnsupportedCompression(String), // AIFF-C compression type
+    UnsupportedBitDepth { sample_type: u16, bit_depth: u32 },
+    InvalidInput(&'static str),
+    UnrecognizedFormat, // not a file format that can be opened
+    InvalidData(&'static str), // sample data that can't be decoded, e.g. a corrupt FLAC frame
+    ChecksumMismatch, // decoded audio doesn't match the checksum stored in the file
+}
+
+impl fmt::Display for WavError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "I/O error: {}", err),
+            Self::InvalidHeader(reason) => write!(f, "Invalid wav header: {}", reason),
+            Self::TruncatedChunk(chunk) => write!(f, "Chunk \"{}\" is truncated", chunk),
+            Self::MissingChunk(chunk) => write!(f, "Missing required \"{}\" chunk", chunk),
+            Self::UnsupportedFormat(code) => write!(f, "Unsupported format code: {:#06x}", code),
+            Self::UnsupportedSubFormat(guid) => write!(f, "Unsupported extensible sub format: {}", guid),
+            Self::UnsupportedCompression(kind) => write!(f, "Unsupported AIFF-C compression type: \"{}\"", kind),
+            Self::UnsupportedBitDepth { sample_type, bit_depth } => {
+                write!(f, "Unsupported bit depth {} for format code {:#06x}", bit_depth, sample_type)
+            }
+            Self::InvalidInput(reason) => write!(f, "Invalid input: {}", reason),
+            Self::UnrecognizedFormat => write!(f, "Unrecognized audio file format"),
+            Self::InvalidData(reason) => write!(f, "Invalid audio data: {}", reason),
+            Self::ChecksumMismatch => write!(f, "Decoded audio doesn't match the stored MD5 checksum"),
+        }
+    }
+}
+
+impl std::error::Error for WavError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for WavError {
+    fn from(err: io::Error) -> Self {
+        // decoders that implement Read pass their own errors through io::Error
+        if err.get_ref().is_some_and(|inner| inner.is::<WavError>()) {
+            return *err.into_inner().unwrap().downcast::<WavError>().unwrap();
+        }
+        Self::Io(err)
+    }
+}
+
+// raw 16 byte GUID, stored the way it is laid out in the file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Guid(pub [u8; 16]);
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the first three fields are little endian, the last 8 bytes are written as is
+        let b = &self.0;
+        write!(
+            f,
+            "{{{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}}}",
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            u16::from_le_bytes([b[4], b[5]]),
+            u16::from_le_bytes([b[6], b[7]]),
+            b[8], b[9], b[10], b[11], b[12], b[13], b[14], b[15],
+        )
+    }
+}
+
+// everything after the format code in the standard KSDATAFORMAT_SUBTYPE_* GUIDs,
+// {XXXXXXXX-0000-0010-8000-00AA00389B71} with the format code in the first field
+const WAVE_GUID_TAIL: [u8; 12] = [0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71];
+// Ambisonic B-Format GUIDs, {XXXXXXXX-0721-11D3-8644-C8C1CA000000}
+const AMBISONIC_GUID_TAIL: [u8; 12] = [0x21, 0x07, 0xD3, 0x11, 0x86, 0x44, 0xC8, 0xC1, 0xCA, 0x00, 0x00, 0x00];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SubFormat {
+    Pcm,
+    IeeeFloat,
+    ALaw,
+    MuLaw,
+    AmbisonicBFormatPcm,
+    AmbisonicBFormatFloat,
+    Unknown(Guid),
+}
+
+impl SubFormat {
+    pub fn from_guid(guid: Guid) -> Self {
+        let code = u32::from_le_bytes(guid.0[0..4].try_into().unwrap());
+        let tail: [u8; 12] = guid.0[4..].try_into().unwrap();
+        match (code, tail) {
+            (1, WAVE_GUID_TAIL) => Self::Pcm,
+            (3, WAVE_GUID_TAIL) => Self::IeeeFloat,
+            (6, WAVE_GUID_TAIL) => Self::ALaw,
+            (7, WAVE_GUID_TAIL) => Self::MuLaw,
+            (1, AMBISONIC_GUID_TAIL) => Self::AmbisonicBFormatPcm,
+            (3, AMBISONIC_GUID_TAIL) => Self::AmbisonicBFormatFloat,
+            _ => Self::Unknown(guid),
+        }
+    }
+
+    pub fn guid(&self) -> Guid {
+        let (code, tail): (u32, [u8; 12]) = match *self {
+            Self::Pcm => (1, WAVE_GUID_TAIL),
+            Self::IeeeFloat => (3, WAVE_GUID_TAIL),
+            Self::ALaw => (6, WAVE_GUID_TAIL),
+            Self::MuLaw => (7, WAVE_GUID_TAIL),
+            Self::AmbisonicBFormatPcm => (1, AMBISONIC_GUID_TAIL),
+            Self::AmbisonicBFormatFloat => (3, AMBISONIC_GUID_TAIL),
+            Self::Unknown(guid) => return guid,
+        };
+        let mut guid = [0u8; 16];
+        guid[0..4].copy_from_slice(&code.to_le_bytes());
+        guid[4..].copy_from_slice(&tail);
+        Guid(guid)
+    }
+
+    // the plain format code the samples are stored as, None if we can't decode them
+    pub fn sample_type(&self) -> Option<u16> {
+        match *self {
+            Self::Pcm | Self::AmbisonicBFormatPcm => Some(1),
+            Self::IeeeFloat | Self::AmbisonicBFormatFloat => Some(3),
+            Self::ALaw => Some(6),
+            Self::MuLaw => Some(7),
+            Self::Unknown(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for SubFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pcm => f.write_str("PCM"),
+            Self::IeeeFloat => f.write_str("IEEE Float"),
+            Self::ALaw => f.write_str("A-law"),
+            Self::MuLaw => f.write_str("µ-law"),
+            Self::AmbisonicBFormatPcm => f.write_str("Ambisonic B-Format PCM"),
+            Self::AmbisonicBFormatFloat => f.write_str("Ambisonic B-Format IEEE Float"),
+            Self::Unknown(guid) => write!(f, "Unknown {}", guid),
+        }
+    }
+}
 
 #[derive(Clone, Copy, Debug)]
 pub enum SpeakerPos {
@@ -110,10 +265,20 @@ pub struct WavInfo {
     pub data_block_size: u32,
     pub bit_depth: u32,
     pub byte_depth: u32,
-    pub chunks: HashMap<String, (u64, u32)>, // {chunk_name: (position, chunk_size)}
-    pub file_size: u32,
+    pub valid_bits_per_sample: u32, // actual precision of the samples, e.g. 20 for 20-bit audio stored in 24-bit containers
+    pub sub_format: Option<SubFormat>, // only set for WAVE_FORMAT_EXTENSIBLE files
+    // how the samples differ from the wav layout, only used by AIFF files
+    pub big_endian: bool,
+    pub signed_8bit: bool,
+    pub format: AudioFormat, // compressed formats are decoded by their own AudioSource
+    pub chunks: HashMap<String, (u64, u64)>, // {chunk_name: (position, chunk_size)}, LIST chunks are stored as "LIST/<list type>"
+    pub file_size: u64,
     pub audio_duration: f32,
     pub channel_map: Vec<(u8, SpeakerPos)>,
+    pub bext: Option<BextChunk>,
+    pub ixml: Option<IxmlChunk>,
+    pub tags: Option<WavTags>,
+    pub cue_points: Vec<CuePoint>,
 }
 
 impl WavInfo {
@@ -122,8 +287,8 @@ impl WavInfo {
         channels: u8,
         sample_rate: u32,
         bit_depth: u32,
-        file_size: u32,
-        chunks: HashMap<String, (u64, u32)>,
+        file_size: u64,
+        chunks: HashMap<String, (u64, u64)>,
         channel_map: Vec<(u8, SpeakerPos)>,
     ) -> Self {
         let byte_depth = bit_depth / 8;
@@ -147,10 +312,19 @@ impl WavInfo {
             data_block_size: byte_depth * channels as u32,
             bit_depth,
             byte_depth: bit_depth / 8,
+            valid_bits_per_sample: bit_depth,
+            sub_format: None,
+            big_endian: false,
+            signed_8bit: false,
+            format: AudioFormat::Wav,
             chunks,
             file_size,
             audio_duration,
-            channel_map
+            channel_map,
+            bext: None,
+            ixml: None,
+            tags: None,
+            cue_points: Vec::new(),
         }
     }
 }
@@ -169,66 +343,182 @@ impl fmt::Display for WavInfo {
     }
 }
 
-pub fn read_wav_sample_rate(f: String) -> u32 {
-    let mut r = BufReader::new(File::open(format!("./res/audio/{}", f)).unwrap());
-    r.seek_relative(24).unwrap();
-    read_le_uint(&mut r, 4)
+// Reads the metadata of any supported format, detected from the first bytes of the stream
+pub fn read_wav_meta<R: Read + Seek>(f: &mut R) -> Result<WavInfo, WavError> {
+    // without a file name to fall back on, anything unknown gets the RIFF reader's error
+    let format = AudioFormat::detect_stream(f, None)?.unwrap_or(AudioFormat::Wav);
+    read_format_meta(f, format)
 }
 
-pub fn read_wav_meta(f: &mut BufReader<File>) -> WavInfo {
-    f.seek(SeekFrom::Start(0)).unwrap();
+// Reads the metadata of a stream whose format has already been detected
+pub fn read_format_meta<R: Read + Seek>(f: &mut R, format: AudioFormat) -> Result<WavInfo, WavError> {
+    f.seek(SeekFrom::Start(0))?;
+    match format {
+        AudioFormat::Wav => read_riff_meta(f),
+        // AIFF files are read into the same WavInfo, so everything else can treat them like wav files
+        AudioFormat::Aiff => read_aiff_meta(f),
+        AudioFormat::Flac => read_flac_meta(f),
+        AudioFormat::Mp3 => read_mp3_meta(f),
+        AudioFormat::Ogg => read_ogg_meta(f),
+    }
+}
 
-    f.seek_relative(4).unwrap();
-    let f_size: u32 = read_le_uint(f, 4);
-    f.seek_relative(12).unwrap();
-    let mut fmt_code = read_le_uint(f, 2) as u8;
-    let channels = read_le_uint(f, 2) as u8;
-    let sample_rate = read_le_uint(f, 4);
-    let _data_rate = read_le_uint(f, 4); // not used since WavInfo::new calculates these
-    let _data_block_size = read_le_uint(f, 2); // not used since WavInfo::new calculates these
-    let bit_depth = read_le_uint(f, 2);
+fn read_riff_meta<R: Read + Seek>(f: &mut R) -> Result<WavInfo, WavError> {
+    let file_len = f.seek(SeekFrom::End(0))?;
+    f.seek(SeekFrom::Start(0))?;
 
-    //only really reading this stuff for potential future use, its not used at the moment
-    let ext_size: u8;
-    let _v_bits_per_sample: u8; // information about the precision of IEEE floats in file
-    let _subformat: String;
-    
-    // mapping from channels to physical speakers
-    let mut channel_mask_num: u32 = 0xFFFFFFFF; // default is all 1s, for a direct mapping
+    if file_len < 12 {
+        return Err(WavError::TruncatedChunk("RIFF".to_string()));
+    }
+    let riff_tag = read_str(f, 4)?;
+    let mut f_size = read_le_uint(f, 4)? as u64;
+    let wave_tag = read_str(f, 4)?;
+    let is_rf64 = riff_tag == "RF64" || riff_tag == "BW64";
+    if !(riff_tag == "RIFF" || is_rf64) || wave_tag != "WAVE" {
+        return Err(WavError::InvalidHeader("not a RIFF/WAVE file"));
+    }
+
+    // RF64 and BW64 files store their real (64-bit) sizes in a ds64 chunk
+    // that has to come right after the header
+    let mut ds64_sizes: HashMap<String, u64> = HashMap::new();
+    if is_rf64 {
+        if read_str(f, 4)? != "ds64" {
+            return Err(WavError::MissingChunk("ds64"));
+        }
+        let ds64_size = read_le_uint(f, 4)? as u64;
+        if ds64_size < DS64_CHUNK_SIZE as u64 || 20 + ds64_size > file_len {
+            return Err(WavError::TruncatedChunk("ds64".to_string()));
+        }
+        f_size = read_le_u64(f)?;
+        ds64_sizes.insert("data".to_string(), read_le_u64(f)?);
+        let _sample_count = read_le_u64(f)?;
+        // any other chunks bigger than 4GiB are listed in a table
+        let table_len = read_le_uint(f, 4)? as u64;
+        if DS64_CHUNK_SIZE as u64 + table_len * 12 > ds64_size {
+            return Err(WavError::TruncatedChunk("ds64".to_string()));
+        }
+        for _ in 0..table_len {
+            let title = read_str(f, 4)?;
+            ds64_sizes.insert(title, read_le_u64(f)?);
+        }
+        f.seek(SeekFrom::Start(12))?;
+    }
+
+    // build the chunk table first, so the fmt chunk doesn't have to be the first one
+    let riff_end = u64::min(f_size.saturating_add(8), file_len);
+    let mut chunks: HashMap<String, (u64, u64)> = HashMap::new();
+    while f.stream_position()? + 8 <= riff_end {
+        let title = read_str(f, 4)?;
+        let mut size = read_le_uint(f, 4)? as u64;
+        if is_rf64 && size == u32::MAX as u64 {
+            // a size of -1 means the actual size is in the ds64 chunk
+            size = *ds64_sizes.get(&title).unwrap_or(&size);
+        }
+        let pos = f.stream_position()?;
+        // ds64 sizes are full 64-bit values, so the end of the chunk can be past what a u64 holds
+        if pos.checked_add(size).is_none_or(|end| end > file_len) {
+            return Err(WavError::TruncatedChunk(title));
+        }
+        // a file can have several LIST chunks, so they are told apart by their list type
+        let title = if title == "LIST" && size >= 4 {
+            format!("LIST/{}", read_str(f, 4)?)
+        } else {
+            title
+        };
+        chunks.insert(title, (pos, size));
+        // chunks are word aligned, odd sized chunks have a padding byte
+        f.seek(SeekFrom::Start(pos + size + (size & 1)))?;
+    }
+
+    let (fmt_pos, fmt_size) = *chunks.get("fmt ").ok_or(WavError::MissingChunk("fmt "))?;
+    if !chunks.contains_key("data") {
+        return Err(WavError::MissingChunk("data"));
+    }
+    if fmt_size < 16 {
+        return Err(WavError::TruncatedChunk("fmt ".to_string()));
+    }
+
+    f.seek(SeekFrom::Start(fmt_pos))?;
+    let mut fmt_code = read_le_uint(f, 2)? as u16;
+    let channels = read_le_uint(f, 2)?;
+    let sample_rate = read_le_uint(f, 4)?;
+    let _data_rate = read_le_uint(f, 4)?; // not used since WavInfo::new calculates these
+    let _data_block_size = read_le_uint(f, 2)?; // not used since WavInfo::new calculates these
+    let bit_depth = read_le_uint(f, 2)?;
 
-    // bit depths of 8 or less in PCM use offset binary instead of 
-    // 2's complement which idk how to parse so ..
-    if fmt_code == 1 && bit_depth <= 8 {
-        panic!("Unsupported bit depth (bit depth 8 or lower)");
+    if channels == 0 || channels > u8::MAX as u32 {
+        return Err(WavError::InvalidHeader("invalid number of channels"));
+    }
+    if sample_rate == 0 {
+        return Err(WavError::InvalidHeader("sample rate of 0"));
     }
 
+    // information about the actual precision of the samples, defaults to the container size
+    let mut valid_bits_per_sample = bit_depth;
+    let mut sub_format = None;
+
+    // mapping from channels to physical speakers
+    let mut channel_mask_num: u32 = 0xFFFFFFFF; // default is all 1s, for a direct mapping
+
     match fmt_code {
         1 => {}, //no extra parsing needed for PCM data
-        3 | 6 | 7 | 0xFE => { //non-PCM data should always have the ext_size field
-            ext_size = read_le_uint(f, 2) as u8;
-            if ext_size > 0 {
-                _v_bits_per_sample = read_le_uint(f, 2) as u8;
-                channel_mask_num = read_le_uint(f, 4);
-                if channel_mask_num == 0 {
-                    // channel mask of 0 actually indicates the default mapping
-                    channel_mask_num = 0xFFFFFFFF;
+        3 | 6 | 7 | 0xFFFE => {
+            // non-PCM data should have the ext_size field, but some
+            // writers leave it out of plain float/A-law/µ-law files
+            if fmt_size < 18 {
+                if fmt_code == 0xFFFE {
+                    return Err(WavError::TruncatedChunk("fmt ".to_string()));
+                }
+            } else {
+                let ext_size = read_le_uint(f, 2)? as u16;
+                // only the extensible format puts a sub format in the extension,
+                // anything else in there is codec specific
+                if ext_size > 0 && fmt_code == 0xFFFE {
+                    if fmt_size < 18 + 22 {
+                        return Err(WavError::TruncatedChunk("fmt ".to_string()));
+                    }
+                    let v_bits = read_le_uint(f, 2)?;
+                    // 0 means the field is unused, anything above the container size is bogus
+                    if v_bits > 0 && v_bits <= bit_depth {
+                        valid_bits_per_sample = v_bits;
+                    }
+                    channel_mask_num = read_le_uint(f, 4)?;
+                    if channel_mask_num == 0 {
+                        // channel mask of 0 actually indicates the default mapping
+                        channel_mask_num = 0xFFFFFFFF;
+                    }
+                    // files with extension data store the actual format
+                    // as a GUID later in the file so now we read it in again ...
+                    let mut guid = [0u8; 16];
+                    f.read_exact(&mut guid)?;
+                    let parsed = SubFormat::from_guid(Guid(guid));
+                    fmt_code = parsed.sample_type().ok_or(WavError::UnsupportedSubFormat(Guid(guid)))?;
+                    sub_format = Some(parsed);
                 }
-                // files with extension data store the actual format code
-                // later in the file so now we read it in again ...
-                fmt_code = read_le_uint(f, 2) as u8;
             }
         },
         _ => {
-            panic!("Unknown format code!");
+            return Err(WavError::UnsupportedFormat(fmt_code));
         }
     }
 
+    // the extensible subformat has to resolve to one of the formats we can read
+    let supported_depth = match fmt_code {
+        1 => matches!(bit_depth, 8 | 16 | 24 | 32),
+        3 => matches!(bit_depth, 32 | 64),
+        6 | 7 => bit_depth == 8,
+        _ => return Err(WavError::UnsupportedFormat(fmt_code)),
+    };
+    if !supported_depth {
+        return Err(WavError::UnsupportedBitDepth { sample_type: fmt_code, bit_depth });
+    }
+
     //assign the channel map
     let mut cur_map = channel_mask_num;
     let mut cur_ch = 0;
     let mut channel_map = Vec::new();
     let mut i = 0;
-    while cur_ch < channels && i < 32 {
+    while cur_ch < channels as u8 && i < 32 {
         if cur_map & 1 != 0 {
             channel_map.push((cur_ch, SpeakerPos::from(2f32.powi(i as i32) as u32))); 
             cur_ch += 1;
@@ -237,285 +527,660 @@ pub fn read_wav_meta(f: &mut BufReader<File>) -> WavInfo {
         i += 1;
     }
 
-    f.seek(SeekFrom::Start(0)).unwrap();
-    f.seek_relative(12).unwrap(); //skip 'RIFF' and 'WAVE' tags
-    let mut chunks: HashMap<String, (u64, u32)> = HashMap::new();
+    // metadata that can't be parsed is left out instead of failing the whole file
+    let bext = read_chunk(f, &chunks, "bext")?.and_then(|buf| BextChunk::from_bytes(&buf).ok());
+    let ixml = read_chunk(f, &chunks, "iXML")?.and_then(|buf| IxmlChunk::from_bytes(&buf).ok());
+    let tags = read_chunk(f, &chunks, "LIST/INFO")?.and_then(|buf| WavTags::from_bytes(&buf).ok());
+    let adtl = read_chunk(f, &chunks, "LIST/adtl")?;
+    let cue_points = read_chunk(f, &chunks, "cue ")?
+        .and_then(|buf| CuePoint::parse_all(&buf, adtl.as_deref()).ok())
+        .unwrap_or_default();
+
+    f.seek(SeekFrom::Start(0))?;
+    let mut info = WavInfo::new(fmt_code as u8, channels as u8, sample_rate, bit_depth, f_size, chunks, channel_map);
+    info.bext = bext;
+    info.ixml = ixml;
+    info.tags = tags;
+    info.cue_points = cue_points;
+    info.valid_bits_per_sample = valid_bits_per_sample;
+    info.sub_format = sub_format;
+    if let Some(sub_format @ (SubFormat::AmbisonicBFormatPcm | SubFormat::AmbisonicBFormatFloat)) = sub_format {
+        info.sample_type_str = sub_format.to_string();
+    }
+    Ok(info)
+}
+
+fn read_chunk<R: Read + Seek>(f: &mut R, chunks: &HashMap<String, (u64, u64)>, name: &str) -> Result<Option<Vec<u8>>, WavError> {
+    let Some(&(pos, size)) = chunks.get(name) else {
+        return Ok(None);
+    };
+    f.seek(SeekFrom::Start(pos))?;
+    let mut buf = vec![0; size as usize];
+    f.read_exact(&mut buf)?;
+    Ok(Some(buf))
+}
+
+// Streams the audio data of a wav file in blocks of deinterleaved frames,
+// so only one block ever has to be held in memory
+pub struct WavReader<R: Read + Seek> {
+    reader: R,
+    info: WavInfo,
+    data_start: u64,
+    num_frames: u64,
+    frame_pos: u64,
+    block_size: usize, // in frames
+}
+
+impl<R: Read + Seek> WavReader<R> {
+    pub fn new(mut reader: R, block_size: usize) -> Result<Self, WavError> {
+        if block_size == 0 {
+            return Err(WavError::InvalidInput("Block size must be at least 1 frame!"));
+        }
+        let info = read_wav_meta(&mut reader)?;
+        Self::with_info(reader, info, block_size)
+    }
+
+    // for streams whose format is already known, e.g. from AudioFormat::detect
+    pub fn with_format(mut reader: R, format: AudioFormat, block_size: usize) -> Result<Self, WavError> {
+        if block_size == 0 {
+            return Err(WavError::InvalidInput("Block size must be at least 1 frame!"));
+        }
+        let info = read_format_meta(&mut reader, format)?;
+        Self::with_info(reader, info, block_size)
+    }
+
+    fn with_info(mut reader: R, info: WavInfo, block_size: usize) -> Result<Self, WavError> {
+        if info.format.is_compressed() {
+            return Err(WavError::InvalidInput("Compressed files have to be read with the reader of their format!"));
+        }
+        let (data_start, data_size) = *info.chunks.get("data").ok_or(WavError::MissingChunk("data"))?;
+        reader.seek(SeekFrom::Start(data_start))?;
+        Ok(Self {
+            num_frames: data_size / info.data_block_size as u64,
+            reader,
+            info,
+            data_start,
+            frame_pos: 0,
+            block_size,
+        })
+    }
+
+    pub fn info(&self) -> &WavInfo {
+        &self.info
+    }
+
+    pub fn num_frames(&self) -> u64 {
+        self.num_frames
+    }
+
+    // index of the next frame that will be read
+    pub fn position(&self) -> u64 {
+        self.frame_pos
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    pub fn set_block_size(&mut self, block_size: usize) -> Result<(), WavError> {
+        if block_size == 0 {
+            return Err(WavError::InvalidInput("Block size must be at least 1 frame!"));
+        }
+        self.block_size = block_size;
+        Ok(())
+    }
+
+    pub fn seek_frame(&mut self, frame: u64) -> Result<(), WavError> {
+        if frame > self.num_frames {
+            return Err(WavError::InvalidInput("Can't seek past the end of the audio data!"));
+        }
+        self.reader.seek(SeekFrom::Start(self.data_start + frame * self.info.data_block_size as u64))?;
+        self.frame_pos = frame;
+        Ok(())
+    }
+
+    // fills as much of `out` with interleaved samples as possible, returns the number of frames read
+    // (0 once the end of the data is reached). `out` should hold a whole number of frames
+    pub fn read_interleaved(&mut self, out: &mut [f32]) -> Result<usize, WavError> {
+        let channels = self.info.channels as usize;
+        let frames = u64::min((out.len() / channels) as u64, self.num_frames - self.frame_pos) as usize;
+        if frames == 0 {
+            return Ok(0);
+        }
+        let samples = read_data_interleaved_unchecked(&mut self.reader, &self.info, frames * channels)?;
+        out[..samples.len()].copy_from_slice(&samples);
+        self.frame_pos += frames as u64;
+        Ok(frames)
+    }
+
+    // reads the next block into `block` (one Vec per channel), returns the number of frames read.
+    // only the last block of the file is shorter than the block size
+    pub fn read_block(&mut self, block: &mut Vec<Vec<f32>>) -> Result<usize, WavError> {
+        let channels = self.info.channels as usize;
+        let frames = u64::min(self.block_size as u64, self.num_frames - self.frame_pos) as usize;
+        block.resize(channels, vec![]);
+        if frames == 0 {
+            block.iter_mut().for_each(|ch| ch.clear());
+            return Ok(0);
+        }
 
-    while f.stream_position().unwrap() < f_size as u64 {
-        let title = read_str(f, 4);
-        let size = read_le_uint(f, 4);
-        chunks.insert(title, (f.stream_position().unwrap(), size));
-        f.seek_relative(size as i64).unwrap();
+        let samples = read_data_interleaved_unchecked(&mut self.reader, &self.info, frames * channels)?;
+        for (c, ch) in block.iter_mut().enumerate() {
+            ch.clear();
+            ch.extend(samples.iter().skip(c).step_by(channels));
+        }
+        self.frame_pos += frames as u64;
+        Ok(frames)
     }
+}
+
+impl<R: Read + Seek> Iterator for WavReader<R> {
+    type Item = Result<Vec<Vec<f32>>, WavError>;
 
-    f.seek(SeekFrom::Start(0)).unwrap();
-    WavInfo::new(fmt_code, channels, sample_rate, bit_depth, f_size, chunks, channel_map)
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut block = vec![];
+        match self.read_block(&mut block) {
+            Ok(0) => None,
+            Ok(_) => Some(Ok(block)),
+            Err(err) => Some(Err(err)),
+        }
+    }
 }
 
-pub fn read_data(
-    f: &mut BufReader<File>,
+pub fn read_data<R: Read + Seek>(
+    f: &mut R,
     file_info: &WavInfo,
     start_time: f32,
     duration: f32,
-) -> Option<Vec<Vec<f32>>> {
+) -> Result<Vec<Vec<f32>>, WavError> {
     let sample_size = (file_info.bit_depth / 8) as usize;
     let channels = file_info.channels as usize;
     let mut samples_per_channel = (duration * file_info.sample_rate as f32) as usize;
     let total_samples = samples_per_channel * channels;
 
-    f.seek(SeekFrom::Start(
-        file_info.chunks.get("data".into()).unwrap().0,
-    ))
-    .unwrap();
-    let file_start_pos = (start_time * file_info.sample_rate as f32 * file_info.channels as f32) as i64;
-    //skip to start_pos in the file
-    f.seek_relative(file_start_pos)
-        .unwrap();
+    let (data_start, data_size) = *file_info.chunks.get("data").ok_or(WavError::MissingChunk("data"))?;
+    let data_end = data_start + data_size;
+
+    //skip to start_pos in the file (always on a sample frame boundary)
+    let file_start_pos = (start_time.max(0.) * file_info.sample_rate as f32) as u64 * file_info.data_block_size as u64;
+    f.seek(SeekFrom::Start(u64::min(data_start + file_start_pos, data_end)))?;
+
+    if file_info.format.is_compressed() {
+        // compressed frames have to be decoded, starting from the one that holds the start frame
+        let start_frame = u64::min(file_start_pos, data_size) / file_info.data_block_size as u64;
+        let mut source: Box<dyn AudioSource + '_> = match file_info.format {
+            AudioFormat::Flac => Box::new(FlacReader::new(&mut *f, 1)?),
+            AudioFormat::Mp3 => Box::new(Mp3Reader::new(&mut *f, 1)?),
+            _ => Box::new(OggReader::new(&mut *f, 1)?),
+        };
+        source.seek_frame(start_frame)?;
+        let available = source.num_frames() - start_frame;
+        let mut samples = vec![0.; u64::min(samples_per_channel as u64, available) as usize * channels];
+        let frames = source.read_interleaved(&mut samples)?;
+        let mut output = vec![Vec::with_capacity(frames); channels];
+        for frame in samples.chunks_exact(channels) {
+            for (ch, &sample) in output.iter_mut().zip(frame) {
+                ch.push(sample);
+            }
+        }
+        return Ok(output);
+    }
 
-    let mut data: Vec<u8>;
-    let (data_start, data_size) = *file_info.chunks.get("data").unwrap();
     //either read the amount of data requested, or read to EOF
-    if f.stream_position().unwrap() + total_samples as u64 * sample_size as u64 > (data_start + data_size as u64) {
-        data = vec![0; (data_start + data_size as u64) as usize - f.stream_position().unwrap() as usize];
-        f.read_exact(&mut data).unwrap();
+    let mut data: Vec<u8>;
+    let cur_pos = f.stream_position()?;
+    if cur_pos + total_samples as u64 * sample_size as u64 > data_end {
+        data = vec![0; (data_end - cur_pos) as usize];
+        f.read_exact(&mut data)?;
         samples_per_channel = data.len() / channels / sample_size;
     } else {
         data = vec![0; total_samples * sample_size];
-        f.read_exact(&mut data).unwrap();
+        f.read_exact(&mut data)?;
     }
+    to_wav_byte_layout(file_info, &mut data);
 
     let mut output = vec![vec![0.; samples_per_channel]; channels];
+    let frames = &data[..samples_per_channel * channels * sample_size];
 
-    match file_info.sample_type {
-        1 => { //Regular PCM data
-            match file_info.bit_depth {
-                16 => {
-                    for i in 0..samples_per_channel {
-                        let idx = i * sample_size * channels;
-                        for j in 0..channels {
-                            let ch_offset = j * sample_size + idx;
-                            output[j][i] = (((data[ch_offset + 1] as i32) << 24
-                                | (data[ch_offset] as i32) << 16)
-                                >> 16) as f32;
-                            output[j][i] /= if output[j][i] < 0. { PCM_16BIT_NEG_MAX } else { PCM_16BIT_POS_MAX };
-                        }
-                    }
-                }
-
-                24 => {
-                    for i in 0..samples_per_channel {
-                        let idx = i * sample_size * channels;
-                        for j in 0..channels {
-                            let ch_idx = j * sample_size + idx;
-                            output[j][i] = (((data[ch_idx + 2] as i32) << 24
-                                | (data[ch_idx + 1] as i32) << 16
-                                | (data[ch_idx] as i32) << 8)
-                                >> 8) as f32;
-                            output[j][i] /= if output[j][i] < 0. { PCM_24BIT_NEG_MAX } else { PCM_24BIT_POS_MAX };
-                        }
-                    }
-                }
-
-                32 => {
-                    for i in 0..samples_per_channel {
-                        let idx = i * sample_size * channels;
-                        for j in 0..channels {
-                            let ch_offset = j * sample_size + idx;
-                            output[j][j] = (((data[ch_offset + 3] as i32) << 24
-                                | (data[ch_offset + 2] as i32) << 16
-                                | (data[ch_offset + 1] as i32) << 8)
-                                | (data[ch_offset] as i32)) as f32;
-                            output[j][i] /= if output[j][i] < 0. { PCM_32BIT_NEG_MAX } else { PCM_32BIT_POS_MAX };
-                        }
-                    }
-                }
-
-                _ => return None,
-            }
-        },
-        3 => { // IEEE float data
-            // Wav supports 64-bit float so may implement this in future but it is very uncommon
-            if file_info.bit_depth > 32 {
-                panic!("Unsupported bit depth!");
-            }
-            for i in 0..samples_per_channel {
-                let idx = i * sample_size * channels;
-                for j in 0..channels {
-                    let ch_idx = j * sample_size + idx;
-                    let dat: &[u8] = &data[ch_idx..ch_idx+sample_size];
-                    output[j][i] = f32::from_le_bytes(dat.try_into().unwrap());
-                }
-            }
-        }
-        6 => {
-            for i in 0..samples_per_channel {
-                let idx = i * sample_size * channels;
-                for j in 0..channels {
-                    let ch_idx = j * sample_size + idx;
-                    output[j][i] = ALAW_TO_PCM[data[ch_idx] as usize];
-                }
-            }
-        }
-        7 => {
-            for i in 0..samples_per_channel {
-                let idx = i * sample_size * channels;
-                for j in 0..channels {
-                    let ch_idx = j * sample_size + idx;
-                    output[j][i] = ULAW_TO_PCM[data[ch_idx] as usize];
-                }
-            }
-        }
+    match (file_info.sample_type, file_info.bit_depth) {
+        (1, 8) => deinterleave(frames, sample_size, &mut output, decode_pcm8),
+        (1, 16) => deinterleave(frames, sample_size, &mut output, decode_pcm16),
+        (1, 24) => deinterleave(frames, sample_size, &mut output, decode_pcm24),
+        (1, 32) => deinterleave(frames, sample_size, &mut output, decode_pcm32),
+        (1, bit_depth) => return Err(WavError::UnsupportedBitDepth { sample_type: 1, bit_depth }),
+        (3, 32) => deinterleave(frames, sample_size, &mut output, decode_float32),
+        (3, 64) => deinterleave(frames, sample_size, &mut output, decode_float64),
+        (3, bit_depth) => return Err(WavError::UnsupportedBitDepth { sample_type: 3, bit_depth }),
+        (6, _) => deinterleave(frames, sample_size, &mut output, decode_alaw),
+        (7, _) => deinterleave(frames, sample_size, &mut output, decode_ulaw),
         _ => {
-            panic!("Unsupported file format!");
+            return Err(WavError::UnsupportedFormat(file_info.sample_type as u16));
         },
     }
 
-
-    Some(output)
+    Ok(output)
 }
 
+// riff size, data size and sample count (u64 each) + a table length of 0
+const DS64_CHUNK_SIZE: u32 = 28;
+
+#[derive(Clone, Debug, Default)]
 pub struct WavWriteInfo {
     pub sample_type: u8,
     pub channels: u8,
     pub sample_rate: u32,
     pub bit_depth: u16,
-    pub channel_mapping: Vec<(u8, SpeakerPos)>
+    pub channel_mapping: Vec<(u8, SpeakerPos)>,
+    pub bext: Option<BextChunk>,
+    pub ixml: Option<IxmlChunk>,
+    pub tags: Option<WavTags>,
+    pub cue_points: Vec<CuePoint>,
 }
 
-pub fn write_wav_file(target_file: String, target_wav_format: &WavWriteInfo, samples: &Vec<Vec<f32>>) -> std::io::Result<()> {
-    let sample_rate = target_wav_format.sample_rate;
-    let sample_type = target_wav_format.sample_type;
-    let bit_depth = target_wav_format.bit_depth;
+pub fn write_wav_file(target_file: String, target_wav_format: &WavWriteInfo, samples: &[Vec<f32>]) -> Result<(), WavError> {
     let channels = target_wav_format.channels;
-    let channel_mapping = target_wav_format.channel_mapping.clone();
-    let samples_per_channel = samples[0].len() as u32;
 
     //check input validity
-    if samples.len() != channels as usize {return Err(Error::new(ErrorKind::InvalidInput, "Wav Format must match given samples!"));}
+    if channels == 0 || samples.len() != channels as usize {return Err(WavError::InvalidInput("Wav Format must match given samples!"));}
+    let samples_per_channel = samples[0].len();
     for c in 0..samples.len() {
-        if samples[c].len() != samples_per_channel as usize {
-            return Err(Error::new(ErrorKind::InvalidInput, "All channels must have the same number of samples!"));
+        if samples[c].len() != samples_per_channel {
+            return Err(WavError::InvalidInput("All channels must have the same number of samples!"));
         }
     }
 
-    let mut is_std_channel_map = true;
-    for i in 0..channels{
-        if channel_mapping[i as usize].0 != i {
-            is_std_channel_map = false;
-        }
+    let mut writer = WavWriter::create(target_file, target_wav_format)?;
+    writer.write_planar(samples)?;
+    writer.finalize()
+}
+
+// Writes a wav file block by block. The header is written up front with placeholder sizes,
+// which get patched on `finalize()` (or when the writer is dropped).
+// Files that end up bigger than 4GiB are turned into RF64 files, using the JUNK chunk
+// that is reserved at the start of every file for the ds64 chunk.
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    sample_type: u8,
+    bit_depth: u16,
+    channels: u8,
+    fact_pos: Option<u64>, // position of the sample count in the fact chunk
+    data_pos: u64, // position of the data chunk size
+    frames_written: u64,
+    finalized: bool,
+    encode_buf: Vec<u8>,
+}
+
+impl WavWriter<BufWriter<File>> {
+    // creates a new file in ./res/audio, same as write_wav_file
+    pub fn create(target_file: String, target_wav_format: &WavWriteInfo) -> Result<Self, WavError> {
+        // check the format before anything touches the disk
+        check_write_format(target_wav_format)?;
+        let file = BufWriter::new(File::create_new(audio_path(&target_file))?);
+        Self::new(file, target_wav_format)
     }
-    let channel_mask: u32 = get_channel_mask(&channel_mapping);
+}
 
-    let is_extended_fmt = sample_type != 1 || channels > 2 || !is_std_channel_map;
-    let ext_size: u16 = if is_extended_fmt && (sample_type == 1 || !is_std_channel_map) {
-        //need to specify subformat or channel mapping
-        22u16
-    } else { 0u16 };
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut writer: W, target_wav_format: &WavWriteInfo) -> Result<Self, WavError> {
+        check_write_format(target_wav_format)?;
 
-    let has_fact_chunk = sample_type == 6 || sample_type == 7;
+        let sample_rate = target_wav_format.sample_rate;
+        let sample_type = target_wav_format.sample_type;
+        let bit_depth = target_wav_format.bit_depth;
+        let channels = target_wav_format.channels;
+        let channel_mapping = &target_wav_format.channel_mapping;
 
-    let data_block_size: u16 = (bit_depth / 8) * channels as u16;
-    let data_rate: u32 = sample_rate * data_block_size as u32;
+        let mut is_std_channel_map = true;
+        for i in 0..channels{
+            if channel_mapping[i as usize].0 != i {
+                is_std_channel_map = false;
+            }
+        }
+        let channel_mask: u32 = get_channel_mask(channel_mapping);
 
-    let fmt_chunk_size: u32 = 16 + if is_extended_fmt {2 + ext_size as u32} else {0};
-    let data_chunk_size: u32 = data_block_size as u32 * samples_per_channel;
+        let is_extended_fmt = sample_type != 1 || channels > 2 || !is_std_channel_map;
+        let ext_size: u16 = if is_extended_fmt && (sample_type == 1 || !is_std_channel_map) {
+            //need to specify subformat or channel mapping
+            22u16
+        } else { 0u16 };
 
-    let file_size: u32 = 4 + fmt_chunk_size as u32 + data_chunk_size + (if has_fact_chunk {4} else {0});
+        let data_block_size: u16 = (bit_depth / 8) * channels as u16;
+        let data_rate: u32 = sample_rate * data_block_size as u32;
 
-    let mut file = BufWriter::new(File::create_new(format!("./res/audio/{}", target_file))?);
+        let fmt_chunk_size: u32 = 16 + if is_extended_fmt {2 + ext_size as u32} else {0};
 
-    // Write RIFF Header
-    file.write_all("RIFF".as_bytes())?;
-    file.write_all(&file_size.to_le_bytes())?;
-    file.write_all("WAVE".as_bytes())?;
+        // Write RIFF Header, the size is filled in by finalize()
+        writer.seek(SeekFrom::Start(0))?;
+        writer.write_all("RIFF".as_bytes())?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all("WAVE".as_bytes())?;
 
-    // Write fmt header
-    file.write_all("fmt ".as_bytes())?;
-    file.write_all(&fmt_chunk_size.to_le_bytes())?;
+        // placeholder for the ds64 chunk, in case the file grows past 4GiB
+        writer.write_all("JUNK".as_bytes())?;
+        writer.write_all(&DS64_CHUNK_SIZE.to_le_bytes())?;
+        writer.write_all(&[0; DS64_CHUNK_SIZE as usize])?;
 
-    // if the extended chunk exists, write this as "Wav Extensible Format" (254)
-    if ext_size > 0 {
-        file.write_all(&254u16.to_le_bytes())?;
-    } else { //otherwise just write the sample type here
-        file.write_all(&(sample_type as u16).to_le_bytes())?;
-    }
-    file.write_all(&(channels as u16).to_le_bytes())?;
-    file.write_all(&sample_rate.to_le_bytes())?;
-    file.write_all(&data_rate.to_le_bytes())?;
-    file.write_all(&data_block_size.to_le_bytes())?;
-    file.write_all(&bit_depth.to_le_bytes())?;
+        // Write fmt header
+        writer.write_all("fmt ".as_bytes())?;
+        writer.write_all(&fmt_chunk_size.to_le_bytes())?;
+
+        // if the extended chunk exists, write this as "Wav Extensible Format" (0xFFFE)
+        if ext_size > 0 {
+            writer.write_all(&0xFFFEu16.to_le_bytes())?;
+        } else { //otherwise just write the sample type here
+            writer.write_all(&(sample_type as u16).to_le_bytes())?;
+        }
+        writer.write_all(&(channels as u16).to_le_bytes())?;
+        writer.write_all(&sample_rate.to_le_bytes())?;
+        writer.write_all(&data_rate.to_le_bytes())?;
+        writer.write_all(&data_block_size.to_le_bytes())?;
+        writer.write_all(&bit_depth.to_le_bytes())?;
 
-    // write the ext chunk if needed
-    if fmt_chunk_size > 16 {
-        file.write_all(&ext_size.to_le_bytes())?;
+        // write the ext chunk if needed
+        if fmt_chunk_size > 16 {
+            writer.write_all(&ext_size.to_le_bytes())?;
 
-        if fmt_chunk_size > 18 {
-            //this is wValidBitsPerSample, which is really just redundant
-            file.write_all(&bit_depth.to_le_bytes())?;
-            // channel mask
-            file.write_all(&channel_mask.to_le_bytes())?;
+            if fmt_chunk_size > 18 {
+                //this is wValidBitsPerSample, which is really just redundant
+                writer.write_all(&bit_depth.to_le_bytes())?;
+                // channel mask
+                writer.write_all(&channel_mask.to_le_bytes())?;
 
-            //this is SubFormat, which includes the sample type as well as a fixed string 
-            // "\x00\x00\x00\x00\x10\x00\x80\x00\x00\xAA\x00\x38\x9B\x71"
-            file.write_all(&(sample_type as u16).to_le_bytes())?;
-            file.write_all(&[0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71])?;
+                //this is SubFormat, the GUID version of the sample type
+                let sub_format = match sample_type {
+                    1 => SubFormat::Pcm,
+                    3 => SubFormat::IeeeFloat,
+                    6 => SubFormat::ALaw,
+                    _ => SubFormat::MuLaw,
+                };
+                writer.write_all(&sub_format.guid().0)?;
+            }
+        }
+
+        // every non-PCM format needs a fact chunk, the sample count is filled in by finalize()
+        let mut fact_pos = None;
+        if sample_type != 1 {
+            writer.write_all("fact".as_bytes())?;
+            // fact chunk will always have a size of 4
+            writer.write_all(&4u32.to_le_bytes())?;
+            fact_pos = Some(writer.stream_position()?);
+            writer.write_all(&0u32.to_le_bytes())?;
+        }
+
+        // metadata chunks go in front of the audio data
+        if let Some(bext) = &target_wav_format.bext {
+            write_chunk(&mut writer, "bext", &bext.to_bytes())?;
         }
+        if let Some(ixml) = &target_wav_format.ixml {
+            write_chunk(&mut writer, "iXML", &ixml.to_bytes())?;
+        }
+        if let Some(tags) = &target_wav_format.tags {
+            write_chunk(&mut writer, "LIST", &tags.to_bytes())?;
+        }
+        if !target_wav_format.cue_points.is_empty() {
+            write_chunk(&mut writer, "cue ", &CuePoint::cue_chunk_bytes(&target_wav_format.cue_points))?;
+            write_chunk(&mut writer, "LIST", &CuePoint::adtl_chunk_bytes(&target_wav_format.cue_points))?;
+        }
+
+        // data chunk, the size is filled in by finalize()
+        writer.write_all("data".as_bytes())?;
+        let data_pos = writer.stream_position()?;
+        writer.write_all(&0u32.to_le_bytes())?;
+
+        Ok(Self {
+            writer,
+            sample_type,
+            bit_depth,
+            channels,
+            fact_pos,
+            data_pos,
+            frames_written: 0,
+            finalized: false,
+            encode_buf: vec![],
+        })
     }
 
-    // write fact chunk if needed
-    if has_fact_chunk {
-        file.write_all("fact".as_bytes())?;
-        // fact chunk will always have a size of 4
-        file.write_all(&4u32.to_le_bytes())?;
-        file.write_all(&samples_per_channel.to_le_bytes())?;
+    pub fn frames_written(&self) -> u64 {
+        self.frames_written
     }
 
-    // data chunk
-    file.write_all("data".as_bytes())?;
-    file.write_all(&data_chunk_size.to_le_bytes())?;
-    match sample_type {
-        1 => {
-            match bit_depth {
-                16 => {
-                    for i in 0..samples[0].len() {
-                        for c in 0..samples.len() {
-                            let sample_scaled: i16 = (samples[c][i] * if samples[c][i] > 0. {PCM_16BIT_POS_MAX} else {PCM_16BIT_NEG_MAX}) as i16;
-                            file.write_all(&sample_scaled.to_le_bytes())?;
-                        }
-                    }
-                }
-                24 => {
-                    for i in 0..samples[0].len() {
-                        for c in 0..samples.len() {
-                            let sample_scaled: i32 = (samples[c][i] * if samples[c][i] > 0. {PCM_24BIT_POS_MAX} else {PCM_24BIT_NEG_MAX}) as i32;
-                            file.write_all(&sample_scaled.to_le_bytes()[0..3])?;
-                        }
-                    }
-                }
-                32 => {
-                    for i in 0..samples[0].len() {
-                        for c in 0..samples.len() {
-                            let sample_scaled: i32 = (samples[c][i] * if samples[c][i] > 0. {PCM_32BIT_POS_MAX} else {PCM_32BIT_NEG_MAX}) as i32;
-                            file.write_all(&sample_scaled.to_le_bytes())?;
-                        }
-                    }
-                }
-                _ => {
+    // samples are interleaved frames, so the length has to be a multiple of the channel count
+    pub fn write_interleaved(&mut self, samples: &[f32]) -> Result<(), WavError> {
+        if self.finalized {
+            return Err(WavError::InvalidInput("Can't write to a finalized wav file!"));
+        }
+        if !samples.len().is_multiple_of(self.channels as usize) {
+            return Err(WavError::InvalidInput("Samples must contain whole frames!"));
+        }
 
-                }
+        let mut buf = std::mem::take(&mut self.encode_buf);
+        buf.clear();
+        encode_samples(self.sample_type, self.bit_depth, samples, &mut buf);
+        let res = self.writer.write_all(&buf);
+        self.encode_buf = buf;
+        res?;
+
+        self.frames_written += (samples.len() / self.channels as usize) as u64;
+        Ok(())
+    }
+
+    // one Vec per channel, all of the same length
+    pub fn write_planar(&mut self, samples: &[Vec<f32>]) -> Result<(), WavError> {
+        if samples.len() != self.channels as usize {
+            return Err(WavError::InvalidInput("Wav Format must match given samples!"));
+        }
+        let frames = samples[0].len();
+        if samples.iter().any(|ch| ch.len() != frames) {
+            return Err(WavError::InvalidInput("All channels must have the same number of samples!"));
+        }
+
+        let mut interleaved = Vec::with_capacity(frames * samples.len());
+        for i in 0..frames {
+            for ch in samples {
+                interleaved.push(ch[i]);
             }
-        },
-        3 => {
-            // idk how to parse f32s into IEEE floats of 16 or 24 bits so..
-            return Err(Error::new(ErrorKind::Unsupported, "Unsupported sample format!"));
         }
-        _ => {
+        self.write_interleaved(&interleaved)
+    }
+
+    // patches all the sizes in the header, the file is complete after this
+    pub fn finalize(mut self) -> Result<(), WavError> {
+        self.write_sizes()
+    }
+
+    fn write_sizes(&mut self) -> Result<(), WavError> {
+        if self.finalized {
+            return Ok(());
+        }
+        self.finalized = true;
+
+        let data_chunk_size = self.frames_written * (self.bit_depth / 8) as u64 * self.channels as u64;
+        // the data chunk needs a padding byte if its size is odd
+        if data_chunk_size & 1 == 1 {
+            self.writer.write_all(&[0])?;
+        }
+        let end_pos = self.writer.stream_position()?;
+        let file_size = end_pos - 8;
+
+        // sizes that don't fit in the u32 RIFF fields have to be written as RF64,
+        // which keeps the real sizes in a ds64 chunk
+        if file_size > u32::MAX as u64 {
+            self.writer.seek(SeekFrom::Start(0))?;
+            self.writer.write_all("RF64".as_bytes())?;
+            self.writer.write_all(&u32::MAX.to_le_bytes())?;
+
+            // turn the JUNK chunk into the ds64 chunk
+            self.writer.seek(SeekFrom::Start(12))?;
+            self.writer.write_all("ds64".as_bytes())?;
+            self.writer.write_all(&DS64_CHUNK_SIZE.to_le_bytes())?;
+            self.writer.write_all(&file_size.to_le_bytes())?;
+            self.writer.write_all(&data_chunk_size.to_le_bytes())?;
+            self.writer.write_all(&self.frames_written.to_le_bytes())?;
+            // no other chunks need an entry in the size table
+            self.writer.write_all(&0u32.to_le_bytes())?;
+
+            self.writer.seek(SeekFrom::Start(self.data_pos))?;
+            self.writer.write_all(&u32::MAX.to_le_bytes())?;
+        } else {
+            self.writer.seek(SeekFrom::Start(4))?;
+            self.writer.write_all(&(file_size as u32).to_le_bytes())?;
 
+            self.writer.seek(SeekFrom::Start(self.data_pos))?;
+            self.writer.write_all(&(data_chunk_size as u32).to_le_bytes())?;
         }
+
+        if let Some(fact_pos) = self.fact_pos {
+            self.writer.seek(SeekFrom::Start(fact_pos))?;
+            self.writer.write_all(&(u64::min(self.frames_written, u32::MAX as u64) as u32).to_le_bytes())?;
+        }
+
+        self.writer.seek(SeekFrom::Start(end_pos))?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> Drop for WavWriter<W> {
+    fn drop(&mut self) {
+        // errors can't be reported from here, call finalize() to handle them
+        let _ = self.write_sizes();
     }
+}
+
+pub(crate) fn check_write_format(target_wav_format: &WavWriteInfo) -> Result<(), WavError> {
+    let sample_type = target_wav_format.sample_type;
+    let bit_depth = target_wav_format.bit_depth;
+    let channels = target_wav_format.channels;
 
-    file.flush()?;
+    if channels == 0 {return Err(WavError::InvalidInput("Wav Format needs at least one channel!"));}
+    if target_wav_format.channel_mapping.len() < channels as usize {return Err(WavError::InvalidInput("Channel mapping must cover every channel!"));}
+    if target_wav_format.sample_rate == 0 {return Err(WavError::InvalidInput("Sample rate can't be 0!"));}
 
+    let supported_depth = match sample_type {
+        1 => matches!(bit_depth, 8 | 16 | 24 | 32),
+        3 => matches!(bit_depth, 32 | 64),
+        6 | 7 => bit_depth == 8,
+        _ => return Err(WavError::UnsupportedFormat(sample_type as u16)),
+    };
+    if !supported_depth {
+        return Err(WavError::UnsupportedBitDepth { sample_type: sample_type as u16, bit_depth: bit_depth as u32 });
+    }
+    Ok(())
+}
+
+// encodes interleaved samples, the format has to be checked by check_write_format first
+pub(crate) fn encode_samples(sample_type: u8, bit_depth: u16, samples: &[f32], out: &mut Vec<u8>) {
+    out.reserve(samples.len() * (bit_depth / 8) as usize);
+    match (sample_type, bit_depth) {
+        (1, 8) => {
+            for &s in samples {
+                let sample_scaled: i16 = (s * if s > 0. {PCM_8BIT_POS_MAX} else {PCM_8BIT_NEG_MAX}) as i16;
+                out.push((sample_scaled + 128).clamp(0, 255) as u8);
+            }
+        }
+        (1, 16) => {
+            for &s in samples {
+                let sample_scaled: i16 = (s * if s > 0. {PCM_16BIT_POS_MAX} else {PCM_16BIT_NEG_MAX}) as i16;
+                out.extend_from_slice(&sample_scaled.to_le_bytes());
+            }
+        }
+        (1, 24) => {
+            for &s in samples {
+                let sample_scaled: i32 = (s * if s > 0. {PCM_24BIT_POS_MAX} else {PCM_24BIT_NEG_MAX}) as i32;
+                out.extend_from_slice(&sample_scaled.to_le_bytes()[0..3]);
+            }
+        }
+        (1, 32) => {
+            for &s in samples {
+                let sample_scaled: i32 = (s * if s > 0. {PCM_32BIT_POS_MAX} else {PCM_32BIT_NEG_MAX}) as i32;
+                out.extend_from_slice(&sample_scaled.to_le_bytes());
+            }
+        }
+        (3, 32) => {
+            for &s in samples {
+                out.extend_from_slice(&s.to_le_bytes());
+            }
+        }
+        (3, 64) => {
+            for &s in samples {
+                out.extend_from_slice(&(s as f64).to_le_bytes());
+            }
+        }
+        (6, 8) => {
+            for &s in samples {
+                out.push(linear_to_alaw(float_to_i16(s)));
+            }
+        }
+        (7, 8) => {
+            for &s in samples {
+                out.push(linear_to_ulaw(float_to_i16(s)));
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+
+// G.711 works on 16 bit samples, scaled the same way as ALAW_TO_PCM/ULAW_TO_PCM
+fn float_to_i16(sample: f32) -> i16 {
+    (sample * 32768.).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16
+}
+
+// index of the first segment end the value fits under, 8 if it's too big for all of them
+fn g711_segment(val: i16, seg_ends: &[i16; 8]) -> usize {
+    seg_ends.iter().position(|&end| val <= end).unwrap_or(8)
+}
+
+// ITU-T G.711 A-law encoding of a 16 bit sample
+fn linear_to_alaw(sample: i16) -> u8 {
+    const SEG_END: [i16; 8] = [0x1F, 0x3F, 0x7F, 0xFF, 0x1FF, 0x3FF, 0x7FF, 0xFFF];
+
+    // A-law only uses the top 13 bits
+    let mut val = sample >> 3;
+    let mask = if val >= 0 {
+        0xD5
+    } else {
+        val = -val - 1;
+        0x55
+    };
+
+    let seg = g711_segment(val, &SEG_END);
+    if seg >= 8 {
+        return 0x7F ^ mask;
+    }
+    let mantissa = if seg < 2 { (val >> 1) & 0xF } else { (val >> seg) & 0xF };
+    ((seg as u8) << 4 | mantissa as u8) ^ mask
+}
+
+// ITU-T G.711 µ-law encoding of a 16 bit sample
+fn linear_to_ulaw(sample: i16) -> u8 {
+    const SEG_END: [i16; 8] = [0x3F, 0x7F, 0xFF, 0x1FF, 0x3FF, 0x7FF, 0xFFF, 0x1FFF];
+    const BIAS: i16 = 0x84;
+    const CLIP: i16 = 8159;
+
+    // µ-law only uses the top 14 bits
+    let mut val = sample >> 2;
+    let mask = if val < 0 {
+        val = -val;
+        0x7F
+    } else {
+        0xFF
+    };
+    val = val.min(CLIP) + (BIAS >> 2);
+
+    let seg = g711_segment(val, &SEG_END);
+    if seg >= 8 {
+        return 0x7F ^ mask;
+    }
+    ((seg as u8) << 4 | ((val >> (seg + 1)) & 0xF) as u8) ^ mask
+}
+
+fn write_chunk<W: Write>(file: &mut W, name: &str, data: &[u8]) -> io::Result<()> {
+    file.write_all(name.as_bytes())?;
+    file.write_all(&(data.len() as u32).to_le_bytes())?;
+    file.write_all(data)?;
+    if data.len() & 1 == 1 {
+        file.write_all(&[0])?;
+    }
     Ok(())
 }
 
@@ -529,72 +1194,118 @@ fn get_channel_mask(mapping: &Vec<(u8, SpeakerPos)>) -> u32 {
 }
 
 pub fn read_data_interleaved_unchecked<T: Read>(
-    f: &mut BufReader<T>,
+    f: &mut T,
     file_info: &WavInfo,
     data_len: usize,
-) -> Vec<f32> {
+) -> Result<Vec<f32>, WavError> {
     let byte_depth = file_info.byte_depth as usize;
     let mut data = vec![0; data_len * byte_depth];
 
-    match f.read_exact(&mut data) {
-        Ok(_) => (),
-        Err(err) => {
-            panic!("Unexpected error while reading file: {}", err)
-        }
-    };
+    f.read_exact(&mut data)?;
+    to_wav_byte_layout(file_info, &mut data);
     let mut out_data = vec![0.; data_len];
 
-    match byte_depth {
-        2 => {
-            for j in 0..out_data.len() {
-                let idx = j * byte_depth;
-                out_data[j] = (((data[idx + 1] as i32) << 24 | (data[idx] as i32) << 16) >> 16)
-                    as f32
-                    / 0xFFFF as f32;
-            }
-        }
+    // decoded the same way as read_data, so both paths give identical samples
+    let decode: fn(&[u8]) -> f32 = match (file_info.sample_type, byte_depth) {
+        (1, 1) => decode_pcm8,
+        (1, 2) => decode_pcm16,
+        (1, 3) => decode_pcm24,
+        (1, 4) => decode_pcm32,
+        (3, 4) => decode_float32,
+        (3, 8) => decode_float64,
+        (6, 1) => decode_alaw,
+        (7, 1) => decode_ulaw,
+        _ => return Err(WavError::UnsupportedBitDepth { sample_type: file_info.sample_type as u16, bit_depth: file_info.bit_depth }),
+    };
+    for (out, sample) in out_data.iter_mut().zip(data.chunks_exact(byte_depth)) {
+        *out = decode(sample);
+    }
 
-        3 => {
-            for j in 0..out_data.len() {
-                let idx = j * byte_depth;
-                out_data[j] = (((data[idx + 2] as i32) << 24
-                    | (data[idx + 1] as i32) << 16
-                    | (data[idx] as i32) << 8)
-                    >> 8) as f32
-                    / 0xFFFFFF as f32;
-            }
-        }
+    Ok(out_data)
+}
 
-        4 => {
-            for j in 0..out_data.len() {
-                let idx = j * byte_depth;
-                out_data[j] = (((data[idx + 3] as i32) << 24
-                    | (data[idx + 2] as i32) << 16
-                    | (data[idx + 1] as i32) << 8)
-                    | (data[idx] as i32)) as f32
-                    / (i32::MAX) as f32;
-            }
+// splits interleaved frames into the channels of `output`
+fn deinterleave(frames: &[u8], sample_size: usize, output: &mut [Vec<f32>], decode: impl Fn(&[u8]) -> f32) {
+    for (i, frame) in frames.chunks_exact(sample_size * output.len()).enumerate() {
+        for (channel, sample) in output.iter_mut().zip(frame.chunks_exact(sample_size)) {
+            channel[i] = decode(sample);
         }
-
-        _ => panic!("Unsupported byte depth"),
     }
+}
+
+// single samples in the wav byte layout, to floats between -1 and 1
+fn scale_pcm(sample: i32, neg_max: f32, pos_max: f32) -> f32 {
+    sample as f32 / if sample < 0 { neg_max } else { pos_max }
+}
+
+// 8 bit PCM is unsigned (offset binary), with silence at 128
+fn decode_pcm8(s: &[u8]) -> f32 {
+    scale_pcm(s[0] as i32 - 128, PCM_8BIT_NEG_MAX, PCM_8BIT_POS_MAX)
+}
+
+fn decode_pcm16(s: &[u8]) -> f32 {
+    scale_pcm(((s[1] as i32) << 24 | (s[0] as i32) << 16) >> 16, PCM_16BIT_NEG_MAX, PCM_16BIT_POS_MAX)
+}
+
+fn decode_pcm24(s: &[u8]) -> f32 {
+    scale_pcm(((s[2] as i32) << 24 | (s[1] as i32) << 16 | (s[0] as i32) << 8) >> 8, PCM_24BIT_NEG_MAX, PCM_24BIT_POS_MAX)
+}
+
+fn decode_pcm32(s: &[u8]) -> f32 {
+    scale_pcm(i32::from_le_bytes(s[..4].try_into().unwrap()), PCM_32BIT_NEG_MAX, PCM_32BIT_POS_MAX)
+}
+
+fn decode_float32(s: &[u8]) -> f32 {
+    f32::from_le_bytes(s[..4].try_into().unwrap())
+}
+
+fn decode_float64(s: &[u8]) -> f32 {
+    f64::from_le_bytes(s[..8].try_into().unwrap()) as f32
+}
 
-    out_data
+fn decode_alaw(s: &[u8]) -> f32 {
+    ALAW_TO_PCM[s[0] as usize]
 }
 
-pub fn read_str(f: &mut BufReader<File>, bytes: usize) -> String {
+fn decode_ulaw(s: &[u8]) -> f32 {
+    ULAW_TO_PCM[s[0] as usize]
+}
+
+// converts samples from other containers into the layout used by wav files
+// (little endian, 8-bit PCM as offset binary)
+fn to_wav_byte_layout(file_info: &WavInfo, data: &mut [u8]) {
+    let byte_depth = file_info.byte_depth as usize;
+    if file_info.big_endian && byte_depth > 1 {
+        for sample in data.chunks_exact_mut(byte_depth) {
+            sample.reverse();
+        }
+    }
+    if file_info.signed_8bit && file_info.sample_type == 1 && byte_depth == 1 {
+        for b in data.iter_mut() {
+            *b ^= 0x80;
+        }
+    }
+}
+
+pub fn read_str<R: Read>(f: &mut R, bytes: usize) -> io::Result<String> {
     let mut buf = vec![0; bytes];
-    let _ = f.read_exact(&mut buf);
-    buf.iter().map(|&e| e as char).collect::<String>()
+    f.read_exact(&mut buf)?;
+    Ok(buf.iter().map(|&e| e as char).collect::<String>())
+}
+
+pub fn read_le_u64<R: Read>(f: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    f.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
 }
 
-pub fn read_le_uint(f: &mut BufReader<File>, bytes: usize) -> u32 {
+pub fn read_le_uint<R: Read>(f: &mut R, bytes: usize) -> io::Result<u32> {
     if bytes > 4 {
-        return 0;
+        return Ok(0);
     }
     let mut buf = vec![0 as u8; bytes];
-    let _ = f.read_exact(&mut buf);
-    buf_to_int(&mut buf, bytes)
+    f.read_exact(&mut buf)?;
+    Ok(buf_to_int(&mut buf, bytes))
 }
 
 pub fn buf_to_int(buf: &[u8], bytes: usize) -> u32 {
@@ -604,3 +1315,101 @@ pub fn buf_to_int(buf: &[u8], bytes: usize) -> u32 {
     }
     out
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a file that only keeps its first bytes, everything after them reads back as 0,
+    // so a writer can be pushed past 4GiB without writing 4GiB
+    #[derive(Default)]
+    struct SparseFile {
+        head: Vec<u8>,
+        pos: u64,
+        len: u64,
+    }
+
+    const SPARSE_HEAD_LEN: u64 = 4096;
+
+    impl Write for SparseFile {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            for (i, &b) in buf.iter().enumerate() {
+                let pos = self.pos + i as u64;
+                if pos < SPARSE_HEAD_LEN {
+                    if self.head.len() <= pos as usize {
+                        self.head.resize(pos as usize + 1, 0);
+                    }
+                    self.head[pos as usize] = b;
+                }
+            }
+            self.pos += buf.len() as u64;
+            self.len = self.len.max(self.pos);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Read for SparseFile {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = u64::min(buf.len() as u64, self.len.saturating_sub(self.pos)) as usize;
+            for (i, b) in buf[..n].iter_mut().enumerate() {
+                *b = self.head.get((self.pos + i as u64) as usize).copied().unwrap_or(0);
+            }
+            self.pos += n as u64;
+            Ok(n)
+        }
+    }
+
+    impl Seek for SparseFile {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            self.pos = match pos {
+                SeekFrom::Start(p) => p,
+                SeekFrom::End(d) => self.len.saturating_add_signed(d),
+                SeekFrom::Current(d) => self.pos.saturating_add_signed(d),
+            };
+            Ok(self.pos)
+        }
+    }
+
+    #[test]
+    fn writer_switches_to_rf64_past_4gib() {
+        let format = WavWriteInfo {
+            sample_type: 1,
+            channels: 1,
+            sample_rate: 8000,
+            bit_depth: 8,
+            channel_mapping: vec![(0, SpeakerPos::FrontLeft)],
+            ..Default::default()
+        };
+        let mut writer = WavWriter::new(SparseFile::default(), &format).unwrap();
+        writer.write_interleaved(&[0.5, -0.5, 1.]).unwrap();
+
+        // pretend the rest of the frames were written, an odd count so the data chunk gets padded
+        let frames = u32::MAX as u64 + 1000;
+        writer.frames_written = frames;
+        writer.writer.seek(SeekFrom::Start(writer.data_pos + 4 + frames)).unwrap();
+        writer.write_sizes().unwrap();
+        let data_pos = writer.data_pos;
+        let mut file = std::mem::take(&mut writer.writer);
+
+        assert_eq!(&file.head[..4], b"RF64");
+        assert_eq!(&file.head[4..8], &u32::MAX.to_le_bytes());
+        assert_eq!(&file.head[12..16], b"ds64");
+        assert_eq!(&file.head[data_pos as usize..data_pos as usize + 4], &u32::MAX.to_le_bytes());
+
+        let meta = read_wav_meta(&mut file).unwrap();
+        assert_eq!(meta.file_size, file.len - 8);
+        assert_eq!(meta.chunks["data"], (data_pos + 4, frames));
+
+        let mut reader = WavReader::new(file, 3).unwrap();
+        assert_eq!(reader.num_frames(), frames);
+        let mut block = vec![];
+        assert_eq!(reader.read_block(&mut block).unwrap(), 3);
+        for (x, expected) in block[0].iter().zip([0.5, -0.5, 1.]) {
+            assert!((x - expected).abs() <= 1. / 127.);
+        }
+    }
+}
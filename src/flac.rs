@@ -0,0 +1,1295 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+
+use crate::audio_source::{audio_path, AudioFormat, AudioSource};
+use crate::file_io::{check_write_format, encode_samples, read_data_interleaved_unchecked, SpeakerPos, WavError, WavInfo, WavWriteInfo};
+use crate::wav_metadata::WavTags;
+
+// frames per block written by the encoder, the same as the reference encoder's default
+const ENCODER_BLOCK_SIZE: usize = 4096;
+const MAX_FIXED_ORDER: usize = 4;
+const MAX_PARTITION_ORDER: u32 = 8;
+// once a seek has narrowed things down to this many bytes, the rest is decoded frame by frame
+const SEEK_LINEAR_BYTES: u64 = 1 << 16;
+const READ_BUFFER_SIZE: usize = 1 << 16;
+
+// the channel orders defined by FLAC for 1-8 channels, as wav channel masks
+const CHANNEL_MASKS: [u32; 8] = [0x4, 0x3, 0x7, 0x33, 0x37, 0x3F, 0x70F, 0x63F];
+
+// CRC-8 (poly 0x07) protects the frame header, CRC-16 (poly 0x8005) the whole frame
+const CRC8_TABLE: [u8; 256] = crc8_table();
+const CRC16_TABLE: [u16; 256] = crc16_table();
+
+// the STREAMINFO metadata block
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FlacStreamInfo {
+    pub min_block_size: u16,
+    pub max_block_size: u16,
+    pub min_frame_size: u32, // in bytes, 0 if unknown
+    pub max_frame_size: u32,
+    pub sample_rate: u32,
+    pub channels: u8,
+    pub bits_per_sample: u32,
+    pub total_samples: u64, // per channel, 0 if unknown
+    pub md5: [u8; 16], // of the decoded samples, all zeros if it wasn't computed
+}
+
+impl FlacStreamInfo {
+    fn from_bytes(buf: &[u8]) -> Result<Self, WavError> {
+        if buf.len() < 34 {
+            return Err(WavError::TruncatedChunk("STREAMINFO".to_string()));
+        }
+        // sample rate (20 bits), channels - 1 (3 bits), bits per sample - 1 (5 bits), total samples (36 bits)
+        let packed = u64::from_be_bytes(buf[10..18].try_into().unwrap());
+        let info = Self {
+            min_block_size: u16::from_be_bytes([buf[0], buf[1]]),
+            max_block_size: u16::from_be_bytes([buf[2], buf[3]]),
+            min_frame_size: u32::from_be_bytes([0, buf[4], buf[5], buf[6]]),
+            max_frame_size: u32::from_be_bytes([0, buf[7], buf[8], buf[9]]),
+            sample_rate: (packed >> 44) as u32,
+            channels: ((packed >> 41) & 0x7) as u8 + 1,
+            bits_per_sample: ((packed >> 36) & 0x1F) as u32 + 1,
+            total_samples: packed & 0xF_FFFF_FFFF,
+            md5: buf[18..34].try_into().unwrap(),
+        };
+        if info.sample_rate == 0 {
+            return Err(WavError::InvalidHeader("invalid sample rate"));
+        }
+        if info.bits_per_sample < 4 {
+            return Err(WavError::UnsupportedBitDepth { sample_type: 1, bit_depth: info.bits_per_sample });
+        }
+        if info.max_block_size < 16 || info.min_block_size > info.max_block_size {
+            return Err(WavError::InvalidHeader("invalid FLAC block size"));
+        }
+        Ok(info)
+    }
+
+    fn to_bytes(&self) -> [u8; 34] {
+        let mut buf = [0u8; 34];
+        buf[0..2].copy_from_slice(&self.min_block_size.to_be_bytes());
+        buf[2..4].copy_from_slice(&self.max_block_size.to_be_bytes());
+        buf[4..7].copy_from_slice(&self.min_frame_size.to_be_bytes()[1..]);
+        buf[7..10].copy_from_slice(&self.max_frame_size.to_be_bytes()[1..]);
+        let packed = (self.sample_rate as u64) << 44
+            | ((self.channels - 1) as u64) << 41
+            | ((self.bits_per_sample - 1) as u64) << 36
+            | self.total_samples;
+        buf[10..18].copy_from_slice(&packed.to_be_bytes());
+        buf[18..34].copy_from_slice(&self.md5);
+        buf
+    }
+}
+
+struct FlacMetadata {
+    stream_info: FlacStreamInfo,
+    seek_table: Vec<(u64, u64)>, // (first sample, byte offset from the first frame)
+    tags: Option<WavTags>,
+    first_frame: u64,
+}
+
+fn read_metadata<R: Read + Seek>(f: &mut R) -> Result<FlacMetadata, WavError> {
+    let file_len = f.seek(SeekFrom::End(0))?;
+    f.seek(SeekFrom::Start(0))?;
+
+    let mut magic = [0u8; 4];
+    f.read_exact(&mut magic)?;
+    if &magic != b"fLaC" {
+        return Err(WavError::InvalidHeader("not a FLAC file"));
+    }
+
+    let mut stream_info = None;
+    let mut seek_table = vec![];
+    let mut tags = None;
+    loop {
+        let mut header = [0u8; 4];
+        f.read_exact(&mut header)?;
+        let is_last = header[0] & 0x80 != 0;
+        let block_type = header[0] & 0x7F;
+        let size = u32::from_be_bytes([0, header[1], header[2], header[3]]) as u64;
+        let pos = f.stream_position()?;
+        if pos + size > file_len {
+            return Err(WavError::TruncatedChunk(format!("FLAC metadata block {}", block_type)));
+        }
+
+        // STREAMINFO has to be the first block
+        if stream_info.is_none() && block_type != 0 {
+            return Err(WavError::MissingChunk("STREAMINFO"));
+        }
+        match block_type {
+            0 | 3 | 4 => {
+                let mut body = vec![0; size as usize];
+                f.read_exact(&mut body)?;
+                match block_type {
+                    0 => stream_info = Some(FlacStreamInfo::from_bytes(&body)?),
+                    // placeholder seek points have a sample number of all ones
+                    3 => seek_table = body
+                        .chunks_exact(18)
+                        .map(|p| (u64::from_be_bytes(p[0..8].try_into().unwrap()), u64::from_be_bytes(p[8..16].try_into().unwrap())))
+                        .filter(|&(sample, _)| sample != u64::MAX)
+                        .collect(),
+                    _ => tags = WavTags::from_vorbis_comment(&body).ok(),
+                }
+            }
+            127 => return Err(WavError::InvalidHeader("invalid FLAC metadata block type")),
+            // padding, application data, cue sheets and pictures
+            _ => {
+                f.seek(SeekFrom::Start(pos + size))?;
+            }
+        }
+        if is_last {
+            break;
+        }
+    }
+
+    Ok(FlacMetadata {
+        stream_info: stream_info.ok_or(WavError::MissingChunk("STREAMINFO"))?,
+        seek_table,
+        tags,
+        first_frame: f.stream_position()?,
+    })
+}
+
+// Reads the metadata blocks of a FLAC file into a WavInfo. The "data" chunk entry is
+// the size the samples decode to in the wav layout, so durations and frame counts work
+// out the same as for wav files, and FlacReader decodes the frames through FlacDecoder.
+pub fn read_flac_meta<R: Read + Seek>(f: &mut R) -> Result<WavInfo, WavError> {
+    let file_len = f.seek(SeekFrom::End(0))?;
+    let meta = read_metadata(f)?;
+    let stream_info = meta.stream_info;
+    if stream_info.total_samples == 0 {
+        return Err(WavError::InvalidHeader("FLAC streams without a sample count aren't supported"));
+    }
+
+    // samples are decoded into whole bytes, so 20-bit audio is read as 24-bit like in wav files
+    let bit_depth = stream_info.bits_per_sample.div_ceil(8) * 8;
+    let channels = stream_info.channels;
+    let mut chunks = HashMap::new();
+    chunks.insert(
+        "data".to_string(),
+        (meta.first_frame, stream_info.total_samples * (bit_depth / 8 * channels as u32) as u64),
+    );
+
+    let mask = CHANNEL_MASKS[channels as usize - 1];
+    let channel_map = (0..32)
+        .filter(|bit| mask & (1 << bit) != 0)
+        .enumerate()
+        .map(|(c, bit)| (c as u8, SpeakerPos::from(1u32 << bit)))
+        .collect();
+
+    f.seek(SeekFrom::Start(0))?;
+    let mut info = WavInfo::new(1, channels, stream_info.sample_rate, bit_depth, file_len, chunks, channel_map);
+    info.sample_type_str = format!("FLAC {}", info.sample_type_str);
+    info.valid_bits_per_sample = stream_info.bits_per_sample;
+    info.tags = meta.tags;
+    info.format = AudioFormat::Flac;
+    Ok(info)
+}
+
+struct FrameHeader {
+    block_size: usize,
+    channel_assignment: u32, // 0-7 independent channels, 8 left/side, 9 side/right, 10 mid/side
+    first_sample: u64,
+}
+
+// Decodes a FLAC stream into interleaved samples in the wav byte layout (little endian,
+// 8-bit as offset binary), which is what FlacReader and read_data read from.
+pub struct FlacDecoder<R: Read + Seek> {
+    input: BitReader<R>,
+    stream_info: FlacStreamInfo,
+    seek_table: Vec<(u64, u64)>,
+    first_frame: u64,
+    stream_len: u64,
+    channels: Vec<Vec<i64>>, // samples of the current frame
+    frame_bytes: Vec<u8>, // the current frame in the wav layout
+    frame_pos: usize, // bytes of frame_bytes that have been read
+    next_sample: u64, // first sample of the next frame
+    md5: Option<Md5>, // dropped after seeking, since the checksum covers the whole stream
+}
+
+impl<R: Read + Seek> FlacDecoder<R> {
+    pub fn new(mut reader: R) -> Result<Self, WavError> {
+        let meta = read_metadata(&mut reader)?;
+        if meta.stream_info.total_samples == 0 {
+            return Err(WavError::InvalidHeader("FLAC streams without a sample count aren't supported"));
+        }
+        let stream_len = reader.seek(SeekFrom::End(0))?;
+        let mut input = BitReader::new(reader);
+        input.seek(meta.first_frame)?;
+        Ok(Self {
+            input,
+            stream_info: meta.stream_info,
+            seek_table: meta.seek_table,
+            first_frame: meta.first_frame,
+            stream_len,
+            channels: vec![],
+            frame_bytes: vec![],
+            frame_pos: 0,
+            next_sample: 0,
+            md5: Some(Md5::new()),
+        })
+    }
+
+    pub fn stream_info(&self) -> &FlacStreamInfo {
+        &self.stream_info
+    }
+
+    // bytes per sample in the decoded output
+    fn byte_depth(&self) -> usize {
+        self.stream_info.bits_per_sample.div_ceil(8) as usize
+    }
+
+    pub fn seek_frame(&mut self, frame: u64) -> Result<(), WavError> {
+        let total = self.stream_info.total_samples;
+        if frame > total {
+            return Err(WavError::InvalidInput("Can't seek past the end of the audio data!"));
+        }
+        self.md5 = if frame == 0 { Some(Md5::new()) } else { None };
+        self.frame_bytes.clear();
+        self.frame_pos = 0;
+        if frame == total {
+            self.next_sample = total;
+            return Ok(());
+        }
+
+        // (byte position, first sample) of a frame at or before the target
+        let mut low = (self.first_frame, 0);
+        let mut high = self.stream_len;
+        // the seek table narrows it down if there is one
+        for &(sample, offset) in &self.seek_table {
+            if sample <= frame {
+                low = (self.first_frame + offset, sample);
+            } else {
+                high = u64::min(high, self.first_frame + offset);
+                break;
+            }
+        }
+        // otherwise bisect on the frame headers
+        while high.saturating_sub(low.0) > SEEK_LINEAR_BYTES {
+            let mid = low.0 + (high - low.0) / 2;
+            match self.find_frame(mid, high)? {
+                Some((pos, sample)) if sample <= frame => low = (pos, sample),
+                _ => high = mid,
+            }
+        }
+
+        self.input.seek(low.0)?;
+        self.next_sample = low.1;
+        let block_bytes = self.byte_depth() * self.stream_info.channels as usize;
+        loop {
+            if !self.decode_frame()? {
+                return Err(WavError::InvalidData("FLAC stream ended before the seek target"));
+            }
+            if self.next_sample > frame {
+                let frame_start = self.next_sample - (self.frame_bytes.len() / block_bytes) as u64;
+                self.frame_pos = (frame - frame_start) as usize * block_bytes;
+                return Ok(());
+            }
+        }
+    }
+
+    // finds the first valid frame header in [from, to), returns its position and first sample
+    fn find_frame(&mut self, from: u64, to: u64) -> Result<Option<(u64, u64)>, WavError> {
+        self.input.seek(from)?;
+        let mut pos = from;
+        let mut prev = 0;
+        while pos < to {
+            let Ok(byte) = self.input.read_bits(8) else {
+                return Ok(None);
+            };
+            pos += 1;
+            // frames start with the sync code 0b11111111_111110
+            if prev == 0xFF && byte & 0xFE == 0xF8 {
+                self.input.seek(pos - 2)?;
+                if let Ok(header) = self.read_frame_header() {
+                    return Ok(Some((pos - 2, header.first_sample)));
+                }
+                self.input.seek(pos)?;
+            }
+            prev = byte;
+        }
+        Ok(None)
+    }
+
+    fn read_frame_header(&mut self) -> Result<FrameHeader, WavError> {
+        let input = &mut self.input;
+        input.reset_crc();
+        // 14 sync bits and a reserved 0 bit
+        if input.read_bits(15)? != 0x7FFC {
+            return Err(WavError::InvalidData("lost FLAC frame sync"));
+        }
+        let variable_block_size = input.read_bits(1)? == 1;
+        let block_size_code = input.read_bits(4)?;
+        let sample_rate_code = input.read_bits(4)?;
+        let channel_assignment = input.read_bits(4)? as u32;
+        let sample_size_code = input.read_bits(3)?;
+        if input.read_bits(1)? != 0 {
+            return Err(WavError::InvalidData("reserved bit set in FLAC frame header"));
+        }
+        // frame number for fixed block sizes, sample number for variable ones
+        let number = input.read_utf8()?;
+
+        let block_size = match block_size_code {
+            0 => return Err(WavError::InvalidData("reserved FLAC block size")),
+            1 => 192,
+            2..=5 => 576 << (block_size_code - 2),
+            6 => input.read_bits(8)? as usize + 1,
+            7 => input.read_bits(16)? as usize + 1,
+            _ => 256 << (block_size_code - 8),
+        };
+        // the sample rate always comes from STREAMINFO, but the extra bytes have to be skipped
+        match sample_rate_code {
+            12 => { input.read_bits(8)?; }
+            13 | 14 => { input.read_bits(16)?; }
+            15 => return Err(WavError::InvalidData("invalid FLAC sample rate")),
+            _ => {}
+        }
+        let bits_per_sample = match sample_size_code {
+            0 => self.stream_info.bits_per_sample,
+            1 => 8,
+            2 => 12,
+            4 => 16,
+            5 => 20,
+            6 => 24,
+            7 => 32,
+            _ => return Err(WavError::InvalidData("reserved FLAC sample size")),
+        };
+        let crc = input.crc8();
+        if input.read_bits(8)? != crc as u64 {
+            return Err(WavError::InvalidData("FLAC frame header CRC mismatch"));
+        }
+
+        let channels = match channel_assignment {
+            0..=7 => channel_assignment + 1,
+            8..=10 => 2,
+            _ => return Err(WavError::InvalidData("reserved FLAC channel assignment")),
+        };
+        // the output layout is based on STREAMINFO, so every frame has to match it
+        if channels != self.stream_info.channels as u32 || bits_per_sample != self.stream_info.bits_per_sample {
+            return Err(WavError::InvalidData("FLAC frame doesn't match the stream format"));
+        }
+        if block_size > self.stream_info.max_block_size as usize {
+            return Err(WavError::InvalidData("FLAC frame is bigger than the maximum block size"));
+        }
+
+        let first_sample = if variable_block_size { number } else { number * self.stream_info.max_block_size as u64 };
+        Ok(FrameHeader { block_size, channel_assignment, first_sample })
+    }
+
+    // decodes the next frame into frame_bytes, returns false at the end of the stream
+    fn decode_frame(&mut self) -> Result<bool, WavError> {
+        let total = self.stream_info.total_samples;
+        if self.next_sample >= total {
+            return Ok(false);
+        }
+        let header = self.read_frame_header()?;
+        let block_size = header.block_size;
+        let bits_per_sample = self.stream_info.bits_per_sample;
+
+        self.channels.resize(self.stream_info.channels as usize, vec![]);
+        for (c, samples) in self.channels.iter_mut().enumerate() {
+            // side channels need an extra bit
+            let side = matches!((header.channel_assignment, c), (8, 1) | (9, 0) | (10, 1));
+            decode_subframe(&mut self.input, bits_per_sample + side as u32, block_size, samples)?;
+        }
+        if let [first, second] = &mut self.channels[..] {
+            decorrelate(header.channel_assignment, first, second);
+        }
+
+        // frames are padded to a whole byte, followed by the CRC-16 of everything before it
+        self.input.align();
+        let crc = self.input.crc16();
+        if self.input.read_bits(16)? != crc as u64 {
+            return Err(WavError::InvalidData("FLAC frame CRC mismatch"));
+        }
+
+        // frames are counted from where decoding started rather than by their frame numbers,
+        // which don't start at 0 in streams that were cut out of a longer one
+        let frames = usize::min(block_size, (total - self.next_sample) as usize);
+        let byte_depth = self.byte_depth();
+        self.frame_bytes.clear();
+        for i in 0..frames {
+            for ch in &self.channels {
+                self.frame_bytes.extend_from_slice(&(ch[i] as i32).to_le_bytes()[..byte_depth]);
+            }
+        }
+        self.frame_pos = 0;
+        self.next_sample += frames as u64;
+
+        // the checksum is over the signed samples as they are, so it's done before converting them
+        if let Some(md5) = &mut self.md5 {
+            md5.update(&self.frame_bytes);
+            if self.next_sample >= total {
+                let md5 = self.md5.take().unwrap().finish();
+                if self.stream_info.md5 != [0; 16] && md5 != self.stream_info.md5 {
+                    return Err(WavError::ChecksumMismatch);
+                }
+            }
+        }
+
+        // samples are left aligned in their bytes, and 8-bit samples are unsigned in wav files
+        let shift = byte_depth as u32 * 8 - bits_per_sample;
+        if shift > 0 || byte_depth == 1 {
+            for sample in self.frame_bytes.chunks_exact_mut(byte_depth) {
+                let value = (sample_from_le(sample) << shift) as i32;
+                sample.copy_from_slice(&value.to_le_bytes()[..byte_depth]);
+                if byte_depth == 1 {
+                    sample[0] ^= 0x80;
+                }
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl<R: Read + Seek> Read for FlacDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.frame_pos == self.frame_bytes.len() {
+            match self.decode_frame() {
+                Ok(true) => {}
+                Ok(false) => return Ok(0),
+                // converted back into the WavError by From<io::Error>
+                Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+            }
+        }
+        let len = usize::min(buf.len(), self.frame_bytes.len() - self.frame_pos);
+        buf[..len].copy_from_slice(&self.frame_bytes[self.frame_pos..self.frame_pos + len]);
+        self.frame_pos += len;
+        Ok(len)
+    }
+}
+
+// Streams a FLAC file in blocks of deinterleaved frames, decoding only the frames that are read
+pub struct FlacReader<R: Read + Seek> {
+    decoder: FlacDecoder<R>,
+    info: WavInfo,
+    num_frames: u64,
+    frame_pos: u64,
+    block_size: usize, // in frames
+}
+
+impl<R: Read + Seek> FlacReader<R> {
+    pub fn new(mut reader: R, block_size: usize) -> Result<Self, WavError> {
+        if block_size == 0 {
+            return Err(WavError::InvalidInput("Block size must be at least 1 frame!"));
+        }
+        let info = read_flac_meta(&mut reader)?;
+        let decoder = FlacDecoder::new(reader)?;
+        Ok(Self {
+            num_frames: decoder.stream_info().total_samples,
+            decoder,
+            info,
+            frame_pos: 0,
+            block_size,
+        })
+    }
+
+    pub fn stream_info(&self) -> &FlacStreamInfo {
+        self.decoder.stream_info()
+    }
+}
+
+impl<R: Read + Seek> AudioSource for FlacReader<R> {
+    fn info(&self) -> &WavInfo {
+        &self.info
+    }
+
+    fn num_frames(&self) -> u64 {
+        self.num_frames
+    }
+
+    fn position(&self) -> u64 {
+        self.frame_pos
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn set_block_size(&mut self, block_size: usize) -> Result<(), WavError> {
+        if block_size == 0 {
+            return Err(WavError::InvalidInput("Block size must be at least 1 frame!"));
+        }
+        self.block_size = block_size;
+        Ok(())
+    }
+
+    fn seek_frame(&mut self, frame: u64) -> Result<(), WavError> {
+        self.decoder.seek_frame(frame)?;
+        self.frame_pos = frame;
+        Ok(())
+    }
+
+    fn read_interleaved(&mut self, out: &mut [f32]) -> Result<usize, WavError> {
+        let channels = self.info.channels as usize;
+        let frames = u64::min((out.len() / channels) as u64, self.num_frames - self.frame_pos) as usize;
+        if frames == 0 {
+            return Ok(0);
+        }
+        let samples = read_data_interleaved_unchecked(&mut self.decoder, &self.info, frames * channels)?;
+        out[..samples.len()].copy_from_slice(&samples);
+        self.frame_pos += frames as u64;
+        Ok(frames)
+    }
+
+    fn read_block(&mut self, block: &mut Vec<Vec<f32>>) -> Result<usize, WavError> {
+        let channels = self.info.channels as usize;
+        let frames = u64::min(self.block_size as u64, self.num_frames - self.frame_pos) as usize;
+        block.resize(channels, vec![]);
+        if frames == 0 {
+            block.iter_mut().for_each(|ch| ch.clear());
+            return Ok(0);
+        }
+
+        let samples = read_data_interleaved_unchecked(&mut self.decoder, &self.info, frames * channels)?;
+        for (c, ch) in block.iter_mut().enumerate() {
+            ch.clear();
+            ch.extend(samples.iter().skip(c).step_by(channels));
+        }
+        self.frame_pos += frames as u64;
+        Ok(frames)
+    }
+}
+
+fn decode_subframe<R: Read>(input: &mut BitReader<R>, bits_per_sample: u32, block_size: usize, out: &mut Vec<i64>) -> Result<(), WavError> {
+    if input.read_bits(1)? != 0 {
+        return Err(WavError::InvalidData("invalid FLAC subframe header"));
+    }
+    let kind = input.read_bits(6)? as usize;
+    // samples can have trailing zero bits that aren't stored
+    let wasted_bits = if input.read_bits(1)? == 1 { input.read_unary()? + 1 } else { 0 };
+    if wasted_bits >= bits_per_sample {
+        return Err(WavError::InvalidData("invalid FLAC wasted bits"));
+    }
+    let bits_per_sample = bits_per_sample - wasted_bits;
+
+    out.clear();
+    match kind {
+        // constant
+        0 => {
+            let value = input.read_signed(bits_per_sample)?;
+            out.resize(block_size, value);
+        }
+        // verbatim
+        1 => {
+            for _ in 0..block_size {
+                out.push(input.read_signed(bits_per_sample)?);
+            }
+        }
+        // fixed predictor of order 0-4
+        8..=12 => {
+            let order = kind - 8;
+            if order > block_size {
+                return Err(WavError::InvalidData("FLAC predictor order is bigger than the block"));
+            }
+            for _ in 0..order {
+                out.push(input.read_signed(bits_per_sample)?);
+            }
+            read_residual(input, order, block_size, out)?;
+            restore_fixed(order, out);
+        }
+        // linear predictor of order 1-32
+        32..=63 => {
+            let order = kind - 31;
+            if order > block_size {
+                return Err(WavError::InvalidData("FLAC predictor order is bigger than the block"));
+            }
+            for _ in 0..order {
+                out.push(input.read_signed(bits_per_sample)?);
+            }
+            let precision = input.read_bits(4)? as u32 + 1;
+            let shift = input.read_signed(5)?;
+            if precision == 16 || shift < 0 {
+                return Err(WavError::InvalidData("invalid FLAC LPC coefficients"));
+            }
+            let mut coefficients = [0i64; 32];
+            for c in coefficients.iter_mut().take(order) {
+                *c = input.read_signed(precision)?;
+            }
+            read_residual(input, order, block_size, out)?;
+            restore_lpc(&coefficients[..order], shift as u32, out);
+        }
+        _ => return Err(WavError::InvalidData("reserved FLAC subframe type")),
+    }
+
+    if wasted_bits > 0 {
+        out.iter_mut().for_each(|s| *s <<= wasted_bits);
+    }
+    Ok(())
+}
+
+// reads the Rice coded residual of a subframe, appending it after the warm-up samples
+fn read_residual<R: Read>(input: &mut BitReader<R>, order: usize, block_size: usize, out: &mut Vec<i64>) -> Result<(), WavError> {
+    // (bits per Rice parameter, escape code for unencoded partitions)
+    let (param_bits, escape) = match input.read_bits(2)? {
+        0 => (4, 0xF),
+        1 => (5, 0x1F),
+        _ => return Err(WavError::InvalidData("reserved FLAC residual coding method")),
+    };
+    let partitions = 1usize << input.read_bits(4)?;
+    if !block_size.is_multiple_of(partitions) || block_size / partitions < order {
+        return Err(WavError::InvalidData("invalid FLAC partition order"));
+    }
+
+    for p in 0..partitions {
+        // the warm-up samples count towards the first partition
+        let len = block_size / partitions - if p == 0 { order } else { 0 };
+        let param = input.read_bits(param_bits)? as u32;
+        if param == escape {
+            let bits = input.read_bits(5)? as u32;
+            for _ in 0..len {
+                out.push(input.read_signed(bits)?);
+            }
+        } else {
+            for _ in 0..len {
+                out.push(input.read_rice(param)?);
+            }
+        }
+    }
+    Ok(())
+}
+
+// wrapping math everywhere, so corrupt frames fail their CRC check instead of panicking
+fn restore_fixed(order: usize, samples: &mut [i64]) {
+    for i in order..samples.len() {
+        let prediction = match order {
+            0 => 0,
+            1 => samples[i - 1],
+            2 => samples[i - 1].wrapping_mul(2).wrapping_sub(samples[i - 2]),
+            3 => samples[i - 1].wrapping_sub(samples[i - 2]).wrapping_mul(3).wrapping_add(samples[i - 3]),
+            _ => samples[i - 1].wrapping_add(samples[i - 3]).wrapping_mul(4)
+                .wrapping_sub(samples[i - 2].wrapping_mul(6))
+                .wrapping_sub(samples[i - 4]),
+        };
+        samples[i] = samples[i].wrapping_add(prediction);
+    }
+}
+
+fn restore_lpc(coefficients: &[i64], shift: u32, samples: &mut [i64]) {
+    for i in coefficients.len()..samples.len() {
+        // the first coefficient belongs to the previous sample
+        let prediction = coefficients
+            .iter()
+            .enumerate()
+            .fold(0i64, |sum, (j, c)| sum.wrapping_add(c.wrapping_mul(samples[i - 1 - j])));
+        samples[i] = samples[i].wrapping_add(prediction >> shift);
+    }
+}
+
+fn decorrelate(channel_assignment: u32, first: &mut [i64], second: &mut [i64]) {
+    match channel_assignment {
+        // left/side
+        8 => second.iter_mut().zip(first.iter()).for_each(|(s, l)| *s = l.wrapping_sub(*s)),
+        // side/right
+        9 => first.iter_mut().zip(second.iter()).for_each(|(s, r)| *s = s.wrapping_add(*r)),
+        // mid/side, mid lost its lowest bit, which is the same as the lowest bit of side
+        10 => {
+            for (m, s) in first.iter_mut().zip(second.iter_mut()) {
+                let mid = (*m << 1) | (*s & 1);
+                *m = mid.wrapping_add(*s) >> 1;
+                *s = mid.wrapping_sub(*s) >> 1;
+            }
+        }
+        _ => {}
+    }
+}
+
+// sign extends a little endian sample of 1-8 bytes
+fn sample_from_le(bytes: &[u8]) -> i64 {
+    let mut buf = [0u8; 8];
+    buf[8 - bytes.len()..].copy_from_slice(bytes);
+    i64::from_le_bytes(buf) >> (64 - 8 * bytes.len())
+}
+
+// Writes PCM audio as a FLAC file, using fixed predictors and stereo decorrelation.
+pub fn write_flac_file(target_file: String, target_wav_format: &WavWriteInfo, samples: &[Vec<f32>]) -> Result<(), WavError> {
+    let channels = target_wav_format.channels as usize;
+    let sample_type = target_wav_format.sample_type;
+    let bit_depth = target_wav_format.bit_depth;
+    let sample_rate = target_wav_format.sample_rate;
+
+    //check input validity
+    if channels == 0 || samples.len() != channels {return Err(WavError::InvalidInput("Wav Format must match given samples!"));}
+    let samples_per_channel = samples[0].len();
+    if samples.iter().any(|ch| ch.len() != samples_per_channel) {
+        return Err(WavError::InvalidInput("All channels must have the same number of samples!"));
+    }
+    check_write_format(target_wav_format)?;
+    if sample_type != 1 {
+        return Err(WavError::UnsupportedFormat(sample_type as u16));
+    }
+    if !matches!(bit_depth, 8 | 16 | 24) {
+        return Err(WavError::UnsupportedBitDepth { sample_type: 1, bit_depth: bit_depth as u32 });
+    }
+    if channels > 8 {return Err(WavError::InvalidInput("FLAC supports at most 8 channels!"));}
+    if sample_rate >= 1 << 20 {return Err(WavError::InvalidInput("Sample rate is too high for FLAC!"));}
+
+    // quantized the same way as wav files, then hashed as signed samples like the decoder does
+    let byte_depth = (bit_depth / 8) as usize;
+    let mut interleaved = Vec::with_capacity(samples_per_channel * channels);
+    for i in 0..samples_per_channel {
+        for ch in samples {
+            interleaved.push(ch[i]);
+        }
+    }
+    let mut data = vec![];
+    encode_samples(1, bit_depth, &interleaved, &mut data);
+    if bit_depth == 8 {
+        data.iter_mut().for_each(|b| *b ^= 0x80);
+    }
+    let mut md5 = Md5::new();
+    md5.update(&data);
+
+    let pcm: Vec<Vec<i64>> = (0..channels)
+        .map(|c| data.chunks_exact(byte_depth).skip(c).step_by(channels).map(sample_from_le).collect())
+        .collect();
+    drop(data);
+
+    let mut frames = vec![];
+    let (mut min_frame_size, mut max_frame_size) = (u32::MAX, 0);
+    for (number, start) in (0..samples_per_channel).step_by(ENCODER_BLOCK_SIZE).enumerate() {
+        let end = usize::min(start + ENCODER_BLOCK_SIZE, samples_per_channel);
+        let block: Vec<&[i64]> = pcm.iter().map(|ch| &ch[start..end]).collect();
+        let frame = encode_frame(number as u64, &block, bit_depth as u32, sample_rate);
+        min_frame_size = min_frame_size.min(frame.len() as u32);
+        max_frame_size = max_frame_size.max(frame.len() as u32);
+        frames.extend_from_slice(&frame);
+    }
+
+    let stream_info = FlacStreamInfo {
+        min_block_size: ENCODER_BLOCK_SIZE as u16,
+        max_block_size: ENCODER_BLOCK_SIZE as u16,
+        min_frame_size: if frames.is_empty() { 0 } else { min_frame_size },
+        max_frame_size,
+        sample_rate,
+        channels: channels as u8,
+        bits_per_sample: bit_depth as u32,
+        total_samples: samples_per_channel as u64,
+        md5: md5.finish(),
+    };
+    let comment = target_wav_format.tags.clone().unwrap_or_default().to_vorbis_comment("Octave");
+
+    let mut file = BufWriter::new(File::create_new(audio_path(&target_file))?);
+    file.write_all(b"fLaC")?;
+    // metadata block headers are a last-block flag, the block type, and a 24-bit size
+    file.write_all(&[0, 0, 0, 34])?;
+    file.write_all(&stream_info.to_bytes())?;
+    file.write_all(&[0x80 | 4])?;
+    file.write_all(&(comment.len() as u32).to_be_bytes()[1..])?;
+    file.write_all(&comment)?;
+    file.write_all(&frames)?;
+    file.flush()?;
+    Ok(())
+}
+
+fn encode_frame(number: u64, block: &[&[i64]], bits_per_sample: u32, sample_rate: u32) -> Vec<u8> {
+    let block_size = block[0].len();
+
+    // stereo can also be stored as one channel and the difference between them,
+    // whichever pair of subframes is the smallest is used
+    let (channel_assignment, subframes) = if let [left, right] = block {
+        let side: Vec<i64> = left.iter().zip(right.iter()).map(|(l, r)| l - r).collect();
+        let mid: Vec<i64> = left.iter().zip(right.iter()).map(|(l, r)| (l + r) >> 1).collect();
+        let encoded = [
+            encode_subframe(left, bits_per_sample),
+            encode_subframe(right, bits_per_sample),
+            encode_subframe(&side, bits_per_sample + 1),
+            encode_subframe(&mid, bits_per_sample),
+        ];
+        // (channel assignment, first subframe, second subframe)
+        let (assignment, a, b) = [(1, 0, 1), (8, 0, 2), (9, 2, 1), (10, 3, 2)]
+            .into_iter()
+            .min_by_key(|&(_, a, b)| encoded[a].bit_len() + encoded[b].bit_len())
+            .unwrap();
+        (assignment, vec![encoded[a].clone(), encoded[b].clone()])
+    } else {
+        let subframes = block.iter().map(|ch| encode_subframe(ch, bits_per_sample)).collect();
+        (block.len() as u32 - 1, subframes)
+    };
+
+    let mut frame = BitWriter::new();
+    // sync code, reserved bit, and fixed block size
+    frame.write_bits(0x3FFE << 2, 16);
+
+    let block_size_code = match block_size {
+        192 => 1,
+        _ => (0..8usize).find(|k| 256 << k == block_size).map(|k| 8 + k as u64)
+            .or((0..4usize).find(|k| 576 << k == block_size).map(|k| 2 + k as u64))
+            .unwrap_or(if block_size <= 256 { 6 } else { 7 }),
+    };
+    let sample_rate_code = match sample_rate {
+        88200 => 1,
+        176400 => 2,
+        192000 => 3,
+        8000 => 4,
+        16000 => 5,
+        22050 => 6,
+        24000 => 7,
+        32000 => 8,
+        44100 => 9,
+        48000 => 10,
+        96000 => 11,
+        _ if sample_rate.is_multiple_of(1000) && sample_rate / 1000 < 256 => 12,
+        _ if sample_rate < 1 << 16 => 13,
+        _ if sample_rate.is_multiple_of(10) && sample_rate / 10 < 1 << 16 => 14,
+        // taken from STREAMINFO
+        _ => 0,
+    };
+    let sample_size_code = match bits_per_sample {
+        8 => 1,
+        16 => 4,
+        _ => 6,
+    };
+    frame.write_bits(block_size_code, 4);
+    frame.write_bits(sample_rate_code, 4);
+    frame.write_bits(channel_assignment as u64, 4);
+    frame.write_bits(sample_size_code, 3);
+    frame.write_bits(0, 1);
+    frame.write_utf8(number);
+    match block_size_code {
+        6 => frame.write_bits(block_size as u64 - 1, 8),
+        7 => frame.write_bits(block_size as u64 - 1, 16),
+        _ => {}
+    }
+    match sample_rate_code {
+        12 => frame.write_bits(sample_rate as u64 / 1000, 8),
+        13 => frame.write_bits(sample_rate as u64, 16),
+        14 => frame.write_bits(sample_rate as u64 / 10, 16),
+        _ => {}
+    }
+    let crc8 = frame.bytes.iter().fold(0u8, |crc, &b| CRC8_TABLE[(crc ^ b) as usize]);
+    frame.write_bits(crc8 as u64, 8);
+
+    for subframe in &subframes {
+        frame.append(subframe);
+    }
+    frame.align();
+    let crc16 = frame.bytes.iter().fold(0u16, |crc, &b| (crc << 8) ^ CRC16_TABLE[((crc >> 8) as u8 ^ b) as usize]);
+    frame.write_bits(crc16 as u64, 16);
+    frame.bytes
+}
+
+// tries a constant subframe, the fixed predictors and a verbatim subframe, and keeps the smallest
+fn encode_subframe(samples: &[i64], bits_per_sample: u32) -> BitWriter {
+    if samples.iter().all(|&s| s == samples[0]) {
+        let mut out = BitWriter::new();
+        out.write_bits(0, 8);
+        out.write_bits(samples[0] as u64, bits_per_sample);
+        return out;
+    }
+
+    let mut best = BitWriter::new();
+    best.write_bits(1 << 1, 8);
+    for &s in samples {
+        best.write_bits(s as u64, bits_per_sample);
+    }
+
+    for order in 0..=usize::min(MAX_FIXED_ORDER, samples.len() - 1) {
+        let mut residual = samples.to_vec();
+        // differentiating `order` times is the inverse of restore_fixed
+        for _ in 0..order {
+            for i in (1..residual.len()).rev() {
+                residual[i] -= residual[i - 1];
+            }
+        }
+        let mut out = BitWriter::new();
+        out.write_bits(((8 + order) << 1) as u64, 8);
+        for &s in &samples[..order] {
+            out.write_bits(s as u64, bits_per_sample);
+        }
+        write_residual(&mut out, &residual[order..], order, samples.len());
+        if out.bit_len() < best.bit_len() {
+            best = out;
+        }
+    }
+    best
+}
+
+fn write_residual(out: &mut BitWriter, residual: &[i64], order: usize, block_size: usize) {
+    // residuals are folded to unsigned: 0, -1, 1, -2, 2...
+    let folded: Vec<u64> = residual.iter().map(|&r| ((r << 1) ^ (r >> 63)) as u64).collect();
+
+    // (estimated bits, partition order, Rice parameters)
+    let mut best = (u64::MAX, 0, vec![]);
+    for partition_order in 0..=MAX_PARTITION_ORDER {
+        let partitions = 1usize << partition_order;
+        if !block_size.is_multiple_of(partitions) || block_size / partitions < order.max(1) {
+            break;
+        }
+        let mut bits = 0;
+        let mut params = Vec::with_capacity(partitions);
+        let mut start = 0;
+        for p in 0..partitions {
+            let len = block_size / partitions - if p == 0 { order } else { 0 };
+            let (param, cost) = rice_parameter(&folded[start..start + len]);
+            params.push(param);
+            bits += cost;
+            start += len;
+        }
+        if bits < best.0 {
+            best = (bits, partition_order, params);
+        }
+    }
+
+    let (_, partition_order, params) = best;
+    // 5-bit parameters are only needed for high bit depths
+    let param_bits = if params.iter().any(|&p| p >= 0xF) { 5 } else { 4 };
+    out.write_bits(param_bits as u64 - 4, 2);
+    out.write_bits(partition_order as u64, 4);
+    let partitions = params.len();
+    let mut start = 0;
+    for (p, &param) in params.iter().enumerate() {
+        let len = block_size / partitions - if p == 0 { order } else { 0 };
+        out.write_bits(param as u64, param_bits);
+        for &value in &folded[start..start + len] {
+            out.write_unary(value >> param);
+            out.write_bits(value, param);
+        }
+        start += len;
+    }
+}
+
+// picks the Rice parameter with the smallest estimated size, returns it and that size in bits
+fn rice_parameter(folded: &[u64]) -> (u32, u64) {
+    let len = folded.len() as u64;
+    let sum: u64 = folded.iter().sum();
+    // 30 is the largest parameter that isn't the escape code
+    (0..=30)
+        .map(|param| (param, 5 + len * (param as u64 + 1) + (sum >> param)))
+        .min_by_key(|&(_, bits)| bits)
+        .unwrap()
+}
+
+struct BitReader<R: Read> {
+    inner: R,
+    buf: Vec<u8>,
+    buf_pos: usize,
+    buf_len: usize,
+    bits: u64,
+    num_bits: u32, // less than 8 between reads
+    crc8: u8,
+    crc16: u16,
+}
+
+impl<R: Read> BitReader<R> {
+    fn new(inner: R) -> Self {
+        Self { inner, buf: vec![0; READ_BUFFER_SIZE], buf_pos: 0, buf_len: 0, bits: 0, num_bits: 0, crc8: 0, crc16: 0 }
+    }
+
+    fn next_byte(&mut self) -> Result<u8, WavError> {
+        if self.buf_pos == self.buf_len {
+            self.buf_len = loop {
+                match self.inner.read(&mut self.buf) {
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    result => break result?,
+                }
+            };
+            self.buf_pos = 0;
+            if self.buf_len == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+        }
+        let byte = self.buf[self.buf_pos];
+        self.buf_pos += 1;
+        self.crc8 = CRC8_TABLE[(self.crc8 ^ byte) as usize];
+        self.crc16 = (self.crc16 << 8) ^ CRC16_TABLE[((self.crc16 >> 8) as u8 ^ byte) as usize];
+        Ok(byte)
+    }
+
+    // reads up to 33 bits (the size of a 32-bit side channel sample)
+    fn read_bits(&mut self, n: u32) -> Result<u64, WavError> {
+        while self.num_bits < n {
+            self.bits = (self.bits << 8) | self.next_byte()? as u64;
+            self.num_bits += 8;
+        }
+        self.num_bits -= n;
+        let value = self.bits >> self.num_bits;
+        self.bits &= (1 << self.num_bits) - 1;
+        Ok(value)
+    }
+
+    fn read_signed(&mut self, n: u32) -> Result<i64, WavError> {
+        if n == 0 {
+            return Ok(0);
+        }
+        let value = self.read_bits(n)?;
+        Ok(((value << (64 - n)) as i64) >> (64 - n))
+    }
+
+    // counts the 0 bits before the next 1 bit
+    fn read_unary(&mut self) -> Result<u32, WavError> {
+        let mut count = 0;
+        loop {
+            if self.num_bits == 0 {
+                self.bits = self.next_byte()? as u64;
+                self.num_bits = 8;
+            }
+            if self.bits == 0 {
+                count += self.num_bits;
+                self.num_bits = 0;
+                continue;
+            }
+            let zeros = self.num_bits - (64 - self.bits.leading_zeros());
+            count += zeros;
+            self.num_bits -= zeros + 1;
+            self.bits &= (1 << self.num_bits) - 1;
+            return Ok(count);
+        }
+    }
+
+    fn read_rice(&mut self, param: u32) -> Result<i64, WavError> {
+        let high = self.read_unary()? as u64;
+        let folded = high.wrapping_shl(param) | self.read_bits(param)?;
+        Ok((folded >> 1) as i64 ^ -((folded & 1) as i64))
+    }
+
+    // UTF-8 style variable length number of up to 36 bits
+    fn read_utf8(&mut self) -> Result<u64, WavError> {
+        let first = self.read_bits(8)? as u8;
+        let len = first.leading_ones();
+        match len {
+            0 => return Ok(first as u64),
+            1 | 8 => return Err(WavError::InvalidData("invalid FLAC frame number")),
+            _ => {}
+        }
+        let mut value = (first & (0x7F >> len)) as u64;
+        for _ in 1..len {
+            let byte = self.read_bits(8)?;
+            if byte & 0xC0 != 0x80 {
+                return Err(WavError::InvalidData("invalid FLAC frame number"));
+            }
+            value = (value << 6) | (byte & 0x3F);
+        }
+        Ok(value)
+    }
+
+    // skips the padding bits up to the next byte
+    fn align(&mut self) {
+        self.bits = 0;
+        self.num_bits = 0;
+    }
+
+    fn reset_crc(&mut self) {
+        self.crc8 = 0;
+        self.crc16 = 0;
+    }
+
+    fn crc8(&self) -> u8 {
+        self.crc8
+    }
+
+    fn crc16(&self) -> u16 {
+        self.crc16
+    }
+}
+
+impl<R: Read + Seek> BitReader<R> {
+    fn seek(&mut self, pos: u64) -> Result<(), WavError> {
+        self.inner.seek(SeekFrom::Start(pos))?;
+        self.buf_pos = 0;
+        self.buf_len = 0;
+        self.align();
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    acc_bits: u32, // less than 8 between writes
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self { bytes: vec![], acc: 0, acc_bits: 0 }
+    }
+
+    // writes the lowest n bits of value, n can be up to 33
+    fn write_bits(&mut self, value: u64, n: u32) {
+        if n == 0 {
+            return;
+        }
+        self.acc = (self.acc << n) | (value & ((1 << n) - 1));
+        self.acc_bits += n;
+        while self.acc_bits >= 8 {
+            self.acc_bits -= 8;
+            self.bytes.push((self.acc >> self.acc_bits) as u8);
+        }
+        self.acc &= (1 << self.acc_bits) - 1;
+    }
+
+    fn write_unary(&mut self, mut zeros: u64) {
+        while zeros >= 32 {
+            self.write_bits(0, 32);
+            zeros -= 32;
+        }
+        self.write_bits(1, zeros as u32 + 1);
+    }
+
+    fn write_utf8(&mut self, value: u64) {
+        // bytes needed for the value, each extra byte holds 6 bits and takes one from the first
+        let len = match value {
+            0..=0x7F => {
+                self.write_bits(value, 8);
+                return;
+            }
+            0x80..=0x7FF => 2,
+            0x800..=0xFFFF => 3,
+            0x1_0000..=0x1F_FFFF => 4,
+            0x20_0000..=0x3FF_FFFF => 5,
+            0x400_0000..=0x7FFF_FFFF => 6,
+            _ => 7,
+        };
+        let prefix = (0xFF00u16 >> len) as u8 as u64;
+        self.write_bits(prefix | (value >> (6 * (len - 1))), 8);
+        for i in (0..len - 1).rev() {
+            self.write_bits(0x80 | ((value >> (6 * i)) & 0x3F), 8);
+        }
+    }
+
+    fn append(&mut self, other: &BitWriter) {
+        for &byte in &other.bytes {
+            self.write_bits(byte as u64, 8);
+        }
+        self.write_bits(other.acc, other.acc_bits);
+    }
+
+    fn align(&mut self) {
+        if self.acc_bits > 0 {
+            self.write_bits(0, 8 - self.acc_bits);
+        }
+    }
+
+    fn bit_len(&self) -> u64 {
+        self.bytes.len() as u64 * 8 + self.acc_bits as u64
+    }
+}
+
+const fn crc8_table() -> [u8; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+const fn crc16_table() -> [u16; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x8005 } else { crc << 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+// per round shift amounts and sine derived constants from RFC 1321
+const MD5_SHIFTS: [u32; 64] = [
+    7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22,
+    5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20,
+    4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23,
+    6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+];
+const MD5_K: [u32; 64] = [
+    0xd76aa478, 0xe8c7b756, 0x242070db, 0xc1bdceee, 0xf57c0faf, 0x4787c62a, 0xa8304613, 0xfd469501,
+    0x698098d8, 0x8b44f7af, 0xffff5bb1, 0x895cd7be, 0x6b901122, 0xfd987193, 0xa679438e, 0x49b40821,
+    0xf61e2562, 0xc040b340, 0x265e5a51, 0xe9b6c7aa, 0xd62f105d, 0x02441453, 0xd8a1e681, 0xe7d3fbc8,
+    0x21e1cde6, 0xc33707d6, 0xf4d50d87, 0x455a14ed, 0xa9e3e905, 0xfcefa3f8, 0x676f02d9, 0x8d2a4c8a,
+    0xfffa3942, 0x8771f681, 0x6d9d6122, 0xfde5380c, 0xa4beea44, 0x4bdecfa9, 0xf6bb4b60, 0xbebfbc70,
+    0x289b7ec6, 0xeaa127fa, 0xd4ef3085, 0x04881d05, 0xd9d4d039, 0xe6db99e5, 0x1fa27cf8, 0xc4ac5665,
+    0xf4292244, 0x432aff97, 0xab9423a7, 0xfc93a039, 0x655b59c3, 0x8f0ccc92, 0xffeff47d, 0x85845dd1,
+    0x6fa87e4f, 0xfe2ce6e0, 0xa3014314, 0x4e0811a1, 0xf7537e82, 0xbd3af235, 0x2ad7d2bb, 0xeb86d391,
+];
+
+// the MD5 that STREAMINFO stores of the decoded samples
+struct Md5 {
+    state: [u32; 4],
+    block: [u8; 64],
+    block_len: usize,
+    total_len: u64,
+}
+
+impl Md5 {
+    fn new() -> Self {
+        Self { state: [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476], block: [0; 64], block_len: 0, total_len: 0 }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.total_len = self.total_len.wrapping_add(data.len() as u64);
+        if self.block_len > 0 {
+            let len = usize::min(64 - self.block_len, data.len());
+            self.block[self.block_len..self.block_len + len].copy_from_slice(&data[..len]);
+            self.block_len += len;
+            data = &data[len..];
+            if self.block_len < 64 {
+                return;
+            }
+            let block = self.block;
+            self.compress(&block);
+            self.block_len = 0;
+        }
+        while data.len() >= 64 {
+            self.compress(data[..64].try_into().unwrap());
+            data = &data[64..];
+        }
+        self.block[..data.len()].copy_from_slice(data);
+        self.block_len = data.len();
+    }
+
+    fn finish(mut self) -> [u8; 16] {
+        let bit_len = self.total_len.wrapping_mul(8);
+        // a 1 bit, zeros up to 8 bytes before the end of a block, then the length in bits
+        self.update(&[0x80]);
+        while self.block_len != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bit_len.to_le_bytes());
+
+        let mut out = [0; 16];
+        for (bytes, word) in out.chunks_exact_mut(4).zip(self.state) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+        out
+    }
+
+    fn compress(&mut self, block: &[u8; 64]) {
+        let mut words = [0u32; 16];
+        for (word, bytes) in words.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_le_bytes(bytes.try_into().unwrap());
+        }
+
+        let [mut a, mut b, mut c, mut d] = self.state;
+        for (i, (&k, &shift)) in MD5_K.iter().zip(MD5_SHIFTS.iter()).enumerate() {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let f = f.wrapping_add(a).wrapping_add(k).wrapping_add(words[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(shift));
+        }
+
+        for (s, v) in self.state.iter_mut().zip([a, b, c, d]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+}
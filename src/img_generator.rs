@@ -1,64 +1,67 @@
-use std::{fs::File, io::BufReader, u32};
+use std::u32;
 
 use slint::{Rgba8Pixel, SharedPixelBuffer, SharedString};
 
-use octave::{audio::{FreqData, ShortTimeDftData}, file_io::{read_data, read_wav_meta}, util::hue_to_rgb, parametric_eq::ParametricEq};
+use octave::{audio::{FreqData, ShortTimeDftData}, audio_source::{audio_path, open_mapped_audio, AudioSource}, file_io::WavError, util::hue_to_rgb, parametric_eq::ParametricEq, psd::PowerSpectrum, fft::Complex, transfer_function::TransferFunction};
 
-pub fn generate_waveform_preview(audio_file: SharedString, imgx: f32, imgy: f32) -> SharedPixelBuffer<Rgba8Pixel> {
-    if audio_file.trim().is_empty() {
-        return SharedPixelBuffer::new(imgx as u32, imgy as u32);
-    }
+// frames read from the file at a time when drawing waveforms
+pub const WAVEFORM_BLOCK_SIZE: usize = 1 << 16;
 
-    let mut file = BufReader::new(File::open(format!("./res/audio/{}", audio_file)).unwrap());
-    let file_info = read_wav_meta(&mut file);
+pub fn generate_waveform_preview(audio_file: SharedString, imgx: f32, imgy: f32) -> Result<SharedPixelBuffer<Rgba8Pixel>, WavError> {
+    if audio_file.trim().is_empty() || imgx < 1. {
+        return Ok(SharedPixelBuffer::new(imgx as u32, imgy as u32));
+    }
 
-    let duration = file_info.audio_duration;
-    let channels = file_info.channels as usize;
+    let mut reader = open_mapped_audio(audio_path(&audio_file))?;
+    reader.set_block_size(WAVEFORM_BLOCK_SIZE)?;
 
-    let samples = read_data(&mut file, &file_info, 0., duration).unwrap();
-    let total_samples = samples[0].len();
+    let channels = reader.info().channels as usize;
+    let total_samples = reader.num_frames() as usize;
     let samples_per_pixel = total_samples as f32 / imgx;
 
     let middle = (imgy as u32 / 2) as f32;
 
-    let mut shared_buf = SharedPixelBuffer::new(imgx as u32, imgy as u32);
-    let buf = shared_buf.make_mut_slice();
-    for x in 0..(imgx as usize) {
-        // Determine the range of sample indices that fall into this x column.
-        let start = (x as f32 * samples_per_pixel).floor() as usize;
-        let end = ((x as f32 + 1.0) * samples_per_pixel).ceil() as usize;
-
-        // Set initial min/max values
-        let mut min_val = f32::INFINITY;
-        let mut max_val = f32::NEG_INFINITY;
+    // min/max of the channel average in every x column
+    let mut min_vals = vec![f32::INFINITY; imgx as usize];
+    let mut max_vals = vec![f32::NEG_INFINITY; imgx as usize];
 
-        // Process every sample in this bin
-        for i in (start.min(total_samples)..end.min(total_samples)).step_by(4) {
+    let mut block = vec![];
+    let mut block_start: usize = 0;
+    while reader.read_block(&mut block)? > 0 {
+        let block_len = block[0].len();
+        // only every 4th sample is needed for the preview
+        for i in (block_start.next_multiple_of(4)..block_start + block_len).step_by(4) {
             // Average across all channels
-            let mut sum = 0.0;
-            for ch in 0..channels {
-                sum += samples[ch][i];
-            }
-            sum /= channels as f32;
+            let sum = block.iter().map(|ch| ch[i - block_start]).sum::<f32>() / channels as f32;
+
+            let x = ((i as f32 / samples_per_pixel) as usize).min(min_vals.len() - 1);
+            if sum < min_vals[x] { min_vals[x] = sum; }
+            if sum > max_vals[x] { max_vals[x] = sum; }
+        }
+        block_start += block_len;
+    }
 
-            if sum < min_val { min_val = sum; }
-            if sum > max_val { max_val = sum; }
+    let mut shared_buf = SharedPixelBuffer::new(imgx as u32, imgy as u32);
+    let buf = shared_buf.make_mut_slice();
+    for x in 0..(imgx as usize) {
+        // columns without any samples stay empty
+        if min_vals[x] > max_vals[x] {
+            continue;
         }
 
         // Map the amplitude values to y coordinates
-        let y_min = (middle + min_val * middle).round() as usize;
-        let y_max = (middle + max_val * middle).round() as usize;
+        let y_min = (middle + min_vals[x] * middle).round() as usize;
+        let y_max = (middle + max_vals[x] * middle).round() as usize;
 
         // Draw a vertical line in this column from y_min to y_max
-        // (Ensure y_min is not greater than y_max)
-        for y in y_min.min(y_max)..=y_min.max(y_max) {
+        for y in y_min..=y_max {
             // Ensure we don't go out of bounds
             if y < imgy as usize {
                 buf[y * (imgx as usize) + x] = Rgba8Pixel::new(0, 255, 0, 255);
             }
         }
     } 
-    shared_buf
+    Ok(shared_buf)
 }
 
 pub fn generate_rta_line(
@@ -117,6 +120,170 @@ pub fn generate_rta_line(
     ).into()
 }
 
+// x of a frequency on a log scale from min_freq at 0 to max_freq at imgx
+fn freq_to_x(f: f32, min_freq: f32, max_freq: f32, imgx: u32) -> f32 {
+    (f.log10() - min_freq.log10()) / (max_freq.log10() - min_freq.log10()) * imgx as f32
+}
+
+// y of a level from min_level at the bottom to max_level at the top, clamped to the image
+fn level_to_y(level: f32, min_level: f32, max_level: f32, imgy: u32) -> f32 {
+    (imgy as f32 - (level - min_level) / (max_level - min_level) * imgy as f32).clamp(0., imgy as f32)
+}
+
+// The power of every fractional octave band, in dB relative to a full scale sine
+#[allow(clippy::too_many_arguments)]
+pub fn generate_ltas_line(
+    imgx: u32, imgy: u32,
+    min_freq: f32, max_freq: f32,
+    min_level: f32, max_level: f32,
+    octave_bandwidth: f32,
+    spectrum: &PowerSpectrum,
+) -> SharedString {
+    let band_multiplier = 2f32.powf(octave_bandwidth);
+    let nyquist = spectrum.sample_rate as f32 / 2.;
+    let freq_to_x = | f: f32 | freq_to_x(f, min_freq, max_freq, imgx);
+    let level_to_y = | level: f32 | level_to_y(level, min_level, max_level, imgy);
+
+    let mut svg_cmds = vec![];
+    let mut low_bound = min_freq;
+    while low_bound < max_freq.min(nyquist) {
+        let upper_bound = low_bound * band_multiplier;
+        let power = spectrum.band_power(low_bound, upper_bound);
+        // bands narrower than a bin can miss every bin
+        if power > 0. {
+            let level = 10. * (2. * power).log10();
+            svg_cmds.push(format!("L {:.1} {:.1} ", freq_to_x((low_bound * upper_bound).sqrt()), level_to_y(level)));
+        }
+        low_bound = upper_bound;
+    }
+    if svg_cmds.is_empty() {
+        return SharedString::new();
+    }
+
+    (format!("M 0 {} ", imgy) + svg_cmds.join("").as_str() + format!("L {:.1} {}", freq_to_x(low_bound.min(max_freq)), imgy).as_str()).into()
+}
+
+// Paths of the magnitude (dB around 0), phase (-180 to 180 degrees) and coherence (0 at the bottom to 1 at the top)
+// of a transfer function, each averaged over fractional octave bands
+#[allow(clippy::too_many_arguments)]
+pub fn generate_transfer_function_lines(
+    imgx: u32, imgy: u32,
+    min_freq: f32, max_freq: f32,
+    min_level: f32, max_level: f32,
+    octave_bandwidth: f32,
+    tf: &TransferFunction,
+) -> (SharedString, SharedString, SharedString) {
+    let band_multiplier = 2f32.powf(octave_bandwidth);
+
+    // the magnitude is drawn around 0 dB in the middle, whatever min_level and max_level are
+    let half_range = (max_level - min_level) / 2.;
+    let freq_to_x = | f: f32 | freq_to_x(f, min_freq, max_freq, imgx);
+    let level_to_y = | level: f32 | level_to_y(level, -half_range, half_range, imgy);
+    let phase_to_y = | degrees: f32 | -> f32 {
+        (imgy / 2) as f32 - degrees / 360. * imgy as f32
+    };
+
+    let mut magnitude = vec![];
+    let mut phase = vec![];
+    let mut coherence = vec![];
+    let mut last_phase = None;
+    let mut bin = 0;
+    let mut low_bound = min_freq;
+    while low_bound < max_freq && bin < tf.bins.len() {
+        let upper_bound = low_bound * band_multiplier;
+        let mut response = Complex::zero();
+        let mut coherence_sum = 0.;
+        let mut count = 0;
+        while bin < tf.bins.len() && tf.bins[bin].frequency < upper_bound {
+            if tf.bins[bin].frequency >= low_bound {
+                response += tf.bins[bin].response();
+                coherence_sum += tf.bins[bin].coherence;
+                count += 1;
+            }
+            bin += 1;
+        }
+
+        // bands narrower than a bin can miss every bin
+        if count > 0 && response.magnitude() > 0. {
+            let x = freq_to_x((low_bound * upper_bound).sqrt());
+            let cmd = |cmds: &Vec<String>| if cmds.is_empty() { "M" } else { "L" };
+            let level = 20. * (response.magnitude() / count as f32).log10();
+            magnitude.push(format!("{} {:.1} {:.1} ", cmd(&magnitude), x, level_to_y(level)));
+
+            // a new line where the phase wraps around, instead of a jump across the graph
+            let degrees = response.phase().to_degrees();
+            let wrapped = last_phase.is_some_and(|last: f32| (degrees - last).abs() > 180.);
+            phase.push(format!("{} {:.1} {:.1} ", if wrapped { "M" } else { cmd(&phase) }, x, phase_to_y(degrees)));
+            last_phase = Some(degrees);
+
+            coherence.push(format!("{} {:.1} {:.1} ", cmd(&coherence), x, imgy as f32 * (1. - coherence_sum / count as f32)));
+        }
+        low_bound = upper_bound;
+    }
+
+    (magnitude.join("").into(), phase.join("").into(), coherence.join("").into())
+}
+
+// The lowest and highest sample in every column of an impulse response, scaled so its peak reaches the edges
+pub fn generate_ir_line(imgx: u32, imgy: u32, ir: &[f32]) -> SharedString {
+    let peak = ir.iter().fold(0f32, |max, x| max.max(x.abs()));
+    if imgx == 0 || peak <= 0. {
+        return SharedString::new();
+    }
+    let sample_to_y = | x: f32 | -> f32 {
+        (imgy as f32 / 2.) * (1. - x / peak)
+    };
+
+    let samples_per_column = ir.len().div_ceil(imgx as usize);
+    ir.chunks(samples_per_column).enumerate().map(|(x, column)| {
+        let (min, max) = column.iter().fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), &s| (min.min(s), max.max(s)));
+        format!("{} {} {:.1} L {} {:.1} ", if x == 0 { "M" } else { "L" }, x, sample_to_y(max), x, sample_to_y(min))
+    }).collect::<String>().into()
+}
+
+// A frequency response in dB relative to reference_level, averaged over fractional octave bands.
+// The frequencies are divided by freq_divisor first, so a harmonic lines up with its fundamental
+#[allow(clippy::too_many_arguments)]
+pub fn generate_response_line(
+    imgx: u32, imgy: u32,
+    min_freq: f32, max_freq: f32,
+    min_level: f32, max_level: f32,
+    octave_bandwidth: f32,
+    response: &[FreqData],
+    freq_divisor: f32,
+    reference_level: f32,
+) -> SharedString {
+    let band_multiplier = 2f32.powf(octave_bandwidth);
+    let freq_to_x = | f: f32 | freq_to_x(f, min_freq, max_freq, imgx);
+    let level_to_y = | level: f32 | level_to_y(level, min_level, max_level, imgy);
+
+    let mut svg_cmds: Vec<String> = vec![];
+    let mut bin = 0;
+    let mut low_bound = min_freq;
+    while low_bound < max_freq && bin < response.len() {
+        let upper_bound = low_bound * band_multiplier;
+        let mut power = 0.;
+        let mut count = 0;
+        while bin < response.len() && response[bin].frequency / freq_divisor < upper_bound {
+            if response[bin].frequency / freq_divisor >= low_bound {
+                power += response[bin].amplitude * response[bin].amplitude;
+                count += 1;
+            }
+            bin += 1;
+        }
+
+        // bands narrower than a bin can miss every bin
+        if count > 0 && power > 0. {
+            let level = 10. * (power / count as f32).log10() - 20. * reference_level.log10();
+            let cmd = if svg_cmds.is_empty() { "M" } else { "L" };
+            svg_cmds.push(format!("{} {:.1} {:.1} ", cmd, freq_to_x((low_bound * upper_bound).sqrt()), level_to_y(level)));
+        }
+        low_bound = upper_bound;
+    }
+
+    svg_cmds.join("").into()
+}
+
 pub fn generate_eq_response(
     param_eq: &ParametricEq,
     min_freq: f32, max_freq: f32,
@@ -191,11 +358,11 @@ pub fn generate_spectrogram_img(
             let y = (input_y as f32 / y_scale).floor() as u32;
 
             if !written_px[y as usize][x as usize] {
-                let (r, g, b) = rgb_from_range(stdft.dft_data[input_x as usize][stdft.num_freq as usize - input_y as usize - 1].amplitude, max_amplitude);
+                let (r, g, b) = rgb_from_range(stdft.amplitude(input_x as usize, stdft.num_freq as usize - input_y as usize - 1), max_amplitude);
                 imgbuf[(y * imgx + x) as usize] = Rgba8Pixel::new(r, g, b, 255);
                 written_px[y as usize][x as usize] = true;
             } else { //if we have already written to this pixel, blend between the two rgb values
-                let cur_col = rgb_from_range(stdft.dft_data[input_x as usize][stdft.num_freq as usize - input_y as usize - 1].amplitude, max_amplitude);
+                let cur_col = rgb_from_range(stdft.amplitude(input_x as usize, stdft.num_freq as usize - input_y as usize - 1), max_amplitude);
                 let other_col: (u8, u8, u8) = imgbuf[(y * imgx + x) as usize].rgb().into();
                 let blended = blend_rgb(cur_col, other_col);
                 imgbuf[(y * imgx + x) as usize] = Rgba8Pixel::new(blended.0, blended.1, blended.2, 255);
@@ -209,39 +376,44 @@ pub fn generate_spectrogram_img(
 pub fn generate_waveform_img(
     imgx: u32,
     imgy: u32,
-    samples: Vec<Vec<f32>>
-) -> SharedPixelBuffer<Rgba8Pixel> {
+    reader: &mut dyn AudioSource,
+) -> Result<SharedPixelBuffer<Rgba8Pixel>, WavError> {
+    if imgx == 0 || imgy == 0 {
+        return Ok(SharedPixelBuffer::new(imgx, imgy));
+    }
     let imgx = imgx as usize;
     
-    let channels = samples.len();
+    let channels = reader.info().channels as usize;
     
-    let x_scale = imgx as f32 / samples[0].len() as f32;
+    let x_scale = imgx as f32 / reader.num_frames() as f32;
     let center_y = (imgy / 2) as usize;
 
     let mut img = SharedPixelBuffer::new(imgx as u32, imgy);
     let imgbuf = img.make_mut_slice();
 
-    for i in 0..samples[0].len() {
-        let mut sum = 0.;
-        for j in 0..channels {
-            sum += samples[j][i];
+    let mut block = vec![];
+    let mut block_start = 0;
+    while reader.read_block(&mut block)? > 0 {
+        for i in 0..block[0].len() {
+            let sum = block.iter().map(|ch| ch[i]).sum::<f32>() / channels as f32;
+            
+            let x = (((block_start + i) as f32 * x_scale).floor() as usize).min(imgx - 1);
+            let y = (((sum * center_y as f32).floor() + center_y as f32) as usize).min(imgy as usize - 1);
+            imgbuf[y * imgx + x] = Rgba8Pixel::new(0, 255, 0, 255);
         }
-        sum /= channels as f32;
-        
-        let x = (i as f32 * x_scale).floor() as usize;
-        let y = ((sum * center_y as f32).floor() + center_y as f32) as usize;
-        imgbuf[y * imgx + x] = Rgba8Pixel::new(0, 255, 0, 255);
+        block_start += block[0].len();
     }
 
-    img
+    Ok(img)
 }
 
 fn find_max_amplitude(stdft: &ShortTimeDftData) -> f32 {
     let mut max = 0.;
-    for dft in &stdft.dft_data {
-        for freq_data in dft {
-            if freq_data.amplitude.abs() > max {
-                max = freq_data.amplitude.abs();
+    for dft in 0..stdft.num_dfts as usize {
+        for bin in 0..stdft.num_freq as usize {
+            let amplitude = stdft.amplitude(dft, bin);
+            if amplitude > max {
+                max = amplitude;
             }
         }
     }
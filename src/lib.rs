@@ -1,10 +1,30 @@
+pub mod aiff;
 pub mod audio;
+pub mod audio_source;
 pub mod circular_buffer;
 pub mod fft;
 pub mod file_analyzer;
 pub mod file_io;
 pub mod fir_filter;
 pub mod fir_filter_constants;
+pub mod flac;
 pub mod lookup_tables;
+pub mod mdct;
+pub mod mmap;
+pub mod mp3;
+pub mod mp3_tables;
+pub mod ogg;
+pub mod opus;
+pub mod opus_celt;
+pub mod opus_silk;
+pub mod opus_tables;
 pub mod parametric_eq;
+pub mod phase_vocoder;
+pub mod psd;
+pub mod raw_pcm;
+pub mod room_acoustics;
+pub mod sweep;
+pub mod transfer_function;
 pub mod util;
+pub mod vorbis;
+pub mod wav_metadata;
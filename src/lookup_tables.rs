@@ -1,7 +1,9 @@
+pub const PCM_8BIT_POS_MAX: f32 = 0x7F as f32;
 pub const PCM_16BIT_POS_MAX: f32 = 0x7FFF as f32;
 pub const PCM_24BIT_POS_MAX: f32 = 0x7FFFFF as f32;
 pub const PCM_32BIT_POS_MAX: f32 = 0x7FFFFFFF as f32;
 
+pub const PCM_8BIT_NEG_MAX: f32 = 0x80 as f32;
 pub const PCM_16BIT_NEG_MAX: f32 = 0x8000 as f32;
 pub const PCM_24BIT_NEG_MAX: f32 = 0x800000 as f32;
 pub const PCM_32BIT_NEG_MAX: f32 = 0x80000000i64 as f32;
@@ -1,38 +1,59 @@
 mod img_generator;
+mod measurement;
 mod players;
 mod rta;
 
-use octave::audio::{do_short_time_fourier_transform, ShortTimeDftData, WindowFunction};
-use octave::file_analyzer::analyze_file;
-use octave::file_io::{read_data, read_wav_meta, read_wav_sample_rate};
+use octave::audio::{FreqData, do_short_time_fourier_transform_streaming, StftOutput, WindowFunction};
+use octave::file_analyzer::{analyze_file, analyze_impulse_response, analyze_impulse_response_source, analyze_source};
+use octave::audio_source::{audio_path, detect_file_format, open_mapped_audio, open_raw_pcm, read_sample_rate, AudioSource, AUDIO_DIR};
+use octave::file_io::WavError;
 use img_generator::{
-    generate_eq_fill_response, generate_eq_response, generate_rta_line, generate_spectrogram_img,
-    generate_waveform_img, generate_waveform_preview,
+    generate_eq_fill_response, generate_eq_response, generate_ir_line, generate_ltas_line, generate_response_line, generate_rta_line,
+    generate_spectrogram_img, generate_transfer_function_lines, generate_waveform_img, generate_waveform_preview, WAVEFORM_BLOCK_SIZE,
 };
 use octave::parametric_eq::{FilterType, ParametricEq};
+use octave::sweep::frequency_response;
+use octave::raw_pcm::{has_raw_pcm_extension, Endianness, RawPcmSpec};
+use octave::room_acoustics::{AcousticParameters, RoomAcoustics};
+use octave::wav_metadata::{BextChunk, IxmlChunk};
 use octave::util::*;
 
+use crate::measurement::measure_sweep;
 use crate::players::AudioPlayer;
-use crate::rta::ExternalRta;
+use crate::rta::{ExternalRta, RtaMode};
 
 use cpal::{
     traits::{DeviceTrait, HostTrait},
     SampleRate,
 };
-use slint::{run_event_loop, Image, Model, ModelRc, SharedString, Timer, TimerMode, VecModel};
+use slint::{run_event_loop, Image, Model, ModelRc, SharedPixelBuffer, SharedString, Timer, TimerMode, VecModel};
 
 use std::cell::RefCell;
-use std::io::BufReader;
+use std::collections::HashMap;
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::{
-    fs::{create_dir, File},
+    fs::create_dir,
     path::Path,
 };
 
 slint::include_modules!();
 
+// size of the file analyzer's spectrum path, the ui scales it to fit
+const LTAS_IMG_X: u32 = 400;
+const LTAS_IMG_Y: u32 = 200;
+// sizes of the sweep measurement's impulse response and frequency response paths
+const SWEEP_IR_IMG_X: u32 = 800;
+const SWEEP_IR_IMG_Y: u32 = 150;
+const SWEEP_RESPONSE_IMG_X: u32 = 800;
+const SWEEP_RESPONSE_IMG_Y: u32 = 300;
+// how much of the linear impulse response goes into the frequency response, long enough for the decay
+// of most rooms and a 1 Hz resolution, while leaving out the noise after it
+const SWEEP_IR_WINDOW_SECS: f32 = 1.;
+// harmonic distortion orders shown along with the linear response
+const SWEEP_HARMONICS: [usize; 4] = [2, 3, 4, 5];
+
 //standard initial 2-stage weighting curve for LKFS measurement
 //param_eq.add_biquad(Biquad::with_coefficients(1.53512485958697, -2.69169618940638, 1.19839281085285, -1.69065929318241, 0.73248077421585, 48000));
 //param_eq.add_biquad(Biquad::with_coefficients(1., -2., 1., -1.99004745483398, 0.99007225036621, 48000));
@@ -45,7 +66,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     if !Path::new("./res/").exists() {
         println!("\"./res\" directory does not exist. Creating res directory...");
         create_dir("./res").unwrap();
-        create_dir("./res/audio").unwrap();
+        create_dir(AUDIO_DIR).unwrap();
         println!("\"./res\" directory created. Please place files you'd like to parse in \"./res/audio\"");
         println!(
             "(full path to \"./res/\": \"{}\")",
@@ -66,14 +87,18 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let rta: Rc<RefCell<Option<ExternalRta>>> = Rc::new(RefCell::new(None));
 
+    // formats given to headerless files, by file name
+    let raw_formats: RawFormats = Arc::new(Mutex::new(HashMap::new()));
+
     // UI Initialization Logic (called when any menu is opened) ----------------
     let init_ptr = main_window.as_weak();
     main_window.on_init_menu(move |menu: i32| {
         let main_window = init_ptr.upgrade().unwrap();
+        main_window.set_error_message("".into());
 
         match menu {
             0 => {
-                let files: Vec<SharedString> = query_directory("./res/audio/")
+                let files: Vec<SharedString> = query_directory(AUDIO_DIR)
                     .into_iter()
                     .map(|e| SharedString::from(e))
                     .collect();
@@ -85,7 +110,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
                 let mut supported_files: Vec<SharedString> = vec![];
                 for f in files {
-                    let sample_rate = read_wav_sample_rate(f.clone().into());
+                    let sample_rate = file_sample_rate(&f);
 
                     if let Some(_) = supported_configs
                         .iter()
@@ -101,7 +126,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 main_window.set_selected_file("".into());
             }
             1 => {
-                let files: Vec<SharedString> = query_directory("./res/audio/")
+                let files: Vec<SharedString> = query_directory(AUDIO_DIR)
                     .into_iter()
                     .map(|e| SharedString::from(e))
                     .collect();
@@ -112,7 +137,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 main_window.set_selected_file("".into());
             }
             3 => {
-                let files: Vec<SharedString> = query_directory("./res/audio/")
+                let files: Vec<SharedString> = query_directory(AUDIO_DIR)
                     .into_iter()
                     .map(|e| SharedString::from(e))
                     .collect();
@@ -135,6 +160,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 0 => {
                     *close_menu_player_ptr.borrow_mut() = None;
                     main_window.set_slider_pos(0.);
+                    main_window.set_player_markers(ModelRc::default());
                     main_window.set_is_playing(false);
                     main_window.set_selected_file("".into());
                 }
@@ -348,14 +374,29 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         let file_sel_ptr = main_window.as_weak();
         let player_eq_ptr = Arc::clone(&player_eq);
         main_window.on_file_select(move |file: SharedString| {
-            let sample_rate = read_wav_sample_rate(file.clone().into());
+            let sample_rate = file_sample_rate(&file);
             player_eq_ptr.lock().unwrap().set_sample_rate(sample_rate);
 
-            *audio_player_ref.borrow_mut() =
-                Some(AudioPlayer::new(file.into(), Arc::clone(&player_eq_ptr)));
             let main_window = file_sel_ptr.upgrade().unwrap();
-            let file_dur = audio_player_ref.borrow().as_ref().unwrap().duration;
-            main_window.set_file_duration(file_dur);
+            match AudioPlayer::new(file.into(), Arc::clone(&player_eq_ptr)) {
+                Ok(new_player) => {
+                    main_window.set_file_duration(new_player.duration);
+                    // the slider runs from 0 to 100
+                    let markers: Vec<SliderMarker> = new_player.markers
+                        .iter()
+                        .map(|(prog, label)| SliderMarker { value: prog * 100., label: label.into() })
+                        .collect();
+                    main_window.set_player_markers(ModelRc::new(Rc::new(VecModel::from(markers))));
+                    main_window.set_error_message("".into());
+                    *audio_player_ref.borrow_mut() = Some(new_player);
+                }
+                Err(err) => {
+                    *audio_player_ref.borrow_mut() = None;
+                    main_window.set_file_duration(0.);
+                    main_window.set_player_markers(ModelRc::default());
+                    main_window.set_error_message(format!("Could not open file: {}", err).into());
+                }
+            }
         });
     }
 
@@ -365,7 +406,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         main_window.on_render_waveform(move |file: SharedString, imgx: f32, imgy: f32| {
             let window_weak = window_weak.clone();
             thread::spawn(move || {
-                let img = generate_waveform_preview(file, imgx, imgy);
+                // errors for this file are already reported when the player is created
+                let img = generate_waveform_preview(file, imgx, imgy)
+                    .unwrap_or_else(|_| SharedPixelBuffer::new(imgx as u32, imgy as u32));
                 window_weak.upgrade_in_event_loop(|handle| {
                     handle.set_waveform_img(Image::from_rgba8(img));
                 })
@@ -373,38 +416,72 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         });
     }
 
+    // Raw File Formats --------------------------------------------------------
+    main_window.on_needs_raw_format(|file: SharedString| needs_raw_format(&file));
+
+    {
+        let window_weak = main_window.as_weak();
+        let raw_formats = Arc::clone(&raw_formats);
+        main_window.on_set_raw_format(move |file: SharedString, format: RawFormat| {
+            let main_window = window_weak.upgrade().unwrap();
+            // opening the file also checks the format against its size
+            match raw_pcm_spec(&format).and_then(|spec| open_raw_pcm(audio_path(&file), spec).map(|_| spec)) {
+                Ok(spec) => {
+                    raw_formats.lock().unwrap().insert(file.to_string(), spec);
+                    main_window.set_error_message("".into());
+                    true
+                }
+                Err(err) => {
+                    main_window.set_error_message(format!("Invalid raw format: {}", err).into());
+                    false
+                }
+            }
+        });
+    }
+
     // Spectrogram Generation --------------------------------------------------
     {
         let window_weak = main_window.as_weak();
+        let raw_formats = Arc::clone(&raw_formats);
         main_window.on_generate_spectrogram(
             move |file: SharedString,
                   imgx: f32,
                   imgy: f32,
                   window_size: i32,
                   window_overlap: f32,
-                  window_type: SharedString| {
+                  window_type: SharedString,
+                  kaiser_beta: f32| {
                 let main_window = window_weak.clone();
+                let raw_formats = Arc::clone(&raw_formats);
 
                 thread::spawn(move || {
-                    let mut reader =
-                        BufReader::new(File::open(format!("./res/audio/{}", file)).unwrap());
-                    let file_info = read_wav_meta(&mut reader);
-                    let file_dur = file_info.audio_duration;
-                    let sample_rate = file_info.sample_rate;
-
-                    let samples = read_data(&mut reader, &file_info, 0., file_dur).unwrap();
-                    let window_func = WindowFunction::from_str(window_type.as_str()).unwrap();
-
-                    let stdft = do_short_time_fourier_transform(
-                        &samples[0],
-                        sample_rate,
+                    let mut reader = match open_vis_file(&file, &raw_formats) {
+                        Ok(reader) => reader,
+                        Err(err) => {
+                            show_vis_error(main_window, err);
+                            return;
+                        }
+                    };
+                    let window_func = window_function(window_type.as_str(), kaiser_beta);
+
+                    let stdft_data = match do_short_time_fourier_transform_streaming(
+                        reader.as_mut(),
+                        0,
                         window_size as f32 / 1000.,
                         window_overlap / 100.,
                         window_func,
-                    );
-                    let num_dfts = stdft.len() as u32;
-                    let num_freqs = stdft[0].len() as u32;
-                    let stdft_data = ShortTimeDftData::new(stdft, num_dfts, num_freqs, sample_rate);
+                        StftOutput::Magnitude,
+                    ) {
+                        Ok(stdft) if stdft.num_dfts > 0 => stdft,
+                        Ok(_) => {
+                            show_vis_error(main_window, WavError::InvalidInput("File is shorter than a single window!"));
+                            return;
+                        }
+                        Err(err) => {
+                            show_vis_error(main_window, err);
+                            return;
+                        }
+                    };
                     let img = generate_spectrogram_img(imgx as u32, imgy as u32, stdft_data);
 
                     main_window
@@ -421,18 +498,21 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Waveform Generation -----------------------------------------------------
     {
         let window_weak = main_window.as_weak();
+        let raw_formats = Arc::clone(&raw_formats);
         main_window.on_generate_waveform(move |file: SharedString, imgx: f32, imgy: f32| {
             let main_window = window_weak.clone();
+            let raw_formats = Arc::clone(&raw_formats);
 
             thread::spawn(move || {
-                let mut reader =
-                    BufReader::new(File::open(format!("./res/audio/{}", file)).unwrap());
-                let file_info = read_wav_meta(&mut reader);
-                let file_dur = file_info.audio_duration;
-
-                let samples = read_data(&mut reader, &file_info, 0., file_dur).unwrap();
-
-                let img = generate_waveform_img(imgx as u32, imgy as u32, samples);
+                let img = match open_vis_file(&file, &raw_formats)
+                    .and_then(|mut reader| generate_waveform_img(imgx as u32, imgy as u32, reader.as_mut()))
+                {
+                    Ok(img) => img,
+                    Err(err) => {
+                        show_vis_error(main_window, err);
+                        return;
+                    }
+                };
 
                 main_window
                     .upgrade_in_event_loop(move |handle| {
@@ -447,19 +527,25 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Start RTA ---------------------------------------------------------------
     {
         let rta_clone = Rc::clone(&rta);
-        main_window.on_start_rta(move |_rta_type: SharedString, rta_response: SharedString| {
+        let rta_window = main_window.as_weak();
+        main_window.on_start_rta(move |_rta_type: SharedString, rta_mode: SharedString, rta_response: SharedString, window_type: SharedString, kaiser_beta: f32| {
             let new_cache_size = match rta_response.as_str() {
                 "Fast" => 6000,
                 "Medium" => 24000,
                 "Slow" => 48000,
                 _ => 6000,
             };
+            let window_func = window_function(window_type.as_str(), kaiser_beta);
+            let mode = match rta_mode.as_str() {
+                "Transfer Function" => RtaMode::TransferFunction,
+                _ => RtaMode::Spectrum,
+            };
 
             let mut rta = rta_clone.borrow_mut();
             let mut new_rta = false;
 
             if let Some(active_rta) = rta.as_mut() {
-                if active_rta.buffer_size != new_cache_size {
+                if active_rta.buffer_size != new_cache_size || active_rta.window_function != window_func || active_rta.mode != mode {
                     new_rta = true;
                 } else {
                     active_rta.start();
@@ -468,8 +554,17 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 new_rta = true;
             }
             if new_rta {
-                *rta = Some(ExternalRta::new(new_cache_size));
-                rta.as_mut().unwrap().start();
+                match ExternalRta::new(new_cache_size, window_func, mode) {
+                    Ok(new_rta) => {
+                        *rta = Some(new_rta);
+                        rta.as_mut().unwrap().start();
+                        rta_window.upgrade().unwrap().set_error_message("".into());
+                    }
+                    Err(err) => {
+                        *rta = None;
+                        rta_window.upgrade().unwrap().set_error_message(err.into());
+                    }
+                }
             }
         });
     }
@@ -518,21 +613,58 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         );
     }
 
+    // Generate Transfer Function SVG Paths ------------------------------------
+    {
+        let rta_clone = Rc::clone(&rta);
+        main_window.on_req_transfer_function_img(
+            move |imgx: f32,
+                  imgy: f32,
+                  min_freq: f32,
+                  max_freq: f32,
+                  min_level: f32,
+                  max_level: f32,
+                  octave_bandwidth: f32| {
+                let rta = rta_clone.borrow();
+                let Some(tf) = rta.as_ref().and_then(|active_rta| active_rta.get_transfer_function()) else {
+                    return TransferFunctionPaths::default();
+                };
+                let (magnitude, phase, coherence) = generate_transfer_function_lines(
+                    imgx as u32,
+                    imgy as u32,
+                    min_freq,
+                    max_freq,
+                    min_level,
+                    max_level,
+                    octave_bandwidth,
+                    &tf,
+                );
+                TransferFunctionPaths { magnitude, phase, coherence, delay_ms: tf.delay as f32 / tf.sample_rate as f32 * 1000. }
+            },
+        );
+    }
+
     // Analyze Audio File ------------------------------------------------------
     {
         let analyzer_clone = main_window.as_weak();
+        let raw_formats = Arc::clone(&raw_formats);
         main_window.on_analyze_file( move | file: SharedString | {
             let main_window = analyzer_clone.clone();
+            let raw_spec = raw_formats.lock().unwrap().get(file.as_str()).copied();
 
             thread::spawn(move || {
-                let res = match analyze_file(format!("./res/audio/{}", file)) {
-                    None => {
-                        main_window.upgrade_in_event_loop(| handle | {
+                let res = match raw_spec {
+                    Some(spec) => open_raw_pcm(audio_path(&file), spec).and_then(|mut reader| analyze_source(reader.as_mut())),
+                    None => analyze_file(audio_path(&file)),
+                };
+                let res = match res {
+                    Err(err) => {
+                        main_window.upgrade_in_event_loop(move | handle | {
+                            handle.set_error_message(format!("Could not analyze file: {}", err).into());
                             handle.set_analyzing_file(false);
                         }).unwrap();
                         return;
                     }
-                    Some(res) => res
+                    Ok(res) => res
                 };
 
                 main_window.upgrade_in_event_loop(move | handle | {
@@ -543,7 +675,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                         data_rate: res.metadata.data_rate as i32,
                         data_block_size: res.metadata.data_block_size as i32,
                         bit_depth: res.metadata.bit_depth as i32,
-                        file_size: res.metadata.file_size as i32,
+                        valid_bits: res.metadata.valid_bits_per_sample as i32,
+                        file_size: res.metadata.file_size as f32,
                         channel_map: ModelRc::new(Rc::new(VecModel::from(
                                     res.metadata.channel_map
                                     .iter()
@@ -561,8 +694,23 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                         lkfs_s: res.lkfs_s as f32,
                         lkfs_m: res.lkfs_m as f32,
                         true_peaks: ModelRc::new(Rc::new(VecModel::from(res.true_peaks))),
+                        ltas_line: res.ltas
+                                    .as_ref()
+                                    .map(|ltas| generate_ltas_line(LTAS_IMG_X, LTAS_IMG_Y, 20., 20000., -100., 0., 1./6., ltas))
+                                    .unwrap_or_default(),
+                        bext_info: ModelRc::new(Rc::new(VecModel::from(
+                                    res.metadata.bext
+                                    .as_ref()
+                                    .map(|b| bext_lines(b, res.metadata.sample_rate))
+                                    .unwrap_or_default()))),
+                        ixml_info: ModelRc::new(Rc::new(VecModel::from(
+                                    res.metadata.ixml
+                                    .as_ref()
+                                    .map(ixml_lines)
+                                    .unwrap_or_default()))),
                     };
 
+                    handle.set_error_message("".into());
                     handle.set_cur_f_results(res_parsed);
                     handle.set_analyzing_finished(true);
                     handle.set_analyzing_file(false);
@@ -571,8 +719,232 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         });
     }
 
+    // Analyze Audio File as Impulse Response --------------------------------
+    {
+        let analyzer_clone = main_window.as_weak();
+        let raw_formats = Arc::clone(&raw_formats);
+        main_window.on_analyze_impulse_response( move | file: SharedString | {
+            let main_window = analyzer_clone.clone();
+            let raw_spec = raw_formats.lock().unwrap().get(file.as_str()).copied();
+
+            thread::spawn(move || {
+                let res = match raw_spec {
+                    Some(spec) => open_raw_pcm(audio_path(&file), spec).and_then(|mut reader| analyze_impulse_response_source(reader.as_mut())),
+                    None => analyze_impulse_response(audio_path(&file)),
+                };
+
+                main_window.upgrade_in_event_loop(move | handle | {
+                    match res {
+                        Err(err) => handle.set_error_message(format!("Could not analyze impulse response: {}", err).into()),
+                        Ok(acoustics) => {
+                            handle.set_error_message("".into());
+                            handle.set_f_room_acoustics(ModelRc::new(Rc::new(VecModel::from(room_acoustics_lines(&acoustics)))));
+                        }
+                    }
+                    handle.set_analyzing_impulse_response(false);
+                }).unwrap();
+            });
+        });
+    }
+
+    // Sweep Measurement ------------------------------------------------------
+    {
+        let sweep_window = main_window.as_weak();
+        main_window.on_measure_sweep(move |start_freq: f32, end_freq: f32, duration: f32, level_db: f32| {
+            let main_window = sweep_window.clone();
+
+            thread::spawn(move || {
+                let measurement = match measure_sweep(start_freq, end_freq, duration, level_db) {
+                    Err(err) => {
+                        main_window.upgrade_in_event_loop(move | handle | {
+                            handle.set_error_message(err.into());
+                            handle.set_measuring_sweep(false);
+                        }).unwrap();
+                        return;
+                    }
+                    Ok(measurement) => measurement
+                };
+                let (sweep, ir) = (&measurement.sweep, &measurement.ir);
+                let sample_rate = sweep.sample_rate;
+
+                // the harmonics all come before the linear response, only the noise after it is cut off
+                let max_len = (SWEEP_IR_WINDOW_SECS * sample_rate as f32) as usize;
+                let linear = ir.harmonic(1);
+                let linear = frequency_response(&linear[..linear.len().min(max_len)], sample_rate);
+                let in_sweep = |bin: &&FreqData, order: usize| {
+                    bin.frequency >= sweep.start_freq * order as f32 && bin.frequency <= sweep.end_freq
+                };
+                let reference_level = linear
+                    .iter()
+                    .filter(|bin| in_sweep(bin, 1))
+                    .fold(0f32, |max, bin| max.max(bin.amplitude));
+
+                let response_line = |response: &[FreqData], order: usize| -> SharedString {
+                    let response: Vec<_> = response.iter().filter(|bin| in_sweep(bin, order)).cloned().collect();
+                    generate_response_line(
+                        SWEEP_RESPONSE_IMG_X, SWEEP_RESPONSE_IMG_Y,
+                        20., 20000., -80., 10., 1./12.,
+                        &response, order as f32, reference_level,
+                    )
+                };
+                let magnitude = response_line(&linear, 1);
+                let harmonics: Vec<SharedString> = SWEEP_HARMONICS
+                    .iter()
+                    .map(|&order| response_line(&frequency_response(ir.harmonic(order), sample_rate), order))
+                    .collect();
+
+                let ir_line = generate_ir_line(SWEEP_IR_IMG_X, SWEEP_IR_IMG_Y, measurement.saved_ir());
+                let latency_ms = ir.latency as f32 / sample_rate as f32 * 1000.;
+                let file = measurement.file;
+
+                main_window.upgrade_in_event_loop(move | handle | {
+                    handle.set_sweep_results(SweepResults {
+                        file: file.into(),
+                        ir: ir_line,
+                        magnitude,
+                        harmonics: ModelRc::new(Rc::new(VecModel::from(harmonics))),
+                        latency_ms,
+                    });
+                    handle.set_error_message("".into());
+                    handle.set_sweep_finished(true);
+                    handle.set_measuring_sweep(false);
+                }).unwrap();
+            });
+        });
+    }
+
     main_window.show()?;
     run_event_loop()?;
 
     Ok(())
 }
+
+// helpers for the file analyzer
+fn bext_lines(bext: &BextChunk, sample_rate: u32) -> Vec<SharedString> {
+    let mut lines = vec![];
+    for (label, val) in [
+        ("Description", &bext.description),
+        ("Originator", &bext.originator),
+        ("Originator Reference", &bext.originator_reference),
+    ] {
+        if !val.is_empty() {
+            lines.push(format!("{}: {}", label, val).into());
+        }
+    }
+    if !bext.origination_date.is_empty() {
+        lines.push(format!("Origination: {} {}", bext.origination_date, bext.origination_time).into());
+    }
+
+    // time reference is in samples since midnight
+    let secs = bext.time_reference as f64 / sample_rate as f64;
+    lines.push(format!(
+        "Time Reference: {:02}:{:02}:{:06.3}",
+        (secs / 3600.) as u64,
+        (secs / 60.) as u64 % 60,
+        secs % 60.
+    ).into());
+
+    for (label, val, units) in [
+        ("Loudness", bext.loudness_value, "LUFS"),
+        ("Loudness Range", bext.loudness_range, "LU"),
+        ("Max True Peak", bext.max_true_peak_level, "dBTP"),
+        ("Max Momentary", bext.max_momentary_loudness, "LUFS"),
+        ("Max Short Term", bext.max_short_term_loudness, "LUFS"),
+    ] {
+        if let Some(v) = val {
+            lines.push(format!("{}: {:.2} {}", label, v, units).into());
+        }
+    }
+    lines
+}
+
+fn ixml_lines(ixml: &IxmlChunk) -> Vec<SharedString> {
+    let mut lines = vec![];
+    for (label, val) in [
+        ("Project", &ixml.project),
+        ("Scene", &ixml.scene),
+        ("Take", &ixml.take),
+        ("Tape", &ixml.tape),
+        ("Note", &ixml.note),
+    ] {
+        if !val.is_empty() {
+            lines.push(format!("{}: {}", label, val).into());
+        }
+    }
+    for track in &ixml.tracks {
+        lines.push(format!("Track {}: {}", track.channel_index, track.name).into());
+    }
+    lines
+}
+
+// 0 for files that can't be opened, which no output device supports
+fn file_sample_rate(file: &str) -> u32 {
+    read_sample_rate(audio_path(file)).unwrap_or(0)
+}
+
+// the window picked in the ui, beta is only used by the kaiser window
+fn window_function(name: &str, kaiser_beta: f32) -> WindowFunction {
+    match WindowFunction::from_str(name).unwrap_or(WindowFunction::Square) {
+        WindowFunction::Kaiser { .. } => WindowFunction::Kaiser { beta: kaiser_beta },
+        window => window,
+    }
+}
+
+// one line for the whole response, then one for every octave band
+fn room_acoustics_lines(acoustics: &RoomAcoustics) -> Vec<SharedString> {
+    let line = |name: String, p: &AcousticParameters| -> SharedString {
+        let time = |t: Option<f32>| t.map_or("-".to_string(), |t| format!("{:.2}s", t));
+        format!(
+            "{}: EDT {}, T20 {}, T30 {}, C50 {:.1}dB, C80 {:.1}dB, D50 {:.0}%",
+            name, time(p.edt), time(p.t20), time(p.t30), p.c50, p.c80, p.d50 * 100.,
+        ).into()
+    };
+
+    let mut lines = vec![line("Broadband".to_string(), &acoustics.broadband)];
+    for (center, parameters) in &acoustics.bands {
+        let name = if *center >= 1000. { format!("{} kHz", center / 1000.) } else { format!("{} Hz", center) };
+        lines.push(line(name, parameters));
+    }
+    lines
+}
+
+// helpers for headerless files
+type RawFormats = Arc<Mutex<HashMap<String, RawPcmSpec>>>;
+
+// raw extensions always ask for a format, anything else only if it isn't a known format
+fn needs_raw_format(file: &str) -> bool {
+    has_raw_pcm_extension(file) || matches!(detect_file_format(audio_path(file)), Ok(None))
+}
+
+fn raw_pcm_spec(format: &RawFormat) -> Result<RawPcmSpec, WavError> {
+    let spec = RawPcmSpec {
+        sample_type: format.sample_type.parse()?,
+        endianness: if format.big_endian { Endianness::Big } else { Endianness::Little },
+        bit_depth: format.bit_depth as u32,
+        channels: format.channels.clamp(0, u8::MAX as i32) as u8,
+        sample_rate: format.sample_rate.max(0) as u32,
+        byte_offset: format.byte_offset.max(0) as u64,
+    };
+    spec.validate()?;
+    Ok(spec)
+}
+
+// helpers for the visualizer threads
+fn open_vis_file(file: &str, raw_formats: &RawFormats) -> Result<Box<dyn AudioSource + Send>, WavError> {
+    let raw_spec = raw_formats.lock().unwrap().get(file).copied();
+    let mut source = match raw_spec {
+        Some(spec) => open_raw_pcm(audio_path(file), spec)?,
+        None => open_mapped_audio(audio_path(file))?,
+    };
+    source.set_block_size(WAVEFORM_BLOCK_SIZE)?;
+    Ok(source)
+}
+
+fn show_vis_error(main_window: slint::Weak<MainWindow>, err: WavError) {
+    main_window
+        .upgrade_in_event_loop(move |handle| {
+            handle.set_error_message(format!("Could not read file: {}", err).into());
+            handle.set_vis_loading(false);
+        })
+        .unwrap();
+}
@@ -0,0 +1,80 @@
+use std::f64::consts::PI;
+
+use crate::fft::{Complex, ComplexFft};
+
+// Inverse MDCT for the Vorbis and Opus (CELT) decoders, computed with a complex FFT of a
+// quarter of the output length. CELT's transforms are 15 * 2^k long, not powers of two,
+// which ComplexFft's mixed radix handles.
+pub struct Imdct {
+    n: usize, // output length, twice the number of coefficients
+    fft: ComplexFft, // of n/4 points
+    pre_rotation: Vec<Complex>,
+    post_rotation: Vec<Complex>,
+    // working memory, so decoding a frame doesn't allocate
+    spectrum: Vec<Complex>,
+    scratch: Vec<Complex>,
+    dct: Vec<f32>,
+}
+
+impl Imdct {
+    pub fn new(n: usize) -> Self {
+        assert!(n.is_multiple_of(8), "IMDCT length has to be a multiple of 8");
+        let fft_len = n / 4;
+        let fft = ComplexFft::new(fft_len);
+        let rotation = |offset: f64| -> Vec<Complex> {
+            (0..fft_len)
+                .map(|k| {
+                    let phase = -PI * (k as f64 + offset) / (n / 2) as f64;
+                    Complex { r: phase.cos() as f32, i: phase.sin() as f32 }
+                })
+                .collect()
+        };
+        let scratch = vec![Complex::zero(); fft.scratch_len()];
+        Self {
+            n,
+            fft,
+            pre_rotation: rotation(0.),
+            post_rotation: rotation(0.25),
+            spectrum: vec![Complex::zero(); fft_len],
+            scratch,
+            dct: vec![0.; n / 2],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.n
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.n == 0
+    }
+
+    // y[i] = sum over k of x[k] * cos(2pi/n * (i + 1/2 + n/4) * (k + 1/2)), for the n/2 coefficients
+    // in `input` and the n samples of `output`
+    pub fn process(&mut self, input: &[f32], output: &mut [f32]) {
+        let half = self.n / 2;
+        let quarter = self.n / 4;
+        assert!(input.len() >= half && output.len() >= self.n);
+
+        // a DCT-IV of the coefficients, as a complex FFT of half its length
+        for (k, value) in self.spectrum.iter_mut().enumerate() {
+            *value = Complex { r: input[2 * k], i: input[half - 1 - 2 * k] } * self.pre_rotation[k];
+        }
+        self.fft.process_with_scratch(&mut self.spectrum, &mut self.scratch);
+        let dct = &mut self.dct;
+        for (k, value) in self.spectrum.iter().enumerate() {
+            let value = *value * self.post_rotation[k];
+            dct[2 * k] = value.r;
+            dct[half - 1 - 2 * k] = -value.i;
+        }
+
+        // the IMDCT output is the DCT-IV output unfolded with its odd and even symmetries
+        output[..quarter].copy_from_slice(&dct[quarter..2 * quarter]);
+        for i in quarter..3 * quarter {
+            output[i] = -dct[3 * quarter - 1 - i];
+        }
+        for i in 3 * quarter..self.n {
+            output[i] = -dct[i - 3 * quarter];
+        }
+    }
+}
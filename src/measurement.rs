@@ -0,0 +1,142 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use cpal::{default_host, traits::{DeviceTrait, HostTrait, StreamTrait}, Device, InputCallbackInfo, OutputCallbackInfo, SampleRate, StreamConfig};
+
+use octave::file_io::{write_wav_file, SpeakerPos, WavWriteInfo};
+use octave::sweep::{ExponentialSweep, ImpulseResponse};
+
+// recorded after the sweep ends, for the latency and the decay of whatever it was played through
+const TAIL_SECS: f32 = 2.;
+// sample rates tried, in order, until both the input and output support one
+const SWEEP_SAMPLE_RATES: [u32; 2] = [48000, 44100];
+
+// A finished sweep measurement, and the file its impulse response was saved to
+pub struct SweepMeasurement {
+    pub sweep: ExponentialSweep,
+    pub ir: ImpulseResponse,
+    pub file: String,
+}
+
+// Plays a sweep out of the default output while recording the first channel of the default input,
+// then deconvolves the recording into an impulse response and saves it to ./res/audio
+pub fn measure_sweep(start_freq: f32, end_freq: f32, duration: f32, level_db: f32) -> Result<SweepMeasurement, String> {
+    let host = default_host();
+    let output = host.default_output_device().ok_or("No output device available!")?;
+    let input = host.default_input_device().ok_or("No input device available!")?;
+
+    let (sample_rate, output_config, input_config) = SWEEP_SAMPLE_RATES
+        .iter()
+        .find_map(|&sr| Some((sr, output_config(&output, sr)?, input_config(&input, sr)?)))
+        .ok_or("The input and output devices don't share a sample rate of 48kHz or 44.1kHz!")?;
+
+    let sweep = ExponentialSweep::new(sample_rate, start_freq, end_freq, duration, 10f32.powf(level_db / 20.))
+        .map_err(|err| format!("Could not make sweep: {}", err))?;
+    let recording = record_sweep(&sweep, &output, &output_config, &input, &input_config)?;
+    let ir = sweep.deconvolve(&recording);
+
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let file = format!("sweep_ir_{}.wav", secs);
+    let format = WavWriteInfo {
+        sample_type: 3,
+        channels: 1,
+        sample_rate,
+        bit_depth: 32,
+        channel_mapping: vec![(0, SpeakerPos::FrontLeft)],
+        ..Default::default()
+    };
+    let measurement = SweepMeasurement { sweep, ir, file };
+    write_wav_file(measurement.file.clone(), &format, &[measurement.saved_ir().to_vec()])
+        .map_err(|err| format!("Could not save impulse response: {}", err))?;
+
+    Ok(measurement)
+}
+
+impl SweepMeasurement {
+    // everything from just before the linear response to TAIL_SECS after its peak, what gets saved
+    pub fn saved_ir(&self) -> &[f32] {
+        let linear = self.ir.linear();
+        &linear[..linear.len().min(self.ir.latency + (TAIL_SECS * self.ir.sample_rate as f32) as usize)]
+    }
+}
+
+fn output_config(device: &Device, sample_rate: u32) -> Option<StreamConfig> {
+    device.supported_output_configs().ok()?
+        .find(|c| c.min_sample_rate() <= SampleRate(sample_rate) && c.max_sample_rate() >= SampleRate(sample_rate))
+        .map(|c| c.with_sample_rate(SampleRate(sample_rate)).config())
+}
+
+fn input_config(device: &Device, sample_rate: u32) -> Option<StreamConfig> {
+    device.supported_input_configs().ok()?
+        .find(|c| c.min_sample_rate() <= SampleRate(sample_rate) && c.max_sample_rate() >= SampleRate(sample_rate))
+        .map(|c| c.with_sample_rate(SampleRate(sample_rate)).config())
+}
+
+// the first input channel, from before the sweep starts until TAIL_SECS after it ends
+fn record_sweep(
+    sweep: &ExponentialSweep,
+    output: &Device, output_config: &StreamConfig,
+    input: &Device, input_config: &StreamConfig,
+) -> Result<Vec<f32>, String> {
+    let total_len = sweep.signal().len() + (TAIL_SECS * sweep.sample_rate as f32) as usize;
+    let recording = Arc::new(Mutex::new(Vec::with_capacity(total_len)));
+    // the first error either stream reports, the measurement stops on it
+    let stream_error: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+
+    let recording_copy = Arc::clone(&recording);
+    let error_copy = Arc::clone(&stream_error);
+    let input_channels = input_config.channels as usize;
+    let input_stream = input.build_input_stream(
+        input_config,
+        move |data: &[f32], _: &InputCallbackInfo| {
+            let mut recording = recording_copy.lock().unwrap();
+            let remaining = total_len - recording.len();
+            recording.extend(data.iter().step_by(input_channels).take(remaining));
+        },
+        move |err| {
+            error_copy.lock().unwrap().get_or_insert(format!("Input failed: {}", err));
+        },
+        None
+    ).map_err(|err| format!("Could not open input: {}", err))?;
+
+    // the same sweep on every channel
+    let signal = sweep.signal().to_vec();
+    let output_channels = output_config.channels as usize;
+    let mut pos = 0;
+    let error_copy = Arc::clone(&stream_error);
+    let output_stream = output.build_output_stream(
+        output_config,
+        move |data: &mut [f32], _: &OutputCallbackInfo| {
+            for frame in data.chunks_mut(output_channels) {
+                frame.fill(signal.get(pos).copied().unwrap_or(0.));
+                pos += 1;
+            }
+        },
+        move |err| {
+            error_copy.lock().unwrap().get_or_insert(format!("Output failed: {}", err));
+        },
+        None
+    ).map_err(|err| format!("Could not open output: {}", err))?;
+
+    // recording first, so the start of the sweep can't be missed
+    input_stream.play().map_err(|err| format!("Could not start input: {}", err))?;
+    output_stream.play().map_err(|err| format!("Could not start output: {}", err))?;
+
+    let timeout = Duration::from_secs_f32(sweep.duration + TAIL_SECS + 5.);
+    let start = Instant::now();
+    while recording.lock().unwrap().len() < total_len {
+        if let Some(err) = stream_error.lock().unwrap().take() {
+            return Err(err);
+        }
+        if start.elapsed() > timeout {
+            return Err("Timed out waiting for the input device!".into());
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    drop(output_stream);
+    drop(input_stream);
+
+    let recording = recording.lock().unwrap().clone();
+    Ok(recording)
+}
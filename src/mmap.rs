@@ -0,0 +1,284 @@
+use std::fs::File;
+use std::io::{self, Cursor};
+use std::ops::{Deref, Range};
+use std::path::Path;
+
+use crate::audio_source::AudioSource;
+use crate::file_io::{read_wav_meta, WavError, WavInfo};
+use crate::lookup_tables::*;
+
+// A read-only map of a whole file. The file must not be truncated while it is mapped,
+// which would turn reads past the new end into a SIGBUS
+pub struct Mmap {
+    ptr: *const u8,
+    len: usize,
+}
+
+// the mapping is read-only, so it can be shared like a &[u8]
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+#[cfg(unix)]
+impl Mmap {
+    pub fn map(file: &File) -> io::Result<Self> {
+        use std::os::unix::io::AsRawFd;
+
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "File is too large to be mapped"))?;
+        // mmap doesn't take empty ranges
+        if len == 0 {
+            return Ok(Self { ptr: std::ptr::NonNull::dangling().as_ptr(), len });
+        }
+        let ptr = unsafe { libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ, libc::MAP_PRIVATE, file.as_raw_fd(), 0) };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { ptr: ptr as *const u8, len })
+    }
+}
+
+// without mmap the callers fall back to reading the file
+#[cfg(not(unix))]
+impl Mmap {
+    pub fn map(_file: &File) -> io::Result<Self> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "Memory mapped files are only supported on unix"))
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if self.len > 0 {
+            unsafe { libc::munmap(self.ptr as *mut libc::c_void, self.len) };
+        }
+    }
+}
+
+// The samples of a data chunk as they are stored, one entry per sample.
+// Byte arrays instead of integers, so the view works at any alignment and byte order
+#[derive(Clone, Copy, Debug)]
+pub enum Samples<'a> {
+    Pcm8(&'a [u8]), // offset binary, unless the view is signed_8bit
+    Pcm16(&'a [[u8; 2]]),
+    Pcm24(&'a [[u8; 3]]),
+    Pcm32(&'a [[u8; 4]]),
+    Float32(&'a [[u8; 4]]),
+    Float64(&'a [[u8; 8]]),
+    ALaw(&'a [u8]),
+    MuLaw(&'a [u8]),
+}
+
+// Random access to interleaved samples without copying them, converted to f32 the same way as read_data
+#[derive(Clone, Copy, Debug)]
+pub struct SampleView<'a> {
+    samples: Samples<'a>,
+    channels: usize,
+    big_endian: bool,
+    signed_8bit: bool,
+}
+
+impl<'a> SampleView<'a> {
+    // a partial frame at the end of `data` is left out
+    pub fn new(data: &'a [u8], info: &WavInfo) -> Result<Self, WavError> {
+        let channels = info.channels as usize;
+        if channels == 0 {
+            return Err(WavError::InvalidHeader("File has no channels"));
+        }
+        let block_size = info.byte_depth as usize * channels;
+        let data = &data[..data.len() / block_size.max(1) * block_size];
+        let samples = match (info.sample_type, info.bit_depth) {
+            (1, 8) => Samples::Pcm8(data),
+            (1, 16) => Samples::Pcm16(data.as_chunks().0),
+            (1, 24) => Samples::Pcm24(data.as_chunks().0),
+            (1, 32) => Samples::Pcm32(data.as_chunks().0),
+            (3, 32) => Samples::Float32(data.as_chunks().0),
+            (3, 64) => Samples::Float64(data.as_chunks().0),
+            (6, 8) => Samples::ALaw(data),
+            (7, 8) => Samples::MuLaw(data),
+            (sample_type, bit_depth) => {
+                return Err(WavError::UnsupportedBitDepth { sample_type: sample_type as u16, bit_depth })
+            }
+        };
+        Ok(Self { samples, channels, big_endian: info.big_endian, signed_8bit: info.signed_8bit })
+    }
+
+    pub fn samples(&self) -> Samples<'a> {
+        self.samples
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    // only matters for the multi-byte sample types
+    pub fn big_endian(&self) -> bool {
+        self.big_endian
+    }
+
+    pub fn num_frames(&self) -> usize {
+        let len = match self.samples {
+            Samples::Pcm8(s) | Samples::ALaw(s) | Samples::MuLaw(s) => s.len(),
+            Samples::Pcm16(s) => s.len(),
+            Samples::Pcm24(s) => s.len(),
+            Samples::Pcm32(s) | Samples::Float32(s) => s.len(),
+            Samples::Float64(s) => s.len(),
+        };
+        len / self.channels
+    }
+
+    // panics if the frame or channel is out of range
+    pub fn get(&self, frame: usize, channel: usize) -> f32 {
+        assert!(channel < self.channels, "channel {} out of range", channel);
+        self.sample(frame * self.channels + channel)
+    }
+
+    // the sample at `index` of the interleaved data
+    fn sample(&self, index: usize) -> f32 {
+        let big_endian = self.big_endian;
+        match self.samples {
+            Samples::Pcm8(s) => {
+                let value = (s[index] ^ if self.signed_8bit { 0x80 } else { 0 }) as i32 - 128;
+                value as f32 / if value < 0 { PCM_8BIT_NEG_MAX } else { PCM_8BIT_POS_MAX }
+            }
+            Samples::Pcm16(s) => {
+                let value = if big_endian { i16::from_be_bytes(s[index]) } else { i16::from_le_bytes(s[index]) } as f32;
+                value / if value < 0. { PCM_16BIT_NEG_MAX } else { PCM_16BIT_POS_MAX }
+            }
+            Samples::Pcm24(s) => {
+                let [b0, b1, b2] = s[index];
+                let bytes = if big_endian { [0, b2, b1, b0] } else { [0, b0, b1, b2] };
+                let value = (i32::from_le_bytes(bytes) >> 8) as f32;
+                value / if value < 0. { PCM_24BIT_NEG_MAX } else { PCM_24BIT_POS_MAX }
+            }
+            Samples::Pcm32(s) => {
+                let value = if big_endian { i32::from_be_bytes(s[index]) } else { i32::from_le_bytes(s[index]) } as f32;
+                value / if value < 0. { PCM_32BIT_NEG_MAX } else { PCM_32BIT_POS_MAX }
+            }
+            Samples::Float32(s) => if big_endian { f32::from_be_bytes(s[index]) } else { f32::from_le_bytes(s[index]) },
+            Samples::Float64(s) => (if big_endian { f64::from_be_bytes(s[index]) } else { f64::from_le_bytes(s[index]) }) as f32,
+            Samples::ALaw(s) => ALAW_TO_PCM[s[index] as usize],
+            Samples::MuLaw(s) => ULAW_TO_PCM[s[index] as usize],
+        }
+    }
+
+    // fills `out` with interleaved frames from `start_frame` on, returns the number of frames written
+    pub fn read_interleaved(&self, start_frame: usize, out: &mut [f32]) -> usize {
+        let frames = (out.len() / self.channels).min(self.num_frames().saturating_sub(start_frame));
+        let start = start_frame * self.channels;
+        for (i, out) in out[..frames * self.channels].iter_mut().enumerate() {
+            *out = self.sample(start + i);
+        }
+        frames
+    }
+
+    // fills `out` with one channel from `start_frame` on, returns the number of frames written
+    pub fn read_channel(&self, channel: usize, start_frame: usize, out: &mut [f32]) -> usize {
+        assert!(channel < self.channels, "channel {} out of range", channel);
+        let frames = out.len().min(self.num_frames().saturating_sub(start_frame));
+        for (i, out) in out[..frames].iter_mut().enumerate() {
+            *out = self.sample((start_frame + i) * self.channels + channel);
+        }
+        frames
+    }
+}
+
+// Reads uncompressed files straight from a memory map, so seeking is free and
+// nothing is decoded until it is asked for
+pub struct MmapReader {
+    map: Mmap,
+    info: WavInfo,
+    data: Range<usize>, // the part of the map holding whole frames
+    frame_pos: u64,
+    block_size: usize, // in frames
+}
+
+impl MmapReader {
+    // fails for compressed formats, which have to go through open_audio
+    pub fn open<P: AsRef<Path>>(path: P, block_size: usize) -> Result<Self, WavError> {
+        if block_size == 0 {
+            return Err(WavError::InvalidInput("Block size must be at least 1 frame!"));
+        }
+        let map = Mmap::map(&File::open(path)?)?;
+        let info = read_wav_meta(&mut Cursor::new(&map[..]))?;
+        if info.format.is_compressed() {
+            return Err(WavError::InvalidInput("Only uncompressed files can be read from a memory map!"));
+        }
+
+        let (data_start, data_size) = *info.chunks.get("data").ok_or(WavError::MissingChunk("data"))?;
+        let start = usize::min(data_start as usize, map.len());
+        let end = usize::min(start.saturating_add(data_size as usize), map.len());
+        let frames = SampleView::new(&map[start..end], &info)?.num_frames();
+        Ok(Self {
+            data: start..start + frames * info.data_block_size as usize,
+            map,
+            info,
+            frame_pos: 0,
+            block_size,
+        })
+    }
+
+    pub fn samples(&self) -> SampleView<'_> {
+        // the format was checked when the reader was opened
+        SampleView::new(&self.map[self.data.clone()], &self.info).unwrap()
+    }
+}
+
+impl AudioSource for MmapReader {
+    fn info(&self) -> &WavInfo {
+        &self.info
+    }
+
+    fn num_frames(&self) -> u64 {
+        (self.data.len() / self.info.data_block_size as usize) as u64
+    }
+
+    fn position(&self) -> u64 {
+        self.frame_pos
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn set_block_size(&mut self, block_size: usize) -> Result<(), WavError> {
+        if block_size == 0 {
+            return Err(WavError::InvalidInput("Block size must be at least 1 frame!"));
+        }
+        self.block_size = block_size;
+        Ok(())
+    }
+
+    fn seek_frame(&mut self, frame: u64) -> Result<(), WavError> {
+        if frame > self.num_frames() {
+            return Err(WavError::InvalidInput("Can't seek past the end of the audio data!"));
+        }
+        self.frame_pos = frame;
+        Ok(())
+    }
+
+    fn read_interleaved(&mut self, out: &mut [f32]) -> Result<usize, WavError> {
+        let frames = self.samples().read_interleaved(self.frame_pos as usize, out);
+        self.frame_pos += frames as u64;
+        Ok(frames)
+    }
+
+    fn read_block(&mut self, block: &mut Vec<Vec<f32>>) -> Result<usize, WavError> {
+        let view = self.samples();
+        let frames = u64::min(self.block_size as u64, self.num_frames() - self.frame_pos) as usize;
+        block.resize(view.channels(), vec![]);
+        for (c, ch) in block.iter_mut().enumerate() {
+            ch.resize(frames, 0.);
+            view.read_channel(c, self.frame_pos as usize, ch);
+        }
+        self.frame_pos += frames as u64;
+        Ok(frames)
+    }
+}
@@ -0,0 +1,1221 @@
+use std::array;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::Arc;
+
+use crate::audio_source::{AudioFormat, AudioSource};
+use crate::file_io::{read_data_interleaved_unchecked, SpeakerPos, WavError, WavInfo};
+use crate::mp3_tables::{
+    HuffmanTable, BIG_VALUE_TABLES, BITRATES, LONG_BAND_WIDTHS, LSF_PARTITIONS, PRETAB, QUAD_TABLES, SAMPLE_RATES,
+    SCALEFACTOR_LENGTHS, SHORT_BAND_WIDTHS, SYNTHESIS_WINDOW,
+};
+
+// frequency lines (and output samples) per channel in a granule, MPEG-1 frames hold two granules, MPEG-2 frames one
+const GRANULE_SAMPLES: usize = 576;
+// delay of the decoder's filterbanks, which LAME leaves out of the encoder delay it stores
+const DECODER_DELAY: u64 = 529;
+// the furthest back a frame can start its main data, in bytes
+const MAX_RESERVOIR: usize = 511;
+const READ_BUFFER_SIZE: usize = 1 << 16;
+
+// the antialias butterfly coefficients c[i] from the standard
+const ANTIALIAS_COEFFICIENTS: [f64; 8] = [-0.6, -0.535, -0.33, -0.185, -0.095, -0.041, -0.0142, -0.0037];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MpegVersion {
+    Mpeg1,
+    Mpeg2,
+    Mpeg25,
+}
+
+impl fmt::Display for MpegVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Mpeg1 => "MPEG-1",
+            Self::Mpeg2 => "MPEG-2",
+            Self::Mpeg25 => "MPEG-2.5",
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mp3StreamInfo {
+    pub version: MpegVersion,
+    pub sample_rate: u32,
+    pub channels: u8,
+    pub bitrate: u32, // average over all frames, in kbit/s
+    pub frames: u64, // audio frames, not counting a Xing/Info frame
+    pub encoder_delay: u32, // from the LAME tag, both 0 if there is no gapless info
+    pub encoder_padding: u32,
+    pub total_samples: u64, // per channel, with the delay and padding removed
+    frame_index: Arc<FrameIndex>, // kept so decoders don't have to scan the stream again
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct FrameHeader {
+    version: MpegVersion,
+    rate_index: usize, // into SAMPLE_RATES and the band tables
+    bitrate: u32, // kbit/s
+    padding: bool,
+    crc: bool,
+    mode: u32, // 0 stereo, 1 joint stereo, 2 dual channel, 3 mono
+    mode_extension: u32, // bit 1 mid/side stereo, bit 0 intensity stereo
+}
+
+impl FrameHeader {
+    // only Layer III headers are accepted, free format streams aren't supported
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        let header = u32::from_be_bytes(bytes.get(..4)?.try_into().ok()?);
+        if header >> 21 != 0x7FF || (header >> 17) & 0x3 != 1 {
+            return None;
+        }
+        let version = match (header >> 19) & 0x3 {
+            0 => MpegVersion::Mpeg25,
+            2 => MpegVersion::Mpeg2,
+            3 => MpegVersion::Mpeg1,
+            _ => return None,
+        };
+        let bitrate_index = (header >> 12) as usize & 0xF;
+        let rate = (header >> 10) as usize & 0x3;
+        if bitrate_index == 0 || bitrate_index == 15 || rate == 3 {
+            return None;
+        }
+        let (table, first_rate) = match version {
+            MpegVersion::Mpeg1 => (0, 0),
+            MpegVersion::Mpeg2 => (1, 3),
+            MpegVersion::Mpeg25 => (1, 6),
+        };
+        Some(Self {
+            version,
+            rate_index: first_rate + rate,
+            bitrate: BITRATES[table][bitrate_index],
+            padding: header & 0x200 != 0,
+            crc: header & 0x10000 == 0,
+            mode: (header >> 6) & 0x3,
+            mode_extension: (header >> 4) & 0x3,
+        })
+    }
+
+    fn is_mpeg1(&self) -> bool {
+        self.version == MpegVersion::Mpeg1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATES[self.rate_index]
+    }
+
+    fn channels(&self) -> usize {
+        if self.mode == 3 { 1 } else { 2 }
+    }
+
+    fn granules(&self) -> usize {
+        if self.is_mpeg1() { 2 } else { 1 }
+    }
+
+    fn samples(&self) -> usize {
+        self.granules() * GRANULE_SAMPLES
+    }
+
+    fn frame_len(&self) -> usize {
+        let slots = if self.is_mpeg1() { 144_000 } else { 72_000 } * self.bitrate / self.sample_rate();
+        slots as usize + self.padding as usize
+    }
+
+    // where the side info starts and ends in the frame
+    fn side_info_range(&self) -> (usize, usize) {
+        let start = 4 + if self.crc { 2 } else { 0 };
+        let len = match (self.is_mpeg1(), self.channels()) {
+            (true, 1) => 17,
+            (true, _) => 32,
+            (false, 1) => 9,
+            (false, _) => 17,
+        };
+        (start, start + len)
+    }
+
+    // whether another header can belong to the same stream
+    fn same_stream(&self, other: &FrameHeader) -> bool {
+        self.version == other.version && self.rate_index == other.rate_index && self.channels() == other.channels()
+    }
+}
+
+// whether a file starts like an MP3 file, with an ID3v2 tag or a Layer III frame header
+pub fn is_mp3_start(header: &[u8]) -> bool {
+    header.starts_with(b"ID3") || FrameHeader::parse(header).is_some()
+}
+
+// where the audio frames of a stream are
+#[derive(PartialEq, Eq)]
+struct FrameIndex {
+    header: FrameHeader, // of the first audio frame
+    frames: Vec<(u64, usize)>, // (position, length) of every audio frame
+    start_skip: u64, // decoded samples that are dropped at the start for gapless playback
+}
+
+impl fmt::Debug for FrameIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // a long file has hundreds of thousands of frames
+        f.debug_struct("FrameIndex").field("frames", &self.frames.len()).finish_non_exhaustive()
+    }
+}
+
+// Steps through the frames of an MP3 stream, skipping ID3v2 tags and anything else that isn't a frame
+struct FrameScanner<'a, R: Read + Seek> {
+    input: ScanReader<'a, R>,
+    stream_len: u64,
+    pos: u64,
+    first: Option<FrameHeader>, // every later frame has to belong to the same stream
+    // right after a frame the next one is trusted, anywhere else a header has to be followed by another one
+    synced: bool,
+}
+
+impl<'a, R: Read + Seek> FrameScanner<'a, R> {
+    fn new(f: &'a mut R) -> Result<Self, WavError> {
+        let stream_len = f.seek(SeekFrom::End(0))?;
+        Ok(Self { input: ScanReader::new(f)?, stream_len, pos: 0, first: None, synced: false })
+    }
+
+    // (position, header, length) of the next frame, None at the end of the stream
+    fn next_frame(&mut self) -> Result<Option<(u64, FrameHeader, usize)>, WavError> {
+        while self.pos + 4 <= self.stream_len {
+            let pos = self.pos;
+            let bytes = self.input.bytes(pos, 10)?;
+            if bytes.len() == 10 && bytes.starts_with(b"ID3") {
+                // the tag size is a syncsafe integer (7 bits per byte), not counting the header and footer
+                let size = bytes[6..10].iter().fold(0, |size, &b| size << 7 | (b & 0x7F) as u64);
+                let footer = if bytes[5] & 0x10 != 0 { 10 } else { 0 };
+                self.pos += 10 + size + footer;
+                self.synced = false;
+                continue;
+            }
+
+            let first = self.first;
+            let Some(header) = FrameHeader::parse(bytes).filter(|h| first.is_none_or(|first| first.same_stream(h))) else {
+                self.pos += 1;
+                self.synced = false;
+                continue;
+            };
+            let len = header.frame_len();
+            if pos + len as u64 > self.stream_len {
+                // a frame cut off at the end of the file
+                self.pos += 1;
+                self.synced = false;
+                continue;
+            }
+            if !self.synced && pos + len as u64 + 4 <= self.stream_len {
+                let next = self.input.bytes(pos + len as u64, 4)?;
+                if !FrameHeader::parse(next).is_some_and(|next| header.same_stream(&next)) {
+                    self.pos += 1;
+                    continue;
+                }
+            }
+
+            self.first.get_or_insert(header);
+            self.pos += len as u64;
+            self.synced = true;
+            return Ok(Some((pos, header, len)));
+        }
+        Ok(None)
+    }
+}
+
+// Finds all frames of an MP3 stream. Indexing the whole file up front gives exact lengths
+// for VBR files and lets seeks go straight to the right frame.
+fn scan_stream<R: Read + Seek>(f: &mut R) -> Result<Mp3StreamInfo, WavError> {
+    let mut scanner = FrameScanner::new(f)?;
+    let mut frames = vec![];
+    let mut gapless = None;
+
+    let mut first = true;
+    while let Some((pos, header, len)) = scanner.next_frame()? {
+        // encoders put their Xing/Info tag in an otherwise empty first frame
+        if first {
+            first = false;
+            if let Some(tag) = read_xing_tag(scanner.input.bytes(pos, len)?, &header) {
+                gapless = tag;
+                continue;
+            }
+        }
+        frames.push((pos, len));
+    }
+
+    let header = scanner.first.ok_or(WavError::InvalidHeader("no MPEG Layer III frames found"))?;
+    if frames.is_empty() {
+        return Err(WavError::InvalidHeader("no MPEG Layer III frames found"));
+    }
+    let decoded = frames.len() as u64 * header.samples() as u64;
+    let (encoder_delay, encoder_padding) = gapless.unwrap_or((0, 0));
+    let start_skip = if gapless.is_some() { encoder_delay as u64 + DECODER_DELAY } else { 0 };
+    let total_samples = u64::min(
+        decoded.saturating_sub(encoder_delay as u64 + encoder_padding as u64),
+        decoded.saturating_sub(start_skip),
+    );
+    let bytes: u64 = frames.iter().map(|&(_, len)| len as u64).sum();
+
+    Ok(Mp3StreamInfo {
+        version: header.version,
+        sample_rate: header.sample_rate(),
+        channels: header.channels() as u8,
+        bitrate: ((bytes * 8 * header.sample_rate() as u64) as f64 / decoded as f64 / 1000.).round() as u32,
+        frames: frames.len() as u64,
+        encoder_delay,
+        encoder_padding,
+        total_samples,
+        frame_index: Arc::new(FrameIndex { header, frames, start_skip }),
+    })
+}
+
+// The sample rate of the first frame, without indexing the rest of the stream
+pub fn read_mp3_sample_rate<R: Read + Seek>(f: &mut R) -> Result<u32, WavError> {
+    let (_, header, _) = FrameScanner::new(f)?.next_frame()?.ok_or(WavError::InvalidHeader("no MPEG Layer III frames found"))?;
+    Ok(header.sample_rate())
+}
+
+// Reads the Xing/Info (or VBRI) tag of a frame. Returns None if the frame holds audio,
+// otherwise the encoder delay and padding of the LAME extension, if there is one.
+fn read_xing_tag(frame: &[u8], header: &FrameHeader) -> Option<Option<(u32, u32)>> {
+    if frame.get(36..40) == Some(b"VBRI") {
+        return Some(None);
+    }
+    let (_, mut pos) = header.side_info_range();
+    let tag = frame.get(pos..pos + 8)?;
+    if &tag[..4] != b"Xing" && &tag[..4] != b"Info" {
+        return None;
+    }
+    // the frame count, byte count, seek table and quality fields are only there if their flag is set
+    let flags = u32::from_be_bytes(tag[4..8].try_into().unwrap());
+    pos += 8;
+    for (flag, len) in [(0x1, 4), (0x2, 4), (0x4, 100), (0x8, 4)] {
+        if flags & flag != 0 {
+            pos += len;
+        }
+    }
+
+    // the encoder version is followed by the delay and padding (12 bits each) at byte 21
+    let lame = frame.get(pos..pos + 24);
+    let gapless = lame
+        .filter(|lame| [b"LAME", b"Lavf", b"Lavc"].iter().any(|encoder| lame.starts_with(*encoder)))
+        .map(|lame| {
+            let delay = (lame[21] as u32) << 4 | (lame[22] as u32) >> 4;
+            let padding = (lame[22] as u32 & 0xF) << 8 | lame[23] as u32;
+            (delay, padding)
+        });
+    Some(gapless)
+}
+
+// Scans an MP3 file into a WavInfo. Like for FLAC files, the "data" chunk entry is the size
+// of the decoded samples (32-bit float) in the wav layout, which Mp3Decoder produces.
+pub fn read_mp3_meta<R: Read + Seek>(f: &mut R) -> Result<WavInfo, WavError> {
+    Ok(read_mp3_stream(f)?.0)
+}
+
+// read_mp3_meta, along with the stream info a decoder needs
+fn read_mp3_stream<R: Read + Seek>(f: &mut R) -> Result<(WavInfo, Mp3StreamInfo), WavError> {
+    let file_len = f.seek(SeekFrom::End(0))?;
+    let stream_info = scan_stream(f)?;
+    let channels = stream_info.channels;
+
+    let mut chunks = HashMap::new();
+    chunks.insert("data".to_string(), (stream_info.frame_index.frames[0].0, stream_info.total_samples * 4 * channels as u64));
+    let channel_map = (0..channels).map(|c| (c, SpeakerPos::from(1u32 << c))).collect();
+
+    f.seek(SeekFrom::Start(0))?;
+    let mut info = WavInfo::new(3, channels, stream_info.sample_rate, 32, file_len, chunks, channel_map);
+    info.sample_type_str = format!("{} Layer III", stream_info.version);
+    info.format = AudioFormat::Mp3;
+    Ok((info, stream_info))
+}
+
+// reads a stream front to back in large chunks, keeping what was read since the last
+// position far enough back in memory
+struct ScanReader<'a, R: Read + Seek> {
+    inner: &'a mut R,
+    buf: Vec<u8>,
+    start: u64, // stream position of buf[0]
+}
+
+impl<'a, R: Read + Seek> ScanReader<'a, R> {
+    fn new(inner: &'a mut R) -> Result<Self, WavError> {
+        inner.seek(SeekFrom::Start(0))?;
+        Ok(Self { inner, buf: vec![], start: 0 })
+    }
+
+    // the `len` bytes at `pos`, fewer at the end of the stream
+    fn bytes(&mut self, pos: u64, len: usize) -> Result<&[u8], WavError> {
+        if pos < self.start || pos > self.start + self.buf.len() as u64 {
+            self.inner.seek(SeekFrom::Start(pos))?;
+            self.buf.clear();
+            self.start = pos;
+        } else if pos - self.start >= 2 * READ_BUFFER_SIZE as u64 {
+            // frames are never longer than a few kB, so that much is kept for looking back
+            let drop = (pos - self.start) as usize - READ_BUFFER_SIZE;
+            self.buf.drain(..drop);
+            self.start += drop as u64;
+        }
+
+        let offset = (pos - self.start) as usize;
+        while self.buf.len() < offset + len {
+            let filled = self.buf.len();
+            self.buf.resize(filled + READ_BUFFER_SIZE, 0);
+            let read = self.inner.read(&mut self.buf[filled..])?;
+            self.buf.truncate(filled + read);
+            if read == 0 {
+                break;
+            }
+        }
+        Ok(&self.buf[offset.min(self.buf.len())..(offset + len).min(self.buf.len())])
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct GranuleChannel {
+    part2_3_length: usize, // bits of scalefactors and Huffman data
+    big_values: usize,
+    global_gain: i32,
+    scalefac_compress: usize,
+    block_type: usize, // 0 normal, 1 start, 2 short, 3 stop
+    mixed: bool, // the lowest two subbands of a short block are long
+    table_select: [usize; 3],
+    subblock_gain: [i32; 3],
+    region_counts: [usize; 2], // scalefactor bands in region 0 and 1, minus one
+    preflag: bool,
+    scalefac_scale: bool,
+    count1_table: usize,
+}
+
+#[derive(Default)]
+struct SideInfo {
+    main_data_begin: usize,
+    scfsi: [[bool; 4]; 2],
+    granules: [[GranuleChannel; 2]; 2],
+}
+
+fn read_side_info(header: &FrameHeader, bits: &mut BitSlice) -> Option<SideInfo> {
+    let mut side = SideInfo::default();
+    let channels = header.channels();
+    if header.is_mpeg1() {
+        side.main_data_begin = bits.read(9) as usize;
+        bits.read(if channels == 1 { 5 } else { 3 });
+        for scfsi in &mut side.scfsi[..channels] {
+            for band in scfsi.iter_mut() {
+                *band = bits.read(1) == 1;
+            }
+        }
+    } else {
+        side.main_data_begin = bits.read(8) as usize;
+        bits.read(if channels == 1 { 1 } else { 2 });
+    }
+
+    for granule in &mut side.granules[..header.granules()] {
+        for g in &mut granule[..channels] {
+            g.part2_3_length = bits.read(12) as usize;
+            g.big_values = bits.read(9) as usize;
+            if g.big_values > GRANULE_SAMPLES / 2 {
+                return None;
+            }
+            g.global_gain = bits.read(8) as i32;
+            g.scalefac_compress = bits.read(if header.is_mpeg1() { 4 } else { 9 }) as usize;
+            if bits.read(1) == 1 {
+                // window switching, the region boundaries are implied by the block type
+                g.block_type = bits.read(2) as usize;
+                if g.block_type == 0 {
+                    return None;
+                }
+                g.mixed = bits.read(1) == 1;
+                for select in &mut g.table_select[..2] {
+                    *select = bits.read(5) as usize;
+                }
+                for gain in &mut g.subblock_gain {
+                    *gain = bits.read(3) as i32;
+                }
+                g.region_counts = [if g.block_type == 2 && !g.mixed { 8 } else { 7 }, GRANULE_SAMPLES];
+            } else {
+                for select in &mut g.table_select {
+                    *select = bits.read(5) as usize;
+                }
+                g.region_counts = [bits.read(4) as usize, bits.read(3) as usize];
+            }
+            // MPEG-2 derives the preflag from scalefac_compress
+            g.preflag = header.is_mpeg1() && bits.read(1) == 1;
+            g.scalefac_scale = bits.read(1) == 1;
+            g.count1_table = bits.read(1) as usize;
+        }
+    }
+    Some(side)
+}
+
+// a scalefactor band as it is laid out in the spectrum of a granule
+#[derive(Clone, Copy)]
+struct Band {
+    start: usize,
+    end: usize,
+    window: Option<usize>, // None for long bands
+    freq: usize, // index of the first line within its window, for short bands
+}
+
+// the bands of long, short and mixed blocks in the order their scalefactors are stored
+fn band_layouts(rate_index: usize) -> [Vec<Band>; 3] {
+    let long_bands = |limit: usize| {
+        let mut bands = vec![];
+        let mut start = 0;
+        for &width in &LONG_BAND_WIDTHS[rate_index] {
+            if start >= limit {
+                break;
+            }
+            bands.push(Band { start, end: start + width as usize, window: None, freq: 0 });
+            start += width as usize;
+        }
+        bands
+    };
+    // short bands from the given line of each window on, splitting the band it falls into
+    let short_bands = |from: usize| {
+        let mut bands = vec![];
+        let mut freq = 0;
+        for &width in &SHORT_BAND_WIDTHS[rate_index] {
+            let (band_start, band_end) = (usize::max(freq, from), freq + width as usize);
+            freq = band_end;
+            if band_end <= from {
+                continue;
+            }
+            for window in 0..3 {
+                let width = band_end - band_start;
+                let start = 3 * band_start + window * width;
+                bands.push(Band { start, end: start + width, window: Some(window), freq: band_start });
+            }
+        }
+        bands
+    };
+
+    // the long part of mixed blocks is the lowest two subbands (36 lines)
+    let mut mixed = long_bands(36);
+    let mixed_end = mixed.last().unwrap().end;
+    mixed.extend(short_bands(mixed_end / 3));
+    [long_bands(GRANULE_SAMPLES), short_bands(0), mixed]
+}
+
+// lookup tables that every decoder computes once
+struct Tables {
+    big_value_trees: Vec<HuffmanTree>, // by table_select
+    quad_trees: [HuffmanTree; 2],
+    pow43: Vec<f32>, // |x|^(4/3) of the Huffman coded values
+    imdct_long: Vec<f32>, // 36 x 18
+    imdct_short: Vec<f32>, // 12 x 6
+    windows: [[f32; 36]; 4], // by block type, the short window only uses 12
+    antialias: [(f32, f32); 8], // (cs, ca)
+    synthesis_cos: Vec<f32>, // 64 x 32 matrixing coefficients
+    synthesis_window: Vec<f32>,
+    layouts: [Vec<Band>; 3],
+}
+
+impl Tables {
+    fn new(rate_index: usize) -> Self {
+        let sine = |i: usize, n: f64| (PI / n * (i as f64 + 0.5)).sin() as f32;
+        let windows = [
+            array::from_fn(|i| sine(i, 36.)),
+            array::from_fn(|i| match i {
+                0..=17 => sine(i, 36.),
+                18..=23 => 1.,
+                24..=29 => sine(i - 18, 12.),
+                _ => 0.,
+            }),
+            array::from_fn(|i| if i < 12 { sine(i, 12.) } else { 0. }),
+            array::from_fn(|i| match i {
+                0..=5 => 0.,
+                6..=11 => sine(i - 6, 12.),
+                12..=17 => 1.,
+                _ => sine(i, 36.),
+            }),
+        ];
+
+        let imdct = |n: usize| {
+            (0..n)
+                .flat_map(|i| (0..n / 2).map(move |k| (PI / (2 * n) as f64 * ((2 * i + 1 + n / 2) * (2 * k + 1)) as f64).cos() as f32))
+                .collect()
+        };
+        let antialias = ANTIALIAS_COEFFICIENTS.map(|c| {
+            let norm = (1. + c * c).sqrt();
+            ((1. / norm) as f32, (c / norm) as f32)
+        });
+
+        Self {
+            big_value_trees: BIG_VALUE_TABLES.iter().map(HuffmanTree::new).collect(),
+            quad_trees: [HuffmanTree::new(&QUAD_TABLES[0]), HuffmanTree::new(&QUAD_TABLES[1])],
+            pow43: (0..8207).map(|x| (x as f64).powf(4. / 3.) as f32).collect(),
+            imdct_long: imdct(36),
+            imdct_short: imdct(12),
+            windows,
+            antialias,
+            synthesis_cos: (0..64)
+                .flat_map(|i| (0..32).map(move |k| ((16 + i) as f64 * (2 * k + 1) as f64 * PI / 64.).cos() as f32))
+                .collect(),
+            synthesis_window: SYNTHESIS_WINDOW.iter().map(|&d| d as f32 / 65536.).collect(),
+            layouts: band_layouts(rate_index),
+        }
+    }
+}
+
+// binary decoding tree of a Huffman table
+struct HuffmanTree {
+    nodes: Vec<[i32; 2]>, // children: > 0 another node, < 0 a leaf holding !index, 0 no code
+}
+
+impl HuffmanTree {
+    fn new(table: &HuffmanTable) -> Self {
+        let mut nodes = vec![[0; 2]];
+        for (index, (&code, &len)) in table.codes.iter().zip(table.lengths).enumerate() {
+            let mut node = 0;
+            for bit in (0..len).rev() {
+                let branch = (code as u32 >> bit) as usize & 1;
+                if bit == 0 {
+                    nodes[node][branch] = !(index as i32);
+                } else {
+                    if nodes[node][branch] <= 0 {
+                        nodes.push([0; 2]);
+                        nodes[node][branch] = nodes.len() as i32 - 1;
+                    }
+                    node = nodes[node][branch] as usize;
+                }
+            }
+        }
+        Self { nodes }
+    }
+
+    fn decode(&self, bits: &mut BitSlice) -> Option<usize> {
+        let mut node = 0;
+        loop {
+            match self.nodes[node][bits.read(1) as usize] {
+                0 => return None,
+                child if child < 0 => return Some(!child as usize),
+                child => node = child as usize,
+            }
+        }
+    }
+}
+
+// reads bits MSB first from a byte slice, past the end it reads zeros
+struct BitSlice<'a> {
+    data: &'a [u8],
+    pos: usize, // in bits
+}
+
+impl<'a> BitSlice<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn read(&mut self, n: u32) -> u32 {
+        let mut value = 0;
+        for _ in 0..n {
+            let byte = self.data.get(self.pos / 8).copied().unwrap_or(0);
+            value = value << 1 | (byte >> (7 - self.pos % 8)) as u32 & 1;
+            self.pos += 1;
+        }
+        value
+    }
+}
+
+// the polyphase synthesis filterbank of one channel
+struct Synthesis {
+    v: [f32; 1024],
+    offset: usize, // where the newest 64 values start in v
+}
+
+impl Synthesis {
+    fn new() -> Self {
+        Self { v: [0.; 1024], offset: 0 }
+    }
+
+    // turns the 18 time slots of 32 subbands (laid out subband by subband) into 576 samples
+    fn synthesize(&mut self, tables: &Tables, subbands: &[f32; GRANULE_SAMPLES], out: &mut [f32; GRANULE_SAMPLES]) {
+        let d = &tables.synthesis_window;
+        for slot in 0..18 {
+            self.offset = (self.offset + 1024 - 64) % 1024;
+            for i in 0..64 {
+                let cos = &tables.synthesis_cos[i * 32..i * 32 + 32];
+                self.v[self.offset + i] = (0..32).map(|k| cos[k] * subbands[k * 18 + slot]).sum();
+            }
+            for j in 0..32 {
+                let mut sum = 0.;
+                for m in 0..8 {
+                    sum += d[64 * m + j] * self.v[(self.offset + 128 * m + j) % 1024];
+                    sum += d[64 * m + 32 + j] * self.v[(self.offset + 128 * m + 96 + j) % 1024];
+                }
+                out[slot * 32 + j] = sum;
+            }
+        }
+    }
+}
+
+// Decodes an MPEG-1/2/2.5 Layer III stream into interleaved 32-bit float samples in the wav
+// byte layout, dropping the encoder delay and padding when there is a LAME tag.
+pub struct Mp3Decoder<R: Read + Seek> {
+    reader: R,
+    reader_pos: u64,
+    stream_info: Mp3StreamInfo,
+    index: Arc<FrameIndex>,
+    tables: Box<Tables>,
+    reservoir: Vec<u8>, // the end of the main data of the previous frames
+    frame: Vec<u8>,
+    overlap: [[f32; GRANULE_SAMPLES]; 2], // second halves of the last IMDCT outputs
+    synthesis: [Synthesis; 2],
+    pcm: Vec<f32>, // decoded samples of the current frame
+    frame_bytes: Vec<u8>, // the part of them that is output, in the wav layout
+    frame_pos: usize, // bytes of frame_bytes that have been read
+    next_frame: usize,
+    skip: u64, // samples still to drop before the output starts
+    remaining: u64, // samples still to output
+}
+
+impl<R: Read + Seek> Mp3Decoder<R> {
+    // the stream info has to come from a scan of the same stream, it holds the frame index
+    pub fn new(reader: R, stream_info: &Mp3StreamInfo) -> Self {
+        let index = Arc::clone(&stream_info.frame_index);
+        Self {
+            reader,
+            reader_pos: u64::MAX,
+            tables: Box::new(Tables::new(index.header.rate_index)),
+            skip: index.start_skip,
+            remaining: stream_info.total_samples,
+            stream_info: stream_info.clone(),
+            index,
+            reservoir: vec![],
+            frame: vec![],
+            overlap: [[0.; GRANULE_SAMPLES]; 2],
+            synthesis: [Synthesis::new(), Synthesis::new()],
+            pcm: vec![],
+            frame_bytes: vec![],
+            frame_pos: 0,
+            next_frame: 0,
+        }
+    }
+
+    pub fn stream_info(&self) -> &Mp3StreamInfo {
+        &self.stream_info
+    }
+
+    pub fn seek_frame(&mut self, frame: u64) -> Result<(), WavError> {
+        let total = self.stream_info.total_samples;
+        if frame > total {
+            return Err(WavError::InvalidInput("Can't seek past the end of the audio data!"));
+        }
+        let frame_samples = self.index.header.samples() as u64;
+        let target = frame + self.index.start_skip;
+        let index = usize::min((target / frame_samples) as usize, self.index.frames.len());
+
+        self.reservoir.clear();
+        self.overlap = [[0.; GRANULE_SAMPLES]; 2];
+        self.synthesis = [Synthesis::new(), Synthesis::new()];
+        self.frame_bytes.clear();
+        self.frame_pos = 0;
+        self.skip = target % frame_samples;
+        self.remaining = total - frame;
+
+        // the target frame overlaps with the granule before it, which for MPEG-2 also overlaps with
+        // the frame before. those have to be decoded in full, so decoding starts early enough to
+        // have the bit reservoir they can borrow from
+        let warm_up = if self.index.header.is_mpeg1() { 1 } else { 2 };
+        let (_, side_info_end) = self.index.header.side_info_range();
+        let mut first = index.saturating_sub(warm_up);
+        let mut reservoir = 0;
+        while first > 0 && reservoir < MAX_RESERVOIR {
+            first -= 1;
+            reservoir += self.index.frames[first].1.saturating_sub(side_info_end);
+        }
+        self.next_frame = first;
+        while self.next_frame < index {
+            self.decode_frame()?;
+        }
+        Ok(())
+    }
+
+    // decodes the next frame into pcm, returns false at the end of the stream
+    fn decode_frame(&mut self) -> Result<bool, WavError> {
+        let Some(&(pos, len)) = self.index.frames.get(self.next_frame) else {
+            return Ok(false);
+        };
+        self.next_frame += 1;
+        if self.reader_pos != pos {
+            self.reader.seek(SeekFrom::Start(pos))?;
+        }
+        self.frame.resize(len, 0);
+        self.reader.read_exact(&mut self.frame)?;
+        self.reader_pos = pos + len as u64;
+
+        let header = FrameHeader::parse(&self.frame).ok_or(WavError::InvalidData("MP3 frame header changed"))?;
+        let channels = header.channels();
+        let (side_start, side_end) = header.side_info_range();
+        let side = self.frame.get(side_start..side_end).and_then(|side| read_side_info(&header, &mut BitSlice::new(side)));
+        let main_data = self.frame.get(side_end..).unwrap_or_default();
+
+        // a frame's main data can start in earlier frames, which are missing at the start of
+        // the stream or after a seek. such frames (or broken ones) are decoded as silence
+        let mut data = vec![];
+        let side = side.filter(|side| side.main_data_begin <= self.reservoir.len());
+        if let Some(side) = &side {
+            data.extend_from_slice(&self.reservoir[self.reservoir.len() - side.main_data_begin..]);
+            data.extend_from_slice(main_data);
+        }
+        self.reservoir.extend_from_slice(main_data);
+        let excess = self.reservoir.len().saturating_sub(MAX_RESERVOIR);
+        self.reservoir.drain(..excess);
+
+        self.pcm.clear();
+        self.pcm.resize(header.samples() * channels, 0.);
+        let mut bits = BitSlice::new(&data);
+        let mut scalefacs = [[[0u8; 39]; 2]; 2];
+        for gr in 0..header.granules() {
+            let mut xr = [[0f32; GRANULE_SAMPLES]; 2];
+            let mut granule = [GranuleChannel::default(); 2];
+            if let Some(side) = &side {
+                granule = side.granules[gr];
+                let mut is_pos = [0u8; 39];
+                for ch in 0..channels {
+                    let g = &mut granule[ch];
+                    let end = bits.pos + g.part2_3_length;
+                    let layout = layout(&self.tables, g);
+                    let copy = if gr == 1 { Some((&side.scfsi[ch], scalefacs[0][ch])) } else { None };
+                    read_scalefactors(&header, g, ch, copy, &mut bits, &mut scalefacs[gr][ch], &mut is_pos);
+                    let mut values = [0i32; GRANULE_SAMPLES];
+                    read_huffman(&self.tables, g, layout, &mut bits, end, &mut values);
+                    requantize(&self.tables, g, layout, &scalefacs[gr][ch], &values, &mut xr[ch]);
+                    bits.pos = end;
+                }
+                if header.mode == 1 && channels == 2 {
+                    joint_stereo(&header, layout(&self.tables, &granule[0]), &is_pos, granule[1].scalefac_compress, &mut xr);
+                }
+            }
+
+            for ch in 0..channels {
+                let g = &granule[ch];
+                reorder(layout(&self.tables, g), &mut xr[ch]);
+                antialias(&self.tables, g, &mut xr[ch]);
+                hybrid_synthesis(&self.tables, g, &mut xr[ch], &mut self.overlap[ch]);
+                let mut out = [0.; GRANULE_SAMPLES];
+                self.synthesis[ch].synthesize(&self.tables, &xr[ch], &mut out);
+                for (i, sample) in out.iter().enumerate() {
+                    self.pcm[(gr * GRANULE_SAMPLES + i) * channels + ch] = *sample;
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    // decodes frames until there is output, returns false once all samples have been output
+    fn next_output(&mut self) -> Result<bool, WavError> {
+        let channels = self.stream_info.channels as usize;
+        while self.remaining > 0 {
+            if !self.decode_frame()? {
+                return Err(WavError::InvalidData("MP3 stream ended early"));
+            }
+            let frame_samples = (self.pcm.len() / channels) as u64;
+            let skip = u64::min(self.skip, frame_samples);
+            let keep = u64::min(frame_samples - skip, self.remaining);
+            self.skip -= skip;
+            self.remaining -= keep;
+            if keep > 0 {
+                let samples = &self.pcm[skip as usize * channels..(skip + keep) as usize * channels];
+                self.frame_bytes.clear();
+                self.frame_bytes.extend(samples.iter().flat_map(|sample| sample.to_le_bytes()));
+                self.frame_pos = 0;
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl<R: Read + Seek> Read for Mp3Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.frame_pos == self.frame_bytes.len() {
+            match self.next_output() {
+                Ok(true) => {}
+                Ok(false) => return Ok(0),
+                // converted back into the WavError by From<io::Error>
+                Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+            }
+        }
+        let len = usize::min(buf.len(), self.frame_bytes.len() - self.frame_pos);
+        buf[..len].copy_from_slice(&self.frame_bytes[self.frame_pos..self.frame_pos + len]);
+        self.frame_pos += len;
+        Ok(len)
+    }
+}
+
+// Streams an MP3 file in blocks of deinterleaved frames, decoding only the frames that are read
+pub struct Mp3Reader<R: Read + Seek> {
+    decoder: Mp3Decoder<R>,
+    info: WavInfo,
+    num_frames: u64,
+    frame_pos: u64,
+    block_size: usize, // in frames
+}
+
+impl<R: Read + Seek> Mp3Reader<R> {
+    pub fn new(mut reader: R, block_size: usize) -> Result<Self, WavError> {
+        if block_size == 0 {
+            return Err(WavError::InvalidInput("Block size must be at least 1 frame!"));
+        }
+        // the frame index from the scan is handed to the decoder, so the stream is only scanned once
+        let (info, stream_info) = read_mp3_stream(&mut reader)?;
+        let decoder = Mp3Decoder::new(reader, &stream_info);
+        Ok(Self {
+            num_frames: decoder.stream_info().total_samples,
+            decoder,
+            info,
+            frame_pos: 0,
+            block_size,
+        })
+    }
+
+    pub fn stream_info(&self) -> &Mp3StreamInfo {
+        self.decoder.stream_info()
+    }
+}
+
+impl<R: Read + Seek> AudioSource for Mp3Reader<R> {
+    fn info(&self) -> &WavInfo {
+        &self.info
+    }
+
+    fn num_frames(&self) -> u64 {
+        self.num_frames
+    }
+
+    fn position(&self) -> u64 {
+        self.frame_pos
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn set_block_size(&mut self, block_size: usize) -> Result<(), WavError> {
+        if block_size == 0 {
+            return Err(WavError::InvalidInput("Block size must be at least 1 frame!"));
+        }
+        self.block_size = block_size;
+        Ok(())
+    }
+
+    fn seek_frame(&mut self, frame: u64) -> Result<(), WavError> {
+        self.decoder.seek_frame(frame)?;
+        self.frame_pos = frame;
+        Ok(())
+    }
+
+    fn read_interleaved(&mut self, out: &mut [f32]) -> Result<usize, WavError> {
+        let channels = self.info.channels as usize;
+        let frames = u64::min((out.len() / channels) as u64, self.num_frames - self.frame_pos) as usize;
+        if frames == 0 {
+            return Ok(0);
+        }
+        let samples = read_data_interleaved_unchecked(&mut self.decoder, &self.info, frames * channels)?;
+        out[..samples.len()].copy_from_slice(&samples);
+        self.frame_pos += frames as u64;
+        Ok(frames)
+    }
+
+    fn read_block(&mut self, block: &mut Vec<Vec<f32>>) -> Result<usize, WavError> {
+        let channels = self.info.channels as usize;
+        let frames = u64::min(self.block_size as u64, self.num_frames - self.frame_pos) as usize;
+        block.resize(channels, vec![]);
+        if frames == 0 {
+            block.iter_mut().for_each(|ch| ch.clear());
+            return Ok(0);
+        }
+
+        let samples = read_data_interleaved_unchecked(&mut self.decoder, &self.info, frames * channels)?;
+        for (c, ch) in block.iter_mut().enumerate() {
+            ch.clear();
+            ch.extend(samples.iter().skip(c).step_by(channels));
+        }
+        self.frame_pos += frames as u64;
+        Ok(frames)
+    }
+}
+
+fn layout<'a>(tables: &'a Tables, g: &GranuleChannel) -> &'a [Band] {
+    match (g.block_type, g.mixed) {
+        (2, false) => &tables.layouts[1],
+        (2, true) => &tables.layouts[2],
+        _ => &tables.layouts[0],
+    }
+}
+
+// Reads the scalefactors of one channel of a granule in the order of its band layout. `copy`
+// has the scfsi flags and scalefactors of the first granule, for the second granule of MPEG-1
+// frames. `is_pos` gets the intensity stereo positions, with 255 for illegal ones.
+fn read_scalefactors(
+    header: &FrameHeader,
+    g: &mut GranuleChannel,
+    ch: usize,
+    copy: Option<(&[bool; 4], [u8; 39])>,
+    bits: &mut BitSlice,
+    scalefacs: &mut [u8; 39],
+    is_pos: &mut [u8; 39],
+) {
+    scalefacs.fill(0);
+    if header.is_mpeg1() {
+        let (slen1, slen2) = SCALEFACTOR_LENGTHS[g.scalefac_compress];
+        if g.block_type == 2 {
+            // 18 values (17 with the 8 long bands of mixed blocks) of slen1, then 18 of slen2
+            let split = if g.mixed { 17 } else { 18 };
+            for (i, scalefac) in scalefacs[..split + 18].iter_mut().enumerate() {
+                *scalefac = bits.read(if i < split { slen1 } else { slen2 }) as u8;
+            }
+        } else {
+            for (group, (start, end)) in [(0, 6), (6, 11), (11, 16), (16, 21)].into_iter().enumerate() {
+                match copy {
+                    Some((scfsi, first)) if scfsi[group] => scalefacs[start..end].copy_from_slice(&first[start..end]),
+                    _ => {
+                        for scalefac in &mut scalefacs[start..end] {
+                            *scalefac = bits.read(if group < 2 { slen1 } else { slen2 }) as u8;
+                        }
+                    }
+                }
+            }
+        }
+        is_pos.copy_from_slice(scalefacs);
+        return;
+    }
+
+    // MPEG-2 packs the lengths of four partitions of scalefactors into scalefac_compress,
+    // differently for the right channel of intensity stereo
+    let block_index = match (g.block_type, g.mixed) {
+        (2, false) => 1,
+        (2, true) => 2,
+        _ => 0,
+    };
+    let intensity = header.mode == 1 && header.mode_extension & 1 != 0 && ch == 1;
+    let (row, slen) = if intensity {
+        let sfc = g.scalefac_compress as u32 >> 1;
+        match sfc {
+            0..=179 => (3, [sfc / 36, sfc % 36 / 6, sfc % 6, 0]),
+            180..=243 => (4, [((sfc - 180) % 64) >> 4, ((sfc - 180) % 16) >> 2, (sfc - 180) % 4, 0]),
+            _ => (5, [(sfc - 244) / 3, (sfc - 244) % 3, 0, 0]),
+        }
+    } else {
+        let sfc = g.scalefac_compress as u32;
+        g.preflag = sfc >= 500;
+        match sfc {
+            0..=399 => (0, [(sfc >> 4) / 5, (sfc >> 4) % 5, (sfc % 16) >> 2, sfc % 4]),
+            400..=499 => (1, [((sfc - 400) >> 2) / 5, ((sfc - 400) >> 2) % 5, (sfc - 400) % 4, 0]),
+            _ => (2, [(sfc - 500) / 3, (sfc - 500) % 3, 0, 0]),
+        }
+    };
+
+    is_pos.fill(0);
+    let mut i = 0;
+    for (partition, &count) in LSF_PARTITIONS[row][block_index].iter().enumerate() {
+        let len = slen[partition];
+        for _ in 0..count {
+            let value = bits.read(len) as u8;
+            scalefacs[i] = value;
+            is_pos[i] = if len > 0 && value as u32 == (1 << len) - 1 { 255 } else { value };
+            i += 1;
+        }
+    }
+}
+
+// decodes the Huffman coded values of a granule's channel, up to bit `end` of the main data
+fn read_huffman(tables: &Tables, g: &GranuleChannel, layout: &[Band], bits: &mut BitSlice, end: usize, values: &mut [i32; GRANULE_SAMPLES]) {
+    let region_start = |bands: usize| layout.get(bands).map_or(GRANULE_SAMPLES, |band| band.start);
+    let region1 = region_start(g.region_counts[0] + 1);
+    let region2 = region_start(g.region_counts[0] + g.region_counts[1] + 2);
+
+    // pairs of values, in up to three regions with their own tables
+    let big_values = usize::min(g.big_values * 2, GRANULE_SAMPLES);
+    let mut i = 0;
+    while i < big_values {
+        let select = g.table_select[if i < region1 { 0 } else if i < region2 { 1 } else { 2 }];
+        let table = &BIG_VALUE_TABLES[select];
+        i += 2;
+        // table 0 codes nothing, all its values are 0
+        if table.dim == 0 {
+            continue;
+        }
+        let Some(index) = tables.big_value_trees[select].decode(bits) else {
+            return;
+        };
+        for (k, value) in [index / table.dim, index % table.dim].into_iter().enumerate() {
+            let mut value = value as i32;
+            if value == 15 && table.linbits > 0 {
+                value += bits.read(table.linbits) as i32;
+            }
+            if value > 0 && bits.read(1) == 1 {
+                value = -value;
+            }
+            values[i - 2 + k] = value;
+        }
+    }
+
+    // then quadruples of -1, 0 or 1 until the bits run out. one that overruns the end is dropped
+    let quads = &tables.quad_trees[g.count1_table];
+    while i + 4 <= GRANULE_SAMPLES && bits.pos < end {
+        let Some(vwxy) = quads.decode(bits) else {
+            return;
+        };
+        if bits.pos > end {
+            return;
+        }
+        for k in 0..4 {
+            if vwxy & (8 >> k) != 0 {
+                values[i + k] = if bits.read(1) == 1 { -1 } else { 1 };
+            }
+        }
+        i += 4;
+    }
+}
+
+fn requantize(tables: &Tables, g: &GranuleChannel, layout: &[Band], scalefacs: &[u8; 39], values: &[i32; GRANULE_SAMPLES], xr: &mut [f32; GRANULE_SAMPLES]) {
+    let shift = 1 + g.scalefac_scale as u32;
+    for (i, band) in layout.iter().enumerate() {
+        // the gain in quarter powers of two
+        let exponent = match band.window {
+            None => {
+                let pretab = if g.preflag { PRETAB[i] } else { 0 };
+                g.global_gain - 210 - (((scalefacs[i] + pretab) as i32) << shift)
+            }
+            Some(window) => g.global_gain - 210 - 8 * g.subblock_gain[window] - ((scalefacs[i] as i32) << shift),
+        };
+        let gain = 2f64.powf(exponent as f64 / 4.) as f32;
+        for j in band.start..band.end {
+            let value = values[j];
+            xr[j] = tables.pow43[value.unsigned_abs() as usize] * gain * value.signum() as f32;
+        }
+    }
+}
+
+// mid/side and intensity stereo, in place of the left and right channels
+fn joint_stereo(header: &FrameHeader, layout: &[Band], is_pos: &[u8; 39], right_scalefac_compress: usize, xr: &mut [[f32; GRANULE_SAMPLES]; 2]) {
+    let mid_side = header.mode_extension & 2 != 0;
+    let [left, right] = xr;
+    let mid_side_band = |left: &mut [f32], right: &mut [f32]| {
+        for (l, r) in left.iter_mut().zip(right) {
+            (*l, *r) = ((*l + *r) * std::f32::consts::FRAC_1_SQRT_2, (*l - *r) * std::f32::consts::FRAC_1_SQRT_2);
+        }
+    };
+    if header.mode_extension & 1 == 0 {
+        if mid_side {
+            mid_side_band(left, right);
+        }
+        return;
+    }
+
+    // intensity stereo covers the bands above the highest one where the right channel has
+    // values, for each window of short blocks
+    let mut max_band = [-1; 3];
+    for (i, band) in layout.iter().enumerate() {
+        if right[band.start..band.end].iter().any(|&x| x != 0.) {
+            max_band[i % 3] = i as i32;
+        }
+    }
+    let windows = if layout[0].window.is_some() { 3 } else { 1 };
+    if layout[0].window.is_none() {
+        max_band = [*max_band.iter().max().unwrap(); 3];
+    }
+
+    // the top band has no scalefactor, it continues the one below unless that one isn't intensity coded
+    let mut is_pos = *is_pos;
+    let default = if header.is_mpeg1() { 3 } else { 0 };
+    let bands = layout.len();
+    for (window, &max_band) in max_band.iter().enumerate().take(windows) {
+        let (top, below) = (bands - windows + window, bands - 2 * windows + window);
+        is_pos[top] = if max_band >= below as i32 { default } else { is_pos[below] };
+    }
+
+    let max_pos = if header.is_mpeg1() { 7 } else { 64 };
+    for (i, band) in layout.iter().enumerate() {
+        let pos = is_pos[i] as usize;
+        let (left, right) = (&mut left[band.start..band.end], &mut right[band.start..band.end]);
+        if i as i32 > max_band[i % 3] && pos < max_pos {
+            let (kl, kr) = if header.is_mpeg1() {
+                // MPEG-1 positions pan in steps of 15 degrees
+                let ratio = (pos as f64 * PI / 12.).tan();
+                if pos == 6 { (1., 0.) } else { (ratio / (1. + ratio), 1. / (1. + ratio)) }
+            } else {
+                // MPEG-2 positions attenuate one side in steps of 1.5 or 3 dB
+                let step = if right_scalefac_compress & 1 == 1 { 0.5 } else { 0.25 };
+                let k = 2f64.powf(-step * ((pos + 1) >> 1) as f64);
+                if pos & 1 == 1 { (k, 1.) } else { (1., k) }
+            };
+            for (l, r) in left.iter_mut().zip(right) {
+                *r = *l * kr as f32;
+                *l *= kl as f32;
+            }
+        } else if mid_side {
+            mid_side_band(left, right);
+        }
+    }
+}
+
+// short blocks are coded band by band and window by window, the IMDCT wants the windows
+// of each frequency next to each other
+fn reorder(layout: &[Band], xr: &mut [f32; GRANULE_SAMPLES]) {
+    if layout.iter().all(|band| band.window.is_none()) {
+        return;
+    }
+    let coded = *xr;
+    for band in layout {
+        if let Some(window) = band.window {
+            for k in 0..band.end - band.start {
+                xr[3 * (band.freq + k) + window] = coded[band.start + k];
+            }
+        }
+    }
+}
+
+// butterflies between the subbands of long blocks
+fn antialias(tables: &Tables, g: &GranuleChannel, xr: &mut [f32; GRANULE_SAMPLES]) {
+    let subbands = match (g.block_type, g.mixed) {
+        (2, false) => 0,
+        (2, true) => 1,
+        _ => 31,
+    };
+    for sb in 1..=subbands {
+        for (i, &(cs, ca)) in tables.antialias.iter().enumerate() {
+            let (lower, upper) = (xr[18 * sb - 1 - i], xr[18 * sb + i]);
+            xr[18 * sb - 1 - i] = lower * cs - upper * ca;
+            xr[18 * sb + i] = upper * cs + lower * ca;
+        }
+    }
+}
+
+// IMDCT of each subband, windowing and overlap-add with the previous granule
+fn hybrid_synthesis(tables: &Tables, g: &GranuleChannel, xr: &mut [f32; GRANULE_SAMPLES], overlap: &mut [f32; GRANULE_SAMPLES]) {
+    for sb in 0..32 {
+        let block_type = if g.mixed && sb < 2 { 0 } else { g.block_type };
+        let lines: [f32; 18] = xr[sb * 18..sb * 18 + 18].try_into().unwrap();
+        let mut out = [0f32; 36];
+        if block_type == 2 {
+            // three overlapping short windows in the middle of the long one
+            let window = &tables.windows[2];
+            for w in 0..3 {
+                for i in 0..12 {
+                    let cos = &tables.imdct_short[i * 6..i * 6 + 6];
+                    let sum: f32 = (0..6).map(|k| lines[3 * k + w] * cos[k]).sum();
+                    out[6 + 6 * w + i] += sum * window[i];
+                }
+            }
+        } else {
+            let window = &tables.windows[block_type];
+            for i in 0..36 {
+                let cos = &tables.imdct_long[i * 18..i * 18 + 18];
+                out[i] = (0..18).map(|k| lines[k] * cos[k]).sum::<f32>() * window[i];
+            }
+        }
+
+        for i in 0..18 {
+            xr[sb * 18 + i] = out[i] + overlap[sb * 18 + i];
+            overlap[sb * 18 + i] = out[18 + i];
+        }
+        // odd subbands are mirrored in frequency, which negates their odd samples
+        if sb % 2 == 1 {
+            for i in (1..18).step_by(2) {
+                xr[sb * 18 + i] = -xr[sb * 18 + i];
+            }
+        }
+    }
+}
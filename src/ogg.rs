@@ -0,0 +1,634 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::audio_source::{AudioFormat, AudioSource};
+use crate::file_io::{read_data_interleaved_unchecked, SpeakerPos, WavError, WavInfo};
+use crate::opus::{OpusDecoder, OpusHeader, OPUS_SAMPLE_RATE};
+use crate::vorbis::{is_vorbis_header, VorbisDecoder, COMMENT_HEADER, IDENTIFICATION_HEADER};
+use crate::wav_metadata::WavTags;
+
+const PAGE_HEADER_LEN: usize = 27;
+// pages on which no packet ends have a granule position of -1
+const NO_GRANULE: u64 = u64::MAX;
+const READ_BUFFER_SIZE: usize = 1 << 16;
+// RFC 7845 recommends decoding 80 ms before a seek target, so the Opus decoder state has converged
+const OPUS_PREROLL: u64 = 3840;
+
+// the speakers of 1 to 8 channels, and which of the channels (in Vorbis order) goes where in the wav order
+const CHANNEL_MASKS: [u32; 8] = [0x4, 0x3, 0x7, 0x33, 0x37, 0x3F, 0x70F, 0x63F];
+const WAV_ORDER: [&[usize]; 8] = [
+    &[0],
+    &[0, 1],
+    &[0, 2, 1],
+    &[0, 1, 2, 3],
+    &[0, 2, 1, 3, 4],
+    &[0, 2, 1, 5, 3, 4],
+    &[0, 2, 1, 6, 5, 3, 4],
+    &[0, 2, 1, 7, 5, 6, 3, 4],
+];
+
+// CRC-32 with polynomial 0x04C11DB7, not reflected, over the page with the CRC field set to 0
+const CRC_TABLE: [u32; 256] = crc32_table();
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OggCodec {
+    Vorbis,
+    Opus,
+}
+
+impl fmt::Display for OggCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Vorbis => "Vorbis",
+            Self::Opus => "Opus",
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OggStreamInfo {
+    pub codec: OggCodec,
+    pub sample_rate: u32, // of the decoded samples, Opus is always decoded at 48 kHz
+    pub input_sample_rate: u32, // what an Opus stream was encoded from, 0 if unknown
+    pub channels: u8,
+    pub bitrate: u32, // average over the whole stream, in kbit/s
+    pub pre_skip: u32, // samples an Opus decoder drops at the start
+    pub output_gain: f32, // in dB, applied to the decoded Opus samples
+    pub total_samples: u64, // per channel, after the start and end have been trimmed
+}
+
+pub fn is_ogg_start(header: &[u8]) -> bool {
+    header.starts_with(b"OggS")
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Page {
+    pos: u64,
+    len: usize,
+    granule: u64, // NO_GRANULE if no packet ends on the page
+}
+
+struct PageData {
+    continued: bool, // the first packet started on an earlier page
+    granule: u64,
+    serial: u32,
+    first_page: bool,
+    last_page: bool,
+    lacing: Vec<u8>,
+    body: Vec<u8>,
+}
+
+impl PageData {
+    fn len(&self) -> usize {
+        PAGE_HEADER_LEN + self.lacing.len() + self.body.len()
+    }
+}
+
+// reads the page at the current position, None if there isn't a valid one
+fn read_page<R: Read>(f: &mut R) -> Result<Option<PageData>, WavError> {
+    let mut header = [0; PAGE_HEADER_LEN];
+    match f.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+    if !is_ogg_start(&header) || header[4] != 0 {
+        return Ok(None);
+    }
+    let mut lacing = vec![0; header[26] as usize];
+    let mut body = vec![];
+    let read = f.read_exact(&mut lacing).and_then(|()| {
+        body.resize(lacing.iter().map(|&len| len as usize).sum(), 0);
+        f.read_exact(&mut body)
+    });
+    match read {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+
+    let checksum = u32::from_le_bytes(header[22..26].try_into().unwrap());
+    header[22..26].fill(0);
+    let crc = [&header[..], &lacing, &body].iter().flat_map(|part| part.iter()).fold(0u32, |crc, &byte| {
+        (crc << 8) ^ CRC_TABLE[((crc >> 24) as u8 ^ byte) as usize]
+    });
+    if crc != checksum {
+        return Ok(None);
+    }
+    Ok(Some(PageData {
+        continued: header[5] & 0x1 != 0,
+        first_page: header[5] & 0x2 != 0,
+        last_page: header[5] & 0x4 != 0,
+        granule: u64::from_le_bytes(header[6..14].try_into().unwrap()),
+        serial: u32::from_le_bytes(header[14..18].try_into().unwrap()),
+        lacing,
+        body,
+    }))
+}
+
+// the position of the next capture pattern at or after `pos`, the stream length if there is none
+fn find_page<R: Read + Seek>(f: &mut R, mut pos: u64, stream_len: u64) -> Result<u64, WavError> {
+    let mut buf = vec![0; READ_BUFFER_SIZE];
+    while pos + PAGE_HEADER_LEN as u64 <= stream_len {
+        f.seek(SeekFrom::Start(pos))?;
+        let len = u64::min(READ_BUFFER_SIZE as u64, stream_len - pos) as usize;
+        f.read_exact(&mut buf[..len])?;
+        if let Some(offset) = buf[..len].windows(4).position(is_ogg_start) {
+            return Ok(pos + offset as u64);
+        }
+        pos += len as u64 - 3;
+    }
+    Ok(stream_len)
+}
+
+struct Packet {
+    data: Vec<u8>,
+    page: usize, // index of the page it ends on
+    granule: Option<u64>, // set for the last packet that ends on a page
+}
+
+// Joins the segments of a stream's pages into packets. After a seek, a packet whose start
+// wasn't read is dropped.
+#[derive(Clone)]
+struct PacketReader {
+    next_page: usize,
+    partial: Vec<u8>,
+    lost: bool, // the packet in progress started before the pages that were read
+    drop_page: Option<usize>, // packets that end on this page are dropped
+    queue: VecDeque<(Vec<u8>, usize, Option<u64>)>,
+}
+
+impl PacketReader {
+    fn new() -> Self {
+        Self { next_page: 0, partial: vec![], lost: true, drop_page: None, queue: VecDeque::new() }
+    }
+
+    // continues at the start of a page, or right after the last packet that ends on it
+    fn seek(&mut self, page: usize, after_page: bool) {
+        *self = Self::new();
+        self.next_page = page;
+        if after_page {
+            self.drop_page = Some(page);
+        }
+    }
+
+    // `reader_pos` is where the reader is, so pages that follow each other are read without seeking
+    fn next<R: Read + Seek>(&mut self, f: &mut R, reader_pos: &mut u64, pages: &[Page]) -> Result<Option<Packet>, WavError> {
+        while self.queue.is_empty() {
+            let Some(page) = pages.get(self.next_page) else {
+                return Ok(None);
+            };
+            if *reader_pos != page.pos {
+                f.seek(SeekFrom::Start(page.pos))?;
+            }
+            let data = read_page(f)?.ok_or(WavError::InvalidData("corrupt Ogg page"))?;
+            *reader_pos = page.pos + page.len as u64;
+            let index = self.next_page;
+            self.next_page += 1;
+            if !data.continued {
+                self.partial.clear();
+                self.lost = false;
+            }
+
+            let mut ends = vec![];
+            let mut offset = 0;
+            for &len in &data.lacing {
+                self.partial.extend_from_slice(&data.body[offset..offset + len as usize]);
+                offset += len as usize;
+                if len < 255 {
+                    let packet = std::mem::take(&mut self.partial);
+                    ends.push((!self.lost && self.drop_page != Some(index)).then_some(packet));
+                    self.lost = false;
+                }
+            }
+            if self.drop_page == Some(index) {
+                // the packet in progress started after the ones that were dropped
+                self.lost = false;
+            }
+            let last = ends.len();
+            for (i, packet) in ends.into_iter().enumerate() {
+                let granule = if i + 1 == last && data.granule != NO_GRANULE { Some(data.granule) } else { None };
+                if let Some(packet) = packet {
+                    self.queue.push_back((packet, index, granule));
+                }
+            }
+        }
+        let (data, page, granule) = self.queue.pop_front().unwrap();
+        Ok(Some(Packet { data, page, granule }))
+    }
+}
+
+enum Codec {
+    Vorbis(Box<VorbisDecoder>),
+    Opus(Box<OpusDecoder>),
+}
+
+impl Codec {
+    fn decode(&mut self, packet: &[u8], out: &mut Vec<f32>) -> usize {
+        match self {
+            Self::Vorbis(decoder) => decoder.decode_packet(packet, out),
+            Self::Opus(decoder) => decoder.decode_packet(packet, out),
+        }
+    }
+
+    fn reset(&mut self) {
+        match self {
+            Self::Vorbis(decoder) => decoder.reset(),
+            Self::Opus(decoder) => decoder.reset(),
+        }
+    }
+
+    fn channels(&self) -> usize {
+        match self {
+            Self::Vorbis(decoder) => decoder.channels(),
+            Self::Opus(decoder) => decoder.channels(),
+        }
+    }
+}
+
+// the pages of the first Vorbis or Opus stream in a file, and where its audio starts and ends
+struct OggStream {
+    pages: Vec<Page>,
+    codec: Codec,
+    audio_start: PacketReader, // right after the header packets
+    origin: i64, // granule position of the first decoded sample
+    start: u64, // granule positions of the first and after the last output sample
+    end: u64,
+    info: OggStreamInfo,
+    tags: Option<WavTags>,
+}
+
+// Indexes the pages of the first Vorbis or Opus stream, reads its headers and works out which
+// decoded samples are audio. Pages of other streams that are multiplexed with it are skipped,
+// chained streams after it are ignored.
+fn scan_stream<R: Read + Seek>(f: &mut R) -> Result<OggStream, WavError> {
+    let stream_len = f.seek(SeekFrom::End(0))?;
+    f.seek(SeekFrom::Start(0))?;
+    let mut pos = 0;
+    let mut pages = vec![];
+    let mut stream: Option<(u32, OggCodec)> = None;
+    while pos + PAGE_HEADER_LEN as u64 <= stream_len {
+        let Some(page) = read_page(f)? else {
+            pos = find_page(f, pos + 1, stream_len)?;
+            f.seek(SeekFrom::Start(pos))?;
+            continue;
+        };
+        if stream.is_none() && page.first_page {
+            let first_len = page.lacing.iter().take_while(|&&len| len == 255).count() * 255
+                + page.lacing.iter().find(|&&len| len < 255).copied().unwrap_or(0) as usize;
+            let first_packet = &page.body[..first_len.min(page.body.len())];
+            if is_vorbis_header(first_packet, IDENTIFICATION_HEADER) {
+                stream = Some((page.serial, OggCodec::Vorbis));
+            } else if first_packet.starts_with(b"OpusHead") {
+                stream = Some((page.serial, OggCodec::Opus));
+            }
+        }
+        if stream.is_some_and(|(serial, _)| serial == page.serial) {
+            pages.push(Page { pos, len: page.len(), granule: page.granule });
+            if page.last_page {
+                break;
+            }
+        }
+        pos += page.len() as u64;
+    }
+    let Some((_, codec)) = stream else {
+        return Err(WavError::InvalidHeader("no Vorbis or Opus stream found"));
+    };
+
+    let mut packets = PacketReader::new();
+    let mut reader_pos = u64::MAX;
+    let mut next_header = |f: &mut R| -> Result<Vec<u8>, WavError> {
+        let packet = packets.next(f, &mut reader_pos, &pages)?.ok_or(WavError::InvalidHeader("Ogg stream ends in its headers"))?;
+        Ok(packet.data)
+    };
+    let identification = next_header(f)?;
+    let comment = next_header(f)?;
+    let (mut codec, tags, mut info) = match codec {
+        OggCodec::Vorbis => {
+            if !is_vorbis_header(&comment, COMMENT_HEADER) {
+                return Err(WavError::InvalidHeader("missing Vorbis comment header"));
+            }
+            let setup = next_header(f)?;
+            let decoder = VorbisDecoder::new(&identification, &setup)?;
+            let info = OggStreamInfo {
+                codec: OggCodec::Vorbis,
+                sample_rate: decoder.sample_rate(),
+                input_sample_rate: decoder.sample_rate(),
+                channels: decoder.channels() as u8,
+                bitrate: 0,
+                pre_skip: 0,
+                output_gain: 0.,
+                total_samples: 0,
+            };
+            (Codec::Vorbis(Box::new(decoder)), WavTags::from_vorbis_comment(&comment[7..]).ok(), info)
+        }
+        OggCodec::Opus => {
+            let header = OpusHeader::parse(&identification)?;
+            if !comment.starts_with(b"OpusTags") {
+                return Err(WavError::InvalidHeader("missing OpusTags header"));
+            }
+            let info = OggStreamInfo {
+                codec: OggCodec::Opus,
+                sample_rate: OPUS_SAMPLE_RATE,
+                input_sample_rate: header.input_sample_rate,
+                channels: header.channels,
+                bitrate: 0,
+                pre_skip: header.pre_skip as u32,
+                output_gain: header.output_gain as f32 / 256.,
+                total_samples: 0,
+            };
+            (Codec::Opus(Box::new(OpusDecoder::new(&header)?)), WavTags::from_vorbis_comment(&comment[8..]).ok(), info)
+        }
+    };
+    let audio_start = packets.clone();
+
+    // the first page that has a granule position says how many samples its packets decode to,
+    // unless it's also the last one, whose granule position cuts off the end
+    let mut origin = 0;
+    let mut pcm = vec![];
+    let mut decoded = 0;
+    while let Some(packet) = packets.next(f, &mut reader_pos, &pages)? {
+        decoded += codec.decode(&packet.data, &mut pcm) as i64;
+        pcm.clear();
+        if let Some(granule) = packet.granule {
+            if packet.page + 1 < pages.len() {
+                origin = granule as i64 - decoded;
+            }
+            break;
+        }
+    }
+    codec.reset();
+
+    let end = pages.iter().rev().map(|page| page.granule).find(|&granule| granule != NO_GRANULE).unwrap_or(0);
+    let start = match info.codec {
+        OggCodec::Vorbis => origin.max(0) as u64,
+        OggCodec::Opus => (origin + info.pre_skip as i64).max(0) as u64,
+    };
+    info.total_samples = end.saturating_sub(start);
+    let bytes: u64 = pages.iter().map(|page| page.len as u64).sum();
+    if info.total_samples > 0 {
+        info.bitrate = (bytes as f64 * 8. * info.sample_rate as f64 / info.total_samples as f64 / 1000.).round() as u32;
+    }
+    Ok(OggStream { pages, codec, audio_start, origin, start, end, info, tags })
+}
+
+// Scans an Ogg Vorbis or Ogg Opus file into a WavInfo. The "data" chunk entry is the size of
+// the decoded samples (32-bit float) in the wav layout, which OggDecoder produces.
+pub fn read_ogg_meta<R: Read + Seek>(f: &mut R) -> Result<WavInfo, WavError> {
+    let file_len = f.seek(SeekFrom::End(0))?;
+    let stream = scan_stream(f)?;
+    let stream_info = stream.info;
+    let channels = stream_info.channels;
+
+    let mut chunks = HashMap::new();
+    chunks.insert("data".to_string(), (stream.pages[0].pos, stream_info.total_samples * 4 * channels as u64));
+    let channel_map = match CHANNEL_MASKS.get(channels as usize - 1) {
+        Some(mask) => (0..32)
+            .filter(|bit| mask & (1 << bit) != 0)
+            .enumerate()
+            .map(|(c, bit)| (c as u8, SpeakerPos::from(1u32 << bit)))
+            .collect(),
+        // there's no standard layout for more channels, so they are kept in their order
+        None => (0..channels).map(|c| (c, SpeakerPos::from(1u32.checked_shl(c as u32).unwrap_or(0)))).collect(),
+    };
+
+    f.seek(SeekFrom::Start(0))?;
+    let mut info = WavInfo::new(3, channels, stream_info.sample_rate, 32, file_len, chunks, channel_map);
+    info.sample_type_str = format!("Ogg {}", stream_info.codec);
+    info.tags = stream.tags;
+    info.format = AudioFormat::Ogg;
+    Ok(info)
+}
+
+// Decodes an Ogg Vorbis or Ogg Opus stream into interleaved 32-bit float samples in the wav
+// byte layout and channel order, trimmed to the samples the granule positions say are audio.
+pub struct OggDecoder<R: Read + Seek> {
+    reader: R,
+    reader_pos: u64,
+    stream: OggStream,
+    packets: PacketReader,
+    position: Option<i64>, // granule position of the next decoded sample, unknown right after a seek
+    output_pos: u64, // granule position of the next sample to output
+    pcm: Vec<f32>,
+    frame_bytes: Vec<u8>, // decoded samples that are output, in the wav layout
+    frame_pos: usize, // bytes of frame_bytes that have been read
+}
+
+impl<R: Read + Seek> OggDecoder<R> {
+    pub fn new(mut reader: R) -> Result<Self, WavError> {
+        let stream = scan_stream(&mut reader)?;
+        Ok(Self {
+            reader,
+            reader_pos: u64::MAX,
+            packets: stream.audio_start.clone(),
+            position: Some(stream.origin),
+            output_pos: stream.start,
+            stream,
+            pcm: vec![],
+            frame_bytes: vec![],
+            frame_pos: 0,
+        })
+    }
+
+    pub fn stream_info(&self) -> &OggStreamInfo {
+        &self.stream.info
+    }
+
+    pub fn seek_frame(&mut self, frame: u64) -> Result<(), WavError> {
+        if frame > self.stream.info.total_samples {
+            return Err(WavError::InvalidInput("Can't seek past the end of the audio data!"));
+        }
+        let target = self.stream.start + frame;
+        let preroll = if self.stream.info.codec == OggCodec::Opus { OPUS_PREROLL } else { 0 };
+        self.stream.codec.reset();
+        self.output_pos = target;
+        self.frame_bytes.clear();
+        self.frame_pos = 0;
+
+        // decoding starts after the page before the last one that ends early enough, so the
+        // packet that ends on that one is decoded and its granule position gives the position
+        let pages = &self.stream.pages;
+        let ends_before = |page: &Page| page.granule != NO_GRANULE && page.granule.saturating_add(preroll) <= target;
+        let first_audio = self.stream.audio_start.next_page.saturating_sub(1);
+        let sync = pages.iter().rposition(ends_before).filter(|&sync| sync > first_audio);
+        let start = sync.and_then(|sync| (first_audio..sync).rev().find(|&page| pages[page].granule != NO_GRANULE));
+        match start {
+            Some(page) => {
+                self.packets.seek(page, true);
+                self.position = None;
+            }
+            None => {
+                self.packets = self.stream.audio_start.clone();
+                self.position = Some(self.stream.origin);
+            }
+        }
+        Ok(())
+    }
+
+    // decodes packets until there is output, returns false once all samples have been output
+    fn next_output(&mut self) -> Result<bool, WavError> {
+        let channels = self.stream.codec.channels();
+        let order = WAV_ORDER.get(channels - 1).copied();
+        while self.output_pos < self.stream.end {
+            let Some(packet) = self.packets.next(&mut self.reader, &mut self.reader_pos, &self.stream.pages)? else {
+                return Err(WavError::InvalidData("Ogg stream ended early"));
+            };
+            self.pcm.clear();
+            let frames = self.stream.codec.decode(&packet.data, &mut self.pcm) as i64;
+            // the last page's granule position only says where the stream ends
+            if let Some(granule) = packet.granule.filter(|_| packet.page + 1 < self.stream.pages.len()) {
+                self.position = Some(granule as i64 - frames);
+            }
+            let Some(start) = self.position else {
+                continue;
+            };
+            self.position = Some(start + frames);
+
+            // samples that are missing (e.g. from a packet that couldn't be decoded) are output as silence
+            let end = u64::min((start + frames).max(0) as u64, self.stream.end);
+            if end <= self.output_pos {
+                continue;
+            }
+            let gap = (start.max(0) as u64).saturating_sub(self.output_pos);
+            let skip = (self.output_pos as i64 - start).max(0) as usize;
+            self.frame_bytes.clear();
+            self.frame_bytes.resize(gap as usize * channels * 4, 0);
+            for frame in self.pcm.chunks_exact(channels).skip(skip).take((end - self.output_pos - gap) as usize) {
+                match order {
+                    Some(order) => self.frame_bytes.extend(order.iter().flat_map(|&c| frame[c].to_le_bytes())),
+                    None => self.frame_bytes.extend(frame.iter().flat_map(|sample| sample.to_le_bytes())),
+                }
+            }
+            self.frame_pos = 0;
+            self.output_pos = end;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+}
+
+impl<R: Read + Seek> Read for OggDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.frame_pos == self.frame_bytes.len() {
+            match self.next_output() {
+                Ok(true) => {}
+                Ok(false) => return Ok(0),
+                // converted back into the WavError by From<io::Error>
+                Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+            }
+        }
+        let len = usize::min(buf.len(), self.frame_bytes.len() - self.frame_pos);
+        buf[..len].copy_from_slice(&self.frame_bytes[self.frame_pos..self.frame_pos + len]);
+        self.frame_pos += len;
+        Ok(len)
+    }
+}
+
+// Streams an Ogg Vorbis or Ogg Opus file in blocks of deinterleaved frames, decoding only the frames that are read
+pub struct OggReader<R: Read + Seek> {
+    decoder: OggDecoder<R>,
+    info: WavInfo,
+    num_frames: u64,
+    frame_pos: u64,
+    block_size: usize, // in frames
+}
+
+impl<R: Read + Seek> OggReader<R> {
+    pub fn new(mut reader: R, block_size: usize) -> Result<Self, WavError> {
+        if block_size == 0 {
+            return Err(WavError::InvalidInput("Block size must be at least 1 frame!"));
+        }
+        let info = read_ogg_meta(&mut reader)?;
+        let decoder = OggDecoder::new(reader)?;
+        Ok(Self {
+            num_frames: decoder.stream_info().total_samples,
+            decoder,
+            info,
+            frame_pos: 0,
+            block_size,
+        })
+    }
+
+    pub fn stream_info(&self) -> &OggStreamInfo {
+        self.decoder.stream_info()
+    }
+}
+
+impl<R: Read + Seek> AudioSource for OggReader<R> {
+    fn info(&self) -> &WavInfo {
+        &self.info
+    }
+
+    fn num_frames(&self) -> u64 {
+        self.num_frames
+    }
+
+    fn position(&self) -> u64 {
+        self.frame_pos
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn set_block_size(&mut self, block_size: usize) -> Result<(), WavError> {
+        if block_size == 0 {
+            return Err(WavError::InvalidInput("Block size must be at least 1 frame!"));
+        }
+        self.block_size = block_size;
+        Ok(())
+    }
+
+    fn seek_frame(&mut self, frame: u64) -> Result<(), WavError> {
+        self.decoder.seek_frame(frame)?;
+        self.frame_pos = frame;
+        Ok(())
+    }
+
+    fn read_interleaved(&mut self, out: &mut [f32]) -> Result<usize, WavError> {
+        let channels = self.info.channels as usize;
+        let frames = u64::min((out.len() / channels) as u64, self.num_frames - self.frame_pos) as usize;
+        if frames == 0 {
+            return Ok(0);
+        }
+        let samples = read_data_interleaved_unchecked(&mut self.decoder, &self.info, frames * channels)?;
+        out[..samples.len()].copy_from_slice(&samples);
+        self.frame_pos += frames as u64;
+        Ok(frames)
+    }
+
+    fn read_block(&mut self, block: &mut Vec<Vec<f32>>) -> Result<usize, WavError> {
+        let channels = self.info.channels as usize;
+        let frames = u64::min(self.block_size as u64, self.num_frames - self.frame_pos) as usize;
+        block.resize(channels, vec![]);
+        if frames == 0 {
+            block.iter_mut().for_each(|ch| ch.clear());
+            return Ok(0);
+        }
+
+        let samples = read_data_interleaved_unchecked(&mut self.decoder, &self.info, frames * channels)?;
+        for (c, ch) in block.iter_mut().enumerate() {
+            ch.clear();
+            ch.extend(samples.iter().skip(c).step_by(channels));
+        }
+        self.frame_pos += frames as u64;
+        Ok(frames)
+    }
+}
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 != 0 { (crc << 1) ^ 0x04C1_1DB7 } else { crc << 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
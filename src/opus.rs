@@ -0,0 +1,790 @@
+use crate::file_io::WavError;
+use crate::opus_celt::CeltDecoder;
+use crate::opus_silk::SilkDecoder;
+
+// Opus is always decoded at 48 kHz
+pub const OPUS_SAMPLE_RATE: u32 = 48000;
+
+// the OpusHead identification header of an Ogg Opus stream
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OpusHeader {
+    pub channels: u8,
+    pub pre_skip: u16,
+    pub input_sample_rate: u32,
+    pub output_gain: i16, // Q7.8 dB
+    pub mapping_family: u8,
+    pub streams: u8,
+    pub coupled_streams: u8, // the first ones are stereo, the rest mono
+    pub mapping: Vec<u8>, // decoded channel of each output channel, 255 for silence
+}
+
+impl OpusHeader {
+    pub fn parse(packet: &[u8]) -> Result<Self, WavError> {
+        let invalid = WavError::InvalidHeader("invalid OpusHead header");
+        if packet.len() < 19 || !packet.starts_with(b"OpusHead") || packet[8] >> 4 != 0 {
+            return Err(invalid);
+        }
+        let channels = packet[9];
+        let mapping_family = packet[18];
+        let (streams, coupled_streams, mapping) = if mapping_family == 0 {
+            if channels == 0 || channels > 2 {
+                return Err(invalid);
+            }
+            (1, channels - 1, (0..channels).collect())
+        } else {
+            let table = packet.get(19..21 + channels as usize).ok_or(WavError::InvalidHeader("invalid OpusHead header"))?;
+            let (streams, coupled_streams) = (table[0], table[1]);
+            let mapping = table[2..].to_vec();
+            if channels == 0 || streams == 0 || coupled_streams > streams
+                || mapping.iter().any(|&c| c != 255 && c as u32 >= streams as u32 + coupled_streams as u32)
+            {
+                return Err(invalid);
+            }
+            (streams, coupled_streams, mapping)
+        };
+        Ok(Self {
+            channels,
+            pre_skip: u16::from_le_bytes([packet[10], packet[11]]),
+            input_sample_rate: u32::from_le_bytes(packet[12..16].try_into().unwrap()),
+            output_gain: i16::from_le_bytes([packet[16], packet[17]]),
+            mapping_family,
+            streams,
+            coupled_streams,
+            mapping,
+        })
+    }
+}
+
+// the range decoder of RFC 6716 section 4.1. Symbols are decoded from the front of a frame,
+// raw bits from its end.
+pub struct RangeDecoder<'a> {
+    data: &'a [u8],
+    storage: usize, // bytes of data that belong to this decoder
+    offset: usize,
+    end_offset: usize,
+    end_window: u32,
+    end_bits: u32,
+    total_bits: i32,
+    range: u32,
+    value: u32,
+    rem: u32,
+    error: bool,
+}
+
+impl<'a> RangeDecoder<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        let mut dec = Self {
+            data,
+            storage: data.len(),
+            offset: 0,
+            end_offset: 0,
+            end_window: 0,
+            end_bits: 0,
+            total_bits: 9,
+            range: 128,
+            value: 0,
+            rem: 0,
+            error: false,
+        };
+        dec.rem = dec.read_byte();
+        dec.value = dec.range - 1 - (dec.rem >> 1);
+        dec.normalize();
+        dec
+    }
+
+    pub fn storage(&self) -> usize {
+        self.storage
+    }
+
+    // gives the last bytes to another decoder, as Opus does with redundant CELT frames
+    pub fn shrink(&mut self, bytes: usize) {
+        self.storage -= bytes;
+    }
+
+    pub fn range(&self) -> u32 {
+        self.range
+    }
+
+    pub fn error(&self) -> bool {
+        self.error
+    }
+
+    // bits used so far, rounded up
+    pub fn tell(&self) -> i32 {
+        self.total_bits - ilog(self.range) as i32
+    }
+
+    // bits used so far in 1/8 bits, rounded up
+    pub fn tell_frac(&self) -> i32 {
+        let bits = self.total_bits << 3;
+        let mut l = ilog(self.range);
+        let mut r = self.range >> (l - 16);
+        for _ in 0..3 {
+            r = (r * r) >> 15;
+            let b = r >> 16;
+            l = l << 1 | b;
+            r >>= b;
+        }
+        bits - l as i32
+    }
+
+    // marks everything as read, for CELT's silence flag
+    pub fn skip_to_end(&mut self) {
+        self.total_bits += (self.storage * 8) as i32 - self.tell();
+    }
+
+    fn read_byte(&mut self) -> u32 {
+        if self.offset < self.storage {
+            self.offset += 1;
+            self.data[self.offset - 1] as u32
+        } else {
+            0
+        }
+    }
+
+    fn read_byte_from_end(&mut self) -> u32 {
+        if self.end_offset < self.storage {
+            self.end_offset += 1;
+            self.data[self.storage - self.end_offset] as u32
+        } else {
+            0
+        }
+    }
+
+    fn normalize(&mut self) {
+        while self.range <= 1 << 23 {
+            self.total_bits += 8;
+            self.range <<= 8;
+            let sym = self.rem;
+            self.rem = self.read_byte();
+            let sym = (sym << 8 | self.rem) >> 1;
+            self.value = ((self.value << 8) + (255 & !sym)) & 0x7FFF_FFFF;
+        }
+    }
+
+    // the cumulative frequency of the next symbol, which update() then consumes
+    pub fn decode(&mut self, total: u32) -> u32 {
+        let ext = self.range / total;
+        let s = self.value / ext;
+        total - u32::min(s + 1, total)
+    }
+
+    pub fn decode_bin(&mut self, bits: u32) -> u32 {
+        let ext = self.range >> bits;
+        let s = self.value / ext;
+        (1 << bits) - u32::min(s + 1, 1 << bits)
+    }
+
+    pub fn update(&mut self, low: u32, high: u32, total: u32) {
+        let ext = self.range / total;
+        let s = ext * (total - high);
+        self.value -= s;
+        self.range = if low > 0 { ext * (high - low) } else { self.range - s };
+        self.normalize();
+    }
+
+    // a bit that is 1 with probability 1 / 2^logp
+    pub fn bit_logp(&mut self, logp: u32) -> bool {
+        let s = self.range >> logp;
+        let bit = self.value < s;
+        if bit {
+            self.range = s;
+        } else {
+            self.value -= s;
+            self.range -= s;
+        }
+        self.normalize();
+        bit
+    }
+
+    // a symbol with an inverse cumulative distribution in units of 1 / 2^bits
+    pub fn icdf(&mut self, icdf: &[u8], bits: u32) -> usize {
+        let r = self.range >> bits;
+        let mut symbol = 0;
+        let mut t;
+        let mut s = self.range;
+        loop {
+            t = s;
+            s = r * icdf[symbol] as u32;
+            if self.value >= s {
+                break;
+            }
+            symbol += 1;
+        }
+        self.value -= s;
+        self.range = t - s;
+        self.normalize();
+        symbol
+    }
+
+    // a uniformly distributed integer below total
+    pub fn uint(&mut self, total: u32) -> u32 {
+        let top = total - 1;
+        let bits = ilog(top);
+        if bits > 8 {
+            let raw = bits - 8;
+            let high = (top >> raw) + 1;
+            let s = self.decode(high);
+            self.update(s, s + 1, high);
+            let value = s << raw | self.bits(raw);
+            if value <= top {
+                return value;
+            }
+            self.error = true;
+            top
+        } else {
+            let s = self.decode(total);
+            self.update(s, s + 1, total);
+            s
+        }
+    }
+
+    // raw bits from the end of the frame
+    pub fn bits(&mut self, bits: u32) -> u32 {
+        let mut window = self.end_window;
+        let mut available = self.end_bits;
+        if available < bits {
+            loop {
+                window |= self.read_byte_from_end() << available;
+                available += 8;
+                if available > 24 {
+                    break;
+                }
+            }
+        }
+        let value = window & ((1u64 << bits) - 1) as u32;
+        self.end_window = window >> bits;
+        self.end_bits = available - bits;
+        self.total_bits += bits as i32;
+        value
+    }
+}
+
+pub fn ilog(value: u32) -> u32 {
+    32 - value.leading_zeros()
+}
+
+// frame sizes at 48 kHz
+const F20: usize = 960;
+const F10: usize = 480;
+const F5: usize = 240;
+const F2_5: usize = 120;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Mode {
+    Silk,
+    Hybrid,
+    Celt,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Bandwidth {
+    Narrow,
+    Medium,
+    Wide,
+    SuperWide,
+    Full,
+}
+
+// what the first byte of a packet says about all of its frames
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Toc {
+    mode: Mode,
+    bandwidth: Bandwidth,
+    frame_size: usize, // at 48 kHz
+    channels: usize,
+}
+
+impl Toc {
+    fn parse(toc: u8) -> Self {
+        const BANDWIDTHS: [Bandwidth; 4] = [Bandwidth::Narrow, Bandwidth::Medium, Bandwidth::Wide, Bandwidth::Full];
+        let config = (toc >> 3) as usize;
+        let (mode, bandwidth, frame_size) = if toc & 0x80 != 0 {
+            let bandwidth = [Bandwidth::Narrow, Bandwidth::Wide, Bandwidth::SuperWide, Bandwidth::Full][(config >> 2) & 3];
+            (Mode::Celt, bandwidth, F2_5 << (config & 3))
+        } else if toc & 0x60 == 0x60 {
+            let bandwidth = if toc & 0x10 != 0 { Bandwidth::Full } else { Bandwidth::SuperWide };
+            (Mode::Hybrid, bandwidth, if toc & 0x08 != 0 { F20 } else { F10 })
+        } else {
+            let frame_size = [F10, F20, 2 * F20, 3 * F20][config & 3];
+            (Mode::Silk, BANDWIDTHS[config >> 2], frame_size)
+        };
+        Self { mode, bandwidth, frame_size, channels: if toc & 0x04 != 0 { 2 } else { 1 } }
+    }
+}
+
+fn parse_frame_size(data: &[u8]) -> Option<(usize, usize)> {
+    match *data {
+        [size @ 0..=251, ..] => Some((size as usize, 1)),
+        [low, high, ..] => Some((4 * high as usize + low as usize, 2)),
+        _ => None,
+    }
+}
+
+// Splits a packet into its frames (RFC 6716 section 3.2) and returns them with the number of
+// bytes the packet takes up, which is less than data.len() for a self-delimited one (RFC 6716
+// appendix B) that is followed by the packets of other streams.
+fn parse_packet(data: &[u8], self_delimited: bool) -> Option<(Toc, Vec<&[u8]>, usize)> {
+    let toc = Toc::parse(*data.first()?);
+    let mut pos = 1;
+    let mut len = data.len() - 1; // bytes left for frames, less any padding
+    let mut sizes = vec![];
+    let mut cbr = false;
+    let mut last_size = len;
+    let count = match data[0] & 3 {
+        0 => 1,
+        1 => {
+            cbr = true;
+            if !self_delimited {
+                if len & 1 != 0 {
+                    return None;
+                }
+                last_size = len / 2;
+                sizes.push(last_size);
+            }
+            2
+        }
+        2 => {
+            let (size, bytes) = parse_frame_size(&data[pos..])?;
+            len -= bytes;
+            pos += bytes;
+            if size > len {
+                return None;
+            }
+            sizes.push(size);
+            last_size = len - size;
+            2
+        }
+        _ => {
+            let &ch = data.get(pos)?;
+            pos += 1;
+            len -= 1;
+            let count = (ch & 0x3f) as usize;
+            if count == 0 || toc.frame_size * count > 5760 {
+                return None;
+            }
+            if ch & 0x40 != 0 {
+                // padding, at the end of the packet
+                loop {
+                    if len == 0 {
+                        return None;
+                    }
+                    let p = data[pos];
+                    pos += 1;
+                    len = len.checked_sub(1 + if p == 255 { 254 } else { p as usize })?;
+                    if p != 255 {
+                        break;
+                    }
+                }
+            }
+            cbr = ch & 0x80 == 0;
+            if !cbr {
+                let mut last = len as isize;
+                for _ in 0..count - 1 {
+                    let (size, bytes) = parse_frame_size(&data[pos..pos + len])?;
+                    len -= bytes;
+                    pos += bytes;
+                    if size > len {
+                        return None;
+                    }
+                    sizes.push(size);
+                    last -= (bytes + size) as isize;
+                }
+                if last < 0 {
+                    return None;
+                }
+                last_size = last as usize;
+            } else if !self_delimited {
+                last_size = len / count;
+                if last_size * count != len {
+                    return None;
+                }
+                sizes.resize(count - 1, last_size);
+            }
+            count
+        }
+    };
+    if self_delimited {
+        let (size, bytes) = parse_frame_size(&data[pos..pos + len])?;
+        len -= bytes;
+        pos += bytes;
+        if size > len {
+            return None;
+        }
+        if cbr {
+            if size * count > len {
+                return None;
+            }
+            sizes = vec![size; count];
+        } else if bytes + size > last_size {
+            return None;
+        } else {
+            sizes.push(size);
+        }
+    } else {
+        if last_size > 1275 {
+            return None;
+        }
+        sizes.push(last_size);
+    }
+    let padding = data.len() - pos - len;
+    let mut frames = Vec::with_capacity(count);
+    for size in sizes {
+        frames.push(&data[pos..pos + size]);
+        pos += size;
+    }
+    Some((toc, frames, pos + padding))
+}
+
+// out = w^2 * in2 + (1 - w^2) * in1 over the CELT window, to cross-fade between two signals
+fn smooth_fade(in1: &[f32], in2: &[f32], out: &mut [f32], channels: usize, window: &[f32]) {
+    for (i, &w) in window.iter().enumerate().take(F2_5) {
+        let w = w * w;
+        for c in 0..channels {
+            let j = i * channels + c;
+            out[j] = w * in2[j] + (1. - w) * in1[j];
+        }
+    }
+}
+
+// A single Opus stream (RFC 6716), mono or stereo, following opus_decoder.c of the reference
+// implementation. SILK, CELT or both decode each frame; switching between them is smoothed
+// with concealed audio or redundant CELT frames.
+struct StreamDecoder {
+    channels: usize,
+    celt: CeltDecoder,
+    silk: SilkDecoder,
+    toc: Toc, // of the current packet
+    prev_mode: Option<Mode>, // None before the first frame
+    prev_redundancy: bool,
+    silk_channels: usize,
+    silk_rate_khz: usize,
+    last_packet_duration: usize,
+    range_final: u32,
+}
+
+impl StreamDecoder {
+    fn new(channels: usize) -> Self {
+        let mut decoder = Self {
+            channels,
+            celt: CeltDecoder::new(channels),
+            silk: SilkDecoder::new(channels),
+            toc: Toc { mode: Mode::Celt, bandwidth: Bandwidth::Full, frame_size: F2_5, channels },
+            prev_mode: None,
+            prev_redundancy: false,
+            silk_channels: channels,
+            silk_rate_khz: 16,
+            last_packet_duration: 0,
+            range_final: 0,
+        };
+        decoder.reset();
+        decoder
+    }
+
+    fn reset(&mut self) {
+        self.celt.reset();
+        self.silk.reset();
+        self.toc.frame_size = F2_5;
+        self.toc.channels = self.channels;
+        self.prev_mode = None;
+        self.prev_redundancy = false;
+        self.last_packet_duration = 0;
+        self.range_final = 0;
+    }
+
+    // Decodes a packet into `pcm`, or conceals `last_packet_duration` samples if it is empty,
+    // and returns the samples per channel or None if the packet is invalid.
+    fn decode_packet(&mut self, packet: &[u8], self_delimited: bool, pcm: &mut Vec<f32>) -> Option<(usize, usize)> {
+        let ch = self.channels;
+        if packet.is_empty() {
+            let frame_size = self.last_packet_duration;
+            pcm.resize(frame_size * ch, 0.);
+            let mut count = 0;
+            while count < frame_size {
+                count += self.decode_frame(&[], &mut pcm[count * ch..], frame_size - count);
+            }
+            return Some((frame_size, 0));
+        }
+        let (toc, frames, packet_len) = parse_packet(packet, self_delimited)?;
+        self.toc = toc;
+        let frame_size = frames.len() * toc.frame_size;
+        pcm.resize(frame_size * ch, 0.);
+        for (i, frame) in frames.iter().enumerate() {
+            self.decode_frame(frame, &mut pcm[i * toc.frame_size * ch..], toc.frame_size);
+        }
+        self.last_packet_duration = frame_size;
+        Some((frame_size, packet_len))
+    }
+
+    fn decode_frame(&mut self, data: &[u8], pcm: &mut [f32], frame_size: usize) -> usize {
+        let ch = self.channels;
+        let mut frame_size = frame_size.min(3 * F20);
+        let lost = data.len() <= 1;
+        let mut dec = (!lost).then(|| RangeDecoder::new(data));
+        let (mode, bandwidth, audiosize) = if lost {
+            frame_size = frame_size.min(self.toc.frame_size);
+            let Some(mode) = self.prev_mode else {
+                pcm[..frame_size * ch].fill(0.);
+                return frame_size;
+            };
+            // concealment happens in frames of at most 20 ms
+            if frame_size > F20 {
+                let mut count = 0;
+                while count < frame_size {
+                    count += self.decode_frame(&[], &mut pcm[count * ch..], (frame_size - count).min(F20));
+                }
+                return frame_size;
+            }
+            let audiosize = if frame_size < F20 && frame_size > F10 {
+                F10
+            } else if mode != Mode::Silk && frame_size > F5 && frame_size < F10 {
+                F5
+            } else {
+                frame_size
+            };
+            (mode, None, audiosize)
+        } else {
+            (self.toc.mode, Some(self.toc.bandwidth), self.toc.frame_size)
+        };
+
+        // switching between CELT and SILK conceals the first 5 ms with the old mode, unless
+        // the encoder sent a redundant CELT frame
+        let mut transition = !lost
+            && self.prev_mode.is_some_and(|prev_mode| {
+                (mode == Mode::Celt && prev_mode != Mode::Celt && !self.prev_redundancy)
+                    || (mode != Mode::Celt && prev_mode == Mode::Celt)
+            });
+        let mut pcm_transition = vec![0.; F5 * ch];
+        if transition && mode == Mode::Celt {
+            self.decode_frame(&[], &mut pcm_transition, F5.min(audiosize));
+        }
+        if audiosize > frame_size {
+            return 0;
+        }
+        frame_size = audiosize;
+
+        let mut pcm_silk = vec![0i16; usize::max(F10, frame_size) * ch];
+        if mode != Mode::Celt {
+            if self.prev_mode == Some(Mode::Celt) {
+                self.silk.reset();
+            }
+            let payload_ms = usize::max(10, audiosize / 48);
+            if !lost {
+                self.silk_channels = self.toc.channels;
+                self.silk_rate_khz = match (mode, bandwidth) {
+                    (Mode::Silk, Some(Bandwidth::Narrow)) => 8,
+                    (Mode::Silk, Some(Bandwidth::Medium)) => 12,
+                    _ => 16,
+                };
+            }
+            let mut decoded = 0;
+            while decoded < frame_size {
+                decoded += self.silk.decode(
+                    dec.as_mut(),
+                    decoded == 0,
+                    self.silk_channels,
+                    self.silk_rate_khz,
+                    payload_ms,
+                    &mut pcm_silk[decoded * ch..],
+                );
+            }
+        }
+
+        let mut len = data.len();
+        let mut redundancy = false;
+        let mut celt_to_silk = false;
+        let mut redundancy_bytes = 0;
+        if let Some(dec) = dec.as_mut() {
+            if mode != Mode::Celt && dec.tell() + 17 + 20 * (mode == Mode::Hybrid) as i32 <= 8 * len as i32 {
+                redundancy = mode == Mode::Silk || dec.bit_logp(12);
+                if redundancy {
+                    celt_to_silk = dec.bit_logp(1);
+                    let bytes = if mode == Mode::Hybrid {
+                        dec.uint(256) as isize + 2
+                    } else {
+                        len as isize - ((dec.tell() as isize + 7) >> 3)
+                    };
+                    let remaining = len as isize - bytes;
+                    if remaining * 8 < dec.tell() as isize {
+                        len = 0;
+                        redundancy = false;
+                    } else {
+                        len = remaining as usize;
+                        redundancy_bytes = bytes as usize;
+                        dec.shrink(redundancy_bytes);
+                    }
+                }
+            }
+        }
+        let start_band = if mode != Mode::Celt { 17 } else { 0 };
+        if redundancy {
+            transition = false;
+        }
+        if transition && mode != Mode::Celt {
+            self.decode_frame(&[], &mut pcm_transition, F5.min(audiosize));
+        }
+
+        if let Some(bandwidth) = bandwidth {
+            self.celt.set_end_band(match bandwidth {
+                Bandwidth::Narrow => 13,
+                Bandwidth::Medium | Bandwidth::Wide => 17,
+                Bandwidth::SuperWide => 19,
+                Bandwidth::Full => 21,
+            });
+        }
+        self.celt.set_stream_channels(self.toc.channels);
+        let mut redundant_audio = vec![0.; F5 * ch];
+        let mut redundant_rng = 0;
+        let redundant_data = &data[len.min(data.len())..(len + redundancy_bytes).min(data.len())];
+        if redundancy && celt_to_silk {
+            self.celt.set_start_band(0);
+            self.celt.decode(Some(&mut RangeDecoder::new(redundant_data)), F5, &mut redundant_audio);
+            redundant_rng = self.celt.final_range();
+        }
+
+        self.celt.set_start_band(start_band);
+        if mode != Mode::Silk {
+            if self.prev_mode.is_some_and(|prev_mode| prev_mode != mode) && !self.prev_redundancy {
+                self.celt.reset();
+            }
+            self.celt.decode(dec.as_mut().filter(|_| len > 1), frame_size.min(F20), pcm);
+        } else {
+            pcm[..frame_size * ch].fill(0.);
+            // flushes the CELT overlap after hybrid frames
+            if self.prev_mode == Some(Mode::Hybrid) && !(redundancy && celt_to_silk && self.prev_redundancy) {
+                self.celt.set_start_band(0);
+                self.celt.decode(Some(&mut RangeDecoder::new(&[0xff, 0xff])), F2_5, pcm);
+            }
+        }
+        if mode != Mode::Celt {
+            for (x, &silk) in pcm[..frame_size * ch].iter_mut().zip(&pcm_silk) {
+                *x += silk as f32 * (1. / 32768.);
+            }
+        }
+
+        let window = self.celt.window().to_vec();
+        if redundancy && !celt_to_silk {
+            self.celt.reset();
+            self.celt.set_start_band(0);
+            self.celt.decode(Some(&mut RangeDecoder::new(redundant_data)), F5, &mut redundant_audio);
+            redundant_rng = self.celt.final_range();
+            let tail = ch * (frame_size - F2_5);
+            let in1 = pcm[tail..].to_vec();
+            smooth_fade(&in1, &redundant_audio[ch * F2_5..], &mut pcm[tail..], ch, &window);
+        }
+        if redundancy && celt_to_silk {
+            pcm[..ch * F2_5].copy_from_slice(&redundant_audio[..ch * F2_5]);
+            let in2 = pcm[ch * F2_5..ch * F5].to_vec();
+            smooth_fade(&redundant_audio[ch * F2_5..], &in2, &mut pcm[ch * F2_5..], ch, &window);
+        }
+        if transition {
+            if audiosize >= F5 {
+                pcm[..ch * F2_5].copy_from_slice(&pcm_transition[..ch * F2_5]);
+                let in2 = pcm[ch * F2_5..ch * F5].to_vec();
+                smooth_fade(&pcm_transition[ch * F2_5..], &in2, &mut pcm[ch * F2_5..], ch, &window);
+            } else {
+                let in2 = pcm[..ch * F2_5].to_vec();
+                smooth_fade(&pcm_transition, &in2, pcm, ch, &window);
+            }
+        }
+
+        self.range_final = match dec {
+            Some(dec) if len > 1 => dec.range() ^ redundant_rng,
+            _ => 0,
+        };
+        self.prev_mode = Some(mode);
+        self.prev_redundancy = redundancy && !celt_to_silk;
+        audiosize
+    }
+}
+
+// Decodes the packets of an Ogg Opus stream (RFC 7845): one or more Opus streams, whose
+// channels are mapped to the output channels, with the output gain of the header applied.
+pub struct OpusDecoder {
+    channels: usize,
+    streams: Vec<StreamDecoder>,
+    coupled_streams: usize,
+    mapping: Vec<u8>,
+    gain: f32,
+    stream_pcm: Vec<Vec<f32>>,
+}
+
+impl OpusDecoder {
+    pub fn new(header: &OpusHeader) -> Result<Self, WavError> {
+        let streams = header.streams as usize;
+        let coupled_streams = header.coupled_streams as usize;
+        Ok(Self {
+            channels: header.channels as usize,
+            streams: (0..streams).map(|s| StreamDecoder::new(if s < coupled_streams { 2 } else { 1 })).collect(),
+            coupled_streams,
+            mapping: header.mapping.clone(),
+            gain: 10f32.powf(header.output_gain as f32 / (20. * 256.)),
+            stream_pcm: vec![vec![]; streams],
+        })
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn reset(&mut self) {
+        for stream in &mut self.streams {
+            stream.reset();
+        }
+    }
+
+    // the range coder state after the last frame, for checking against the reference decoder
+    pub fn final_range(&self) -> u32 {
+        self.streams.iter().fold(0, |rng, stream| rng ^ stream.range_final)
+    }
+
+    // Decodes a packet, appending interleaved samples to `out`, and returns the number of samples
+    // per channel. An empty packet is treated as lost and concealed; an invalid one decodes to
+    // nothing.
+    pub fn decode_packet(&mut self, packet: &[u8], out: &mut Vec<f32>) -> usize {
+        // all streams have to be valid and the same length before any are decoded
+        if !packet.is_empty() {
+            let mut pos = 0;
+            let mut frame_size = None;
+            for s in 0..self.streams.len() {
+                let Some((toc, frames, len)) = parse_packet(&packet[pos..], s + 1 < self.streams.len()) else {
+                    return 0;
+                };
+                let size = toc.frame_size * frames.len();
+                if *frame_size.get_or_insert(size) != size {
+                    return 0;
+                }
+                pos += len;
+            }
+        }
+
+        let mut pos = 0;
+        let mut frames = 0;
+        for (s, stream) in self.streams.iter_mut().enumerate() {
+            let self_delimited = s + 1 < self.stream_pcm.len();
+            let pcm = &mut self.stream_pcm[s];
+            let data = if packet.is_empty() { packet } else { &packet[pos..] };
+            let Some((stream_frames, len)) = stream.decode_packet(data, self_delimited, pcm) else {
+                return 0;
+            };
+            frames = stream_frames;
+            pos += len;
+        }
+
+        let start = out.len();
+        out.resize(start + frames * self.channels, 0.);
+        for (c, &m) in self.mapping.iter().enumerate() {
+            let m = m as usize;
+            let (stream, channel, stream_channels) = match m {
+                255 => continue,
+                m if m < 2 * self.coupled_streams => (m / 2, m % 2, 2),
+                m => (m - self.coupled_streams, 0, 1),
+            };
+            let pcm = &self.stream_pcm[stream];
+            for (i, frame) in out[start..].chunks_exact_mut(self.channels).enumerate() {
+                frame[c] = pcm.get(i * stream_channels + channel).copied().unwrap_or(0.) * self.gain;
+            }
+        }
+        frames
+    }
+}
@@ -0,0 +1,2013 @@
+use std::f64::consts::{LN_2, PI};
+
+use crate::mdct::Imdct;
+use crate::opus::{ilog, RangeDecoder};
+use crate::opus_tables::*;
+
+// CELT as used by Opus: 21 bands, 2.5 ms short MDCTs with 120 samples of overlap
+const NB_BANDS: usize = 21;
+const OVERLAP: usize = 120;
+const SHORT_MDCT_SIZE: usize = 120;
+const MAX_LM: usize = 3;
+const DECODE_BUFFER_SIZE: usize = 2048;
+const MAX_PERIOD: usize = 1024;
+const LPC_ORDER: usize = 24;
+const PLC_PITCH_LAG_MAX: usize = 720;
+const PLC_PITCH_LAG_MIN: usize = 100;
+const COMBFILTER_MIN_PERIOD: usize = 15;
+const PREEMPHASIS: f32 = 0.850_006_1;
+// allocation is done in 1/8 bits
+const BITRES: i32 = 3;
+const MAX_FINE_BITS: i32 = 8;
+const FINE_OFFSET: i32 = 21;
+const SPREAD_NONE: usize = 0;
+const SPREAD_NORMAL: usize = 2;
+const SPREAD_AGGRESSIVE: usize = 3;
+// internally samples are scaled to 16 bits
+const SIGNAL_SCALE: f32 = 32768.;
+
+// energy prediction coefficients of the coarse energy, by LM
+const PRED_COEF: [f32; 4] = [29440. / 32768., 26112. / 32768., 21248. / 32768., 16384. / 32768.];
+const BETA_COEF: [f32; 4] = [30147. / 32768., 22282. / 32768., 12124. / 32768., 6554. / 32768.];
+const BETA_INTRA: f32 = 4915. / 32768.;
+// taps of the pitch post-filter, by tapset
+const COMB_GAINS: [[f32; 3]; 3] = [[0.306_640_63, 0.217_041_02, 0.129_638_67], [0.463_867_2, 0.268_066_4, 0.], [
+    0.799_804_7,
+    0.100_097_656,
+    0.,
+]];
+const SPREAD_FACTOR: [i32; 3] = [15, 10, 5];
+const EXP2_TABLE8: [i32; 8] = [16384, 17866, 19483, 21247, 23170, 25267, 27554, 30048];
+const BIT_INTERLEAVE_TABLE: [u32; 16] = [0, 1, 1, 1, 2, 3, 3, 3, 2, 3, 3, 3, 2, 3, 3, 3];
+const BIT_DEINTERLEAVE_TABLE: [u32; 16] =
+    [0x00, 0x03, 0x0C, 0x0F, 0x30, 0x33, 0x3C, 0x3F, 0xC0, 0xC3, 0xCC, 0xCF, 0xF0, 0xF3, 0xFC, 0xFF];
+
+// The CELT layer of an Opus decoder (RFC 6716 section 4.3), following the float build of the
+// reference decoder. Output is at 48 kHz.
+pub struct CeltDecoder {
+    channels: usize,
+    stream_channels: usize,
+    start: usize, // first coded band, 17 in hybrid frames
+    end: usize, // bands above this are not coded
+    disable_inv: bool,
+    rng: u32,
+    last_pitch_index: usize,
+    loss_count: u32,
+    skip_plc: bool,
+    postfilter_period: usize,
+    postfilter_period_old: usize,
+    postfilter_gain: f32,
+    postfilter_gain_old: f32,
+    postfilter_tapset: usize,
+    postfilter_tapset_old: usize,
+    preemph_mem: [f32; 2],
+    decode_mem: [Vec<f32>; 2], // past output and the overlap of the next frame, per channel
+    lpc: [[f32; LPC_ORDER]; 2], // of the packet loss concealment
+    old_band_e: [f32; 2 * NB_BANDS],
+    old_log_e: [f32; 2 * NB_BANDS],
+    old_log_e2: [f32; 2 * NB_BANDS],
+    background_log_e: [f32; 2 * NB_BANDS],
+    window: [f32; OVERLAP],
+    imdcts: [Imdct; MAX_LM + 1], // by log2 of the block size over 120
+}
+
+impl CeltDecoder {
+    pub fn new(channels: usize) -> Self {
+        let mut window = [0.; OVERLAP];
+        for (i, w) in window.iter_mut().enumerate() {
+            let x = (0.5 * PI * (i as f64 + 0.5) / OVERLAP as f64).sin();
+            *w = (0.5 * PI * x * x).sin() as f32;
+        }
+        let mut decoder = Self {
+            channels,
+            stream_channels: channels,
+            start: 0,
+            end: NB_BANDS,
+            disable_inv: channels == 1,
+            rng: 0,
+            last_pitch_index: 0,
+            loss_count: 0,
+            skip_plc: false,
+            postfilter_period: 0,
+            postfilter_period_old: 0,
+            postfilter_gain: 0.,
+            postfilter_gain_old: 0.,
+            postfilter_tapset: 0,
+            postfilter_tapset_old: 0,
+            preemph_mem: [0.; 2],
+            decode_mem: [vec![0.; DECODE_BUFFER_SIZE + OVERLAP], vec![0.; DECODE_BUFFER_SIZE + OVERLAP]],
+            lpc: [[0.; LPC_ORDER]; 2],
+            old_band_e: [0.; 2 * NB_BANDS],
+            old_log_e: [0.; 2 * NB_BANDS],
+            old_log_e2: [0.; 2 * NB_BANDS],
+            background_log_e: [0.; 2 * NB_BANDS],
+            window,
+            imdcts: std::array::from_fn(|shift| Imdct::new((2 * SHORT_MDCT_SIZE) << shift)),
+        };
+        decoder.reset();
+        decoder
+    }
+
+    pub fn reset(&mut self) {
+        self.rng = 0;
+        self.last_pitch_index = 0;
+        self.loss_count = 0;
+        self.skip_plc = true;
+        self.postfilter_period = 0;
+        self.postfilter_period_old = 0;
+        self.postfilter_gain = 0.;
+        self.postfilter_gain_old = 0.;
+        self.postfilter_tapset = 0;
+        self.postfilter_tapset_old = 0;
+        self.preemph_mem = [0.; 2];
+        for mem in &mut self.decode_mem {
+            mem.fill(0.);
+        }
+        self.lpc = [[0.; LPC_ORDER]; 2];
+        self.old_band_e = [0.; 2 * NB_BANDS];
+        self.old_log_e = [-28.; 2 * NB_BANDS];
+        self.old_log_e2 = [-28.; 2 * NB_BANDS];
+        self.background_log_e = [0.; 2 * NB_BANDS];
+    }
+
+    pub fn set_start_band(&mut self, start: usize) {
+        self.start = start;
+    }
+
+    pub fn set_end_band(&mut self, end: usize) {
+        self.end = end;
+    }
+
+    pub fn set_stream_channels(&mut self, channels: usize) {
+        self.stream_channels = channels;
+    }
+
+    pub fn final_range(&self) -> u32 {
+        self.rng
+    }
+
+    // the overlap window, which Opus also cross-fades with when switching modes
+    pub fn window(&self) -> &[f32] {
+        &self.window
+    }
+
+    // Decodes a frame of 120, 240, 480 or 960 samples into `out` (interleaved, in +-1), or
+    // conceals a lost one if there is no frame data.
+    pub fn decode(&mut self, dec: Option<&mut RangeDecoder>, frame_size: usize, out: &mut [f32]) {
+        let lm = (0..=MAX_LM).find(|&lm| SHORT_MDCT_SIZE << lm == frame_size).expect("invalid CELT frame size");
+        match dec {
+            Some(dec) if dec.storage() > 1 => self.decode_frame(dec, lm, out),
+            _ => {
+                self.decode_lost(frame_size);
+                self.deemphasis(frame_size, out);
+            }
+        }
+    }
+
+    fn decode_frame(&mut self, dec: &mut RangeDecoder, lm: usize, out: &mut [f32]) {
+        let c_count = self.stream_channels;
+        let m = 1 << lm;
+        let n = m * SHORT_MDCT_SIZE;
+        let (start, end) = (self.start, self.end);
+        let len = dec.storage() as i32;
+        self.skip_plc = self.loss_count != 0;
+
+        if c_count == 1 {
+            for i in 0..NB_BANDS {
+                self.old_band_e[i] = self.old_band_e[i].max(self.old_band_e[NB_BANDS + i]);
+            }
+        }
+        let mut total_bits = len * 8;
+        let mut tell = dec.tell();
+        let silence = if tell >= total_bits {
+            true
+        } else if tell == 1 {
+            dec.bit_logp(15)
+        } else {
+            false
+        };
+        if silence {
+            dec.skip_to_end();
+            tell = len * 8;
+        }
+
+        let mut postfilter_gain = 0.;
+        let mut postfilter_pitch = 0;
+        let mut postfilter_tapset = 0;
+        if start == 0 && tell + 16 <= total_bits {
+            if dec.bit_logp(1) {
+                let octave = dec.uint(6);
+                postfilter_pitch = ((16 << octave) + dec.bits(4 + octave) - 1) as usize;
+                let qg = dec.bits(3);
+                if dec.tell() + 2 <= total_bits {
+                    postfilter_tapset = dec.icdf(&TAPSET_ICDF, 2);
+                }
+                postfilter_gain = 0.09375 * (qg + 1) as f32;
+            }
+            tell = dec.tell();
+        }
+        let is_transient = if lm > 0 && tell + 3 <= total_bits {
+            let transient = dec.bit_logp(3);
+            tell = dec.tell();
+            transient
+        } else {
+            false
+        };
+        let short_blocks = if is_transient { m } else { 0 };
+        let intra = tell + 3 <= total_bits && dec.bit_logp(3);
+        self.unquant_coarse_energy(dec, intra, c_count, lm);
+
+        let mut tf_res = [0; NB_BANDS];
+        tf_decode(start, end, is_transient, &mut tf_res, lm, dec);
+        tell = dec.tell();
+        let spread = if tell + 4 <= total_bits { dec.icdf(&SPREAD_ICDF, 5) } else { SPREAD_NORMAL };
+
+        let mut cap = [0; NB_BANDS];
+        for (i, cap) in cap.iter_mut().enumerate() {
+            let width = ((EBANDS[i + 1] - EBANDS[i]) as i32) << lm;
+            *cap = ((CACHE_CAPS[NB_BANDS * (2 * lm + c_count - 1) + i] as i32 + 64) * c_count as i32 * width) >> 2;
+        }
+        let mut offsets = [0; NB_BANDS];
+        let mut dynalloc_logp = 6;
+        total_bits <<= BITRES;
+        let mut tell = dec.tell_frac();
+        for i in start..end {
+            let width = (c_count as i32 * (EBANDS[i + 1] - EBANDS[i]) as i32) << lm;
+            let quanta = i32::min(width << BITRES, i32::max(6 << BITRES, width));
+            let mut loop_logp = dynalloc_logp;
+            let mut boost = 0;
+            while tell + ((loop_logp as i32) << BITRES) < total_bits && boost < cap[i] {
+                let flag = dec.bit_logp(loop_logp);
+                tell = dec.tell_frac();
+                if !flag {
+                    break;
+                }
+                boost += quanta;
+                total_bits -= quanta;
+                loop_logp = 1;
+            }
+            offsets[i] = boost;
+            if boost > 0 {
+                dynalloc_logp = u32::max(2, dynalloc_logp - 1);
+            }
+        }
+        let alloc_trim = if tell + (6 << BITRES) <= total_bits { dec.icdf(&TRIM_ICDF, 7) as i32 } else { 5 };
+        let mut bits = ((len * 8) << BITRES) - dec.tell_frac() - 1;
+        let anti_collapse_rsv = if is_transient && lm >= 2 && bits >= (lm as i32 + 2) << BITRES { 1 << BITRES } else { 0 };
+        bits -= anti_collapse_rsv;
+
+        let mut alloc = Allocation::default();
+        let coded_bands = compute_allocation(start, end, &offsets, &cap, alloc_trim, bits, c_count, lm, dec, &mut alloc);
+        unquant_fine_energy(start, end, &mut self.old_band_e, &alloc.fine_quant, dec, c_count);
+
+        for c in 0..self.channels {
+            self.decode_mem[c].copy_within(n..DECODE_BUFFER_SIZE + OVERLAP / 2, 0);
+        }
+        let mut collapse_masks = [0u8; 2 * NB_BANDS];
+        let mut x = vec![0.; c_count * n];
+        let mut seed = self.rng;
+        quant_all_bands(
+            &mut BandParams {
+                start,
+                end,
+                short_blocks,
+                spread,
+                dual_stereo: alloc.dual_stereo,
+                intensity: alloc.intensity,
+                tf_res: &tf_res,
+                total_bits: len * (8 << BITRES) - anti_collapse_rsv,
+                balance: alloc.balance,
+                lm,
+                coded_bands,
+                disable_inv: self.disable_inv,
+            },
+            &mut x,
+            c_count,
+            &mut collapse_masks,
+            &alloc.pulses,
+            dec,
+            &mut seed,
+        );
+        self.rng = seed;
+        let anti_collapse_on = anti_collapse_rsv > 0 && dec.bits(1) == 1;
+        let bits_left = len * 8 - dec.tell();
+        unquant_energy_finalise(start, end, &mut self.old_band_e, &alloc.fine_quant, &alloc.fine_priority, bits_left, dec, c_count);
+        if anti_collapse_on {
+            anti_collapse(
+                &mut x,
+                &collapse_masks,
+                lm,
+                c_count,
+                n,
+                start,
+                end,
+                &self.old_band_e,
+                &self.old_log_e,
+                &self.old_log_e2,
+                &alloc.pulses,
+                self.rng,
+            );
+        }
+        if silence {
+            self.old_band_e = [-28.; 2 * NB_BANDS];
+        }
+        self.synthesis(&x, start, end.min(NB_BANDS), c_count, is_transient, lm, silence);
+
+        for c in 0..self.channels {
+            self.postfilter_period = self.postfilter_period.max(COMBFILTER_MIN_PERIOD);
+            self.postfilter_period_old = self.postfilter_period_old.max(COMBFILTER_MIN_PERIOD);
+            let mem = &mut self.decode_mem[c];
+            let syn = DECODE_BUFFER_SIZE - n;
+            comb_filter(
+                mem,
+                syn,
+                [self.postfilter_period_old, self.postfilter_period],
+                SHORT_MDCT_SIZE,
+                [self.postfilter_gain_old, self.postfilter_gain],
+                [self.postfilter_tapset_old, self.postfilter_tapset],
+                &self.window,
+            );
+            if lm != 0 {
+                comb_filter(
+                    mem,
+                    syn + SHORT_MDCT_SIZE,
+                    [self.postfilter_period, postfilter_pitch],
+                    n - SHORT_MDCT_SIZE,
+                    [self.postfilter_gain, postfilter_gain],
+                    [self.postfilter_tapset, postfilter_tapset],
+                    &self.window,
+                );
+            }
+        }
+        self.postfilter_period_old = self.postfilter_period;
+        self.postfilter_gain_old = self.postfilter_gain;
+        self.postfilter_tapset_old = self.postfilter_tapset;
+        self.postfilter_period = postfilter_pitch;
+        self.postfilter_gain = postfilter_gain;
+        self.postfilter_tapset = postfilter_tapset;
+        if lm != 0 {
+            self.postfilter_period_old = self.postfilter_period;
+            self.postfilter_gain_old = self.postfilter_gain;
+            self.postfilter_tapset_old = self.postfilter_tapset;
+        }
+
+        if c_count == 1 {
+            self.old_band_e.copy_within(0..NB_BANDS, NB_BANDS);
+        }
+        if !is_transient {
+            self.old_log_e2 = self.old_log_e;
+            self.old_log_e = self.old_band_e;
+            let max_increase = if self.loss_count < 10 { m as f32 * 0.001 } else { 1. };
+            for i in 0..2 * NB_BANDS {
+                self.background_log_e[i] = f32::min(self.background_log_e[i] + max_increase, self.old_band_e[i]);
+            }
+        } else {
+            for i in 0..2 * NB_BANDS {
+                self.old_log_e[i] = self.old_log_e[i].min(self.old_band_e[i]);
+            }
+        }
+        for c in 0..2 {
+            for i in (0..start).chain(end..NB_BANDS) {
+                self.old_band_e[c * NB_BANDS + i] = 0.;
+                self.old_log_e[c * NB_BANDS + i] = -28.;
+                self.old_log_e2[c * NB_BANDS + i] = -28.;
+            }
+        }
+        self.rng = dec.range();
+        self.deemphasis(n, out);
+        self.loss_count = 0;
+    }
+
+    fn unquant_coarse_energy(&mut self, dec: &mut RangeDecoder, intra: bool, c_count: usize, lm: usize) {
+        let prob_model = &E_PROB_MODEL[lm][intra as usize];
+        let (coef, beta) = if intra { (0., BETA_INTRA) } else { (PRED_COEF[lm], BETA_COEF[lm]) };
+        let mut prev = [0f32; 2];
+        let budget = dec.storage() as i32 * 8;
+        for i in self.start..self.end {
+            for (c, prev) in prev.iter_mut().enumerate().take(c_count) {
+                let tell = dec.tell();
+                let qi = if budget - tell >= 15 {
+                    let pi = 2 * i.min(20);
+                    laplace_decode(dec, (prob_model[pi] as u32) << 7, (prob_model[pi + 1] as i32) << 6)
+                } else if budget - tell >= 2 {
+                    let qi = dec.icdf(&SMALL_ENERGY_ICDF, 2) as i32;
+                    (qi >> 1) ^ -(qi & 1)
+                } else if budget - tell >= 1 {
+                    -(dec.bit_logp(1) as i32)
+                } else {
+                    -1
+                };
+                let q = qi as f32;
+                let old = &mut self.old_band_e[i + c * NB_BANDS];
+                *old = old.max(-9.);
+                *old = coef * *old + *prev + q;
+                *prev += q - beta * q;
+            }
+        }
+    }
+
+    // inverse MDCTs of the denormalised bands into the end of decode_mem, overlap-added with the
+    // previous frame
+    #[allow(clippy::too_many_arguments)]
+    fn synthesis(&mut self, x: &[f32], start: usize, eff_end: usize, c_count: usize, is_transient: bool, lm: usize, silence: bool) {
+        let m = 1 << lm;
+        let n = m * SHORT_MDCT_SIZE;
+        let (blocks, shift) = if is_transient { (m, 0) } else { (1, lm) };
+        let mut freq = vec![0.; n];
+        if self.channels == 2 && c_count == 1 {
+            denormalise_bands(x, &mut freq, &self.old_band_e[..NB_BANDS], start, eff_end, m, silence);
+            for c in 0..2 {
+                self.imdct(&freq, c, blocks, shift, n);
+            }
+        } else if self.channels == 1 && c_count == 2 {
+            let mut freq2 = vec![0.; n];
+            denormalise_bands(x, &mut freq, &self.old_band_e[..NB_BANDS], start, eff_end, m, silence);
+            denormalise_bands(&x[n..], &mut freq2, &self.old_band_e[NB_BANDS..], start, eff_end, m, silence);
+            for (f, f2) in freq.iter_mut().zip(&freq2) {
+                *f = 0.5 * *f + 0.5 * f2;
+            }
+            self.imdct(&freq, 0, blocks, shift, n);
+        } else {
+            for c in 0..self.channels {
+                let bands = &self.old_band_e[c * NB_BANDS..(c + 1) * NB_BANDS];
+                denormalise_bands(&x[c * n..], &mut freq, bands, start, eff_end, m, silence);
+                self.imdct(&freq, c, blocks, shift, n);
+            }
+        }
+    }
+
+    // the blocks of a frame, each an inverse MDCT whose middle half is stored and whose first
+    // `OVERLAP` samples are windowed and folded with the pending ones of the previous block
+    fn imdct(&mut self, freq: &[f32], c: usize, blocks: usize, shift: usize, n: usize) {
+        let block_len = SHORT_MDCT_SIZE << shift;
+        let imdct = &mut self.imdcts[shift];
+        let mut coefs = vec![0.; block_len];
+        let mut y = vec![0.; 2 * block_len];
+        let mem = &mut self.decode_mem[c];
+        let w = &self.window;
+        for b in 0..blocks {
+            for (k, coef) in coefs.iter_mut().enumerate() {
+                *coef = freq[b + blocks * k];
+            }
+            imdct.process(&coefs, &mut y);
+            let base = DECODE_BUFFER_SIZE - n + block_len * b;
+            mem[base + OVERLAP / 2..base + OVERLAP / 2 + block_len].copy_from_slice(&y[block_len / 2..block_len / 2 + block_len]);
+            for i in 0..OVERLAP / 2 {
+                let x1 = mem[base + OVERLAP - 1 - i];
+                let x2 = mem[base + i];
+                mem[base + i] = w[OVERLAP - 1 - i] * x2 - w[i] * x1;
+                mem[base + OVERLAP - 1 - i] = w[i] * x2 + w[OVERLAP - 1 - i] * x1;
+            }
+        }
+    }
+
+    fn deemphasis(&mut self, n: usize, out: &mut [f32]) {
+        let channels = self.channels;
+        for c in 0..channels {
+            let mut m = self.preemph_mem[c];
+            let x = &self.decode_mem[c][DECODE_BUFFER_SIZE - n..DECODE_BUFFER_SIZE];
+            for (j, &x) in x.iter().enumerate() {
+                let tmp = x + 1e-30 + m;
+                m = PREEMPHASIS * tmp;
+                out[j * channels + c] = tmp * (1. / SIGNAL_SCALE);
+            }
+            self.preemph_mem[c] = m;
+        }
+    }
+
+    fn decode_lost(&mut self, n: usize) {
+        let channels = self.channels;
+        let lm = (n / SHORT_MDCT_SIZE).trailing_zeros() as usize;
+        let noise_based = self.loss_count >= 5 || self.start != 0 || self.skip_plc;
+        if noise_based {
+            let (start, end) = (self.start, self.end);
+            let eff_end = start.max(end.min(NB_BANDS));
+            let decay = if self.loss_count == 0 { 1.5 } else { 0.5 };
+            for c in 0..channels {
+                for i in start..end {
+                    let band = &mut self.old_band_e[c * NB_BANDS + i];
+                    *band = self.background_log_e[c * NB_BANDS + i].max(*band - decay);
+                }
+            }
+            let mut seed = self.rng;
+            let mut x = vec![0.; channels * n];
+            for c in 0..channels {
+                for i in start..eff_end {
+                    let band = &mut x[n * c + ((EBANDS[i] as usize) << lm)..n * c + ((EBANDS[i + 1] as usize) << lm)];
+                    for x in band.iter_mut() {
+                        seed = lcg_rand(seed);
+                        *x = (seed as i32 >> 20) as f32;
+                    }
+                    renormalise_vector(band, 1.);
+                }
+            }
+            self.rng = seed;
+            for c in 0..channels {
+                self.decode_mem[c].copy_within(n..DECODE_BUFFER_SIZE + OVERLAP / 2, 0);
+            }
+            self.synthesis(&x, start, eff_end, channels, false, lm, false);
+        } else {
+            let mut fade = 1.;
+            let pitch_index = if self.loss_count == 0 {
+                self.last_pitch_index = self.plc_pitch_search();
+                self.last_pitch_index
+            } else {
+                fade = 0.8;
+                self.last_pitch_index
+            };
+            let exc_length = usize::min(2 * pitch_index, MAX_PERIOD);
+            let window = self.window;
+            for c in 0..channels {
+                let buf = &mut self.decode_mem[c];
+                // the excitation, with LPC_ORDER samples of history before it
+                let mut exc: Vec<f32> = buf[DECODE_BUFFER_SIZE - MAX_PERIOD - LPC_ORDER..DECODE_BUFFER_SIZE].to_vec();
+                if self.loss_count == 0 {
+                    let mut ac = autocorr(&exc[LPC_ORDER..], Some(&window), LPC_ORDER);
+                    ac[0] *= 1.0001;
+                    for (i, ac) in ac.iter_mut().enumerate().skip(1) {
+                        *ac -= *ac * (0.008 * 0.008) * i as f32 * i as f32;
+                    }
+                    celt_lpc(&mut self.lpc[c], &ac);
+                }
+                let lpc = &self.lpc[c];
+                let exc_start = LPC_ORDER + MAX_PERIOD - exc_length;
+                let filtered: Vec<f32> = (exc_start..exc_start + exc_length)
+                    .map(|i| exc[i] + lpc.iter().enumerate().map(|(k, &a)| a * exc[i - k - 1]).sum::<f32>())
+                    .collect();
+                exc[exc_start..exc_start + exc_length].copy_from_slice(&filtered);
+
+                let decay = {
+                    let decay_length = exc_length >> 1;
+                    let (mut e1, mut e2) = (1f32, 1f32);
+                    for i in 0..decay_length {
+                        let e = exc[LPC_ORDER + MAX_PERIOD - decay_length + i];
+                        e1 += e * e;
+                        let e = exc[LPC_ORDER + MAX_PERIOD - 2 * decay_length + i];
+                        e2 += e * e;
+                    }
+                    (e1.min(e2) / e2).sqrt()
+                };
+                buf.copy_within(n..DECODE_BUFFER_SIZE, 0);
+                let extrapolation_offset = MAX_PERIOD - pitch_index;
+                let extrapolation_len = n + OVERLAP;
+                let mut attenuation = fade * decay;
+                let mut s1 = 0f32;
+                let mut j = 0;
+                for i in 0..extrapolation_len {
+                    if j >= pitch_index {
+                        j -= pitch_index;
+                        attenuation *= decay;
+                    }
+                    buf[DECODE_BUFFER_SIZE - n + i] = attenuation * exc[LPC_ORDER + extrapolation_offset + j];
+                    let tmp = buf[DECODE_BUFFER_SIZE - MAX_PERIOD - n + extrapolation_offset + j];
+                    s1 += tmp * tmp;
+                    j += 1;
+                }
+                let mut lpc_mem = [0.; LPC_ORDER];
+                for (i, mem) in lpc_mem.iter_mut().enumerate() {
+                    *mem = buf[DECODE_BUFFER_SIZE - n - 1 - i];
+                }
+                celt_iir(&mut buf[DECODE_BUFFER_SIZE - n..DECODE_BUFFER_SIZE - n + extrapolation_len], lpc, &lpc_mem);
+
+                let s2: f32 = buf[DECODE_BUFFER_SIZE - n..DECODE_BUFFER_SIZE - n + extrapolation_len].iter().map(|x| x * x).sum();
+                let extrapolated = &mut buf[DECODE_BUFFER_SIZE - n..DECODE_BUFFER_SIZE - n + extrapolation_len];
+                // written so a NaN energy also clears the extrapolation
+                if s1.partial_cmp(&(0.2 * s2)) != Some(std::cmp::Ordering::Greater) {
+                    extrapolated.fill(0.);
+                } else if s1 < s2 {
+                    let ratio = ((s1 + 1.) / (s2 + 1.)).sqrt();
+                    for (i, x) in extrapolated.iter_mut().enumerate() {
+                        *x *= if i < OVERLAP { 1. - window[i] * (1. - ratio) } else { ratio };
+                    }
+                }
+
+                // the post-filter is undone on the overlap, which the next frame folds in
+                let mut etmp = [0.; OVERLAP];
+                comb_filter_const(
+                    &mut etmp,
+                    buf,
+                    DECODE_BUFFER_SIZE,
+                    self.postfilter_period,
+                    -self.postfilter_gain,
+                    self.postfilter_tapset,
+                );
+                for i in 0..OVERLAP / 2 {
+                    buf[DECODE_BUFFER_SIZE + i] = window[i] * etmp[OVERLAP - 1 - i] + window[OVERLAP - i - 1] * etmp[i];
+                }
+            }
+        }
+        self.loss_count += 1;
+    }
+
+    fn plc_pitch_search(&self) -> usize {
+        let mut lp = vec![0.; DECODE_BUFFER_SIZE >> 1];
+        pitch_downsample(&self.decode_mem[..self.channels], &mut lp);
+        let pitch = pitch_search(
+            &lp[PLC_PITCH_LAG_MAX >> 1..],
+            &lp,
+            DECODE_BUFFER_SIZE - PLC_PITCH_LAG_MAX,
+            PLC_PITCH_LAG_MAX - PLC_PITCH_LAG_MIN,
+        );
+        PLC_PITCH_LAG_MAX - pitch
+    }
+}
+
+fn lcg_rand(seed: u32) -> u32 {
+    seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223)
+}
+
+fn exp2(x: f32) -> f32 {
+    (LN_2 * x as f64).exp() as f32
+}
+
+// (16384 + a * b) >> 15 on 16-bit operands
+fn frac_mul16(a: i32, b: i32) -> i32 {
+    (16384 + (a as i16 as i32) * (b as i16 as i32)) >> 15
+}
+
+fn bitexact_cos(x: i32) -> i32 {
+    let tmp = (4096 + x * x) >> 13;
+    let x2 = tmp;
+    let x2 = (32767 - x2) + frac_mul16(x2, -7651 + frac_mul16(x2, 8277 + frac_mul16(-626, x2)));
+    1 + x2
+}
+
+fn bitexact_log2tan(isin: i32, icos: i32) -> i32 {
+    let lc = ilog(icos as u32) as i32;
+    let ls = ilog(isin as u32) as i32;
+    let icos = icos << (15 - lc);
+    let isin = isin << (15 - ls);
+    (ls - lc) * (1 << 11) + frac_mul16(isin, frac_mul16(isin, -2597) + 7932)
+        - frac_mul16(icos, frac_mul16(icos, -2597) + 7932)
+}
+
+fn laplace_decode(dec: &mut RangeDecoder, fs: u32, decay: i32) -> i32 {
+    const MIN_P: u32 = 1;
+    const N_MIN: u32 = 16;
+    let mut val = 0;
+    let mut fs = fs;
+    let fm = dec.decode_bin(15);
+    let mut fl = 0;
+    if fm >= fs {
+        val += 1;
+        fl = fs;
+        fs = (((32768 - MIN_P * 2 * N_MIN - fs) * (16384 - decay) as u32) >> 15) + MIN_P;
+        while fs > MIN_P && fm >= fl + 2 * fs {
+            fs *= 2;
+            fl += fs;
+            fs = ((fs - 2 * MIN_P) as i32 * decay) as u32 >> 15;
+            fs += MIN_P;
+            val += 1;
+        }
+        if fs <= MIN_P {
+            let di = (fm - fl) >> 1;
+            val += di as i32;
+            fl += 2 * di * MIN_P;
+        }
+        if fm < fl + fs {
+            val = -val;
+        } else {
+            fl += fs;
+        }
+    }
+    dec.update(fl, u32::min(fl + fs, 32768), 32768);
+    val
+}
+
+fn tf_decode(start: usize, end: usize, is_transient: bool, tf_res: &mut [i32], lm: usize, dec: &mut RangeDecoder) {
+    let mut budget = dec.storage() as u32 * 8;
+    let mut tell = dec.tell() as u32;
+    let mut logp = if is_transient { 2 } else { 4 };
+    let tf_select_rsv = lm > 0 && tell + logp < budget;
+    budget -= tf_select_rsv as u32;
+    let mut tf_changed = 0;
+    let mut curr = 0;
+    for res in &mut tf_res[start..end] {
+        if tell + logp <= budget {
+            curr ^= dec.bit_logp(logp) as i32;
+            tell = dec.tell() as u32;
+            tf_changed |= curr;
+        }
+        *res = curr;
+        logp = if is_transient { 4 } else { 5 };
+    }
+    let row = &TF_SELECT_TABLE[lm];
+    let base = 4 * is_transient as usize;
+    let mut tf_select = 0;
+    if tf_select_rsv && row[base + tf_changed as usize] != row[base + 2 + tf_changed as usize] {
+        tf_select = dec.bit_logp(1) as usize;
+    }
+    for res in &mut tf_res[start..end] {
+        *res = row[base + 2 * tf_select + *res as usize] as i32;
+    }
+}
+
+#[derive(Default)]
+struct Allocation {
+    pulses: [i32; NB_BANDS],
+    fine_quant: [i32; NB_BANDS],
+    fine_priority: [i32; NB_BANDS],
+    intensity: usize,
+    dual_stereo: bool,
+    balance: i32,
+}
+
+// the bits of each band, RFC 6716 section 4.3.3. Returns the number of coded bands.
+#[allow(clippy::too_many_arguments)]
+fn compute_allocation(
+    start: usize,
+    end: usize,
+    offsets: &[i32],
+    cap: &[i32],
+    alloc_trim: i32,
+    total: i32,
+    c_count: usize,
+    lm: usize,
+    dec: &mut RangeDecoder,
+    alloc: &mut Allocation,
+) -> usize {
+    let c = c_count as i32;
+    let lm_i = lm as i32;
+    let width = |j: usize| (EBANDS[j + 1] - EBANDS[j]) as i32;
+    let mut total = total.max(0);
+    let skip_rsv = if total >= 1 << BITRES { 1 << BITRES } else { 0 };
+    total -= skip_rsv;
+    let mut intensity_rsv = 0;
+    let mut dual_stereo_rsv = 0;
+    if c_count == 2 {
+        intensity_rsv = LOG2_FRAC_TABLE[end - start] as i32;
+        if intensity_rsv > total {
+            intensity_rsv = 0;
+        } else {
+            total -= intensity_rsv;
+            dual_stereo_rsv = if total >= 1 << BITRES { 1 << BITRES } else { 0 };
+            total -= dual_stereo_rsv;
+        }
+    }
+    let mut thresh = [0; NB_BANDS];
+    let mut trim_offset = [0; NB_BANDS];
+    for j in start..end {
+        thresh[j] = i32::max(c << BITRES, (3 * width(j)) << lm << BITRES >> 4);
+        trim_offset[j] = (c * width(j) * (alloc_trim - 5 - lm_i) * (end - j - 1) as i32 * (1 << (lm_i + BITRES))) >> 6;
+        if width(j) << lm == 1 {
+            trim_offset[j] -= c << BITRES;
+        }
+    }
+    let alloc_vectors = BAND_ALLOCATION.len();
+    let band_bits = |q: usize, j: usize| (c * width(j) * BAND_ALLOCATION[q][j] as i32) << lm >> 2;
+    let mut lo = 1;
+    let mut hi = alloc_vectors - 1;
+    loop {
+        let mut done = false;
+        let mut psum = 0;
+        let mid = (lo + hi) >> 1;
+        for j in (start..end).rev() {
+            let mut bits = band_bits(mid, j);
+            if bits > 0 {
+                bits = i32::max(0, bits + trim_offset[j]);
+            }
+            bits += offsets[j];
+            if bits >= thresh[j] || done {
+                done = true;
+                psum += bits.min(cap[j]);
+            } else if bits >= c << BITRES {
+                psum += c << BITRES;
+            }
+        }
+        if psum > total {
+            if mid == 0 {
+                break;
+            }
+            hi = mid - 1;
+        } else {
+            lo = mid + 1;
+        }
+        if lo > hi {
+            break;
+        }
+    }
+    hi = lo;
+    lo -= 1;
+    let mut bits1 = [0; NB_BANDS];
+    let mut bits2 = [0; NB_BANDS];
+    let mut skip_start = start;
+    for j in start..end {
+        let mut bits1j = band_bits(lo, j);
+        let mut bits2j = if hi >= alloc_vectors { cap[j] } else { band_bits(hi, j) };
+        if bits1j > 0 {
+            bits1j = i32::max(0, bits1j + trim_offset[j]);
+        }
+        if bits2j > 0 {
+            bits2j = i32::max(0, bits2j + trim_offset[j]);
+        }
+        if lo > 0 {
+            bits1j += offsets[j];
+        }
+        bits2j += offsets[j];
+        if offsets[j] > 0 {
+            skip_start = j;
+        }
+        bits1[j] = bits1j;
+        bits2[j] = i32::max(0, bits2j - bits1j);
+    }
+
+    // interpolates between the two allocation vectors
+    const ALLOC_STEPS: i32 = 6;
+    let alloc_floor = c << BITRES;
+    let stereo = (c_count > 1) as i32;
+    let log_m = lm_i << BITRES;
+    let mut lo = 0;
+    let mut hi = 1 << ALLOC_STEPS;
+    for _ in 0..ALLOC_STEPS {
+        let mid = (lo + hi) >> 1;
+        let mut psum = 0;
+        let mut done = false;
+        for j in (start..end).rev() {
+            let tmp = bits1[j] + ((mid * bits2[j]) >> ALLOC_STEPS);
+            if tmp >= thresh[j] || done {
+                done = true;
+                psum += tmp.min(cap[j]);
+            } else if tmp >= alloc_floor {
+                psum += alloc_floor;
+            }
+        }
+        if psum > total {
+            hi = mid;
+        } else {
+            lo = mid;
+        }
+    }
+    let bits = &mut alloc.pulses;
+    let ebits = &mut alloc.fine_quant;
+    let fine_priority = &mut alloc.fine_priority;
+    let mut psum = 0;
+    let mut done = false;
+    for j in (start..end).rev() {
+        let mut tmp = bits1[j] + ((lo * bits2[j]) >> ALLOC_STEPS);
+        if tmp < thresh[j] && !done {
+            tmp = if tmp >= alloc_floor { alloc_floor } else { 0 };
+        } else {
+            done = true;
+        }
+        tmp = tmp.min(cap[j]);
+        bits[j] = tmp;
+        psum += tmp;
+    }
+    let band_start = EBANDS[start] as i32;
+    let mut coded_bands = end;
+    loop {
+        let j = coded_bands - 1;
+        if j <= skip_start {
+            total += skip_rsv;
+            break;
+        }
+        let mut left = total - psum;
+        let percoeff = left / (EBANDS[coded_bands] as i32 - band_start);
+        left -= (EBANDS[coded_bands] as i32 - band_start) * percoeff;
+        let rem = i32::max(left - (EBANDS[j] as i32 - band_start), 0);
+        let band_width = (EBANDS[coded_bands] - EBANDS[j]) as i32;
+        let mut band_bits = bits[j] + percoeff * band_width + rem;
+        if band_bits >= i32::max(thresh[j], alloc_floor + (1 << BITRES)) {
+            if dec.bit_logp(1) {
+                break;
+            }
+            psum += 1 << BITRES;
+            band_bits -= 1 << BITRES;
+        }
+        psum -= bits[j] + intensity_rsv;
+        if intensity_rsv > 0 {
+            intensity_rsv = LOG2_FRAC_TABLE[j - start] as i32;
+        }
+        psum += intensity_rsv;
+        if band_bits >= alloc_floor {
+            psum += alloc_floor;
+            bits[j] = alloc_floor;
+        } else {
+            bits[j] = 0;
+        }
+        coded_bands -= 1;
+    }
+    alloc.intensity = if intensity_rsv > 0 { start + dec.uint((coded_bands + 1 - start) as u32) as usize } else { 0 };
+    if alloc.intensity <= start {
+        total += dual_stereo_rsv;
+        dual_stereo_rsv = 0;
+    }
+    alloc.dual_stereo = dual_stereo_rsv > 0 && dec.bit_logp(1);
+
+    let mut left = total - psum;
+    let percoeff = left / (EBANDS[coded_bands] as i32 - band_start);
+    left -= (EBANDS[coded_bands] as i32 - band_start) * percoeff;
+    for (j, bits) in bits.iter_mut().enumerate().take(coded_bands).skip(start) {
+        *bits += percoeff * width(j);
+    }
+    for (j, bits) in bits.iter_mut().enumerate().take(coded_bands).skip(start) {
+        let tmp = left.min(width(j));
+        *bits += tmp;
+        left -= tmp;
+    }
+    let mut balance = 0;
+    for j in start..coded_bands {
+        let n0 = width(j);
+        let n = n0 << lm;
+        let bit = bits[j] + balance;
+        let mut excess;
+        if n > 1 {
+            excess = i32::max(bit - cap[j], 0);
+            bits[j] = bit - excess;
+            let den = c * n + (c_count == 2 && n > 2 && !alloc.dual_stereo && j < alloc.intensity) as i32;
+            let nclogn = den * (LOG_N[j] as i32 + log_m);
+            let mut offset = (nclogn >> 1) - den * FINE_OFFSET;
+            if n == 2 {
+                offset += den << BITRES >> 2;
+            }
+            if bits[j] + offset < (den * 2) << BITRES {
+                offset += nclogn >> 2;
+            } else if bits[j] + offset < (den * 3) << BITRES {
+                offset += nclogn >> 3;
+            }
+            ebits[j] = i32::max(0, bits[j] + offset + (den << (BITRES - 1)));
+            ebits[j] = (ebits[j] / den) >> BITRES;
+            if c * ebits[j] > bits[j] >> BITRES {
+                ebits[j] = bits[j] >> stereo >> BITRES;
+            }
+            ebits[j] = ebits[j].min(MAX_FINE_BITS);
+            fine_priority[j] = (ebits[j] * (den << BITRES) >= bits[j] + offset) as i32;
+            bits[j] -= (c * ebits[j]) << BITRES;
+        } else {
+            excess = i32::max(0, bit - (c << BITRES));
+            bits[j] = bit - excess;
+            ebits[j] = 0;
+            fine_priority[j] = 1;
+        }
+        if excess > 0 {
+            let extra_fine = i32::min(excess >> (stereo + BITRES), MAX_FINE_BITS - ebits[j]);
+            ebits[j] += extra_fine;
+            let extra_bits = (extra_fine * c) << BITRES;
+            fine_priority[j] = (extra_bits >= excess - balance) as i32;
+            excess -= extra_bits;
+        }
+        balance = excess;
+    }
+    alloc.balance = balance;
+    for j in coded_bands..end {
+        ebits[j] = bits[j] >> stereo >> BITRES;
+        bits[j] = 0;
+        fine_priority[j] = (ebits[j] < 1) as i32;
+    }
+    coded_bands
+}
+
+fn unquant_fine_energy(start: usize, end: usize, old_band_e: &mut [f32], fine_quant: &[i32], dec: &mut RangeDecoder, c_count: usize) {
+    for i in start..end {
+        if fine_quant[i] <= 0 {
+            continue;
+        }
+        for c in 0..c_count {
+            let q2 = dec.bits(fine_quant[i] as u32);
+            let offset = (q2 as f32 + 0.5) * (1 << (14 - fine_quant[i])) as f32 * (1. / 16384.) - 0.5;
+            old_band_e[i + c * NB_BANDS] += offset;
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn unquant_energy_finalise(
+    start: usize,
+    end: usize,
+    old_band_e: &mut [f32],
+    fine_quant: &[i32],
+    fine_priority: &[i32],
+    mut bits_left: i32,
+    dec: &mut RangeDecoder,
+    c_count: usize,
+) {
+    for prio in 0..2 {
+        for i in start..end {
+            if bits_left < c_count as i32 {
+                break;
+            }
+            if fine_quant[i] >= MAX_FINE_BITS || fine_priority[i] != prio {
+                continue;
+            }
+            for c in 0..c_count {
+                let q2 = dec.bits(1);
+                let offset = (q2 as f32 - 0.5) * (1 << (14 - fine_quant[i] - 1)) as f32 * (1. / 16384.);
+                old_band_e[i + c * NB_BANDS] += offset;
+                bits_left -= 1;
+            }
+        }
+    }
+}
+
+fn denormalise_bands(x: &[f32], freq: &mut [f32], band_log_e: &[f32], start: usize, end: usize, m: usize, silence: bool) {
+    let n = m * SHORT_MDCT_SIZE;
+    let (start, end, bound) = if silence { (0, 0, 0) } else { (start, end, m * EBANDS[end] as usize) };
+    freq[..m * EBANDS[start] as usize].fill(0.);
+    for i in start..end {
+        let g = exp2(f32::min(32., band_log_e[i] + E_MEANS[i]));
+        for j in m * EBANDS[i] as usize..m * EBANDS[i + 1] as usize {
+            freq[j] = x[j] * g;
+        }
+    }
+    freq[bound..n].fill(0.);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn anti_collapse(
+    x: &mut [f32],
+    collapse_masks: &[u8],
+    lm: usize,
+    c_count: usize,
+    size: usize,
+    start: usize,
+    end: usize,
+    log_e: &[f32],
+    prev1_log_e: &[f32],
+    prev2_log_e: &[f32],
+    pulses: &[i32],
+    mut seed: u32,
+) {
+    for i in start..end {
+        let n0 = (EBANDS[i + 1] - EBANDS[i]) as usize;
+        let depth = ((1 + pulses[i]) as u32 / n0 as u32) >> lm;
+        let thresh = 0.5 * exp2(-0.125 * depth as f32);
+        let sqrt_1 = 1. / ((n0 << lm) as f32).sqrt();
+        for c in 0..c_count {
+            let mut prev1 = prev1_log_e[c * NB_BANDS + i];
+            let mut prev2 = prev2_log_e[c * NB_BANDS + i];
+            if c_count == 1 {
+                prev1 = prev1.max(prev1_log_e[NB_BANDS + i]);
+                prev2 = prev2.max(prev2_log_e[NB_BANDS + i]);
+            }
+            let ediff = f32::max(0., log_e[c * NB_BANDS + i] - prev1.min(prev2));
+            let mut r = 2. * exp2(-ediff);
+            if lm == 3 {
+                r *= std::f32::consts::SQRT_2;
+            }
+            let r = r.min(thresh) * sqrt_1;
+            let band = &mut x[c * size + ((EBANDS[i] as usize) << lm)..c * size + ((EBANDS[i + 1] as usize) << lm)];
+            let mut renormalize = false;
+            for k in 0..1 << lm {
+                if collapse_masks[i * c_count + c] & 1 << k == 0 {
+                    for j in 0..n0 {
+                        seed = lcg_rand(seed);
+                        band[(j << lm) + k] = if seed & 0x8000 != 0 { r } else { -r };
+                    }
+                    renormalize = true;
+                }
+            }
+            if renormalize {
+                renormalise_vector(band, 1.);
+            }
+        }
+    }
+}
+
+fn renormalise_vector(x: &mut [f32], gain: f32) {
+    let e = 1e-15 + x.iter().map(|x| x * x).sum::<f32>();
+    let g = gain * (1. / e.sqrt());
+    for x in x {
+        *x *= g;
+    }
+}
+
+fn bits2pulses(band: usize, lm: i32, bits: i32) -> i32 {
+    let cache = &CACHE_BITS[CACHE_INDEX[(lm + 1) as usize * NB_BANDS + band] as usize..];
+    let mut lo = 0;
+    let mut hi = cache[0] as i32;
+    let bits = bits - 1;
+    for _ in 0..6 {
+        let mid = (lo + hi + 1) >> 1;
+        if cache[mid as usize] as i32 >= bits {
+            hi = mid;
+        } else {
+            lo = mid;
+        }
+    }
+    let lo_bits = if lo == 0 { -1 } else { cache[lo as usize] as i32 };
+    if bits - lo_bits <= cache[hi as usize] as i32 - bits {
+        lo
+    } else {
+        hi
+    }
+}
+
+fn pulses2bits(band: usize, lm: i32, pulses: i32) -> i32 {
+    let cache = &CACHE_BITS[CACHE_INDEX[(lm + 1) as usize * NB_BANDS + band] as usize..];
+    if pulses == 0 {
+        0
+    } else {
+        cache[pulses as usize] as i32 + 1
+    }
+}
+
+fn get_pulses(i: i32) -> i32 {
+    if i < 8 {
+        i
+    } else {
+        (8 + (i & 7)) << ((i >> 3) - 1)
+    }
+}
+
+// the pulse vector with index i of the ones with k pulses in n dimensions (RFC 6716 section
+// 4.3.4.2), using one row of the U(n, k) table that is updated as the dimensions are decoded
+fn decode_pulses(y: &mut [i32], n: usize, k: usize, dec: &mut RangeDecoder) -> f32 {
+    let mut u = vec![0u32; k + 2];
+    u[1] = 1;
+    for (j, u) in u.iter_mut().enumerate().skip(2) {
+        *u = (j as u32) * 2 - 1;
+    }
+    for _ in 2..n {
+        // the next row of U(n, k) = U(n - 1, k) + U(n, k - 1) + U(n - 1, k - 1)
+        let mut ui0 = 1u32;
+        for j in 2..k + 2 {
+            let ui1 = u[j].wrapping_add(u[j - 1]).wrapping_add(ui0);
+            u[j - 1] = ui0;
+            ui0 = ui1;
+        }
+        u[k + 1] = ui0;
+    }
+    let total = u[k].wrapping_add(u[k + 1]);
+    let mut i = dec.uint(total);
+    let mut k = k;
+    let mut yy = 0.;
+    for y in y.iter_mut().take(n) {
+        let p = u[k + 1];
+        let negative = i >= p;
+        if negative {
+            i -= p;
+        }
+        let yj = k;
+        let mut p = u[k];
+        while p > i {
+            k -= 1;
+            p = u[k];
+        }
+        i -= p;
+        let val = (yj - k) as i32;
+        *y = if negative { -val } else { val };
+        yy += (val * val) as f32;
+        // back to the previous row of U
+        let mut ui0 = 0u32;
+        for j in 1..k + 2 {
+            let ui1 = u[j].wrapping_sub(u[j - 1]).wrapping_sub(ui0);
+            u[j - 1] = ui0;
+            ui0 = ui1;
+        }
+        u[k + 1] = ui0;
+    }
+    yy
+}
+
+fn exp_rotation1(x: &mut [f32], len: usize, stride: usize, c: f32, s: f32) {
+    let ms = -s;
+    for i in 0..len - stride {
+        let x1 = x[i];
+        let x2 = x[i + stride];
+        x[i + stride] = c * x2 + s * x1;
+        x[i] = c * x1 + ms * x2;
+    }
+    if len > 2 * stride {
+        for i in (0..len - 2 * stride).rev() {
+            let x1 = x[i];
+            let x2 = x[i + stride];
+            x[i + stride] = c * x2 + s * x1;
+            x[i] = c * x1 + ms * x2;
+        }
+    }
+}
+
+// undoes the spreading rotation of the encoder
+fn exp_rotation(x: &mut [f32], len: usize, stride: usize, k: usize, spread: usize) {
+    if 2 * k >= len || spread == SPREAD_NONE {
+        return;
+    }
+    let factor = SPREAD_FACTOR[spread - 1];
+    let gain = len as f32 / (len as i32 + factor * k as i32) as f32;
+    let theta = 0.5 * (gain * gain);
+    let c = (0.5 * PI * theta as f64).cos() as f32;
+    let s = (0.5 * PI * (1. - theta) as f64).cos() as f32;
+    let mut stride2 = 0;
+    if len >= 8 * stride {
+        stride2 = 1;
+        while (stride2 * stride2 + stride2) * stride + (stride >> 2) < len {
+            stride2 += 1;
+        }
+    }
+    let len = len / stride;
+    for i in 0..stride {
+        let x = &mut x[i * len..(i + 1) * len];
+        if stride2 != 0 {
+            exp_rotation1(x, len, stride2, s, c);
+        }
+        exp_rotation1(x, len, 1, c, s);
+    }
+}
+
+fn alg_unquant(x: &mut [f32], n: usize, k: usize, spread: usize, b: usize, dec: &mut RangeDecoder, gain: f32) -> u32 {
+    let mut iy = vec![0; n];
+    let ryy = decode_pulses(&mut iy, n, k, dec);
+    let g = gain * (1. / ryy.sqrt());
+    for (x, &y) in x.iter_mut().zip(&iy) {
+        *x = g * y as f32;
+    }
+    exp_rotation(x, n, b, k, spread);
+    if b <= 1 {
+        return 1;
+    }
+    let n0 = n / b;
+    (0..b).filter(|&i| iy[i * n0..(i + 1) * n0].iter().any(|&y| y != 0)).fold(0, |mask, i| mask | 1 << i)
+}
+
+fn haar1(x: &mut [f32], n0: usize, stride: usize) {
+    for i in 0..stride {
+        for j in 0..n0 >> 1 {
+            let tmp1 = std::f32::consts::FRAC_1_SQRT_2 * x[stride * 2 * j + i];
+            let tmp2 = std::f32::consts::FRAC_1_SQRT_2 * x[stride * (2 * j + 1) + i];
+            x[stride * 2 * j + i] = tmp1 + tmp2;
+            x[stride * (2 * j + 1) + i] = tmp1 - tmp2;
+        }
+    }
+}
+
+fn deinterleave_hadamard(x: &mut [f32], n0: usize, stride: usize, hadamard: bool) {
+    let mut tmp = vec![0.; n0 * stride];
+    for i in 0..stride {
+        let row = if hadamard { ORDERY_TABLE[stride - 2 + i] } else { i };
+        for j in 0..n0 {
+            tmp[row * n0 + j] = x[j * stride + i];
+        }
+    }
+    x[..n0 * stride].copy_from_slice(&tmp);
+}
+
+fn interleave_hadamard(x: &mut [f32], n0: usize, stride: usize, hadamard: bool) {
+    let mut tmp = vec![0.; n0 * stride];
+    for i in 0..stride {
+        let row = if hadamard { ORDERY_TABLE[stride - 2 + i] } else { i };
+        for j in 0..n0 {
+            tmp[j * stride + i] = x[row * n0 + j];
+        }
+    }
+    x[..n0 * stride].copy_from_slice(&tmp);
+}
+
+fn compute_qn(n: i32, b: i32, offset: i32, pulse_cap: i32, stereo: bool) -> i32 {
+    let mut n2 = 2 * n - 1;
+    if stereo && n == 2 {
+        n2 -= 1;
+    }
+    let mut qb = (b + n2 * offset) / n2;
+    qb = qb.min(b - pulse_cap - (4 << BITRES));
+    qb = qb.min(8 << BITRES);
+    if qb < (1 << BITRES >> 1) {
+        1
+    } else {
+        let qn = EXP2_TABLE8[(qb & 7) as usize] >> (14 - (qb >> BITRES));
+        (qn + 1) >> 1 << 1
+    }
+}
+
+// the per-frame parameters of quant_all_bands
+struct BandParams<'a> {
+    start: usize,
+    end: usize,
+    short_blocks: usize,
+    spread: usize,
+    dual_stereo: bool,
+    intensity: usize,
+    tf_res: &'a [i32],
+    total_bits: i32,
+    balance: i32,
+    lm: usize,
+    coded_bands: usize,
+    disable_inv: bool,
+}
+
+// state while decoding the shapes of the bands
+struct BandContext<'a, 'b> {
+    dec: &'a mut RangeDecoder<'b>,
+    band: usize,
+    intensity: usize,
+    spread: usize,
+    tf_change: i32,
+    remaining_bits: i32,
+    seed: u32,
+    disable_inv: bool,
+    avoid_split_noise: bool,
+}
+
+struct Split {
+    inv: bool,
+    imid: i32,
+    iside: i32,
+    delta: i32,
+    itheta: i32,
+    qalloc: i32,
+}
+
+impl BandContext<'_, '_> {
+    // the angle between two halves of a band, or between the channels, RFC 6716 section 4.3.4.4
+    #[allow(clippy::too_many_arguments)]
+    fn compute_theta(&mut self, n: usize, b: &mut i32, blocks: usize, blocks0: usize, lm: i32, stereo: bool, fill: &mut u32) -> Split {
+        let n = n as i32;
+        let pulse_cap = LOG_N[self.band] as i32 + lm * (1 << BITRES);
+        let offset = (pulse_cap >> 1) - if stereo && n == 2 { 16 } else { 4 };
+        let mut qn = compute_qn(n, *b, offset, pulse_cap, stereo);
+        if stereo && self.band >= self.intensity {
+            qn = 1;
+        }
+        let dec = &mut *self.dec;
+        let tell = dec.tell_frac();
+        let mut itheta = 0;
+        let mut inv = false;
+        if qn != 1 {
+            if stereo && n > 2 {
+                let p0 = 3;
+                let x0 = qn as u32 / 2;
+                let ft = p0 * (x0 + 1) + x0;
+                let fs = dec.decode(ft);
+                let x = if fs < (x0 + 1) * p0 { fs / p0 } else { x0 + 1 + (fs - (x0 + 1) * p0) };
+                let (fl, fh) = if x <= x0 { (p0 * x, p0 * (x + 1)) } else { ((x - 1 - x0) + (x0 + 1) * p0, (x - x0) + (x0 + 1) * p0) };
+                dec.update(fl, fh, ft);
+                itheta = x as i32;
+            } else if blocks0 > 1 || stereo {
+                itheta = dec.uint(qn as u32 + 1) as i32;
+            } else {
+                let half = qn >> 1;
+                let ft = ((half + 1) * (half + 1)) as u32;
+                let fm = dec.decode(ft) as i32;
+                let (fl, fs);
+                if fm < ((half * (half + 1)) >> 1) {
+                    itheta = (((8 * fm + 1) as u32).isqrt() as i32 - 1) >> 1;
+                    fs = itheta + 1;
+                    fl = (itheta * (itheta + 1)) >> 1;
+                } else {
+                    itheta = (2 * (qn + 1) - ((8 * (ft as i32 - fm - 1) + 1) as u32).isqrt() as i32) >> 1;
+                    fs = qn + 1 - itheta;
+                    fl = ft as i32 - (((qn + 1 - itheta) * (qn + 2 - itheta)) >> 1);
+                }
+                dec.update(fl as u32, (fl + fs) as u32, ft);
+            }
+            itheta = itheta * 16384 / qn;
+        } else if stereo {
+            if *b > 2 << BITRES && self.remaining_bits > 2 << BITRES {
+                inv = dec.bit_logp(2);
+            }
+            if self.disable_inv {
+                inv = false;
+            }
+            itheta = 0;
+        }
+        let qalloc = dec.tell_frac() - tell;
+        *b -= qalloc;
+        let (imid, iside, delta);
+        if itheta == 0 {
+            imid = 32767;
+            iside = 0;
+            *fill &= (1 << blocks) - 1;
+            delta = -16384;
+        } else if itheta == 16384 {
+            imid = 0;
+            iside = 32767;
+            *fill &= ((1 << blocks) - 1) << blocks;
+            delta = 16384;
+        } else {
+            imid = bitexact_cos(itheta);
+            iside = bitexact_cos(16384 - itheta);
+            delta = frac_mul16((n - 1) << 7, bitexact_log2tan(iside, imid));
+        }
+        Split { inv, imid, iside, delta, itheta, qalloc }
+    }
+
+    fn quant_band_n1(&mut self, x: &mut [f32], y: Option<&mut [f32]>, lowband_out: Option<&mut [f32]>) -> u32 {
+        let mut decode_sign = |x: &mut f32| {
+            let mut sign = 0;
+            if self.remaining_bits >= 1 << BITRES {
+                sign = self.dec.bits(1);
+                self.remaining_bits -= 1 << BITRES;
+            }
+            *x = if sign != 0 { -1. } else { 1. };
+        };
+        decode_sign(&mut x[0]);
+        if let Some(y) = y {
+            decode_sign(&mut y[0]);
+        }
+        if let Some(lowband_out) = lowband_out {
+            lowband_out[0] = x[0];
+        }
+        1
+    }
+
+    // a band or a part of one that is split recursively while that is cheaper than coding it
+    // with a single pulse vector
+    #[allow(clippy::too_many_arguments)]
+    fn quant_partition(&mut self, x: &mut [f32], n: usize, mut b: i32, mut blocks: usize, lowband: Option<&[f32]>, mut lm: i32, gain: f32, mut fill: u32) -> u32 {
+        let blocks0 = blocks;
+        let cache = &CACHE_BITS[CACHE_INDEX[((lm + 1) as usize) * NB_BANDS + self.band] as usize..];
+        if lm != -1 && b > cache[cache[0] as usize] as i32 + 12 && n > 2 {
+            let n = n >> 1;
+            let (x, y) = x.split_at_mut(n);
+            lm -= 1;
+            if blocks == 1 {
+                fill = (fill & 1) | (fill << 1);
+            }
+            blocks = (blocks + 1) >> 1;
+            let split = self.compute_theta(n, &mut b, blocks, blocks0, lm, false, &mut fill);
+            let mid = split.imid as f32 / 32768.;
+            let side = split.iside as f32 / 32768.;
+            let mut delta = split.delta;
+            if blocks0 > 1 && split.itheta & 0x3fff != 0 {
+                if split.itheta > 8192 {
+                    delta -= delta >> (4 - lm);
+                } else {
+                    delta = i32::min(0, delta + ((n as i32) << BITRES >> (5 - lm)));
+                }
+            }
+            let mut mbits = i32::max(0, i32::min(b, (b - delta) / 2));
+            let mut sbits = b - mbits;
+            self.remaining_bits -= split.qalloc;
+            let next_lowband2 = lowband.map(|lowband| &lowband[n..]);
+            let mut rebalance = self.remaining_bits;
+            let mut cm;
+            if mbits >= sbits {
+                cm = self.quant_partition(x, n, mbits, blocks, lowband, lm, gain * mid, fill);
+                rebalance = mbits - (rebalance - self.remaining_bits);
+                if rebalance > 3 << BITRES && split.itheta != 0 {
+                    sbits += rebalance - (3 << BITRES);
+                }
+                cm |= self.quant_partition(y, n, sbits, blocks, next_lowband2, lm, gain * side, fill >> blocks) << (blocks0 >> 1);
+            } else {
+                cm = self.quant_partition(y, n, sbits, blocks, next_lowband2, lm, gain * side, fill >> blocks) << (blocks0 >> 1);
+                rebalance = sbits - (rebalance - self.remaining_bits);
+                if rebalance > 3 << BITRES && split.itheta != 16384 {
+                    mbits += rebalance - (3 << BITRES);
+                }
+                cm |= self.quant_partition(x, n, mbits, blocks, lowband, lm, gain * mid, fill);
+            }
+            return cm;
+        }
+
+        let mut q = bits2pulses(self.band, lm, b);
+        let mut curr_bits = pulses2bits(self.band, lm, q);
+        self.remaining_bits -= curr_bits;
+        while self.remaining_bits < 0 && q > 0 {
+            self.remaining_bits += curr_bits;
+            q -= 1;
+            curr_bits = pulses2bits(self.band, lm, q);
+            self.remaining_bits -= curr_bits;
+        }
+        let x = &mut x[..n];
+        if q != 0 {
+            let k = get_pulses(q) as usize;
+            return alg_unquant(x, n, k, self.spread, blocks, self.dec, gain);
+        }
+        // no pulses: the band is folded from lower ones, or filled with noise
+        let cm_mask = (1u32 << blocks) - 1;
+        fill &= cm_mask;
+        if fill == 0 {
+            x.fill(0.);
+            return 0;
+        }
+        let cm = match lowband {
+            None => {
+                for x in x.iter_mut() {
+                    self.seed = lcg_rand(self.seed);
+                    *x = (self.seed as i32 >> 20) as f32;
+                }
+                cm_mask
+            }
+            Some(lowband) => {
+                for (x, &l) in x.iter_mut().zip(lowband) {
+                    self.seed = lcg_rand(self.seed);
+                    let tmp = 1. / 256.;
+                    *x = l + if self.seed & 0x8000 != 0 { tmp } else { -tmp };
+                }
+                fill
+            }
+        };
+        renormalise_vector(x, gain);
+        cm
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn quant_band(
+        &mut self,
+        x: &mut [f32],
+        n: usize,
+        b: i32,
+        mut blocks: usize,
+        lowband: Option<&[f32]>,
+        lm: i32,
+        lowband_out: Option<&mut [f32]>,
+        gain: f32,
+        mut fill: u32,
+    ) -> u32 {
+        let n0 = n;
+        let mut n_b = n / blocks;
+        let long_blocks = blocks == 1;
+        let mut tf_change = self.tf_change;
+        if n == 1 {
+            return self.quant_band_n1(x, None, lowband_out);
+        }
+        let recombine = tf_change.max(0) as usize;
+        let mut lowband: Option<Vec<f32>> = lowband.map(|lowband| lowband[..n].to_vec());
+        for k in 0..recombine {
+            if let Some(lowband) = &mut lowband {
+                haar1(lowband, n >> k, 1 << k);
+            }
+            fill = BIT_INTERLEAVE_TABLE[(fill & 0xF) as usize] | BIT_INTERLEAVE_TABLE[(fill >> 4) as usize] << 2;
+        }
+        blocks >>= recombine;
+        n_b <<= recombine;
+        let mut time_divide = 0;
+        while n_b & 1 == 0 && tf_change < 0 {
+            if let Some(lowband) = &mut lowband {
+                haar1(lowband, n_b, blocks);
+            }
+            fill |= fill << blocks;
+            blocks <<= 1;
+            n_b >>= 1;
+            time_divide += 1;
+            tf_change += 1;
+        }
+        let blocks0 = blocks;
+        let n_b0 = n_b;
+        if blocks0 > 1 {
+            if let Some(lowband) = &mut lowband {
+                deinterleave_hadamard(lowband, n_b >> recombine, blocks0 << recombine, long_blocks);
+            }
+        }
+        let mut cm = self.quant_partition(x, n, b, blocks, lowband.as_deref(), lm, gain, fill);
+
+        if blocks0 > 1 {
+            interleave_hadamard(x, n_b >> recombine, blocks0 << recombine, long_blocks);
+        }
+        n_b = n_b0;
+        blocks = blocks0;
+        for _ in 0..time_divide {
+            blocks >>= 1;
+            n_b <<= 1;
+            cm |= cm >> blocks;
+            haar1(x, n_b, blocks);
+        }
+        for k in 0..recombine {
+            cm = BIT_DEINTERLEAVE_TABLE[cm as usize];
+            haar1(x, n0 >> k, 1 << k);
+        }
+        blocks <<= recombine;
+        if let Some(lowband_out) = lowband_out {
+            let scale = (n0 as f32).sqrt();
+            for (out, &x) in lowband_out.iter_mut().zip(&x[..n0]) {
+                *out = scale * x;
+            }
+        }
+        cm & ((1 << blocks) - 1)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn quant_band_stereo(
+        &mut self,
+        x: &mut [f32],
+        y: &mut [f32],
+        n: usize,
+        mut b: i32,
+        blocks: usize,
+        lowband: Option<&[f32]>,
+        lm: i32,
+        lowband_out: Option<&mut [f32]>,
+        mut fill: u32,
+    ) -> u32 {
+        if n == 1 {
+            return self.quant_band_n1(x, Some(y), lowband_out);
+        }
+        let orig_fill = fill;
+        let split = self.compute_theta(n, &mut b, blocks, blocks, lm, true, &mut fill);
+        let mid = split.imid as f32 / 32768.;
+        let side = split.iside as f32 / 32768.;
+        let mut cm;
+        if n == 2 {
+            let mut mbits = b;
+            let mut sbits = 0;
+            if split.itheta != 0 && split.itheta != 16384 {
+                sbits = 1 << BITRES;
+            }
+            mbits -= sbits;
+            let c = split.itheta > 8192;
+            self.remaining_bits -= split.qalloc + sbits;
+            let (x2, y2) = if c { (&mut *y, &mut *x) } else { (&mut *x, &mut *y) };
+            let mut sign = 0;
+            if sbits != 0 {
+                sign = self.dec.bits(1) as i32;
+            }
+            let sign = (1 - 2 * sign) as f32;
+            cm = self.quant_band(x2, n, mbits, blocks, lowband, lm, lowband_out, 1., orig_fill);
+            y2[0] = -sign * x2[1];
+            y2[1] = sign * x2[0];
+            x[0] *= mid;
+            x[1] *= mid;
+            y[0] *= side;
+            y[1] *= side;
+            let tmp = x[0];
+            x[0] = tmp - y[0];
+            y[0] += tmp;
+            let tmp = x[1];
+            x[1] = tmp - y[1];
+            y[1] += tmp;
+        } else {
+            let mut mbits = i32::max(0, i32::min(b, (b - split.delta) / 2));
+            let mut sbits = b - mbits;
+            self.remaining_bits -= split.qalloc;
+            let mut rebalance = self.remaining_bits;
+            if mbits >= sbits {
+                cm = self.quant_band(x, n, mbits, blocks, lowband, lm, lowband_out, 1., fill);
+                rebalance = mbits - (rebalance - self.remaining_bits);
+                if rebalance > 3 << BITRES && split.itheta != 0 {
+                    sbits += rebalance - (3 << BITRES);
+                }
+                cm |= self.quant_band(y, n, sbits, blocks, None, lm, None, side, fill >> blocks);
+            } else {
+                cm = self.quant_band(y, n, sbits, blocks, None, lm, None, side, fill >> blocks);
+                rebalance = sbits - (rebalance - self.remaining_bits);
+                if rebalance > 3 << BITRES && split.itheta != 16384 {
+                    mbits += rebalance - (3 << BITRES);
+                }
+                cm |= self.quant_band(x, n, mbits, blocks, lowband, lm, lowband_out, 1., fill);
+            }
+        }
+        if n != 2 {
+            stereo_merge(&mut x[..n], &mut y[..n], mid);
+        }
+        if split.inv {
+            for y in &mut y[..n] {
+                *y = -*y;
+            }
+        }
+        cm
+    }
+}
+
+fn stereo_merge(x: &mut [f32], y: &mut [f32], mid: f32) {
+    let mut xp = 0.;
+    let mut side = 0.;
+    for (&x, &y) in x.iter().zip(y.iter()) {
+        xp += y * x;
+        side += y * y;
+    }
+    let xp = mid * xp;
+    let mid2 = mid;
+    let el = mid2 * mid2 + side - 2. * xp;
+    let er = mid2 * mid2 + side + 2. * xp;
+    if er < 6e-4 || el < 6e-4 {
+        y.copy_from_slice(x);
+        return;
+    }
+    let lgain = 1. / el.sqrt();
+    let rgain = 1. / er.sqrt();
+    for (x, y) in x.iter_mut().zip(y.iter_mut()) {
+        let l = mid * *x;
+        let r = *y;
+        *x = lgain * (l - r);
+        *y = rgain * (l + r);
+    }
+}
+
+// decodes the normalised shapes of all bands, RFC 6716 section 4.3.4
+#[allow(clippy::too_many_arguments)]
+fn quant_all_bands(
+    params: &mut BandParams,
+    x_all: &mut [f32],
+    c_count: usize,
+    collapse_masks: &mut [u8],
+    pulses: &[i32],
+    dec: &mut RangeDecoder,
+    seed: &mut u32,
+) {
+    let BandParams { start, end, short_blocks, spread, intensity, tf_res, total_bits, lm, coded_bands, .. } = *params;
+    let mut balance = params.balance;
+    let mut dual_stereo = params.dual_stereo;
+    let m = 1 << lm;
+    let blocks = if short_blocks != 0 { m } else { 1 };
+    let n_total = m * SHORT_MDCT_SIZE;
+    let norm_offset = m * EBANDS[start] as usize;
+    let norm_len = m * EBANDS[NB_BANDS - 1] as usize - norm_offset;
+    // the folding source, per channel
+    let mut norm = vec![0.; c_count * norm_len];
+    let mut lowband_offset = 0;
+    let mut update_lowband = true;
+    let (x_buf, y_buf) = x_all.split_at_mut(if c_count == 2 { n_total } else { x_all.len() });
+    let mut ctx = BandContext {
+        dec,
+        band: 0,
+        intensity,
+        spread,
+        tf_change: 0,
+        remaining_bits: 0,
+        seed: *seed,
+        disable_inv: params.disable_inv,
+        avoid_split_noise: blocks > 1,
+    };
+    for i in start..end {
+        ctx.band = i;
+        let last = i == end - 1;
+        let band_start = m * EBANDS[i] as usize;
+        let n = m * EBANDS[i + 1] as usize - band_start;
+        let tell = ctx.dec.tell_frac();
+        if i != start {
+            balance -= tell;
+        }
+        let remaining_bits = total_bits - tell - 1;
+        ctx.remaining_bits = remaining_bits;
+        let b = if i < coded_bands {
+            let curr_balance = balance / i32::min(3, (coded_bands - i) as i32);
+            i32::max(0, i32::min(16383, i32::min(remaining_bits + 1, pulses[i] + curr_balance)))
+        } else {
+            0
+        };
+        if (band_start as i32 - n as i32 >= norm_offset as i32 || i == start + 1) && (update_lowband || lowband_offset == 0) {
+            lowband_offset = i;
+        }
+        if i == start + 1 {
+            // special hybrid folding: the first band is too narrow to fold the second from
+            let n1 = m * (EBANDS[start + 1] - EBANDS[start]) as usize;
+            let n2 = m * (EBANDS[start + 2] - EBANDS[start + 1]) as usize;
+            if n2 > n1 {
+                norm.copy_within(2 * n1 - n2..n1, n1);
+                if dual_stereo {
+                    norm.copy_within(norm_len + 2 * n1 - n2..norm_len + n1, norm_len + n1);
+                }
+            }
+        }
+        let tf_change = tf_res[i];
+        ctx.tf_change = tf_change;
+
+        let mut effective_lowband = None;
+        let (x_cm, y_cm);
+        if lowband_offset != 0 && (spread != SPREAD_AGGRESSIVE || blocks > 1 || tf_change < 0) {
+            let effective = (m * EBANDS[lowband_offset] as usize).saturating_sub(norm_offset + n);
+            effective_lowband = Some(effective);
+            let mut fold_start = lowband_offset;
+            loop {
+                fold_start -= 1;
+                if m * EBANDS[fold_start] as usize <= effective + norm_offset {
+                    break;
+                }
+            }
+            let mut fold_end = lowband_offset - 1;
+            loop {
+                fold_end += 1;
+                if !(fold_end < i && (m * EBANDS[fold_end] as usize) < effective + norm_offset + n) {
+                    break;
+                }
+            }
+            let (mut xm, mut ym) = (0, 0);
+            for fold_i in fold_start..fold_end.max(fold_start + 1) {
+                xm |= collapse_masks[fold_i * c_count] as u32;
+                ym |= collapse_masks[fold_i * c_count + c_count - 1] as u32;
+            }
+            x_cm = xm;
+            y_cm = ym;
+        } else {
+            x_cm = (1 << blocks) - 1;
+            y_cm = x_cm;
+        }
+
+        if dual_stereo && i == intensity {
+            dual_stereo = false;
+            for j in 0..band_start - norm_offset {
+                norm[j] = 0.5 * (norm[j] + norm[norm_len + j]);
+            }
+        }
+        let out_start = band_start - norm_offset;
+        let x = &mut x_buf[band_start..band_start + n];
+        let (new_x_cm, new_y_cm);
+        if dual_stereo {
+            let y = &mut y_buf[band_start..band_start + n];
+            let lowband = effective_lowband.map(|e| norm[e..e + n].to_vec());
+            let out = if last { None } else { Some(&mut norm[out_start..out_start + n]) };
+            new_x_cm = ctx.quant_band(x, n, b / 2, blocks, lowband.as_deref(), lm as i32, out, 1., x_cm);
+            let lowband = effective_lowband.map(|e| norm[norm_len + e..norm_len + e + n].to_vec());
+            let out = if last { None } else { Some(&mut norm[norm_len + out_start..norm_len + out_start + n]) };
+            new_y_cm = ctx.quant_band(y, n, b / 2, blocks, lowband.as_deref(), lm as i32, out, 1., y_cm);
+        } else {
+            let lowband = effective_lowband.map(|e| norm[e..e + n].to_vec());
+            let out = if last { None } else { Some(&mut norm[out_start..out_start + n]) };
+            if c_count == 2 {
+                let y = &mut y_buf[band_start..band_start + n];
+                new_x_cm = ctx.quant_band_stereo(x, y, n, b, blocks, lowband.as_deref(), lm as i32, out, x_cm | y_cm);
+            } else {
+                new_x_cm = ctx.quant_band(x, n, b, blocks, lowband.as_deref(), lm as i32, out, 1., x_cm | y_cm);
+            }
+            new_y_cm = new_x_cm;
+        }
+        collapse_masks[i * c_count] = new_x_cm as u8;
+        collapse_masks[i * c_count + c_count - 1] = new_y_cm as u8;
+        balance += pulses[i] + tell;
+        update_lowband = b > (n as i32) << BITRES;
+        ctx.avoid_split_noise = false;
+    }
+    *seed = ctx.seed;
+}
+
+// the comb filter of the pitch post-filter, applied in place to n samples from `start` and
+// cross-faded from the old parameters to the new ones over the overlap
+fn comb_filter(buf: &mut [f32], start: usize, t: [usize; 2], n: usize, g: [f32; 2], tapset: [usize; 2], window: &[f32]) {
+    if g[0] == 0. && g[1] == 0. {
+        return;
+    }
+    let t0 = t[0].max(COMBFILTER_MIN_PERIOD);
+    let t1 = t[1].max(COMBFILTER_MIN_PERIOD);
+    let g0 = COMB_GAINS[tapset[0]].map(|gain| g[0] * gain);
+    let g1 = COMB_GAINS[tapset[1]].map(|gain| g[1] * gain);
+    let overlap = if g[0] == g[1] && t0 == t1 && tapset[0] == tapset[1] { 0 } else { OVERLAP.min(n) };
+    for i in start..start + overlap {
+        let f = window[i - start] * window[i - start];
+        buf[i] = buf[i]
+            + (1. - f) * g0[0] * buf[i - t0]
+            + (1. - f) * g0[1] * (buf[i - t0 + 1] + buf[i - t0 - 1])
+            + (1. - f) * g0[2] * (buf[i - t0 + 2] + buf[i - t0 - 2])
+            + f * g1[0] * buf[i - t1]
+            + f * g1[1] * (buf[i - t1 + 1] + buf[i - t1 - 1])
+            + f * g1[2] * (buf[i - t1 + 2] + buf[i - t1 - 2]);
+    }
+    if g[1] == 0. {
+        return;
+    }
+    for i in start + overlap..start + n {
+        buf[i] = buf[i] + g1[0] * buf[i - t1] + g1[1] * (buf[i - t1 + 1] + buf[i - t1 - 1]) + g1[2] * (buf[i - t1 + 2] + buf[i - t1 - 2]);
+    }
+}
+
+// the comb filter with constant parameters, from the samples of x at `start` into y
+fn comb_filter_const(y: &mut [f32], x: &[f32], start: usize, t: usize, g: f32, tapset: usize) {
+    if g == 0. {
+        y.copy_from_slice(&x[start..start + y.len()]);
+        return;
+    }
+    let t = t.max(COMBFILTER_MIN_PERIOD);
+    let g = COMB_GAINS[tapset].map(|gain| g * gain);
+    for (i, y) in y.iter_mut().enumerate() {
+        let i = start + i;
+        *y = x[i] + g[0] * x[i - t] + g[1] * (x[i - t + 1] + x[i - t - 1]) + g[2] * (x[i - t + 2] + x[i - t - 2]);
+    }
+}
+
+// autocorrelation up to `lag` of x, with its ends windowed
+fn autocorr(x: &[f32], window: Option<&[f32]>, lag: usize) -> Vec<f32> {
+    let n = x.len();
+    let mut xx = x.to_vec();
+    if let Some(window) = window {
+        for (i, &w) in window.iter().enumerate() {
+            xx[i] = x[i] * w;
+            xx[n - i - 1] = x[n - i - 1] * w;
+        }
+    }
+    (0..=lag).map(|k| (k..n).map(|i| xx[i] * xx[i - k]).sum()).collect()
+}
+
+// LPC coefficients from an autocorrelation with the Levinson-Durbin recursion
+fn celt_lpc(lpc: &mut [f32], ac: &[f32]) {
+    let p = lpc.len();
+    let mut error = ac[0];
+    lpc.fill(0.);
+    if ac[0] == 0. {
+        return;
+    }
+    for i in 0..p {
+        let mut rr = 0.;
+        for j in 0..i {
+            rr += lpc[j] * ac[i - j];
+        }
+        rr += ac[i + 1];
+        let r = -rr / error;
+        lpc[i] = r;
+        for j in 0..(i + 1) >> 1 {
+            let tmp1 = lpc[j];
+            let tmp2 = lpc[i - 1 - j];
+            lpc[j] = tmp1 + r * tmp2;
+            lpc[i - 1 - j] = tmp2 + r * tmp1;
+        }
+        error -= r * r * error;
+        if error < 0.001 * ac[0] {
+            break;
+        }
+    }
+}
+
+// the all-pole filter 1 / A(z) in place, with mem holding the last outputs before x
+fn celt_iir(x: &mut [f32], den: &[f32], mem: &[f32]) {
+    let ord = den.len();
+    let mut y = vec![0.; ord + x.len()];
+    for i in 0..ord {
+        y[i] = mem[ord - i - 1];
+    }
+    for i in 0..x.len() {
+        let mut sum = x[i];
+        for j in 0..ord {
+            sum -= den[j] * y[i + ord - j - 1];
+        }
+        y[i + ord] = sum;
+        x[i] = sum;
+    }
+}
+
+// the sum of the channels, low-passed and decimated by 2, then whitened
+fn pitch_downsample(x: &[Vec<f32>], x_lp: &mut [f32]) {
+    let len = x_lp.len();
+    for (c, x) in x.iter().enumerate() {
+        for i in 1..len {
+            let value = 0.5 * (0.5 * (x[2 * i - 1] + x[2 * i + 1]) + x[2 * i]);
+            x_lp[i] = if c == 0 { value } else { x_lp[i] + value };
+        }
+        let value = 0.5 * (0.5 * x[1] + x[0]);
+        x_lp[0] = if c == 0 { value } else { x_lp[0] + value };
+    }
+    let mut ac = autocorr(x_lp, None, 4);
+    ac[0] *= 1.0001;
+    for (i, ac) in ac.iter_mut().enumerate().skip(1) {
+        *ac -= *ac * (0.008 * i as f32) * (0.008 * i as f32);
+    }
+    let mut lpc = [0.; 4];
+    celt_lpc(&mut lpc, &ac);
+    let mut tmp = 1.;
+    for lpc in &mut lpc {
+        tmp *= 0.9;
+        *lpc *= tmp;
+    }
+    let c1 = 0.8;
+    let num = [lpc[0] + 0.8, lpc[1] + c1 * lpc[0], lpc[2] + c1 * lpc[1], lpc[3] + c1 * lpc[2], c1 * lpc[3]];
+    let mut mem = [0.; 5];
+    for x in x_lp.iter_mut() {
+        let sum = *x + num[0] * mem[0] + num[1] * mem[1] + num[2] * mem[2] + num[3] * mem[3] + num[4] * mem[4];
+        mem = [*x, mem[0], mem[1], mem[2], mem[3]];
+        *x = sum;
+    }
+}
+
+fn pitch_xcorr(x: &[f32], y: &[f32], len: usize, max_pitch: usize) -> Vec<f32> {
+    (0..max_pitch).map(|i| x[..len].iter().zip(&y[i..i + len]).map(|(x, y)| x * y).sum()).collect()
+}
+
+fn find_best_pitch(xcorr: &[f32], y: &[f32], len: usize, max_pitch: usize) -> [usize; 2] {
+    let mut syy = 1f32;
+    let mut best_num = [-1f32; 2];
+    let mut best_den = [0f32; 2];
+    let mut best_pitch = [0, 1];
+    for &y in &y[..len] {
+        syy += y * y;
+    }
+    for i in 0..max_pitch {
+        if xcorr[i] > 0. {
+            let xcorr16 = xcorr[i] * 1e-12;
+            let num = xcorr16 * xcorr16;
+            if num * best_den[1] > best_num[1] * syy {
+                if num * best_den[0] > best_num[0] * syy {
+                    best_num[1] = best_num[0];
+                    best_den[1] = best_den[0];
+                    best_pitch[1] = best_pitch[0];
+                    best_num[0] = num;
+                    best_den[0] = syy;
+                    best_pitch[0] = i;
+                } else {
+                    best_num[1] = num;
+                    best_den[1] = syy;
+                    best_pitch[1] = i;
+                }
+            }
+        }
+        syy += y[i + len] * y[i + len] - y[i] * y[i];
+        syy = syy.max(1.);
+    }
+    best_pitch
+}
+
+// the lag of x_lp in y, searched at a quarter of the rate and then refined
+fn pitch_search(x_lp: &[f32], y: &[f32], len: usize, max_pitch: usize) -> usize {
+    let lag = len + max_pitch;
+    let x_lp4: Vec<f32> = (0..len >> 2).map(|j| x_lp[2 * j]).collect();
+    let y_lp4: Vec<f32> = (0..lag >> 2).map(|j| y[2 * j]).collect();
+    let xcorr = pitch_xcorr(&x_lp4, &y_lp4, len >> 2, max_pitch >> 2);
+    let best_pitch = find_best_pitch(&xcorr, &y_lp4, len >> 2, max_pitch >> 2);
+
+    let mut xcorr = vec![0.; max_pitch >> 1];
+    for (i, xcorr) in xcorr.iter_mut().enumerate() {
+        if (i as i32 - 2 * best_pitch[0] as i32).abs() > 2 && (i as i32 - 2 * best_pitch[1] as i32).abs() > 2 {
+            continue;
+        }
+        let sum: f32 = x_lp[..len >> 1].iter().zip(&y[i..]).map(|(x, y)| x * y).sum();
+        *xcorr = sum.max(-1.);
+    }
+    let best_pitch = find_best_pitch(&xcorr, y, len >> 1, max_pitch >> 1);
+    let mut offset = 0;
+    if best_pitch[0] > 0 && best_pitch[0] < (max_pitch >> 1) - 1 {
+        let a = xcorr[best_pitch[0] - 1];
+        let b = xcorr[best_pitch[0]];
+        let c = xcorr[best_pitch[0] + 1];
+        if c - a > 0.7 * (b - a) {
+            offset = 1;
+        } else if a - c > 0.7 * (b - c) {
+            offset = -1;
+        }
+    }
+    (2 * best_pitch[0] as i32 - offset) as usize
+}
@@ -0,0 +1,1678 @@
+use crate::opus::RangeDecoder;
+use crate::opus_tables::*;
+
+// SILK as used by Opus (RFC 6716 section 4.2), a fixed-point port of the reference decoder. The
+// internal rate is 8, 12 or 16 kHz; the output is resampled to 48 kHz.
+const MAX_LPC_ORDER: usize = 16;
+const MAX_NB_SUBFR: usize = 4;
+const MAX_FRAME_LENGTH: usize = 320;
+const MAX_SUB_FRAME_LENGTH: usize = 80;
+const MAX_FRAMES_PER_PACKET: usize = 3;
+const LTP_ORDER: usize = 5;
+const LTP_MEM_LENGTH_MS: usize = 20;
+const SUB_FRAME_LENGTH_MS: usize = 5;
+const SHELL_CODEC_FRAME_LENGTH: usize = 16;
+const SILK_MAX_PULSES: usize = 16;
+const N_RATE_LEVELS: usize = 10;
+const NLSF_QUANT_MAX_AMPLITUDE: i32 = 4;
+const NLSF_QUANT_LEVEL_ADJ_Q10: i32 = 102;
+const QUANT_LEVEL_ADJUST_Q10: i32 = 80;
+const N_LEVELS_QGAIN: i32 = 64;
+const MIN_DELTA_GAIN_QUANT: i32 = -4;
+const MAX_DELTA_GAIN_QUANT: i32 = 36;
+const GAIN_OFFSET: i32 = 2090;
+const GAIN_INV_SCALE_Q16: i32 = 1_907_825;
+const BWE_AFTER_LOSS_Q16: i32 = 63570;
+const STEREO_INTERP_LEN_MS: usize = 8;
+const RESAMPLER_ORDER_FIR_12: usize = 8;
+
+const TYPE_NO_VOICE_ACTIVITY: i32 = 0;
+const TYPE_VOICED: i32 = 2;
+const CODE_INDEPENDENTLY: i32 = 0;
+const CODE_INDEPENDENTLY_NO_LTP_SCALING: i32 = 1;
+const CODE_CONDITIONALLY: i32 = 2;
+
+// packet loss concealment
+const BWE_COEF_Q16: i32 = 64881;
+const V_PITCH_GAIN_START_MIN_Q14: i32 = 11469;
+const V_PITCH_GAIN_START_MAX_Q14: i32 = 15565;
+const MAX_PITCH_LAG_MS: i32 = 18;
+const RAND_BUF_SIZE: usize = 128;
+const LOG2_INV_LPC_GAIN_HIGH_THRES: i32 = 3;
+const LOG2_INV_LPC_GAIN_LOW_THRES: i32 = 8;
+const PITCH_DRIFT_FAC_Q16: i32 = 655;
+const HARM_ATT_Q15: [i32; 2] = [32440, 31130];
+const PLC_RAND_ATTENUATE_V_Q15: [i32; 2] = [31130, 26214];
+const PLC_RAND_ATTENUATE_UV_Q15: [i32; 2] = [32440, 29491];
+
+// comfort noise generation
+const CNG_BUF_MASK_MAX: usize = 255;
+const CNG_GAIN_SMTH_Q16: i32 = 4634;
+const CNG_GAIN_SMTH_THRESHOLD_Q16: i32 = 46396;
+const CNG_NLSF_SMTH_Q16: i32 = 16348;
+
+// the fixed-point primitives of the reference implementation
+
+fn smulwb(a: i32, b: i32) -> i32 {
+    ((a as i64 * b as i16 as i64) >> 16) as i32
+}
+
+fn smlawb(a: i32, b: i32, c: i32) -> i32 {
+    a.wrapping_add(smulwb(b, c))
+}
+
+fn smulww(a: i32, b: i32) -> i32 {
+    ((a as i64 * b as i64) >> 16) as i32
+}
+
+fn smlaww(a: i32, b: i32, c: i32) -> i32 {
+    a.wrapping_add(smulww(b, c))
+}
+
+fn smulbb(a: i32, b: i32) -> i32 {
+    a as i16 as i32 * b as i16 as i32
+}
+
+fn smultt(a: i32, b: i32) -> i32 {
+    (a >> 16) * (b >> 16)
+}
+
+fn smmul(a: i32, b: i32) -> i32 {
+    ((a as i64 * b as i64) >> 32) as i32
+}
+
+fn rshift_round(a: i32, shift: u32) -> i32 {
+    if shift == 1 {
+        (a >> 1) + (a & 1)
+    } else {
+        ((a >> (shift - 1)) + 1) >> 1
+    }
+}
+
+fn rshift_round64(a: i64, shift: u32) -> i64 {
+    if shift == 1 {
+        (a >> 1) + (a & 1)
+    } else {
+        ((a >> (shift - 1)) + 1) >> 1
+    }
+}
+
+fn lshift_sat32(a: i32, shift: u32) -> i32 {
+    a.clamp(i32::MIN >> shift, i32::MAX >> shift) << shift
+}
+
+fn sat16(a: i32) -> i32 {
+    a.clamp(i16::MIN as i32, i16::MAX as i32)
+}
+
+fn clz32(a: i32) -> i32 {
+    a.leading_zeros() as i32
+}
+
+// leading zeros and the 7 bits below the leading one
+fn clz_frac(a: i32) -> (i32, i32) {
+    let lz = clz32(a);
+    (lz, (a as u32).rotate_right((24 - lz) as u32) as i32 & 0x7f)
+}
+
+fn sqrt_approx(x: i32) -> i32 {
+    if x <= 0 {
+        return 0;
+    }
+    let (lz, frac_q7) = clz_frac(x);
+    let y = if lz & 1 != 0 { 32768 } else { 46214 } >> (lz >> 1);
+    smlawb(y, y, smulbb(213, frac_q7))
+}
+
+// a / b in Q`q`
+fn div32_var_q(a: i32, b: i32, q: i32) -> i32 {
+    let a_headroom = clz32(a.abs()) - 1;
+    let mut a_nrm = a << a_headroom;
+    let b_headroom = clz32(b.abs()) - 1;
+    let b_nrm = b << b_headroom;
+    let b_inv = (i32::MAX >> 2) / (b_nrm >> 16);
+    let mut result = smulwb(a_nrm, b_inv);
+    a_nrm = a_nrm.wrapping_sub(smmul(b_nrm, result).wrapping_shl(3));
+    result = smlawb(result, a_nrm, b_inv);
+    let lshift = 29 + a_headroom - b_headroom - q;
+    if lshift < 0 {
+        lshift_sat32(result, -lshift as u32)
+    } else if lshift < 32 {
+        result >> lshift
+    } else {
+        0
+    }
+}
+
+// 1 / b in Q`q`
+fn inverse32_var_q(b: i32, q: i32) -> i32 {
+    let b_headroom = clz32(b.abs()) - 1;
+    let b_nrm = b << b_headroom;
+    let b_inv = (i32::MAX >> 2) / (b_nrm >> 16);
+    let mut result = b_inv << 16;
+    let err_q32 = ((1 << 29) - smulwb(b_nrm, b_inv)) << 3;
+    result = smlaww(result, err_q32, b_inv);
+    let lshift = 61 - b_headroom - q;
+    if lshift <= 0 {
+        lshift_sat32(result, -lshift as u32)
+    } else if lshift < 32 {
+        result >> lshift
+    } else {
+        0
+    }
+}
+
+// 2^(x / 128), approximately
+fn log2lin(in_log_q7: i32) -> i32 {
+    if in_log_q7 < 0 {
+        return 0;
+    } else if in_log_q7 >= 3967 {
+        return i32::MAX;
+    }
+    let out = 1 << (in_log_q7 >> 7);
+    let frac_q7 = in_log_q7 & 0x7f;
+    let poly = smlawb(frac_q7, smulbb(frac_q7, 128 - frac_q7), -174);
+    if in_log_q7 < 2048 {
+        out + ((out * poly) >> 7)
+    } else {
+        out + (out >> 7) * poly
+    }
+}
+
+// energy of x, shifted right so that it fits in 31 bits
+fn sum_sqr_shift(x: &[i16]) -> (i32, i32) {
+    let sum = |mut nrg: u32, shift: i32| {
+        for pair in x.chunks(2) {
+            let mut tmp = smulbb(pair[0] as i32, pair[0] as i32) as u32;
+            if let Some(&y) = pair.get(1) {
+                tmp = tmp.wrapping_add(smulbb(y as i32, y as i32) as u32);
+            }
+            nrg = nrg.wrapping_add(tmp >> shift);
+        }
+        nrg as i32
+    };
+    let shift = 31 - clz32(x.len() as i32);
+    let nrg = sum(x.len() as u32, shift);
+    let shift = i32::max(0, shift + 3 - clz32(nrg));
+    (sum(0, shift), shift)
+}
+
+fn rand(seed: i32) -> i32 {
+    seed.wrapping_mul(196_314_165).wrapping_add(907_633_515)
+}
+
+fn bwexpander(ar: &mut [i16], mut chirp_q16: i32) {
+    let chirp_minus_one_q16 = chirp_q16 - 65536;
+    let d = ar.len();
+    for a in &mut ar[..d - 1] {
+        *a = rshift_round(chirp_q16 * *a as i32, 16) as i16;
+        chirp_q16 += rshift_round(chirp_q16 * chirp_minus_one_q16, 16);
+    }
+    ar[d - 1] = rshift_round(chirp_q16 * ar[d - 1] as i32, 16) as i16;
+}
+
+fn bwexpander_32(ar: &mut [i32], mut chirp_q16: i32) {
+    let chirp_minus_one_q16 = chirp_q16 - 65536;
+    let d = ar.len();
+    for a in &mut ar[..d - 1] {
+        *a = smulww(chirp_q16, *a);
+        chirp_q16 += rshift_round(chirp_q16 * chirp_minus_one_q16, 16);
+    }
+    ar[d - 1] = smulww(chirp_q16, ar[d - 1]);
+}
+
+// the residual of `input` through the prediction filter b, with the first d samples zeroed
+fn lpc_analysis_filter(out: &mut [i16], input: &[i16], b: &[i16], len: usize) {
+    let d = b.len();
+    for ix in d..len {
+        let mut out32_q12 = 0i32;
+        for (j, &b) in b.iter().enumerate() {
+            out32_q12 = out32_q12.wrapping_add(smulbb(input[ix - 1 - j] as i32, b as i32));
+        }
+        out32_q12 = ((input[ix] as i32) << 12).wrapping_sub(out32_q12);
+        out[ix] = sat16(rshift_round(out32_q12, 12)) as i16;
+    }
+    out[..d].fill(0);
+}
+
+// inverse prediction gain of the filter in Q30, or 0 if it is unstable
+fn lpc_inverse_pred_gain(a_q12: &[i16]) -> i32 {
+    const QA: u32 = 24;
+    const A_LIMIT: i32 = 16_773_022;
+    const MIN_INV_GAIN_Q30: i32 = 107_374;
+    let order = a_q12.len();
+    let mut a_qa = [0i32; MAX_LPC_ORDER];
+    let mut dc_resp = 0;
+    for (k, &a) in a_q12.iter().enumerate() {
+        dc_resp += a as i32;
+        a_qa[k] = (a as i32) << (QA - 12);
+    }
+    if dc_resp >= 4096 {
+        return 0;
+    }
+    let mut inv_gain_q30 = 1 << 30;
+    for k in (1..order).rev() {
+        if a_qa[k] > A_LIMIT || a_qa[k] < -A_LIMIT {
+            return 0;
+        }
+        let rc_q31 = -(a_qa[k] << (31 - QA));
+        let rc_mult1_q30 = (1 << 30) - smmul(rc_q31, rc_q31);
+        inv_gain_q30 = smmul(inv_gain_q30, rc_mult1_q30) << 2;
+        if inv_gain_q30 < MIN_INV_GAIN_Q30 {
+            return 0;
+        }
+        let mult2q = 32 - clz32(rc_mult1_q30.abs());
+        let rc_mult2 = inverse32_var_q(rc_mult1_q30, mult2q + 30);
+        for n in 0..(k + 1) >> 1 {
+            let tmp1 = a_qa[n];
+            let tmp2 = a_qa[k - n - 1];
+            let frac = |x: i32| rshift_round64(x as i64 * rc_q31 as i64, 31) as i32;
+            let t = rshift_round64(tmp1.saturating_sub(frac(tmp2)) as i64 * rc_mult2 as i64, mult2q as u32);
+            if t > i32::MAX as i64 || t < i32::MIN as i64 {
+                return 0;
+            }
+            a_qa[n] = t as i32;
+            let t = rshift_round64(tmp2.saturating_sub(frac(tmp1)) as i64 * rc_mult2 as i64, mult2q as u32);
+            if t > i32::MAX as i64 || t < i32::MIN as i64 {
+                return 0;
+            }
+            a_qa[k - n - 1] = t as i32;
+        }
+    }
+    if a_qa[0] > A_LIMIT || a_qa[0] < -A_LIMIT {
+        return 0;
+    }
+    let rc_q31 = -(a_qa[0] << (31 - QA));
+    let rc_mult1_q30 = (1 << 30) - smmul(rc_q31, rc_q31);
+    inv_gain_q30 = smmul(inv_gain_q30, rc_mult1_q30) << 2;
+    if inv_gain_q30 < MIN_INV_GAIN_Q30 {
+        return 0;
+    }
+    inv_gain_q30
+}
+
+fn nlsf2a_find_poly(out: &mut [i32], c_lsf: &[i32], dd: usize) {
+    out[0] = 1 << 16;
+    out[1] = -c_lsf[0];
+    for k in 1..dd {
+        let ftmp = c_lsf[2 * k];
+        out[k + 1] = (out[k - 1] << 1) - rshift_round64(ftmp as i64 * out[k] as i64, 16) as i32;
+        for n in (2..=k).rev() {
+            out[n] += out[n - 2] - rshift_round64(ftmp as i64 * out[n - 1] as i64, 16) as i32;
+        }
+        out[1] -= ftmp;
+    }
+}
+
+// prediction coefficients in Q12 from normalised line spectral frequencies in Q15
+fn nlsf2a(a_q12: &mut [i16], nlsf: &[i16]) {
+    const ORDERING16: [usize; 16] = [0, 15, 8, 7, 4, 11, 12, 3, 2, 13, 10, 5, 6, 9, 14, 1];
+    const ORDERING10: [usize; 10] = [0, 9, 6, 3, 4, 5, 8, 1, 2, 7];
+    let d = nlsf.len();
+    let ordering: &[usize] = if d == 16 { &ORDERING16 } else { &ORDERING10 };
+    let mut cos_lsf_qa = [0i32; MAX_LPC_ORDER];
+    for k in 0..d {
+        let f_int = (nlsf[k] >> 8) as usize;
+        let f_frac = nlsf[k] as i32 - ((f_int as i32) << 8);
+        let cos_val = LSF_COS_TAB_Q12[f_int];
+        let delta = LSF_COS_TAB_Q12[f_int + 1] - cos_val;
+        cos_lsf_qa[ordering[k]] = rshift_round((cos_val << 8) + delta * f_frac, 20 - 16);
+    }
+    let dd = d >> 1;
+    let mut p = [0i32; MAX_LPC_ORDER / 2 + 1];
+    let mut q = [0i32; MAX_LPC_ORDER / 2 + 1];
+    nlsf2a_find_poly(&mut p, &cos_lsf_qa, dd);
+    nlsf2a_find_poly(&mut q, &cos_lsf_qa[1..], dd);
+    let mut a32_qa1 = [0i32; MAX_LPC_ORDER];
+    for k in 0..dd {
+        let p_tmp = p[k + 1] + p[k];
+        let q_tmp = q[k + 1] - q[k];
+        a32_qa1[k] = -q_tmp - p_tmp;
+        a32_qa1[d - k - 1] = q_tmp - p_tmp;
+    }
+    let a32_qa1 = &mut a32_qa1[..d];
+    lpc_fit(a_q12, a32_qa1, 12, 17);
+    let mut i = 0;
+    while lpc_inverse_pred_gain(a_q12) == 0 && i < 16 {
+        bwexpander_32(a32_qa1, 65536 - (2 << i));
+        for k in 0..d {
+            a_q12[k] = rshift_round(a32_qa1[k], 17 - 12) as i16;
+        }
+        i += 1;
+    }
+}
+
+// converts to 16 bits, with bandwidth expansion for as long as coefficients overflow
+fn lpc_fit(a_qout: &mut [i16], a_qin: &mut [i32], qout: u32, qin: u32) {
+    let d = a_qin.len();
+    let mut i = 0;
+    while i < 10 {
+        let (idx, maxabs) = a_qin.iter().map(|a| a.abs()).enumerate().fold((0, 0), |best, (k, a)| if a > best.1 { (k, a) } else { best });
+        let maxabs = rshift_round(maxabs, qin - qout);
+        if maxabs > i16::MAX as i32 {
+            let maxabs = maxabs.min(163_838);
+            let chirp_q16 = 65470 - ((maxabs - i16::MAX as i32) << 14) / ((maxabs * (idx as i32 + 1)) >> 2);
+            bwexpander_32(a_qin, chirp_q16);
+        } else {
+            break;
+        }
+        i += 1;
+    }
+    if i == 10 {
+        for k in 0..d {
+            a_qout[k] = sat16(rshift_round(a_qin[k], qin - qout)) as i16;
+            a_qin[k] = (a_qout[k] as i32) << (qin - qout);
+        }
+    } else {
+        for k in 0..d {
+            a_qout[k] = rshift_round(a_qin[k], qin - qout) as i16;
+        }
+    }
+}
+
+// enforces the minimum distances between the frequencies
+fn nlsf_stabilize(nlsf_q15: &mut [i16], delta_min_q15: &[i16]) {
+    let l = nlsf_q15.len();
+    for _ in 0..20 {
+        let mut min_diff_q15 = nlsf_q15[0] as i32 - delta_min_q15[0] as i32;
+        let mut index = 0;
+        for i in 1..l {
+            let diff_q15 = nlsf_q15[i] as i32 - (nlsf_q15[i - 1] as i32 + delta_min_q15[i] as i32);
+            if diff_q15 < min_diff_q15 {
+                min_diff_q15 = diff_q15;
+                index = i;
+            }
+        }
+        let diff_q15 = (1 << 15) - (nlsf_q15[l - 1] as i32 + delta_min_q15[l] as i32);
+        if diff_q15 < min_diff_q15 {
+            min_diff_q15 = diff_q15;
+            index = l;
+        }
+        if min_diff_q15 >= 0 {
+            return;
+        }
+        if index == 0 {
+            nlsf_q15[0] = delta_min_q15[0];
+        } else if index == l {
+            nlsf_q15[l - 1] = ((1 << 15) - delta_min_q15[l] as i32) as i16;
+        } else {
+            let mut min_center_q15: i32 = delta_min_q15[..index].iter().map(|&d| d as i32).sum();
+            min_center_q15 += delta_min_q15[index] as i32 >> 1;
+            let mut max_center_q15 = 1 << 15;
+            for k in (index + 1..=l).rev() {
+                max_center_q15 -= delta_min_q15[k] as i32;
+            }
+            max_center_q15 -= delta_min_q15[index] as i32 >> 1;
+            let center_freq_q15 = rshift_round(nlsf_q15[index - 1] as i32 + nlsf_q15[index] as i32, 1);
+            let center_freq_q15 = if min_center_q15 > max_center_q15 {
+                center_freq_q15.clamp(max_center_q15, min_center_q15)
+            } else {
+                center_freq_q15.clamp(min_center_q15, max_center_q15)
+            } as i16;
+            nlsf_q15[index - 1] = center_freq_q15 - (delta_min_q15[index] >> 1);
+            nlsf_q15[index] = nlsf_q15[index - 1] + delta_min_q15[index];
+        }
+    }
+    // fall back to sorting and clamping
+    nlsf_q15.sort_unstable();
+    nlsf_q15[0] = nlsf_q15[0].max(delta_min_q15[0]);
+    for i in 1..l {
+        nlsf_q15[i] = nlsf_q15[i].max(nlsf_q15[i - 1].saturating_add(delta_min_q15[i]));
+    }
+    nlsf_q15[l - 1] = nlsf_q15[l - 1].min(((1 << 15) - delta_min_q15[l] as i32) as i16);
+    for i in (0..l - 1).rev() {
+        nlsf_q15[i] = nlsf_q15[i].min(nlsf_q15[i + 1] - delta_min_q15[i + 1]);
+    }
+}
+
+struct NlsfCodebook {
+    order: usize,
+    quant_step_size_q16: i32,
+    cb1_nlsf_q8: &'static [u8],
+    cb1_wght_q9: &'static [i16],
+    cb1_icdf: &'static [u8],
+    pred_q8: &'static [u8],
+    ec_sel: &'static [u8],
+    ec_icdf: &'static [u8],
+    delta_min_q15: &'static [i16],
+}
+
+const NLSF_CB_NB_MB: NlsfCodebook = NlsfCodebook {
+    order: 10,
+    quant_step_size_q16: 11796,
+    cb1_nlsf_q8: &NLSF_CB1_NB_MB_Q8,
+    cb1_wght_q9: &NLSF_CB1_NB_MB_WGHT_Q9,
+    cb1_icdf: &NLSF_CB1_ICDF_NB_MB,
+    pred_q8: &NLSF_PRED_NB_MB_Q8,
+    ec_sel: &NLSF_CB2_SELECT_NB_MB,
+    ec_icdf: &NLSF_CB2_ICDF_NB_MB,
+    delta_min_q15: &NLSF_DELTA_MIN_NB_MB_Q15,
+};
+
+const NLSF_CB_WB: NlsfCodebook = NlsfCodebook {
+    order: 16,
+    quant_step_size_q16: 9830,
+    cb1_nlsf_q8: &NLSF_CB1_WB_Q8,
+    cb1_wght_q9: &NLSF_CB1_WB_WGHT_Q9,
+    cb1_icdf: &NLSF_CB1_ICDF_WB,
+    pred_q8: &NLSF_PRED_WB_Q8,
+    ec_sel: &NLSF_CB2_SELECT_WB,
+    ec_icdf: &NLSF_CB2_ICDF_WB,
+    delta_min_q15: &NLSF_DELTA_MIN_WB_Q15,
+};
+
+impl NlsfCodebook {
+    // the entropy coding tables and predictor of each coefficient for a first stage vector
+    fn unpack(&self, cb1_index: usize) -> ([usize; MAX_LPC_ORDER], [u8; MAX_LPC_ORDER]) {
+        let mut ec_ix = [0; MAX_LPC_ORDER];
+        let mut pred_q8 = [0; MAX_LPC_ORDER];
+        let sel = &self.ec_sel[cb1_index * self.order / 2..];
+        for i in (0..self.order).step_by(2) {
+            let entry = sel[i / 2] as usize;
+            ec_ix[i] = ((entry >> 1) & 7) * (2 * NLSF_QUANT_MAX_AMPLITUDE as usize + 1);
+            pred_q8[i] = self.pred_q8[i + (entry & 1) * (self.order - 1)];
+            ec_ix[i + 1] = ((entry >> 5) & 7) * (2 * NLSF_QUANT_MAX_AMPLITUDE as usize + 1);
+            pred_q8[i + 1] = self.pred_q8[i + ((entry >> 4) & 1) * (self.order - 1) + 1];
+        }
+        (ec_ix, pred_q8)
+    }
+
+    fn decode(&self, nlsf_q15: &mut [i16], indices: &[i8]) {
+        let order = self.order;
+        let (_, pred_q8) = self.unpack(indices[0] as usize);
+        let mut res_q10 = [0i32; MAX_LPC_ORDER];
+        let mut out_q10 = 0;
+        for i in (0..order).rev() {
+            let pred_q10 = smulbb(out_q10, pred_q8[i] as i32) >> 8;
+            out_q10 = (indices[i + 1] as i32) << 10;
+            if out_q10 > 0 {
+                out_q10 -= NLSF_QUANT_LEVEL_ADJ_Q10;
+            } else if out_q10 < 0 {
+                out_q10 += NLSF_QUANT_LEVEL_ADJ_Q10;
+            }
+            out_q10 = smlawb(pred_q10, out_q10, self.quant_step_size_q16);
+            res_q10[i] = out_q10 as i16 as i32;
+        }
+        let cb_element = &self.cb1_nlsf_q8[indices[0] as usize * order..];
+        let cb_wght_q9 = &self.cb1_wght_q9[indices[0] as usize * order..];
+        for i in 0..order {
+            let tmp = ((res_q10[i] << 14) / cb_wght_q9[i] as i32) + ((cb_element[i] as i32) << 7);
+            nlsf_q15[i] = tmp.clamp(0, 32767) as i16;
+        }
+        nlsf_stabilize(&mut nlsf_q15[..order], self.delta_min_q15);
+    }
+}
+
+// the parameters of a frame as coded in the bitstream
+#[derive(Clone, Copy, Default)]
+struct SideInfoIndices {
+    gains_indices: [i8; MAX_NB_SUBFR],
+    ltp_index: [i8; MAX_NB_SUBFR],
+    nlsf_indices: [i8; MAX_LPC_ORDER + 1],
+    lag_index: i16,
+    contour_index: i8,
+    signal_type: i32,
+    quant_offset_type: i32,
+    nlsf_interp_coef_q2: i8,
+    per_index: i8,
+    ltp_scale_index: i8,
+    seed: i8,
+}
+
+// the dequantised parameters of a frame
+#[derive(Default)]
+struct DecoderControl {
+    pitch_l: [i32; MAX_NB_SUBFR],
+    gains_q16: [i32; MAX_NB_SUBFR],
+    pred_coef_q12: [[i16; MAX_LPC_ORDER]; 2],
+    ltp_coef_q14: [i16; LTP_ORDER * MAX_NB_SUBFR],
+    ltp_scale_q14: i32,
+}
+
+#[derive(Clone, Default)]
+struct PlcState {
+    pitch_l_q8: i32,
+    ltp_coef_q14: [i16; LTP_ORDER],
+    prev_lpc_q12: [i16; MAX_LPC_ORDER],
+    last_frame_lost: bool,
+    rand_seed: i32,
+    rand_scale_q14: i16,
+    conc_energy: i32,
+    conc_energy_shift: i32,
+    prev_ltp_scale_q14: i16,
+    prev_gain_q16: [i32; 2],
+    fs_khz: usize,
+    nb_subfr: usize,
+    subfr_length: usize,
+}
+
+#[derive(Clone)]
+struct CngState {
+    exc_buf_q14: [i32; MAX_FRAME_LENGTH],
+    smth_nlsf_q15: [i16; MAX_LPC_ORDER],
+    synth_state: [i32; MAX_LPC_ORDER],
+    smth_gain_q16: i32,
+    rand_seed: i32,
+    fs_khz: usize,
+}
+
+// upsampling to 48 kHz: a 2x allpass-based interpolator followed by a 12-phase FIR
+#[derive(Clone, Default)]
+struct Resampler {
+    s_iir: [i32; 6],
+    s_fir: [i16; RESAMPLER_ORDER_FIR_12],
+    delay_buf: [i16; 16],
+    input_delay: usize,
+    fs_in_khz: usize,
+    batch_size: usize,
+    inv_ratio_q16: i32,
+}
+
+impl Resampler {
+    fn new(fs_in_khz: usize) -> Self {
+        let fs_in = fs_in_khz as i32 * 1000;
+        let fs_out = 48000;
+        let mut inv_ratio_q16 = ((fs_in << 15) / fs_out) << 2;
+        while smulww(inv_ratio_q16, fs_out) < fs_in << 1 {
+            inv_ratio_q16 += 1;
+        }
+        Self {
+            input_delay: match fs_in_khz {
+                8 => 0,
+                12 => 4,
+                _ => 7,
+            },
+            fs_in_khz,
+            batch_size: fs_in_khz * 10,
+            inv_ratio_q16,
+            ..Default::default()
+        }
+    }
+
+    fn process(&mut self, out: &mut [i16], input: &[i16]) {
+        let n = self.fs_in_khz;
+        let n_samples = n - self.input_delay;
+        self.delay_buf[self.input_delay..n].copy_from_slice(&input[..n_samples]);
+        let delay_buf = self.delay_buf;
+        let (head, tail) = out.split_at_mut(48);
+        self.iir_fir(head, &delay_buf[..n]);
+        self.iir_fir(tail, &input[n_samples..input.len() - self.input_delay]);
+        self.delay_buf[..self.input_delay].copy_from_slice(&input[input.len() - self.input_delay..]);
+    }
+
+    fn iir_fir(&mut self, out: &mut [i16], mut input: &[i16]) {
+        let mut buf = vec![0i16; 2 * self.batch_size + RESAMPLER_ORDER_FIR_12];
+        buf[..RESAMPLER_ORDER_FIR_12].copy_from_slice(&self.s_fir);
+        let mut out_pos = 0;
+        let mut n_samples_in;
+        loop {
+            n_samples_in = input.len().min(self.batch_size);
+            self.up2_hq(&mut buf[RESAMPLER_ORDER_FIR_12..], &input[..n_samples_in]);
+            let max_index_q16 = (n_samples_in as i32) << 17;
+            let mut index_q16 = 0;
+            while index_q16 < max_index_q16 {
+                let table_index = smulwb(index_q16 & 0xffff, 12) as usize;
+                let b = &buf[(index_q16 >> 16) as usize..];
+                let fir = &RESAMPLER_FRAC_FIR_12[table_index];
+                let fir_rev = &RESAMPLER_FRAC_FIR_12[11 - table_index];
+                let mut res_q15 = smulbb(b[0] as i32, fir[0]);
+                res_q15 += smulbb(b[1] as i32, fir[1]);
+                res_q15 += smulbb(b[2] as i32, fir[2]);
+                res_q15 += smulbb(b[3] as i32, fir[3]);
+                res_q15 += smulbb(b[4] as i32, fir_rev[3]);
+                res_q15 += smulbb(b[5] as i32, fir_rev[2]);
+                res_q15 += smulbb(b[6] as i32, fir_rev[1]);
+                res_q15 += smulbb(b[7] as i32, fir_rev[0]);
+                out[out_pos] = sat16(rshift_round(res_q15, 15)) as i16;
+                out_pos += 1;
+                index_q16 += self.inv_ratio_q16;
+            }
+            input = &input[n_samples_in..];
+            if input.is_empty() {
+                break;
+            }
+            buf.copy_within(n_samples_in << 1..(n_samples_in << 1) + RESAMPLER_ORDER_FIR_12, 0);
+        }
+        self.s_fir.copy_from_slice(&buf[n_samples_in << 1..(n_samples_in << 1) + RESAMPLER_ORDER_FIR_12]);
+    }
+
+    fn up2_hq(&mut self, out: &mut [i16], input: &[i16]) {
+        let s = &mut self.s_iir;
+        let section = |s: &mut [i32], coefs: &[i32; 3], in32: i32| {
+            let y = in32 - s[0];
+            let x = smulwb(y, coefs[0]);
+            let out1 = s[0] + x;
+            s[0] = in32 + x;
+            let y = out1 - s[1];
+            let x = smulwb(y, coefs[1]);
+            let out2 = s[1] + x;
+            s[1] = out1 + x;
+            let y = out2 - s[2];
+            let x = smlawb(y, y, coefs[2]);
+            let out1 = s[2] + x;
+            s[2] = out2 + x;
+            sat16(rshift_round(out1, 10)) as i16
+        };
+        for (k, &x) in input.iter().enumerate() {
+            let in32 = (x as i32) << 10;
+            out[2 * k] = section(&mut s[..3], &RESAMPLER_UP2_HQ_0, in32);
+            out[2 * k + 1] = section(&mut s[3..], &RESAMPLER_UP2_HQ_1, in32);
+        }
+    }
+}
+
+#[derive(Clone)]
+struct ChannelState {
+    prev_gain_q16: i32,
+    exc_q14: [i32; MAX_FRAME_LENGTH],
+    s_lpc_q14_buf: [i32; MAX_LPC_ORDER],
+    out_buf: [i16; MAX_FRAME_LENGTH + 2 * MAX_SUB_FRAME_LENGTH],
+    lag_prev: i32,
+    last_gain_index: i8,
+    fs_khz: usize,
+    nb_subfr: usize,
+    frame_length: usize,
+    subfr_length: usize,
+    ltp_mem_length: usize,
+    lpc_order: usize,
+    prev_nlsf_q15: [i16; MAX_LPC_ORDER],
+    first_frame_after_reset: bool,
+    n_frames_decoded: usize,
+    n_frames_per_packet: usize,
+    ec_prev_signal_type: i32,
+    ec_prev_lag_index: i16,
+    vad_flags: [bool; MAX_FRAMES_PER_PACKET],
+    lbrr_flag: bool,
+    lbrr_flags: [bool; MAX_FRAMES_PER_PACKET],
+    resampler: Resampler,
+    indices: SideInfoIndices,
+    cng: CngState,
+    loss_cnt: i32,
+    prev_signal_type: i32,
+    plc: PlcState,
+}
+
+impl ChannelState {
+    fn new() -> Self {
+        let mut state = Self {
+            prev_gain_q16: 65536,
+            exc_q14: [0; MAX_FRAME_LENGTH],
+            s_lpc_q14_buf: [0; MAX_LPC_ORDER],
+            out_buf: [0; MAX_FRAME_LENGTH + 2 * MAX_SUB_FRAME_LENGTH],
+            lag_prev: 0,
+            last_gain_index: 0,
+            fs_khz: 0,
+            nb_subfr: 0,
+            frame_length: 0,
+            subfr_length: 0,
+            ltp_mem_length: 0,
+            lpc_order: 0,
+            prev_nlsf_q15: [0; MAX_LPC_ORDER],
+            first_frame_after_reset: true,
+            n_frames_decoded: 0,
+            n_frames_per_packet: 0,
+            ec_prev_signal_type: 0,
+            ec_prev_lag_index: 0,
+            vad_flags: [false; MAX_FRAMES_PER_PACKET],
+            lbrr_flag: false,
+            lbrr_flags: [false; MAX_FRAMES_PER_PACKET],
+            resampler: Resampler::default(),
+            indices: SideInfoIndices::default(),
+            cng: CngState {
+                exc_buf_q14: [0; MAX_FRAME_LENGTH],
+                smth_nlsf_q15: [0; MAX_LPC_ORDER],
+                synth_state: [0; MAX_LPC_ORDER],
+                smth_gain_q16: 0,
+                rand_seed: 0,
+                fs_khz: 0,
+            },
+            loss_cnt: 0,
+            prev_signal_type: 0,
+            plc: PlcState::default(),
+        };
+        state.cng_reset();
+        state.plc_reset();
+        state
+    }
+
+    fn nlsf_cb(&self) -> &'static NlsfCodebook {
+        if self.lpc_order == 16 {
+            &NLSF_CB_WB
+        } else {
+            &NLSF_CB_NB_MB
+        }
+    }
+
+    fn set_fs(&mut self, fs_khz: usize) {
+        self.subfr_length = SUB_FRAME_LENGTH_MS * fs_khz;
+        let frame_length = self.nb_subfr * self.subfr_length;
+        if self.fs_khz != fs_khz {
+            self.resampler = Resampler::new(fs_khz);
+        }
+        if self.fs_khz != fs_khz || frame_length != self.frame_length {
+            if self.fs_khz != fs_khz {
+                self.ltp_mem_length = LTP_MEM_LENGTH_MS * fs_khz;
+                self.lpc_order = if fs_khz == 16 { 16 } else { 10 };
+                self.first_frame_after_reset = true;
+                self.lag_prev = 100;
+                self.last_gain_index = 10;
+                self.prev_signal_type = TYPE_NO_VOICE_ACTIVITY;
+                self.out_buf.fill(0);
+                self.s_lpc_q14_buf.fill(0);
+            }
+            self.fs_khz = fs_khz;
+            self.frame_length = frame_length;
+        }
+    }
+
+    fn pitch_contour_icdf(&self) -> &'static [u8] {
+        match (self.fs_khz == 8, self.nb_subfr == MAX_NB_SUBFR) {
+            (true, true) => &PITCH_CONTOUR_NB_ICDF,
+            (true, false) => &PITCH_CONTOUR_10_MS_NB_ICDF,
+            (false, true) => &PITCH_CONTOUR_ICDF,
+            (false, false) => &PITCH_CONTOUR_10_MS_ICDF,
+        }
+    }
+
+    fn pitch_lag_low_bits_icdf(&self) -> &'static [u8] {
+        match self.fs_khz {
+            16 => &UNIFORM8_ICDF,
+            12 => &UNIFORM6_ICDF,
+            _ => &UNIFORM4_ICDF,
+        }
+    }
+
+    fn decode_indices(&mut self, dec: &mut RangeDecoder, frame_index: usize, decode_lbrr: bool, cond_coding: i32) {
+        let ix = if decode_lbrr || self.vad_flags[frame_index] {
+            dec.icdf(&TYPE_OFFSET_VAD_ICDF, 8) + 2
+        } else {
+            dec.icdf(&TYPE_OFFSET_NO_VAD_ICDF, 8)
+        };
+        let cb = self.nlsf_cb();
+        let lag_low_bits_icdf = self.pitch_lag_low_bits_icdf();
+        let contour_icdf = self.pitch_contour_icdf();
+        let indices = &mut self.indices;
+        indices.signal_type = ix as i32 >> 1;
+        indices.quant_offset_type = ix as i32 & 1;
+        if cond_coding == CODE_CONDITIONALLY {
+            indices.gains_indices[0] = dec.icdf(&DELTA_GAIN_ICDF, 8) as i8;
+        } else {
+            indices.gains_indices[0] = (dec.icdf(&GAIN_ICDF[indices.signal_type as usize], 8) << 3) as i8;
+            indices.gains_indices[0] += dec.icdf(&UNIFORM8_ICDF, 8) as i8;
+        }
+        for i in 1..self.nb_subfr {
+            indices.gains_indices[i] = dec.icdf(&DELTA_GAIN_ICDF, 8) as i8;
+        }
+
+        let n_vectors = cb.cb1_icdf.len() / 2;
+        indices.nlsf_indices[0] = dec.icdf(&cb.cb1_icdf[(indices.signal_type as usize >> 1) * n_vectors..], 8) as i8;
+        let (ec_ix, _) = cb.unpack(indices.nlsf_indices[0] as usize);
+        for (i, &ec_offset) in ec_ix.iter().enumerate().take(cb.order) {
+            let mut ix = dec.icdf(&cb.ec_icdf[ec_offset..], 8) as i32;
+            if ix == 0 {
+                ix -= dec.icdf(&NLSF_EXT_ICDF, 8) as i32;
+            } else if ix == 2 * NLSF_QUANT_MAX_AMPLITUDE {
+                ix += dec.icdf(&NLSF_EXT_ICDF, 8) as i32;
+            }
+            indices.nlsf_indices[i + 1] = (ix - NLSF_QUANT_MAX_AMPLITUDE) as i8;
+        }
+        indices.nlsf_interp_coef_q2 =
+            if self.nb_subfr == MAX_NB_SUBFR { dec.icdf(&NLSF_INTERPOLATION_FACTOR_ICDF, 8) as i8 } else { 4 };
+
+        if indices.signal_type == TYPE_VOICED {
+            let mut decode_absolute_lag_index = true;
+            if cond_coding == CODE_CONDITIONALLY && self.ec_prev_signal_type == TYPE_VOICED {
+                let delta_lag_index = dec.icdf(&PITCH_DELTA_ICDF, 8) as i16;
+                if delta_lag_index > 0 {
+                    indices.lag_index = self.ec_prev_lag_index + delta_lag_index - 9;
+                    decode_absolute_lag_index = false;
+                }
+            }
+            if decode_absolute_lag_index {
+                indices.lag_index = (dec.icdf(&PITCH_LAG_ICDF, 8) * (self.fs_khz >> 1)) as i16;
+                indices.lag_index += dec.icdf(lag_low_bits_icdf, 8) as i16;
+            }
+            self.ec_prev_lag_index = indices.lag_index;
+            indices.contour_index = dec.icdf(contour_icdf, 8) as i8;
+            indices.per_index = dec.icdf(&LTP_PER_INDEX_ICDF, 8) as i8;
+            for k in 0..self.nb_subfr {
+                indices.ltp_index[k] = dec.icdf(LTP_GAIN_ICDF[indices.per_index as usize], 8) as i8;
+            }
+            indices.ltp_scale_index = if cond_coding == CODE_INDEPENDENTLY { dec.icdf(&LTP_SCALE_ICDF, 8) as i8 } else { 0 };
+        }
+        self.ec_prev_signal_type = indices.signal_type;
+        indices.seed = dec.icdf(&UNIFORM4_ICDF, 8) as i8;
+    }
+
+    fn decode_parameters(&mut self, ctrl: &mut DecoderControl, cond_coding: i32) {
+        let indices = &mut self.indices;
+        gains_dequant(&mut ctrl.gains_q16, &indices.gains_indices, &mut self.last_gain_index, cond_coding == CODE_CONDITIONALLY, self.nb_subfr);
+
+        let order = self.lpc_order;
+        let mut nlsf_q15 = [0i16; MAX_LPC_ORDER];
+        let cb = if order == 16 { &NLSF_CB_WB } else { &NLSF_CB_NB_MB };
+        cb.decode(&mut nlsf_q15, &indices.nlsf_indices);
+        nlsf2a(&mut ctrl.pred_coef_q12[1][..order], &nlsf_q15[..order]);
+        if self.first_frame_after_reset {
+            indices.nlsf_interp_coef_q2 = 4;
+        }
+        if indices.nlsf_interp_coef_q2 < 4 {
+            let mut nlsf0_q15 = [0i16; MAX_LPC_ORDER];
+            for i in 0..order {
+                let diff = nlsf_q15[i] as i32 - self.prev_nlsf_q15[i] as i32;
+                nlsf0_q15[i] = (self.prev_nlsf_q15[i] as i32 + ((indices.nlsf_interp_coef_q2 as i32 * diff) >> 2)) as i16;
+            }
+            nlsf2a(&mut ctrl.pred_coef_q12[0][..order], &nlsf0_q15[..order]);
+        } else {
+            ctrl.pred_coef_q12[0] = ctrl.pred_coef_q12[1];
+        }
+        self.prev_nlsf_q15[..order].copy_from_slice(&nlsf_q15[..order]);
+        if self.loss_cnt != 0 {
+            bwexpander(&mut ctrl.pred_coef_q12[0][..order], BWE_AFTER_LOSS_Q16);
+            bwexpander(&mut ctrl.pred_coef_q12[1][..order], BWE_AFTER_LOSS_Q16);
+        }
+
+        if indices.signal_type == TYPE_VOICED {
+            decode_pitch(indices.lag_index, indices.contour_index as usize, &mut ctrl.pitch_l, self.fs_khz, self.nb_subfr);
+            let cbk_q7 = LTP_VQ[indices.per_index as usize];
+            for k in 0..self.nb_subfr {
+                let ix = indices.ltp_index[k] as usize;
+                for (coef, &tap) in ctrl.ltp_coef_q14[k * LTP_ORDER..(k + 1) * LTP_ORDER].iter_mut().zip(&cbk_q7[ix]) {
+                    *coef = (tap as i16) << 7;
+                }
+            }
+            ctrl.ltp_scale_q14 = LTP_SCALES_Q14[indices.ltp_scale_index as usize];
+        } else {
+            ctrl.pitch_l = [0; MAX_NB_SUBFR];
+            ctrl.ltp_coef_q14 = [0; LTP_ORDER * MAX_NB_SUBFR];
+            indices.per_index = 0;
+            ctrl.ltp_scale_q14 = 0;
+        }
+    }
+
+    // excitation, long-term and short-term synthesis
+    fn decode_core(&mut self, ctrl: &mut DecoderControl, xq: &mut [i16], pulses: &[i16]) {
+        let ltp_mem_length = self.ltp_mem_length;
+        let subfr_length = self.subfr_length;
+        let order = self.lpc_order;
+        let mut s_ltp = vec![0i16; ltp_mem_length];
+        let mut s_ltp_q15 = vec![0i32; ltp_mem_length + self.frame_length];
+        let mut res_q14 = vec![0i32; subfr_length];
+        let mut s_lpc_q14 = vec![0i32; subfr_length + MAX_LPC_ORDER];
+
+        let offset_q10 = QUANTIZATION_OFFSETS_Q10[self.indices.signal_type as usize >> 1][self.indices.quant_offset_type as usize];
+        let nlsf_interpolation = self.indices.nlsf_interp_coef_q2 < 4;
+        let mut rand_seed = self.indices.seed as i32;
+        for (i, &pulse) in pulses.iter().enumerate().take(self.frame_length) {
+            rand_seed = rand(rand_seed);
+            let mut exc = (pulse as i32) << 14;
+            if exc > 0 {
+                exc -= QUANT_LEVEL_ADJUST_Q10 << 4;
+            } else if exc < 0 {
+                exc += QUANT_LEVEL_ADJUST_Q10 << 4;
+            }
+            exc += offset_q10 << 4;
+            if rand_seed < 0 {
+                exc = -exc;
+            }
+            self.exc_q14[i] = exc;
+            rand_seed = rand_seed.wrapping_add(pulse as i32);
+        }
+
+        s_lpc_q14[..MAX_LPC_ORDER].copy_from_slice(&self.s_lpc_q14_buf);
+        let mut s_ltp_buf_idx = ltp_mem_length;
+        for k in 0..self.nb_subfr {
+            let a_q12 = ctrl.pred_coef_q12[k >> 1];
+            let b_q14 = &mut ctrl.ltp_coef_q14[k * LTP_ORDER..(k + 1) * LTP_ORDER];
+            let mut signal_type = self.indices.signal_type;
+            let gain_q10 = ctrl.gains_q16[k] >> 6;
+            let mut inv_gain_q31 = inverse32_var_q(ctrl.gains_q16[k], 47);
+            let gain_adj_q16 = if ctrl.gains_q16[k] != self.prev_gain_q16 {
+                let gain_adj_q16 = div32_var_q(self.prev_gain_q16, ctrl.gains_q16[k], 16);
+                for s in &mut s_lpc_q14[..MAX_LPC_ORDER] {
+                    *s = smulww(gain_adj_q16, *s);
+                }
+                gain_adj_q16
+            } else {
+                1 << 16
+            };
+            self.prev_gain_q16 = ctrl.gains_q16[k];
+
+            // after a lost voiced frame, keep some pitch in the first half of an unvoiced one
+            if self.loss_cnt != 0 && self.prev_signal_type == TYPE_VOICED && self.indices.signal_type != TYPE_VOICED && k < MAX_NB_SUBFR / 2 {
+                b_q14.fill(0);
+                b_q14[LTP_ORDER / 2] = 4096;
+                signal_type = TYPE_VOICED;
+                ctrl.pitch_l[k] = self.lag_prev;
+            }
+
+            let exc = &self.exc_q14[k * subfr_length..(k + 1) * subfr_length];
+            if signal_type == TYPE_VOICED {
+                let lag = ctrl.pitch_l[k] as usize;
+                if k == 0 || (k == 2 && nlsf_interpolation) {
+                    // re-whiten the past output with the current filter
+                    let start_idx = ltp_mem_length - lag - order - LTP_ORDER / 2;
+                    if k == 2 {
+                        self.out_buf[ltp_mem_length..ltp_mem_length + 2 * subfr_length].copy_from_slice(&xq[..2 * subfr_length]);
+                    }
+                    lpc_analysis_filter(
+                        &mut s_ltp[start_idx..],
+                        &self.out_buf[start_idx + k * subfr_length..],
+                        &a_q12[..order],
+                        ltp_mem_length - start_idx,
+                    );
+                    if k == 0 {
+                        inv_gain_q31 = smulwb(inv_gain_q31, ctrl.ltp_scale_q14) << 2;
+                    }
+                    for i in 0..lag + LTP_ORDER / 2 {
+                        s_ltp_q15[s_ltp_buf_idx - i - 1] = smulwb(inv_gain_q31, s_ltp[ltp_mem_length - i - 1] as i32);
+                    }
+                } else if gain_adj_q16 != 1 << 16 {
+                    for i in 0..lag + LTP_ORDER / 2 {
+                        s_ltp_q15[s_ltp_buf_idx - i - 1] = smulww(gain_adj_q16, s_ltp_q15[s_ltp_buf_idx - i - 1]);
+                    }
+                }
+                for i in 0..subfr_length {
+                    let p = s_ltp_buf_idx - lag + LTP_ORDER / 2;
+                    let mut ltp_pred_q13 = 2;
+                    for (j, &b) in b_q14.iter().enumerate() {
+                        ltp_pred_q13 = smlawb(ltp_pred_q13, s_ltp_q15[p - j], b as i32);
+                    }
+                    res_q14[i] = exc[i].wrapping_add(ltp_pred_q13 << 1);
+                    s_ltp_q15[s_ltp_buf_idx] = res_q14[i] << 1;
+                    s_ltp_buf_idx += 1;
+                }
+            } else {
+                res_q14.copy_from_slice(exc);
+            }
+
+            for i in 0..subfr_length {
+                let mut lpc_pred_q10 = (order >> 1) as i32;
+                for (j, &a) in a_q12[..order].iter().enumerate() {
+                    lpc_pred_q10 = smlawb(lpc_pred_q10, s_lpc_q14[MAX_LPC_ORDER + i - j - 1], a as i32);
+                }
+                s_lpc_q14[MAX_LPC_ORDER + i] = res_q14[i].saturating_add(lshift_sat32(lpc_pred_q10, 4));
+                xq[k * subfr_length + i] = sat16(rshift_round(smulww(s_lpc_q14[MAX_LPC_ORDER + i], gain_q10), 8)) as i16;
+            }
+            s_lpc_q14.copy_within(subfr_length..subfr_length + MAX_LPC_ORDER, 0);
+        }
+        self.s_lpc_q14_buf.copy_from_slice(&s_lpc_q14[..MAX_LPC_ORDER]);
+    }
+
+    fn decode_frame(&mut self, dec: Option<&mut RangeDecoder>, out: &mut [i16], cond_coding: i32) {
+        let l = self.frame_length;
+        let mut ctrl = DecoderControl::default();
+        match dec {
+            Some(dec) => {
+                let mut pulses = vec![0i16; (l + SHELL_CODEC_FRAME_LENGTH - 1) & !(SHELL_CODEC_FRAME_LENGTH - 1)];
+                self.decode_indices(dec, self.n_frames_decoded, false, cond_coding);
+                decode_pulses(dec, &mut pulses, self.indices.signal_type, self.indices.quant_offset_type, l);
+                self.decode_parameters(&mut ctrl, cond_coding);
+                self.decode_core(&mut ctrl, out, &pulses);
+                self.plc(&mut ctrl, out, false);
+                self.loss_cnt = 0;
+                self.prev_signal_type = self.indices.signal_type;
+                self.first_frame_after_reset = false;
+            }
+            None => self.plc(&mut ctrl, out, true),
+        }
+        let mv_len = self.ltp_mem_length - l;
+        self.out_buf.copy_within(l..l + mv_len, 0);
+        self.out_buf[mv_len..mv_len + l].copy_from_slice(&out[..l]);
+        self.cng(&ctrl, &mut out[..l]);
+        self.plc_glue_frames(&mut out[..l]);
+        self.lag_prev = ctrl.pitch_l[self.nb_subfr - 1];
+    }
+
+    fn plc_reset(&mut self) {
+        self.plc.pitch_l_q8 = (self.frame_length as i32) << 7;
+        self.plc.prev_gain_q16 = [1 << 16; 2];
+        self.plc.subfr_length = 20;
+        self.plc.nb_subfr = 2;
+    }
+
+    fn plc(&mut self, ctrl: &mut DecoderControl, frame: &mut [i16], lost: bool) {
+        if self.fs_khz != self.plc.fs_khz {
+            self.plc_reset();
+            self.plc.fs_khz = self.fs_khz;
+        }
+        if lost {
+            self.plc_conceal(ctrl, frame);
+            self.loss_cnt += 1;
+        } else {
+            self.plc_update(ctrl);
+        }
+    }
+
+    fn plc_update(&mut self, ctrl: &DecoderControl) {
+        let plc = &mut self.plc;
+        let nb_subfr = self.nb_subfr;
+        self.prev_signal_type = self.indices.signal_type;
+        let mut ltp_gain_q14 = 0;
+        if self.indices.signal_type == TYPE_VOICED {
+            let mut j = 0;
+            while j * (self.subfr_length as i32) < ctrl.pitch_l[nb_subfr - 1] && j < nb_subfr as i32 {
+                let sub = nb_subfr - 1 - j as usize;
+                let coefs = &ctrl.ltp_coef_q14[sub * LTP_ORDER..(sub + 1) * LTP_ORDER];
+                let tmp: i32 = coefs.iter().map(|&c| c as i32).sum();
+                if tmp > ltp_gain_q14 {
+                    ltp_gain_q14 = tmp;
+                    plc.ltp_coef_q14.copy_from_slice(coefs);
+                    plc.pitch_l_q8 = ctrl.pitch_l[sub] << 8;
+                }
+                j += 1;
+            }
+            plc.ltp_coef_q14 = [0; LTP_ORDER];
+            plc.ltp_coef_q14[LTP_ORDER / 2] = ltp_gain_q14 as i16;
+            if ltp_gain_q14 < V_PITCH_GAIN_START_MIN_Q14 {
+                let scale_q10 = (V_PITCH_GAIN_START_MIN_Q14 << 10) / ltp_gain_q14.max(1);
+                for c in &mut plc.ltp_coef_q14 {
+                    *c = (smulbb(*c as i32, scale_q10) >> 10) as i16;
+                }
+            } else if ltp_gain_q14 > V_PITCH_GAIN_START_MAX_Q14 {
+                let scale_q14 = (V_PITCH_GAIN_START_MAX_Q14 << 14) / ltp_gain_q14.max(1);
+                for c in &mut plc.ltp_coef_q14 {
+                    *c = (smulbb(*c as i32, scale_q14) >> 14) as i16;
+                }
+            }
+        } else {
+            plc.pitch_l_q8 = smulbb(self.fs_khz as i32, 18) << 8;
+            plc.ltp_coef_q14 = [0; LTP_ORDER];
+        }
+        plc.prev_lpc_q12[..self.lpc_order].copy_from_slice(&ctrl.pred_coef_q12[1][..self.lpc_order]);
+        plc.prev_ltp_scale_q14 = ctrl.ltp_scale_q14 as i16;
+        plc.prev_gain_q16.copy_from_slice(&ctrl.gains_q16[nb_subfr - 2..nb_subfr]);
+        plc.subfr_length = self.subfr_length;
+        plc.nb_subfr = nb_subfr;
+    }
+
+    fn plc_conceal(&mut self, ctrl: &mut DecoderControl, frame: &mut [i16]) {
+        let ltp_mem_length = self.ltp_mem_length;
+        let order = self.lpc_order;
+        let mut s_ltp_q14 = vec![0i32; ltp_mem_length + self.frame_length];
+        let mut s_ltp = vec![0i16; ltp_mem_length];
+        let plc = &mut self.plc;
+        let prev_gain_q10 = [plc.prev_gain_q16[0] >> 6, plc.prev_gain_q16[1] >> 6];
+        if self.first_frame_after_reset {
+            plc.prev_lpc_q12 = [0; MAX_LPC_ORDER];
+        }
+
+        // the noise is taken from the last subframe of the excitation with lower energy
+        let subfr_length = self.subfr_length;
+        let nb_subfr = self.nb_subfr;
+        let mut exc_buf = vec![0i16; 2 * subfr_length];
+        for k in 0..2 {
+            for i in 0..subfr_length {
+                exc_buf[k * subfr_length + i] = sat16(smulww(self.exc_q14[i + (k + nb_subfr - 2) * subfr_length], prev_gain_q10[k]) >> 8) as i16;
+            }
+        }
+        let (energy1, shift1) = sum_sqr_shift(&exc_buf[..subfr_length]);
+        let (energy2, shift2) = sum_sqr_shift(&exc_buf[subfr_length..]);
+        let rand_offset = if energy1 >> shift2 < energy2 >> shift1 {
+            ((plc.nb_subfr - 1) * plc.subfr_length).saturating_sub(RAND_BUF_SIZE)
+        } else {
+            (plc.nb_subfr * plc.subfr_length).saturating_sub(RAND_BUF_SIZE)
+        };
+
+        let mut b_q14 = plc.ltp_coef_q14;
+        let mut rand_scale_q14 = plc.rand_scale_q14;
+        let att = (self.loss_cnt as usize).min(1);
+        let harm_gain_q15 = HARM_ATT_Q15[att];
+        let mut rand_gain_q15 =
+            if self.prev_signal_type == TYPE_VOICED { PLC_RAND_ATTENUATE_V_Q15[att] } else { PLC_RAND_ATTENUATE_UV_Q15[att] };
+        bwexpander(&mut plc.prev_lpc_q12[..order], BWE_COEF_Q16);
+        let a_q12 = plc.prev_lpc_q12;
+        if self.loss_cnt == 0 {
+            rand_scale_q14 = 1 << 14;
+            if self.prev_signal_type == TYPE_VOICED {
+                for &b in &b_q14 {
+                    rand_scale_q14 = rand_scale_q14.wrapping_sub(b);
+                }
+                rand_scale_q14 = rand_scale_q14.max(3277);
+                rand_scale_q14 = (smulbb(rand_scale_q14 as i32, plc.prev_ltp_scale_q14 as i32) >> 14) as i16;
+            } else {
+                let inv_gain_q30 = lpc_inverse_pred_gain(&plc.prev_lpc_q12[..order]);
+                let mut down_scale_q30 = i32::min((1 << 30) >> LOG2_INV_LPC_GAIN_HIGH_THRES, inv_gain_q30);
+                down_scale_q30 = i32::max((1 << 30) >> LOG2_INV_LPC_GAIN_LOW_THRES, down_scale_q30);
+                down_scale_q30 <<= LOG2_INV_LPC_GAIN_HIGH_THRES;
+                rand_gain_q15 = smulwb(down_scale_q30, rand_gain_q15) >> 14;
+            }
+        }
+
+        let mut rand_seed = plc.rand_seed;
+        let mut lag = rshift_round(plc.pitch_l_q8, 8) as usize;
+        let mut s_ltp_buf_idx = ltp_mem_length;
+        let idx = ltp_mem_length - lag - order - LTP_ORDER / 2;
+        lpc_analysis_filter(&mut s_ltp[idx..], &self.out_buf[idx..], &a_q12[..order], ltp_mem_length - idx);
+        let inv_gain_q30 = inverse32_var_q(plc.prev_gain_q16[1], 46).min(i32::MAX >> 1);
+        for i in idx + order..ltp_mem_length {
+            s_ltp_q14[i] = smulwb(inv_gain_q30, s_ltp[i] as i32);
+        }
+
+        // long-term prediction with random noise
+        for _ in 0..nb_subfr {
+            for _ in 0..subfr_length {
+                let p = s_ltp_buf_idx - lag + LTP_ORDER / 2;
+                let mut ltp_pred_q12 = 2;
+                for (j, &b) in b_q14.iter().enumerate() {
+                    ltp_pred_q12 = smlawb(ltp_pred_q12, s_ltp_q14[p - j], b as i32);
+                }
+                rand_seed = rand(rand_seed);
+                let idx = (rand_seed >> 25) as usize & (RAND_BUF_SIZE - 1);
+                s_ltp_q14[s_ltp_buf_idx] = smlawb(ltp_pred_q12, self.exc_q14[rand_offset + idx], rand_scale_q14 as i32) << 2;
+                s_ltp_buf_idx += 1;
+            }
+            for b in &mut b_q14 {
+                *b = (smulbb(harm_gain_q15, *b as i32) >> 15) as i16;
+            }
+            rand_scale_q14 = (smulbb(rand_scale_q14 as i32, rand_gain_q15) >> 15) as i16;
+            plc.pitch_l_q8 = smlawb(plc.pitch_l_q8, plc.pitch_l_q8, PITCH_DRIFT_FAC_Q16);
+            plc.pitch_l_q8 = plc.pitch_l_q8.min(smulbb(MAX_PITCH_LAG_MS, self.fs_khz as i32) << 8);
+            lag = rshift_round(plc.pitch_l_q8, 8) as usize;
+        }
+
+        // short-term synthesis, in place over the excitation
+        let base = ltp_mem_length - MAX_LPC_ORDER;
+        s_ltp_q14[base..ltp_mem_length].copy_from_slice(&self.s_lpc_q14_buf);
+        for i in 0..self.frame_length {
+            let mut lpc_pred_q10 = (order >> 1) as i32;
+            for (j, &a) in a_q12[..order].iter().enumerate() {
+                lpc_pred_q10 = smlawb(lpc_pred_q10, s_ltp_q14[base + MAX_LPC_ORDER + i - j - 1], a as i32);
+            }
+            let s = &mut s_ltp_q14[base + MAX_LPC_ORDER + i];
+            *s = s.saturating_add(lshift_sat32(lpc_pred_q10, 4));
+            frame[i] = sat16(rshift_round(smulww(*s, prev_gain_q10[1]), 8)) as i16;
+        }
+        self.s_lpc_q14_buf.copy_from_slice(&s_ltp_q14[base + self.frame_length..base + self.frame_length + MAX_LPC_ORDER]);
+        plc.ltp_coef_q14 = b_q14;
+        plc.rand_seed = rand_seed;
+        plc.rand_scale_q14 = rand_scale_q14;
+        ctrl.pitch_l = [lag as i32; MAX_NB_SUBFR];
+    }
+
+    // smooths the energy jump from a concealed frame to a decoded one
+    fn plc_glue_frames(&mut self, frame: &mut [i16]) {
+        let plc = &mut self.plc;
+        if self.loss_cnt != 0 {
+            (plc.conc_energy, plc.conc_energy_shift) = sum_sqr_shift(frame);
+            plc.last_frame_lost = true;
+            return;
+        }
+        if plc.last_frame_lost {
+            let (mut energy, energy_shift) = sum_sqr_shift(frame);
+            if energy_shift > plc.conc_energy_shift {
+                plc.conc_energy >>= energy_shift - plc.conc_energy_shift;
+            } else if energy_shift < plc.conc_energy_shift {
+                energy >>= plc.conc_energy_shift - energy_shift;
+            }
+            if energy > plc.conc_energy {
+                let lz = clz32(plc.conc_energy) - 1;
+                plc.conc_energy <<= lz;
+                energy >>= i32::max(24 - lz, 0);
+                let frac_q24 = plc.conc_energy / energy.max(1);
+                let mut gain_q16 = sqrt_approx(frac_q24) << 4;
+                let slope_q16 = (((1 << 16) - gain_q16) / frame.len() as i32) << 2;
+                for x in frame.iter_mut() {
+                    *x = smulwb(gain_q16, *x as i32) as i16;
+                    gain_q16 += slope_q16;
+                    if gain_q16 > 1 << 16 {
+                        break;
+                    }
+                }
+            }
+        }
+        plc.last_frame_lost = false;
+    }
+
+    fn cng_reset(&mut self) {
+        let step_q15 = i16::MAX as i32 / (self.lpc_order as i32 + 1);
+        let mut acc_q15 = 0;
+        for nlsf in &mut self.cng.smth_nlsf_q15[..self.lpc_order] {
+            acc_q15 += step_q15;
+            *nlsf = acc_q15 as i16;
+        }
+        self.cng.smth_gain_q16 = 0;
+        self.cng.rand_seed = 3_176_576;
+    }
+
+    // comfort noise for lost frames, shaped like the last inactive ones
+    fn cng(&mut self, ctrl: &DecoderControl, frame: &mut [i16]) {
+        if self.fs_khz != self.cng.fs_khz {
+            self.cng_reset();
+            self.cng.fs_khz = self.fs_khz;
+        }
+        let order = self.lpc_order;
+        let cng = &mut self.cng;
+        if self.loss_cnt == 0 && self.prev_signal_type == TYPE_NO_VOICE_ACTIVITY {
+            for i in 0..order {
+                let diff = self.prev_nlsf_q15[i] as i32 - cng.smth_nlsf_q15[i] as i32;
+                cng.smth_nlsf_q15[i] = (cng.smth_nlsf_q15[i] as i32 + smulwb(diff, CNG_NLSF_SMTH_Q16)) as i16;
+            }
+            let mut max_gain_q16 = 0;
+            let mut subfr = 0;
+            for i in 0..self.nb_subfr {
+                if ctrl.gains_q16[i] > max_gain_q16 {
+                    max_gain_q16 = ctrl.gains_q16[i];
+                    subfr = i;
+                }
+            }
+            let subfr_length = self.subfr_length;
+            cng.exc_buf_q14.copy_within(0..(self.nb_subfr - 1) * subfr_length, subfr_length);
+            cng.exc_buf_q14[..subfr_length].copy_from_slice(&self.exc_q14[subfr * subfr_length..(subfr + 1) * subfr_length]);
+            for i in 0..self.nb_subfr {
+                cng.smth_gain_q16 += smulwb(ctrl.gains_q16[i] - cng.smth_gain_q16, CNG_GAIN_SMTH_Q16);
+                if smulww(cng.smth_gain_q16, CNG_GAIN_SMTH_THRESHOLD_Q16) > ctrl.gains_q16[i] {
+                    cng.smth_gain_q16 = ctrl.gains_q16[i];
+                }
+            }
+        }
+        if self.loss_cnt == 0 {
+            cng.synth_state[..order].fill(0);
+            return;
+        }
+        let length = frame.len();
+        let mut gain_q16 = smulww(self.plc.rand_scale_q14 as i32, self.plc.prev_gain_q16[1]);
+        if gain_q16 >= 1 << 21 || cng.smth_gain_q16 > 1 << 23 {
+            gain_q16 = smultt(gain_q16, gain_q16);
+            gain_q16 = smultt(cng.smth_gain_q16, cng.smth_gain_q16).wrapping_sub(gain_q16 << 5);
+            gain_q16 = sqrt_approx(gain_q16) << 16;
+        } else {
+            gain_q16 = smulww(gain_q16, gain_q16);
+            gain_q16 = smulww(cng.smth_gain_q16, cng.smth_gain_q16).wrapping_sub(gain_q16 << 5);
+            gain_q16 = sqrt_approx(gain_q16) << 8;
+        }
+        let gain_q10 = gain_q16 >> 6;
+
+        let mut sig_q14 = vec![0i32; length + MAX_LPC_ORDER];
+        let mut exc_mask = CNG_BUF_MASK_MAX;
+        while exc_mask > length {
+            exc_mask >>= 1;
+        }
+        let mut seed = cng.rand_seed;
+        for s in &mut sig_q14[MAX_LPC_ORDER..] {
+            seed = rand(seed);
+            *s = cng.exc_buf_q14[(seed >> 24) as usize & exc_mask];
+        }
+        cng.rand_seed = seed;
+        let mut a_q12 = [0i16; MAX_LPC_ORDER];
+        nlsf2a(&mut a_q12[..order], &cng.smth_nlsf_q15[..order]);
+        sig_q14[..MAX_LPC_ORDER].copy_from_slice(&cng.synth_state);
+        for i in 0..length {
+            let mut lpc_pred_q10 = (order >> 1) as i32;
+            for (j, &a) in a_q12[..order].iter().enumerate() {
+                lpc_pred_q10 = smlawb(lpc_pred_q10, sig_q14[MAX_LPC_ORDER + i - j - 1], a as i32);
+            }
+            let s = &mut sig_q14[MAX_LPC_ORDER + i];
+            *s = s.saturating_add(lshift_sat32(lpc_pred_q10, 4));
+            frame[i] = (frame[i] as i32 + sat16(rshift_round(smulww(*s, gain_q10), 8))).clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        }
+        cng.synth_state.copy_from_slice(&sig_q14[length..length + MAX_LPC_ORDER]);
+    }
+}
+
+fn gains_dequant(gain_q16: &mut [i32], ind: &[i8], prev_ind: &mut i8, conditional: bool, nb_subfr: usize) {
+    for k in 0..nb_subfr {
+        let mut prev = *prev_ind as i32;
+        if k == 0 && !conditional {
+            prev = i32::max(ind[k] as i32, prev - 16);
+        } else {
+            let ind_tmp = ind[k] as i32 + MIN_DELTA_GAIN_QUANT;
+            let double_step_size_threshold = 2 * MAX_DELTA_GAIN_QUANT - N_LEVELS_QGAIN + prev;
+            if ind_tmp > double_step_size_threshold {
+                prev += (ind_tmp << 1) - double_step_size_threshold;
+            } else {
+                prev += ind_tmp;
+            }
+        }
+        prev = prev.clamp(0, N_LEVELS_QGAIN - 1);
+        *prev_ind = prev as i8;
+        gain_q16[k] = log2lin(i32::min(smulwb(GAIN_INV_SCALE_Q16, prev) + GAIN_OFFSET, 3967));
+    }
+}
+
+fn decode_pitch(lag_index: i16, contour_index: usize, pitch_lags: &mut [i32], fs_khz: usize, nb_subfr: usize) {
+    let min_lag = 2 * fs_khz as i32;
+    let max_lag = 18 * fs_khz as i32;
+    let lag = min_lag + lag_index as i32;
+    for (k, pitch_lag) in pitch_lags.iter_mut().enumerate().take(nb_subfr) {
+        let delta = match (fs_khz == 8, nb_subfr == MAX_NB_SUBFR) {
+            (true, true) => CB_LAGS_STAGE2[k][contour_index],
+            (true, false) => CB_LAGS_STAGE2_10_MS[k][contour_index],
+            (false, true) => CB_LAGS_STAGE3[k][contour_index],
+            (false, false) => CB_LAGS_STAGE3_10_MS[k][contour_index],
+        };
+        *pitch_lag = (lag + delta as i32).clamp(min_lag, max_lag);
+    }
+}
+
+// the excitation pulses, coded in blocks of 16 with a binary split of the pulse counts
+fn decode_pulses(dec: &mut RangeDecoder, pulses: &mut [i16], signal_type: i32, quant_offset_type: i32, frame_length: usize) {
+    let rate_level_index = dec.icdf(&RATE_LEVELS_ICDF[signal_type as usize >> 1], 8);
+    let iter = frame_length.div_ceil(SHELL_CODEC_FRAME_LENGTH);
+    let mut sum_pulses = [0usize; MAX_FRAME_LENGTH / SHELL_CODEC_FRAME_LENGTH];
+    let mut n_lshifts = [0usize; MAX_FRAME_LENGTH / SHELL_CODEC_FRAME_LENGTH];
+    for i in 0..iter {
+        sum_pulses[i] = dec.icdf(&PULSES_PER_BLOCK_ICDF[rate_level_index], 8);
+        while sum_pulses[i] == SILK_MAX_PULSES + 1 {
+            n_lshifts[i] += 1;
+            let icdf = &PULSES_PER_BLOCK_ICDF[N_RATE_LEVELS - 1][(n_lshifts[i] == 10) as usize..];
+            sum_pulses[i] = dec.icdf(icdf, 8);
+        }
+    }
+    for i in 0..iter {
+        let block = &mut pulses[i * SHELL_CODEC_FRAME_LENGTH..(i + 1) * SHELL_CODEC_FRAME_LENGTH];
+        if sum_pulses[i] > 0 {
+            shell_decoder(block, dec, sum_pulses[i]);
+        } else {
+            block.fill(0);
+        }
+    }
+    for i in 0..iter {
+        if n_lshifts[i] > 0 {
+            let block = &mut pulses[i * SHELL_CODEC_FRAME_LENGTH..(i + 1) * SHELL_CODEC_FRAME_LENGTH];
+            for p in block.iter_mut() {
+                let mut abs_q = *p as i32;
+                for _ in 0..n_lshifts[i] {
+                    abs_q = (abs_q << 1) + dec.icdf(&LSB_ICDF, 8) as i32;
+                }
+                *p = abs_q as i16;
+            }
+            sum_pulses[i] |= n_lshifts[i] << 5;
+        }
+    }
+
+    // signs
+    let sign_icdf = &SIGN_ICDF[7 * (quant_offset_type as usize + ((signal_type as usize) << 1))..];
+    for i in 0..(frame_length + SHELL_CODEC_FRAME_LENGTH / 2) >> 4 {
+        let p = sum_pulses[i];
+        if p > 0 {
+            let icdf = [sign_icdf[(p & 0x1f).min(6)], 0];
+            for q in &mut pulses[i * SHELL_CODEC_FRAME_LENGTH..(i + 1) * SHELL_CODEC_FRAME_LENGTH] {
+                if *q > 0 {
+                    *q *= (dec.icdf(&icdf, 8) as i16) * 2 - 1;
+                }
+            }
+        }
+    }
+}
+
+fn shell_decoder(pulses0: &mut [i16], dec: &mut RangeDecoder, pulses4: usize) {
+    let mut split = |p: i16, table: &[u8]| -> (i16, i16) {
+        if p > 0 {
+            let child1 = dec.icdf(&table[SHELL_CODE_TABLE_OFFSETS[p as usize]..], 8) as i16;
+            (child1, p - child1)
+        } else {
+            (0, 0)
+        }
+    };
+    let mut pulses3 = [0i16; 2];
+    let mut pulses2 = [0i16; 4];
+    let mut pulses1 = [0i16; 8];
+    (pulses3[0], pulses3[1]) = split(pulses4 as i16, &SHELL_CODE_TABLE3);
+    for (i3, &p3) in pulses3.iter().enumerate() {
+        (pulses2[2 * i3], pulses2[2 * i3 + 1]) = split(p3, &SHELL_CODE_TABLE2);
+        for i2 in 2 * i3..2 * i3 + 2 {
+            (pulses1[2 * i2], pulses1[2 * i2 + 1]) = split(pulses2[i2], &SHELL_CODE_TABLE1);
+            for i1 in 2 * i2..2 * i2 + 2 {
+                (pulses0[2 * i1], pulses0[2 * i1 + 1]) = split(pulses1[i1], &SHELL_CODE_TABLE0);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct StereoState {
+    pred_prev_q13: [i32; 2],
+    s_mid: [i16; 2],
+    s_side: [i16; 2],
+}
+
+fn stereo_decode_pred(dec: &mut RangeDecoder) -> [i32; 2] {
+    let n = dec.icdf(&STEREO_PRED_JOINT_ICDF, 8);
+    let mut ix = [[0usize; 3]; 2];
+    ix[0][2] = n / 5;
+    ix[1][2] = n - 5 * ix[0][2];
+    for ix in &mut ix {
+        ix[0] = dec.icdf(&UNIFORM3_ICDF, 8);
+        ix[1] = dec.icdf(&UNIFORM5_ICDF, 8);
+    }
+    let mut pred_q13 = [0; 2];
+    for n in 0..2 {
+        ix[n][0] += 3 * ix[n][2];
+        let low_q13 = STEREO_PRED_QUANT_Q13[ix[n][0]];
+        let step_q13 = smulwb(STEREO_PRED_QUANT_Q13[ix[n][0] + 1] - low_q13, 6554);
+        pred_q13[n] = low_q13 + step_q13 * (2 * ix[n][1] as i32 + 1);
+    }
+    pred_q13[0] -= pred_q13[1];
+    pred_q13
+}
+
+// converts mid/side to left/right; both buffers hold two samples of history before the frame
+fn stereo_ms_to_lr(state: &mut StereoState, x1: &mut [i16], x2: &mut [i16], pred_q13: [i32; 2], fs_khz: usize, frame_length: usize) {
+    x1[..2].copy_from_slice(&state.s_mid);
+    x2[..2].copy_from_slice(&state.s_side);
+    state.s_mid.copy_from_slice(&x1[frame_length..frame_length + 2]);
+    state.s_side.copy_from_slice(&x2[frame_length..frame_length + 2]);
+    let mut pred0_q13 = state.pred_prev_q13[0];
+    let mut pred1_q13 = state.pred_prev_q13[1];
+    let interp_len = STEREO_INTERP_LEN_MS * fs_khz;
+    let denom_q16 = (1 << 16) / interp_len as i32;
+    let delta0_q13 = rshift_round(smulbb(pred_q13[0] - state.pred_prev_q13[0], denom_q16), 16);
+    let delta1_q13 = rshift_round(smulbb(pred_q13[1] - state.pred_prev_q13[1], denom_q16), 16);
+    for n in 0..frame_length {
+        if n < interp_len {
+            pred0_q13 += delta0_q13;
+            pred1_q13 += delta1_q13;
+        } else {
+            pred0_q13 = pred_q13[0];
+            pred1_q13 = pred_q13[1];
+        }
+        let mut sum = ((x1[n] as i32 + x1[n + 2] as i32) + ((x1[n + 1] as i32) << 1)) << 9;
+        sum = smlawb((x2[n + 1] as i32) << 8, sum, pred0_q13);
+        sum = smlawb(sum, (x1[n + 1] as i32) << 11, pred1_q13);
+        x2[n + 1] = sat16(rshift_round(sum, 8)) as i16;
+    }
+    state.pred_prev_q13 = pred_q13;
+    for n in 0..frame_length {
+        let sum = x1[n + 1] as i32 + x2[n + 1] as i32;
+        let diff = x1[n + 1] as i32 - x2[n + 1] as i32;
+        x1[n + 1] = sat16(sum) as i16;
+        x2[n + 1] = sat16(diff) as i16;
+    }
+}
+
+pub struct SilkDecoder {
+    channel_state: [ChannelState; 2],
+    stereo: StereoState,
+    channels: usize,
+    n_channels_api: usize,
+    n_channels_internal: usize,
+    prev_decode_only_middle: bool,
+}
+
+impl SilkDecoder {
+    pub fn new(channels: usize) -> Self {
+        Self {
+            channel_state: [ChannelState::new(), ChannelState::new()],
+            stereo: StereoState::default(),
+            channels,
+            n_channels_api: 0,
+            n_channels_internal: 0,
+            prev_decode_only_middle: false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.channel_state = [ChannelState::new(), ChannelState::new()];
+        self.stereo = StereoState::default();
+        self.prev_decode_only_middle = false;
+    }
+
+    // Decodes one SILK frame of a packet (or conceals one if `dec` is None) into `out` at 48 kHz,
+    // interleaved, and returns the number of samples per channel. `new_packet` is set for the
+    // first frame of each packet.
+    pub fn decode(
+        &mut self,
+        mut dec: Option<&mut RangeDecoder>,
+        new_packet: bool,
+        internal_channels: usize,
+        internal_rate_khz: usize,
+        payload_ms: usize,
+        out: &mut [i16],
+    ) -> usize {
+        let states = &mut self.channel_state;
+        let mut decode_only_middle = false;
+        let mut ms_pred_q13 = [0; 2];
+        if new_packet {
+            for state in &mut states[..internal_channels] {
+                state.n_frames_decoded = 0;
+            }
+        }
+        if internal_channels > self.n_channels_internal {
+            states[1] = ChannelState::new();
+        }
+        let stereo_to_mono = internal_channels == 1 && self.n_channels_internal == 2 && internal_rate_khz == states[0].fs_khz;
+        if states[0].n_frames_decoded == 0 {
+            for state in &mut states[..internal_channels] {
+                (state.n_frames_per_packet, state.nb_subfr) = match payload_ms {
+                    10 => (1, 2),
+                    20 => (1, 4),
+                    40 => (2, 4),
+                    _ => (3, 4),
+                };
+                state.set_fs(internal_rate_khz);
+            }
+        }
+        if self.channels == 2 && internal_channels == 2 && (self.n_channels_api == 1 || self.n_channels_internal == 1) {
+            self.stereo.pred_prev_q13 = [0; 2];
+            self.stereo.s_side = [0; 2];
+            states[1].resampler = states[0].resampler.clone();
+        }
+        self.n_channels_api = self.channels;
+        self.n_channels_internal = internal_channels;
+
+        if let Some(dec) = dec.as_deref_mut() {
+            if states[0].n_frames_decoded == 0 {
+                // voice activity and in-band FEC flags of the whole packet
+                for state in &mut states[..internal_channels] {
+                    for i in 0..state.n_frames_per_packet {
+                        state.vad_flags[i] = dec.bit_logp(1);
+                    }
+                    state.lbrr_flag = dec.bit_logp(1);
+                }
+                for state in &mut states[..internal_channels] {
+                    state.lbrr_flags = [false; MAX_FRAMES_PER_PACKET];
+                    if state.lbrr_flag {
+                        if state.n_frames_per_packet == 1 {
+                            state.lbrr_flags[0] = true;
+                        } else {
+                            let icdf: &[u8] = if state.n_frames_per_packet == 2 { &LBRR_FLAGS_2_ICDF } else { &LBRR_FLAGS_3_ICDF };
+                            let symbol = dec.icdf(icdf, 8) + 1;
+                            for i in 0..state.n_frames_per_packet {
+                                state.lbrr_flags[i] = (symbol >> i) & 1 != 0;
+                            }
+                        }
+                    }
+                }
+                // the redundant frames are skipped
+                for i in 0..states[0].n_frames_per_packet {
+                    for n in 0..internal_channels {
+                        if states[n].lbrr_flags[i] {
+                            if internal_channels == 2 && n == 0 {
+                                stereo_decode_pred(dec);
+                                if !states[1].lbrr_flags[i] {
+                                    dec.icdf(&STEREO_ONLY_CODE_MID_ICDF, 8);
+                                }
+                            }
+                            let cond_coding = if i > 0 && states[n].lbrr_flags[i - 1] { CODE_CONDITIONALLY } else { CODE_INDEPENDENTLY };
+                            states[n].decode_indices(dec, i, true, cond_coding);
+                            let mut pulses = [0i16; MAX_FRAME_LENGTH];
+                            let state = &states[n];
+                            decode_pulses(dec, &mut pulses, state.indices.signal_type, state.indices.quant_offset_type, state.frame_length);
+                        }
+                    }
+                }
+            }
+        }
+
+        if internal_channels == 2 {
+            match dec.as_deref_mut() {
+                Some(dec) => {
+                    ms_pred_q13 = stereo_decode_pred(dec);
+                    decode_only_middle = !states[1].vad_flags[states[0].n_frames_decoded] && dec.icdf(&STEREO_ONLY_CODE_MID_ICDF, 8) == 1;
+                }
+                None => ms_pred_q13 = self.stereo.pred_prev_q13,
+            }
+        }
+        if internal_channels == 2 && !decode_only_middle && self.prev_decode_only_middle {
+            let side = &mut states[1];
+            side.out_buf.fill(0);
+            side.s_lpc_q14_buf.fill(0);
+            side.lag_prev = 100;
+            side.last_gain_index = 10;
+            side.prev_signal_type = TYPE_NO_VOICE_ACTIVITY;
+            side.first_frame_after_reset = true;
+        }
+
+        let frame_length = states[0].frame_length;
+        let mut samples = [vec![0i16; frame_length + 2], vec![0i16; frame_length + 2]];
+        let has_side = if dec.is_some() { !decode_only_middle } else { !self.prev_decode_only_middle };
+        let mut n_samples_out_dec = frame_length;
+        for n in 0..internal_channels {
+            if n == 0 || has_side {
+                let frame_index = states[0].n_frames_decoded as i32 - n as i32;
+                let cond_coding = if frame_index <= 0 {
+                    CODE_INDEPENDENTLY
+                } else if n > 0 && self.prev_decode_only_middle {
+                    CODE_INDEPENDENTLY_NO_LTP_SCALING
+                } else {
+                    CODE_CONDITIONALLY
+                };
+                states[n].decode_frame(dec.as_deref_mut(), &mut samples[n][2..], cond_coding);
+                n_samples_out_dec = states[n].frame_length;
+            } else {
+                samples[n][2..2 + n_samples_out_dec].fill(0);
+            }
+            states[n].n_frames_decoded += 1;
+        }
+
+        let (mid, side) = samples.split_at_mut(1);
+        if self.channels == 2 && internal_channels == 2 {
+            stereo_ms_to_lr(&mut self.stereo, &mut mid[0], &mut side[0], ms_pred_q13, states[0].fs_khz, n_samples_out_dec);
+        } else {
+            mid[0][..2].copy_from_slice(&self.stereo.s_mid);
+            self.stereo.s_mid.copy_from_slice(&mid[0][n_samples_out_dec..n_samples_out_dec + 2]);
+        }
+
+        let n_samples_out = n_samples_out_dec * 48 / states[0].fs_khz;
+        let mut resampled = vec![0i16; n_samples_out];
+        let channels = self.channels;
+        for n in 0..channels.min(internal_channels) {
+            states[n].resampler.process(&mut resampled, &samples[n][1..1 + n_samples_out_dec]);
+            for (i, &x) in resampled.iter().enumerate() {
+                out[channels * i + n] = x;
+            }
+        }
+        if channels == 2 && internal_channels == 1 {
+            if stereo_to_mono {
+                states[1].resampler.process(&mut resampled, &samples[0][1..1 + n_samples_out_dec]);
+                for (i, &x) in resampled.iter().enumerate() {
+                    out[2 * i + 1] = x;
+                }
+            } else {
+                for i in 0..n_samples_out {
+                    out[2 * i + 1] = out[2 * i];
+                }
+            }
+        }
+
+        if dec.is_none() {
+            for state in &mut states[..self.n_channels_internal] {
+                state.last_gain_index = 10;
+            }
+        } else {
+            self.prev_decode_only_middle = decode_only_middle;
+        }
+        n_samples_out
+    }
+}
@@ -0,0 +1,176 @@
+use std::f64::consts::{PI, TAU};
+use std::path::Path;
+
+use crate::audio::{inverse_stft, Stft, StftFrames, StftOutput, WindowFunction};
+use crate::audio_source::open_audio;
+use crate::fft::Complex;
+use crate::file_io::{write_wav_file, SpeakerPos, WavError, WavWriteInfo};
+
+// ~43 ms at 48 kHz, long enough to resolve low notes
+const WINDOW_LEN: usize = 2048;
+// 87.5% overlap keeps the phases coherent from one window to the next
+const ANALYSIS_STEP: usize = WINDOW_LEN / 8;
+
+// limits of the time stretch, the synthesis windows mustn't overlap by less than half
+pub const MIN_STRETCH: f32 = 0.125;
+pub const MAX_STRETCH: f32 = 4.;
+// the pitch shift is a time stretch by 2^(semitones / 12), so the same limits in semitones
+pub const MIN_SEMITONES: f32 = -36.;
+pub const MAX_SEMITONES: f32 = 24.;
+
+// half the width of the resampling filter, in input samples at the cutoff
+const RESAMPLE_TAPS: f64 = 16.;
+
+// Makes the samples `factor` times longer without changing their pitch.
+// The result is as long as the input times the factor actually used, which is a multiple of 1/256
+pub fn time_stretch(samples: &[f32], factor: f32) -> Result<Vec<f32>, WavError> {
+    if !(MIN_STRETCH..=MAX_STRETCH).contains(&factor) {
+        return Err(WavError::InvalidInput("Time stretch factor must be between 0.125 and 4!"));
+    }
+    let synthesis_step = (ANALYSIS_STEP as f32 * factor).round() as usize;
+
+    // pad both ends so every sample is covered by as many windows as the middle of the signal
+    let mut padded = vec![0.; WINDOW_LEN];
+    padded.extend_from_slice(samples);
+    padded.resize(padded.len() + WINDOW_LEN, 0.);
+    // the sample rate only sets the frequency of each bin, which isn't needed here
+    let mut stft = Stft::from_lengths(0, WINDOW_LEN, ANALYSIS_STEP, WindowFunction::Hann, StftOutput::Complex)?;
+    stft.push(&padded);
+    let num_bins = stft.num_bins();
+    let StftFrames::Complex(mut frames) = stft.finish().frames else {
+        unreachable!("the stft was asked for complex frames")
+    };
+
+    // keep every bin's magnitude, but move the phase of every peak on by its true frequency over the new step.
+    // The bins around a peak keep their phase relative to it, or the windows stop adding up coherently
+    let mut last_phase = vec![0f64; num_bins];
+    let mut synthesis_phase = vec![0f64; num_bins];
+    let mut phase = vec![0f64; num_bins];
+    let mut magnitude = vec![0f32; num_bins];
+    for (m, frame) in frames.chunks_exact_mut(num_bins).enumerate() {
+        for ((bin, phase), magnitude) in frame.iter().zip(phase.iter_mut()).zip(magnitude.iter_mut()) {
+            *phase = bin.phase() as f64;
+            *magnitude = bin.magnitude();
+        }
+
+        if m == 0 {
+            synthesis_phase.copy_from_slice(&phase);
+        } else {
+            let peaks = find_peaks(&magnitude);
+            for &k in &peaks {
+                let bin_freq = TAU * k as f64 / WINDOW_LEN as f64;
+                let deviation = wrap_phase(phase[k] - last_phase[k] - bin_freq * ANALYSIS_STEP as f64);
+                let true_freq = bin_freq + deviation / ANALYSIS_STEP as f64;
+                synthesis_phase[k] = wrap_phase(synthesis_phase[k] + true_freq * synthesis_step as f64);
+            }
+            // every bin belongs to the closest peak
+            let mut peak = 0;
+            for k in 0..num_bins {
+                while peak + 1 < peaks.len() && k > (peaks[peak] + peaks[peak + 1]) / 2 {
+                    peak += 1;
+                }
+                if let Some(&p) = peaks.get(peak) {
+                    if k != p {
+                        synthesis_phase[k] = wrap_phase(synthesis_phase[p] + phase[k] - phase[p]);
+                    }
+                }
+            }
+        }
+
+        last_phase.copy_from_slice(&phase);
+        for ((bin, &magnitude), &phase) in frame.iter_mut().zip(&magnitude).zip(&synthesis_phase) {
+            *bin = Complex::from_polar(magnitude, phase as f32);
+        }
+    }
+
+    let out = inverse_stft(&frames, WindowFunction::Hann, WINDOW_LEN, synthesis_step);
+    let start = WINDOW_LEN * synthesis_step / ANALYSIS_STEP;
+    let len = samples.len() * synthesis_step / ANALYSIS_STEP;
+    Ok(out[start..start + len].to_vec())
+}
+
+// Moves the pitch by a number of semitones, keeping the length
+pub fn pitch_shift(samples: &[f32], semitones: f32) -> Result<Vec<f32>, WavError> {
+    if !(MIN_SEMITONES..=MAX_SEMITONES).contains(&semitones) {
+        return Err(WavError::InvalidInput("Pitch shift must be between -36 and 24 semitones!"));
+    }
+    let ratio = 2f32.powf(semitones / 12.);
+    let stretched = time_stretch(samples, ratio)?;
+    // played back faster by the stretch actually used, to land on the original length
+    let step = stretched.len() as f64 / samples.len().max(1) as f64;
+    Ok(resample(&stretched, step, samples.len()))
+}
+
+pub fn time_stretch_file<P: AsRef<Path>>(path: P, target_file: String, factor: f32) -> Result<(), WavError> {
+    process_file(path, target_file, |samples| time_stretch(samples, factor))
+}
+
+pub fn pitch_shift_file<P: AsRef<Path>>(path: P, target_file: String, semitones: f32) -> Result<(), WavError> {
+    process_file(path, target_file, |samples| pitch_shift(samples, semitones))
+}
+
+// runs every channel of a file through `process`, and writes the result as a 32 bit float wav
+fn process_file<P: AsRef<Path>>(
+    path: P,
+    target_file: String,
+    process: impl Fn(&[f32]) -> Result<Vec<f32>, WavError>,
+) -> Result<(), WavError> {
+    let mut reader = open_audio(path)?;
+    let info = reader.info().clone();
+    let mut samples = vec![Vec::with_capacity(reader.num_frames() as usize); info.channels as usize];
+    let mut block = vec![];
+    while reader.read_block(&mut block)? > 0 {
+        for (channel, block) in samples.iter_mut().zip(&block) {
+            channel.extend_from_slice(block);
+        }
+    }
+
+    let processed = samples.iter().map(|channel| process(channel)).collect::<Result<Vec<_>, _>>()?;
+    let channel_mapping = if info.channel_map.len() >= info.channels as usize {
+        info.channel_map.clone()
+    } else {
+        (0..info.channels).map(|c| (c, SpeakerPos::from(1u32.checked_shl(c as u32).unwrap_or(0)))).collect()
+    };
+    let format = WavWriteInfo {
+        sample_type: 3,
+        channels: info.channels,
+        sample_rate: info.sample_rate,
+        bit_depth: 32,
+        channel_mapping,
+        ..Default::default()
+    };
+    write_wav_file(target_file, &format, &processed)
+}
+
+// bins louder than the two on either side
+fn find_peaks(magnitude: &[f32]) -> Vec<usize> {
+    (0..magnitude.len()).filter(|&k| {
+        let neighbours = k.saturating_sub(2)..usize::min(k + 3, magnitude.len());
+        magnitude[k] > 0. && neighbours.into_iter().all(|j| j == k || magnitude[j] < magnitude[k])
+    }).collect()
+}
+
+fn wrap_phase(phase: f64) -> f64 {
+    phase - TAU * (phase / TAU).round()
+}
+
+// Reads `len` samples, `step` input samples apart, with a windowed sinc that also
+// filters out what would alias when reading faster than 1 sample per sample
+fn resample(samples: &[f32], step: f64, len: usize) -> Vec<f32> {
+    let cutoff = f64::min(1., 1. / step);
+    let half_width = RESAMPLE_TAPS / cutoff;
+    (0..len).map(|i| {
+        let pos = i as f64 * step;
+        let first = (pos - half_width).ceil().max(0.) as usize;
+        let last = ((pos + half_width).floor() as usize).min(samples.len().saturating_sub(1));
+        let mut sum = 0.;
+        for (j, &x) in samples.iter().enumerate().take(last + 1).skip(first) {
+            let t = pos - j as f64;
+            // blackman window over the taps
+            let window = 0.42 + 0.5 * (PI * t / half_width).cos() + 0.08 * (TAU * t / half_width).cos();
+            let sinc = if t == 0. { 1. } else { (PI * cutoff * t).sin() / (PI * cutoff * t) };
+            sum += x as f64 * cutoff * sinc * window;
+        }
+        sum as f32
+    }).collect()
+}
@@ -1,21 +1,24 @@
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use cpal::{Stream, Data, Host, OutputCallbackInfo, SampleFormat, SampleRate};
 
-use std::fs::File;
-use std::io::{BufReader, Seek, SeekFrom};
 use std::sync::{Mutex, Arc};
 
-use octave::file_io::{read_data_interleaved_unchecked, read_wav_meta, WavInfo};
+use octave::audio_source::{audio_path, open_audio, AudioSource};
+use octave::file_io::WavError;
 use octave::parametric_eq::ParametricEq;
-use octave::audio::FreqData;
+use octave::audio::{FreqData, WindowFunction};
 
 use crate::rta::RTA;
 
+// the player reads exactly as many frames as cpal asks for, this only limits `AudioSource::read_block`
+const PLAYER_BLOCK_SIZE: usize = 4096;
+
 pub struct AudioPlayer {
     internal_player: Arc<Mutex<FilePlayer>>,
     internal_rta: Arc<Mutex<RTA>>,
     pub playing: bool,
     pub duration: f32,
+    pub markers: Vec<(f32, String)>, // (progress, label) of every cue point in the file
 
     // this is not actually dead code, since the stream wont work if dropped out of scope. (ask me how i know)
     #[allow(dead_code)]
@@ -23,16 +26,26 @@ pub struct AudioPlayer {
 }
 
 impl AudioPlayer {
-    pub fn new(file_path: String, parametric_eq: Arc<Mutex<ParametricEq>>) -> Self {
-        let mut reader = BufReader::new(File::open(format!("./res/audio/{}", file_path)).unwrap());
-        let meta = read_wav_meta(&mut reader);
-        
-        let internal_player = Arc::new(Mutex::new(FilePlayer::new_from_reader(reader, meta.clone())));
+    pub fn new(file_path: String, parametric_eq: Arc<Mutex<ParametricEq>>) -> Result<Self, WavError> {
+        let mut reader = open_audio(audio_path(&file_path))?;
+        reader.set_block_size(PLAYER_BLOCK_SIZE)?;
+        let meta = reader.info().clone();
+        let num_frames = reader.num_frames().max(1);
+
+        let internal_player = Arc::new(Mutex::new(FilePlayer::new(reader)));
         internal_player.lock().unwrap().paused = true;
 
         let sample_rate = meta.sample_rate;
 
-        let internal_rta = Arc::new(Mutex::new(RTA::new(2usize.pow(14), sample_rate)));
+        let markers = meta.cue_points
+            .iter()
+            .map(|c| {
+                let label = if c.label.is_empty() { format!("Marker {}", c.id) } else { c.label.clone() };
+                ((c.position as f32 / num_frames as f32).min(1.), label)
+            })
+            .collect();
+
+        let internal_rta = Arc::new(Mutex::new(RTA::new(2usize.pow(14), sample_rate, WindowFunction::Square)));
         
         let host: Host = cpal::default_host();
         let device = host.default_output_device().expect("No audio device available!");
@@ -75,13 +88,14 @@ impl AudioPlayer {
             ).unwrap();
         stream.play().unwrap();
 
-        Self {
+        Ok(Self {
             internal_player,
             internal_rta,
             playing: false,
             duration: meta.audio_duration,
+            markers,
             stream
-        }
+        })
     }
 
     pub fn start(&mut self) {
@@ -158,47 +172,32 @@ impl Play for SignalPlayer {
 }
 
 pub struct FilePlayer {
-    pub file_meta: WavInfo,
     pub finished: bool,
     pub paused: bool,
     pub progress: f32,
-    reader: BufReader<File>,
-    pos: usize,
-    start_pos: usize,
-    end_pos: usize,
-    size: usize,
+    reader: Box<dyn AudioSource + Send>,
 }
 
 impl FilePlayer {
-    pub fn new_from_reader(mut reader: BufReader<File>, file_meta: WavInfo) -> Self {
-        //advance reader to beginning of audio data
-        reader
-            .seek(SeekFrom::Start(file_meta.chunks.get("data").unwrap().0))
-            .unwrap();
-        let start_pos = reader.stream_position().unwrap() as usize;
-        let end_pos = start_pos + file_meta.chunks.get("data").unwrap().1 as usize;
+    pub fn new(reader: Box<dyn AudioSource + Send>) -> Self {
         Self {
-            file_meta,
             finished: false,
             paused: false,
             progress: 0.,
             reader,
-            pos: 0,
-            start_pos,
-            end_pos,
-            size: end_pos - start_pos,
         }
     }
 
     pub fn set_progress(&mut self, prog: f32) {
-        let mut new_pos = (prog * self.size as f32) as usize; 
-        //this pos must be a multiple of the bit depth and channels
-        new_pos -= new_pos % self.file_meta.data_block_size as usize;
-
-        new_pos = new_pos.clamp(self.start_pos, self.end_pos);
+        // prog is relative to the audio data, rounded to the closest frame
+        let num_frames = self.reader.num_frames();
+        let frame = ((prog.clamp(0., 1.) as f64 * num_frames as f64).round() as u64).min(num_frames);
 
-        self.pos = new_pos;
-        self.reader.seek(SeekFrom::Start(self.pos as u64)).unwrap();
+        // the frame is always within the data, so this can only fail on an I/O error
+        if self.reader.seek_frame(frame).is_err() {
+            self.finished = true;
+        }
+        self.progress = frame as f32 / num_frames.max(1) as f32;
     }
 }
 
@@ -210,26 +209,23 @@ impl Play for FilePlayer {
             return;
         }
         if self.finished {
-            if self.pos == self.start_pos { return; }
-            self.pos = self.start_pos;
-            self.reader.seek(SeekFrom::Start(self.pos as u64)).unwrap();
-            self.progress = 0.;
+            if self.reader.position() == 0 { return; }
+            self.set_progress(0.);
             self.paused = true;
             return;
         }
 
         let dat_slice = data.as_slice_mut().unwrap();
-        if self.pos + dat_slice.len() * self.file_meta.byte_depth as usize >= self.end_pos {
+        let channels = self.reader.info().channels as usize;
+        // treat a failed read like the end of the file instead of killing the audio thread
+        let frames = self.reader.read_interleaved(dat_slice).unwrap_or_default();
+        if frames * channels < dat_slice.len() {
+            // pad the last chunk with silence
+            dat_slice[frames * channels..].fill(0f32);
             self.finished = true;
-            return;
         }
 
-        let data =
-            read_data_interleaved_unchecked(&mut self.reader, &self.file_meta, dat_slice.len());
-        dat_slice[..].clone_from_slice(&data);
-
-        self.pos += data.len() * self.file_meta.byte_depth as usize;
-        self.progress = self.pos as f32 / (self.size) as f32;
+        self.progress = self.reader.position() as f32 / self.reader.num_frames().max(1) as f32;
     }
 }
 
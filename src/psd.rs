@@ -0,0 +1,180 @@
+use crate::audio::{Stft, StftFrames, StftOutput, WindowFunction};
+use crate::file_io::WavError;
+
+// the median needs every periodogram until the end, so it keeps at most this many values (64 MB),
+// an hour at 48 kHz with 4096 sample segments and half overlap would already be about 700 MB
+pub const MAX_MEDIAN_VALUES: usize = 1 << 24;
+
+// How the periodograms of the segments are combined into one spectrum
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Averaging {
+    Mean,
+    // ignores short loud events, and is corrected so noise reads the same as with the mean.
+    // limited to MAX_MEDIAN_VALUES periodogram bins in total, longer signals are rejected
+    Median,
+    // the highest power every bin reached
+    MaxHold,
+}
+
+// A one-sided power spectral density, in squared sample values per Hz
+#[derive(Clone, Debug, PartialEq)]
+pub struct PowerSpectrum {
+    pub sample_rate: u32,
+    pub frequency_step: f32,
+    pub density: Vec<f32>, // from 0 Hz up to and including nyquist
+    pub num_segments: usize,
+}
+
+impl PowerSpectrum {
+    pub fn frequency(&self, bin: usize) -> f32 {
+        bin as f32 * self.frequency_step
+    }
+
+    // power of the bins from `low` up to `high`, the mean square of the signal in that band
+    pub fn band_power(&self, low: f32, high: f32) -> f32 {
+        let first = (low / self.frequency_step).ceil().max(0.) as usize;
+        let sum: f32 = self.density.iter().enumerate().skip(first).take_while(|&(k, _)| self.frequency(k) < high).map(|(_, p)| p).sum();
+        sum * self.frequency_step
+    }
+
+    // the mean square of the whole signal
+    pub fn total_power(&self) -> f32 {
+        self.density.iter().sum::<f32>() * self.frequency_step
+    }
+}
+
+// Welch's method: the periodograms of overlapping windowed segments, combined as they are completed
+pub struct Welch {
+    stft: Stft,
+    averaging: Averaging,
+    sample_rate: u32,
+    segment_len: usize,
+    num_bins: usize,
+    scale: f32, // from a squared bin to power per Hz
+    acc: Vec<f64>, // sum or max of every bin
+    periodograms: Vec<f32>, // every segment, only kept for the median
+    num_segments: usize,
+    too_long: bool, // more segments than the median can keep
+}
+
+impl Welch {
+    // segment_len is in samples, overlap is the fraction of each segment shared with the next
+    pub fn new(sample_rate: u32, segment_len: usize, overlap: f32, window_func: WindowFunction, averaging: Averaging) -> Result<Self, WavError> {
+        if !(0. ..1.).contains(&overlap) {
+            return Err(WavError::InvalidInput("Overlap must be at least 0 and less than 1!"));
+        }
+        if sample_rate == 0 {
+            return Err(WavError::InvalidInput("Sample rate must be above 0!"));
+        }
+        let step_size = segment_len - (segment_len as f32 * overlap).floor() as usize;
+        let stft = Stft::from_lengths(sample_rate, segment_len, step_size, window_func, StftOutput::Complex)?;
+        let num_bins = stft.num_bins();
+
+        // dividing by the window's power makes the density independent of the window
+        let window_power: f64 = window_func.coefficients(segment_len).iter().map(|&w| w as f64 * w as f64).sum();
+        Ok(Self {
+            stft,
+            averaging,
+            sample_rate,
+            segment_len,
+            num_bins,
+            scale: (1. / (sample_rate as f64 * window_power)) as f32,
+            acc: vec![0.; num_bins],
+            periodograms: vec![],
+            num_segments: 0,
+            too_long: false,
+        })
+    }
+
+    // segments added so far, the stft holds back a few until it has a whole batch or the welch is finished
+    pub fn num_segments(&self) -> usize {
+        self.num_segments
+    }
+
+    // adds samples to the end of the signal
+    pub fn push(&mut self, samples: &[f32]) {
+        if self.stft.push(samples) > 0 {
+            self.add_frames();
+        }
+    }
+
+    // the periodograms of the windows the stft has finished
+    fn add_frames(&mut self) {
+        let StftFrames::Complex(frames) = self.stft.take_frames() else {
+            unreachable!("the stft was asked for complex frames")
+        };
+
+        for frame in frames.chunks_exact(self.num_bins) {
+            let power = frame.iter().enumerate().map(|(k, bin)| {
+                let p = (bin.r * bin.r + bin.i * bin.i) * self.scale;
+                // everything but dc and nyquist also stands for its negative frequency
+                if k > 0 && 2 * k < self.segment_len { 2. * p } else { p }
+            });
+            match self.averaging {
+                Averaging::Mean => self.acc.iter_mut().zip(power).for_each(|(acc, p)| *acc += p as f64),
+                Averaging::MaxHold => self.acc.iter_mut().zip(power).for_each(|(acc, p)| *acc = acc.max(p as f64)),
+                Averaging::Median if self.periodograms.len() + self.num_bins > MAX_MEDIAN_VALUES => self.too_long = true,
+                Averaging::Median => self.periodograms.extend(power),
+            }
+            self.num_segments += 1;
+        }
+    }
+
+    pub fn finish(mut self) -> Result<PowerSpectrum, WavError> {
+        // the last windows, that didn't make up a whole batch
+        self.stft.flush();
+        self.add_frames();
+        let n = self.num_segments;
+        if n == 0 {
+            return Err(WavError::InvalidInput("Signal is shorter than one segment!"));
+        }
+        if self.too_long {
+            return Err(WavError::InvalidInput("Signal is too long for the median, use the mean or longer segments!"));
+        }
+
+        let density = match self.averaging {
+            Averaging::Mean => self.acc.iter().map(|&sum| (sum / n as f64) as f32).collect(),
+            Averaging::MaxHold => self.acc.iter().map(|&max| max as f32).collect(),
+            Averaging::Median => {
+                let bias = median_bias(n);
+                let mut bin = vec![0.; n];
+                (0..self.num_bins).map(|k| {
+                    for (b, p) in bin.iter_mut().zip(self.periodograms.iter().skip(k).step_by(self.num_bins)) {
+                        *b = *p;
+                    }
+                    bin.sort_unstable_by(f32::total_cmp);
+                    let median = if n % 2 == 1 { bin[n / 2] } else { (bin[n / 2 - 1] + bin[n / 2]) / 2. };
+                    median / bias
+                }).collect()
+            }
+        };
+
+        Ok(PowerSpectrum {
+            sample_rate: self.sample_rate,
+            frequency_step: self.stft.frequency_step(),
+            density,
+            num_segments: n,
+        })
+    }
+}
+
+// The median of n periodogram values of noise over their mean, which is below 1
+// since each of them is exponentially distributed
+fn median_bias(n: usize) -> f32 {
+    let sum: f64 = (1..=(n - 1) / 2).map(|i| 1. / (2 * i + 1) as f64 - 1. / (2 * i) as f64).sum();
+    (1. + sum) as f32
+}
+
+// Estimates the power spectral density of samples that are already in memory
+pub fn welch_psd(
+    samples: &[f32],
+    sample_rate: u32,
+    segment_len: usize,
+    overlap: f32,
+    window_func: WindowFunction,
+    averaging: Averaging,
+) -> Result<PowerSpectrum, WavError> {
+    let mut welch = Welch::new(sample_rate, segment_len, overlap, window_func, averaging)?;
+    welch.push(samples);
+    welch.finish()
+}
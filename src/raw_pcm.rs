@@ -0,0 +1,338 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::audio_source::AudioSource;
+use crate::file_io::{buf_to_int, SpeakerPos, WavError, WavInfo};
+use crate::lookup_tables::*;
+
+// extensions of headerless dumps, which are read as raw samples even if they happen to start like another format
+pub const RAW_PCM_EXTENSIONS: [&str; 2] = ["pcm", "raw"];
+
+pub fn has_raw_pcm_extension<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref()
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| RAW_PCM_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RawSampleType {
+    SignedPcm,
+    UnsignedPcm, // offset binary, with silence halfway up the range
+    IeeeFloat,
+    ALaw,
+    MuLaw,
+}
+
+impl RawSampleType {
+    // the wav format code of the decoded samples
+    fn format_code(&self) -> u8 {
+        match self {
+            Self::SignedPcm | Self::UnsignedPcm => 1,
+            Self::IeeeFloat => 3,
+            Self::ALaw => 6,
+            Self::MuLaw => 7,
+        }
+    }
+}
+
+// parses the names Display gives, plus a few short forms
+impl FromStr for RawSampleType {
+    type Err = WavError;
+
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        match str.to_lowercase().as_str() {
+            "signed pcm" | "signed" => Ok(Self::SignedPcm),
+            "unsigned pcm" | "unsigned" => Ok(Self::UnsignedPcm),
+            "ieee float" | "float" => Ok(Self::IeeeFloat),
+            "a-law" | "alaw" => Ok(Self::ALaw),
+            "µ-law" | "mu-law" | "ulaw" => Ok(Self::MuLaw),
+            _ => Err(WavError::InvalidInput("Unknown raw sample type!")),
+        }
+    }
+}
+
+impl fmt::Display for RawSampleType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::SignedPcm => "Signed PCM",
+            Self::UnsignedPcm => "Unsigned PCM",
+            Self::IeeeFloat => "IEEE Float",
+            Self::ALaw => "A-law",
+            Self::MuLaw => "µ-law",
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+// Everything needed to read a headerless file, which has to come from the user
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RawPcmSpec {
+    pub sample_type: RawSampleType,
+    pub endianness: Endianness, // ignored for 8-bit samples
+    pub bit_depth: u32,
+    pub channels: u8,
+    pub sample_rate: u32,
+    pub byte_offset: u64, // where the samples start, e.g. after a device's own header
+}
+
+impl Default for RawPcmSpec {
+    fn default() -> Self {
+        Self {
+            sample_type: RawSampleType::SignedPcm,
+            endianness: Endianness::Little,
+            bit_depth: 16,
+            channels: 1,
+            sample_rate: 48000,
+            byte_offset: 0,
+        }
+    }
+}
+
+impl RawPcmSpec {
+    pub fn validate(&self) -> Result<(), WavError> {
+        let supported = match self.sample_type {
+            RawSampleType::SignedPcm | RawSampleType::UnsignedPcm => matches!(self.bit_depth, 8 | 16 | 24 | 32),
+            RawSampleType::IeeeFloat => matches!(self.bit_depth, 32 | 64),
+            RawSampleType::ALaw | RawSampleType::MuLaw => self.bit_depth == 8,
+        };
+        if !supported {
+            return Err(WavError::UnsupportedBitDepth {
+                sample_type: self.sample_type.format_code() as u16,
+                bit_depth: self.bit_depth,
+            });
+        }
+        if self.channels == 0 {
+            return Err(WavError::InvalidInput("Raw files need at least 1 channel!"));
+        }
+        if self.sample_rate == 0 {
+            return Err(WavError::InvalidInput("Sample rate must be above 0 Hz!"));
+        }
+        Ok(())
+    }
+
+    pub fn byte_depth(&self) -> usize {
+        self.bit_depth as usize / 8
+    }
+
+    // bytes per frame
+    pub fn block_size(&self) -> usize {
+        self.byte_depth() * self.channels as usize
+    }
+
+    // describes the file like any other format, with everything after the byte offset as the data chunk.
+    // a partial frame at the end is left out
+    pub fn wav_info(&self, file_size: u64) -> Result<WavInfo, WavError> {
+        self.validate()?;
+        if self.byte_offset > file_size {
+            return Err(WavError::InvalidInput("Byte offset is past the end of the file!"));
+        }
+        let block_size = self.block_size() as u64;
+        let data_size = (file_size - self.byte_offset) / block_size * block_size;
+        let mut chunks = HashMap::new();
+        chunks.insert("data".to_string(), (self.byte_offset, data_size));
+
+        // there is nothing to say which speaker is which, so channels map to them in wav order
+        let channel_map = (0..self.channels)
+            .map(|c| (c, SpeakerPos::from(1u32.checked_shl(c as u32).unwrap_or(0))))
+            .collect();
+
+        let mut info = WavInfo::new(self.sample_type.format_code(), self.channels, self.sample_rate, self.bit_depth, file_size, chunks, channel_map);
+        info.sample_type_str = match (self.sample_type, self.byte_depth()) {
+            (RawSampleType::ALaw | RawSampleType::MuLaw, _) => format!("Raw {}", info.sample_type_str),
+            (sample_type, 1) => format!("Raw {}", sample_type),
+            (sample_type, _) => format!("Raw {} ({} endian)", sample_type, match self.endianness {
+                Endianness::Little => "little",
+                Endianness::Big => "big",
+            }),
+        };
+        info.big_endian = self.endianness == Endianness::Big;
+        info.signed_8bit = self.sample_type == RawSampleType::SignedPcm && self.bit_depth == 8;
+        Ok(info)
+    }
+
+    // converts whole interleaved samples to f32, scaled the same way as read_data.
+    // `out` has to hold data.len() / byte_depth samples
+    pub fn decode(&self, data: &[u8], out: &mut [f32]) {
+        let byte_depth = self.byte_depth();
+        let (neg_max, pos_max) = match byte_depth {
+            1 => (PCM_8BIT_NEG_MAX, PCM_8BIT_POS_MAX),
+            2 => (PCM_16BIT_NEG_MAX, PCM_16BIT_POS_MAX),
+            3 => (PCM_24BIT_NEG_MAX, PCM_24BIT_POS_MAX),
+            _ => (PCM_32BIT_NEG_MAX, PCM_32BIT_POS_MAX),
+        };
+
+        let mut sample = [0u8; 8];
+        for (bytes, out) in data.chunks_exact(byte_depth).zip(out.iter_mut()) {
+            // everything below works on little endian samples
+            sample[..byte_depth].copy_from_slice(bytes);
+            if self.endianness == Endianness::Big {
+                sample[..byte_depth].reverse();
+            }
+
+            *out = match self.sample_type {
+                RawSampleType::SignedPcm | RawSampleType::UnsignedPcm => {
+                    let shift = 32 - self.bit_depth;
+                    let raw = buf_to_int(&sample, byte_depth);
+                    let value = if self.sample_type == RawSampleType::SignedPcm {
+                        ((raw << shift) as i32 >> shift) as f32
+                    } else {
+                        (raw as i64 - (1i64 << (self.bit_depth - 1))) as f32
+                    };
+                    value / if value < 0. { neg_max } else { pos_max }
+                }
+                RawSampleType::IeeeFloat if byte_depth == 4 => f32::from_le_bytes(sample[..4].try_into().unwrap()),
+                RawSampleType::IeeeFloat => f64::from_le_bytes(sample) as f32,
+                RawSampleType::ALaw => ALAW_TO_PCM[sample[0] as usize],
+                RawSampleType::MuLaw => ULAW_TO_PCM[sample[0] as usize],
+            };
+        }
+    }
+}
+
+// the raw counterpart of read_data, reading `duration` seconds from `start_time` (or up to the end of the file)
+pub fn read_raw_pcm<R: Read + Seek>(
+    f: &mut R,
+    spec: &RawPcmSpec,
+    start_time: f32,
+    duration: f32,
+) -> Result<Vec<Vec<f32>>, WavError> {
+    let file_size = f.seek(SeekFrom::End(0))?;
+    let info = spec.wav_info(file_size)?;
+    let (data_start, data_size) = info.chunks["data"];
+    let channels = spec.channels as usize;
+    let block_size = spec.block_size() as u64;
+
+    let total_frames = data_size / block_size;
+    let start_frame = u64::min((start_time.max(0.) * spec.sample_rate as f32) as u64, total_frames);
+    let frames = u64::min((duration.max(0.) * spec.sample_rate as f32) as u64, total_frames - start_frame) as usize;
+
+    f.seek(SeekFrom::Start(data_start + start_frame * block_size))?;
+    let mut data = vec![0; frames * block_size as usize];
+    f.read_exact(&mut data)?;
+    let mut samples = vec![0.; frames * channels];
+    spec.decode(&data, &mut samples);
+
+    let mut output = vec![Vec::with_capacity(frames); channels];
+    for frame in samples.chunks_exact(channels) {
+        for (ch, &sample) in output.iter_mut().zip(frame) {
+            ch.push(sample);
+        }
+    }
+    Ok(output)
+}
+
+// Streams a headerless file in blocks of deinterleaved frames, like WavReader does for everything else
+pub struct RawPcmReader<R: Read + Seek> {
+    reader: R,
+    spec: RawPcmSpec,
+    info: WavInfo,
+    num_frames: u64,
+    frame_pos: u64,
+    block_size: usize, // in frames
+}
+
+impl<R: Read + Seek> RawPcmReader<R> {
+    pub fn new(mut reader: R, spec: RawPcmSpec, block_size: usize) -> Result<Self, WavError> {
+        if block_size == 0 {
+            return Err(WavError::InvalidInput("Block size must be at least 1 frame!"));
+        }
+        let file_size = reader.seek(SeekFrom::End(0))?;
+        let info = spec.wav_info(file_size)?;
+        let (data_start, data_size) = info.chunks["data"];
+        reader.seek(SeekFrom::Start(data_start))?;
+        Ok(Self {
+            reader,
+            num_frames: data_size / spec.block_size() as u64,
+            spec,
+            info,
+            frame_pos: 0,
+            block_size,
+        })
+    }
+
+    pub fn spec(&self) -> &RawPcmSpec {
+        &self.spec
+    }
+
+    // reads and converts the next `frames` frames
+    fn read_frames(&mut self, frames: usize) -> Result<Vec<f32>, WavError> {
+        let mut data = vec![0; frames * self.spec.block_size()];
+        self.reader.read_exact(&mut data)?;
+        let mut samples = vec![0.; frames * self.spec.channels as usize];
+        self.spec.decode(&data, &mut samples);
+        self.frame_pos += frames as u64;
+        Ok(samples)
+    }
+}
+
+impl<R: Read + Seek> AudioSource for RawPcmReader<R> {
+    fn info(&self) -> &WavInfo {
+        &self.info
+    }
+
+    fn num_frames(&self) -> u64 {
+        self.num_frames
+    }
+
+    fn position(&self) -> u64 {
+        self.frame_pos
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn set_block_size(&mut self, block_size: usize) -> Result<(), WavError> {
+        if block_size == 0 {
+            return Err(WavError::InvalidInput("Block size must be at least 1 frame!"));
+        }
+        self.block_size = block_size;
+        Ok(())
+    }
+
+    fn seek_frame(&mut self, frame: u64) -> Result<(), WavError> {
+        if frame > self.num_frames {
+            return Err(WavError::InvalidInput("Can't seek past the end of the audio data!"));
+        }
+        self.reader.seek(SeekFrom::Start(self.spec.byte_offset + frame * self.spec.block_size() as u64))?;
+        self.frame_pos = frame;
+        Ok(())
+    }
+
+    fn read_interleaved(&mut self, out: &mut [f32]) -> Result<usize, WavError> {
+        let channels = self.spec.channels as usize;
+        let frames = u64::min((out.len() / channels) as u64, self.num_frames - self.frame_pos) as usize;
+        if frames == 0 {
+            return Ok(0);
+        }
+        let samples = self.read_frames(frames)?;
+        out[..samples.len()].copy_from_slice(&samples);
+        Ok(frames)
+    }
+
+    fn read_block(&mut self, block: &mut Vec<Vec<f32>>) -> Result<usize, WavError> {
+        let channels = self.spec.channels as usize;
+        let frames = u64::min(self.block_size as u64, self.num_frames - self.frame_pos) as usize;
+        block.resize(channels, vec![]);
+        if frames == 0 {
+            block.iter_mut().for_each(|ch| ch.clear());
+            return Ok(0);
+        }
+
+        let samples = self.read_frames(frames)?;
+        for (c, ch) in block.iter_mut().enumerate() {
+            ch.clear();
+            ch.extend(samples.iter().skip(c).step_by(channels));
+        }
+        Ok(frames)
+    }
+}
@@ -0,0 +1,158 @@
+use crate::file_io::WavError;
+use crate::parametric_eq::{Biquad, FilterType};
+
+// ISO 3382 octave bands, the ones above 0.45x the sample rate are left out
+pub const OCTAVE_BAND_CENTERS: [f32; 8] = [63., 125., 250., 500., 1000., 2000., 4000., 8000.];
+// below this there are no bands at all, the biquads need a few kHz to be designed
+const MIN_BAND_SAMPLE_RATE: u32 = 8000;
+// slopes of the 2 biquads in a 4th order butterworth high or low pass, Q 0.54 and 1.31 (S = 2Q²)
+const BUTTERWORTH_SLOPES: [f32; 2] = [0.5858, 3.4142];
+// the noise floor is the mean energy of this last part of the response
+const NOISE_TAIL_FRACTION: f32 = 0.1;
+// energy is averaged over this long when looking for where the decay meets the noise
+const NOISE_WINDOW_SECS: f32 = 0.01;
+
+// ISO 3382 parameters of one impulse response, or one octave band of it
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AcousticParameters {
+    // decay times in seconds, from fits between 0 and -10 dB, -5 and -25 dB, and -5 and -35 dB,
+    // None when the response doesn't decay far enough above its noise floor to fit
+    pub edt: Option<f32>,
+    pub t20: Option<f32>,
+    pub t30: Option<f32>,
+    pub c50: f32, // dB, energy in the first 50ms over the rest
+    pub c80: f32, // dB, energy in the first 80ms over the rest
+    pub d50: f32, // from 0 to 1, share of the energy in the first 50ms
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RoomAcoustics {
+    pub sample_rate: u32,
+    pub onset: usize, // sample where the direct sound arrives
+    pub broadband: AcousticParameters,
+    pub bands: Vec<(f32, AcousticParameters)>, // (center frequency, parameters) of every octave band
+}
+
+// Everything ISO 3382 asks for from a room impulse response, for the whole response and every octave band
+pub fn room_acoustics(ir: &[f32], sample_rate: u32) -> Result<RoomAcoustics, WavError> {
+    if sample_rate == 0 {
+        return Err(WavError::InvalidInput("Sample rate must be above 0!"));
+    }
+    let onset = find_onset(ir).ok_or(WavError::InvalidInput("Impulse response is silent!"))?;
+    let broadband = acoustic_parameters(&ir[onset..], sample_rate);
+
+    // the bands use the onset of the whole response, so every band is measured from the same direct sound
+    let bands = OCTAVE_BAND_CENTERS
+        .iter()
+        .filter(|&&center| sample_rate >= MIN_BAND_SAMPLE_RATE && center * 2f32.sqrt() < sample_rate as f32 * 0.45)
+        .map(|&center| {
+            let filtered = octave_filter(ir, center, sample_rate);
+            (center, acoustic_parameters(&filtered[onset..], sample_rate))
+        })
+        .collect();
+
+    Ok(RoomAcoustics { sample_rate, onset, broadband, bands })
+}
+
+// the first sample within 20 dB of the peak, where ISO 3382 says the response starts
+fn find_onset(ir: &[f32]) -> Option<usize> {
+    let peak = ir.iter().fold(0f32, |max, x| max.max(x.abs()));
+    if peak <= 0. {
+        return None;
+    }
+    ir.iter().position(|x| x.abs() >= peak * 0.1)
+}
+
+// 4th order butterworth high and low passes an octave apart, around `center`
+fn octave_filter(ir: &[f32], center: f32, sample_rate: u32) -> Vec<f32> {
+    let mut filters: Vec<Biquad> = BUTTERWORTH_SLOPES
+        .iter()
+        .flat_map(|&slope| [
+            Biquad::new(FilterType::HPF, center / 2f32.sqrt(), 0., slope, sample_rate),
+            Biquad::new(FilterType::LPF, center * 2f32.sqrt(), 0., slope, sample_rate),
+        ])
+        .collect();
+    ir.iter().map(|&x| filters.iter_mut().fold(x, |s, filter| filter.process(s))).collect()
+}
+
+fn acoustic_parameters(ir: &[f32], sample_rate: u32) -> AcousticParameters {
+    let energy: Vec<f64> = ir.iter().map(|&x| x as f64 * x as f64).collect();
+    let (end, dynamic_range) = decay_end(&energy, sample_rate);
+    let curve = schroeder_curve(&ir[..end]);
+
+    // ISO 3382 wants the bottom of a fit at least 10 dB above the noise
+    let decay_time = |upper: f32, lower: f32| {
+        if dynamic_range < -lower + 10. {
+            return None;
+        }
+        decay_slope(&curve, upper, lower, sample_rate).map(|slope| -60. / slope)
+    };
+
+    let early = |ms: f32| -> f64 { energy.iter().take((ms / 1000. * sample_rate as f32).round() as usize).sum() };
+    let total: f64 = energy.iter().sum();
+    let clarity = |early: f64| 10. * (early / (total - early)).log10() as f32;
+    AcousticParameters {
+        edt: decay_time(0., -10.),
+        t20: decay_time(-5., -25.),
+        t30: decay_time(-5., -35.),
+        c50: clarity(early(50.)),
+        c80: clarity(early(80.)),
+        d50: if total > 0. { (early(50.) / total) as f32 } else { 0. },
+    }
+}
+
+// Where the decay first sinks into the noise at the end of the response, and how far in dB the
+// loudest part of the response is above that noise
+fn decay_end(energy: &[f64], sample_rate: u32) -> (usize, f32) {
+    let tail_len = ((energy.len() as f32 * NOISE_TAIL_FRACTION) as usize).max(1).min(energy.len());
+    let noise = energy[energy.len() - tail_len..].iter().sum::<f64>() / tail_len as f64;
+    if noise <= 0. {
+        return (energy.len(), f32::INFINITY);
+    }
+
+    // averaged, so neither the peak nor the noise depend on single samples
+    let window = ((NOISE_WINDOW_SECS * sample_rate as f32) as usize).max(1);
+    let averages: Vec<f64> = energy.chunks(window).map(|chunk| chunk.iter().sum::<f64>() / chunk.len() as f64).collect();
+    let peak = averages.iter().fold(0f64, |max, &e| max.max(e));
+    let end = averages.iter().position(|&e| e <= noise).map_or(energy.len(), |i| i * window);
+    (end.max(1), 10. * (peak / noise).log10() as f32)
+}
+
+// Schroeder's backward integration: the energy left in the response from every sample on,
+// in dB relative to all of it. Starts at 0 and only goes down
+pub fn schroeder_curve(ir: &[f32]) -> Vec<f32> {
+    let mut remaining = 0f64;
+    let mut curve: Vec<f64> = ir.iter().rev().map(|&x| {
+        remaining += x as f64 * x as f64;
+        remaining
+    }).collect();
+    curve.reverse();
+
+    let total = curve.first().copied().unwrap_or(0.);
+    if total <= 0. {
+        return vec![f32::NEG_INFINITY; ir.len()];
+    }
+    curve.iter().map(|&e| 10. * (e / total).log10() as f32).collect()
+}
+
+// dB per second of a least squares line through the part of the curve between `upper` and `lower` dB,
+// None if the curve doesn't get down to `lower`
+pub fn decay_slope(curve: &[f32], upper: f32, lower: f32, sample_rate: u32) -> Option<f32> {
+    let start = curve.iter().position(|&level| level <= upper)?;
+    let end = start + curve[start..].iter().position(|&level| level < lower)?;
+    if end - start < 2 {
+        return None;
+    }
+
+    let n = (end - start) as f64;
+    let (mut sum_t, mut sum_l, mut sum_tt, mut sum_tl) = (0f64, 0f64, 0f64, 0f64);
+    for (i, &level) in curve[start..end].iter().enumerate() {
+        let t = i as f64 / sample_rate as f64;
+        sum_t += t;
+        sum_l += level as f64;
+        sum_tt += t * t;
+        sum_tl += t * level as f64;
+    }
+    let slope = (n * sum_tl - sum_t * sum_l) / (n * sum_tt - sum_t * sum_t);
+    (slope < 0.).then_some(slope as f32)
+}
@@ -2,19 +2,22 @@ use std::sync::{Arc, Mutex};
 
 use cpal::{default_host, traits::{DeviceTrait, HostTrait, StreamTrait}, InputCallbackInfo, SampleRate, Stream, SupportedStreamConfigRange};
 
-use octave::{circular_buffer::CircularBuffer, fft::Fft};
+use octave::{circular_buffer::CircularBuffer, fft::{Fft, FftScratch}};
 use octave::audio::{FreqData, WindowFunction};
+use octave::transfer_function::{transfer_function, TransferFunction};
 
 pub struct RTA {
     cached_samples: CircularBuffer,
     fft: Fft,
+    scratch: FftScratch,
 }
 
 impl RTA {
-    pub fn new(num_samples: usize, sample_rate: u32) -> Self {
-        let fft = Fft::new(sample_rate, num_samples, WindowFunction::Square);
+    pub fn new(num_samples: usize, sample_rate: u32, window_function: WindowFunction) -> Self {
+        let fft = Fft::new(sample_rate, num_samples, window_function);
         Self {
             cached_samples: CircularBuffer::new(num_samples),
+            scratch: fft.scratch(),
             fft,
         }
     }
@@ -23,19 +26,68 @@ impl RTA {
         self.cached_samples.append_slice(data);
     }
 
-    pub fn get_fft(&self) -> Vec<FreqData> {
-        self.fft.process(self.cached_samples.get_ordered().as_slice())
+    pub fn get_fft(&mut self) -> Vec<FreqData> {
+        self.fft.process_with_scratch(self.cached_samples.get_ordered().as_slice(), &mut self.scratch)
+    }
+}
+
+// Keeps the last samples of a measurement and a reference input, to compare them
+pub struct DualChannelRta {
+    measured: CircularBuffer,
+    reference: CircularBuffer,
+    sample_rate: u32,
+    window_function: WindowFunction,
+    fft_len: usize,
+    deinterleaved: Vec<f32>,
+}
+
+impl DualChannelRta {
+    pub fn new(num_samples: usize, sample_rate: u32, window_function: WindowFunction) -> Self {
+        Self {
+            measured: CircularBuffer::new(num_samples),
+            reference: CircularBuffer::new(num_samples),
+            sample_rate,
+            window_function,
+            // about 10 half-overlapping segments to average, the longer the buffer the finer the resolution
+            fft_len: 1 << (num_samples / 4).max(1).ilog2(),
+            deinterleaved: vec![],
+        }
+    }
+
+    // interleaved frames, with the measurement in the first channel and the reference in the second
+    pub fn update(&mut self, data: &[f32], channels: usize) {
+        for (buffer, channel) in [(&mut self.measured, 0), (&mut self.reference, 1)] {
+            self.deinterleaved.clear();
+            self.deinterleaved.extend(data.iter().skip(channel).step_by(channels));
+            buffer.append_slice(&self.deinterleaved);
+        }
     }
 }
 
+// What the external rta shows
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RtaMode {
+    Spectrum,
+    // input 1 measured against input 2 as the reference
+    TransferFunction,
+}
+
+enum Analyzer {
+    Spectrum(Arc<Mutex<RTA>>),
+    TransferFunction(Arc<Mutex<DualChannelRta>>),
+}
+
 pub struct ExternalRta {
     stream: Stream,
-    rta: Arc<Mutex<RTA>>,
+    analyzer: Analyzer,
     pub buffer_size: usize,
+    pub window_function: WindowFunction,
+    pub mode: RtaMode,
 }
 
 impl ExternalRta {
-    pub fn new(buffer_size: usize) -> Self {
+    // fails if a transfer function is asked for and the input device doesn't have two channels
+    pub fn new(buffer_size: usize, window_function: WindowFunction, mode: RtaMode) -> Result<Self, &'static str> {
 
         let host = default_host();
         let device = host.default_input_device().expect("No input device available!");
@@ -45,41 +97,91 @@ impl ExternalRta {
         //find a sample rate at or under 48kHz
         let mut config_opt = None;
         for c in supported_configs.iter().rev() {
+            if mode == RtaMode::TransferFunction && c.channels() < 2 {
+                continue;
+            }
             if c.max_sample_rate() == SampleRate(48000) || c.max_sample_rate() < SampleRate(48000) {
                 config_opt = Some(c.with_max_sample_rate().config());
                 break;
             }
         }
         if config_opt == None {
+            if mode == RtaMode::TransferFunction {
+                return Err("The input device needs two channels for a transfer function!");
+            }
             panic!("No supported input configs!");
         }
 
         let config = config_opt.unwrap();
+        let channels = config.channels as usize;
 
-        let rta = Arc::new(Mutex::new(RTA::new(buffer_size, config.sample_rate.0)));
-        
-        let rta_copy = Arc::clone(&rta);
-        let stream = device.build_input_stream(
-            &config,
-            move |data: &[f32], _: &InputCallbackInfo| {
-                rta_copy.lock().unwrap().update(data);
-            },
-            move |err| {
-                panic!("something went bad {}", err);
-            }, 
-            None
-        ).unwrap();
+        let analyzer = match mode {
+            RtaMode::Spectrum => Analyzer::Spectrum(Arc::new(Mutex::new(RTA::new(buffer_size, config.sample_rate.0, window_function)))),
+            RtaMode::TransferFunction => {
+                Analyzer::TransferFunction(Arc::new(Mutex::new(DualChannelRta::new(buffer_size, config.sample_rate.0, window_function))))
+            }
+        };
+
+        let stream = match &analyzer {
+            Analyzer::Spectrum(rta) => {
+                let rta_copy = Arc::clone(rta);
+                device.build_input_stream(
+                    &config,
+                    move |data: &[f32], _: &InputCallbackInfo| {
+                        rta_copy.lock().unwrap().update(data);
+                    },
+                    move |err| {
+                        panic!("something went bad {}", err);
+                    },
+                    None
+                )
+            }
+            Analyzer::TransferFunction(rta) => {
+                let rta_copy = Arc::clone(rta);
+                device.build_input_stream(
+                    &config,
+                    move |data: &[f32], _: &InputCallbackInfo| {
+                        rta_copy.lock().unwrap().update(data, channels);
+                    },
+                    move |err| {
+                        panic!("something went bad {}", err);
+                    },
+                    None
+                )
+            }
+        }.unwrap();
         stream.pause().unwrap();
 
-        Self {
+        Ok(Self {
             stream,
-            rta,
+            analyzer,
             buffer_size,
-        }
+            window_function,
+            mode,
+        })
     }
 
+    // empty when measuring a transfer function
     pub fn get_fft(&self) -> Vec<FreqData> {
-        return self.rta.lock().unwrap().get_fft();
+        match &self.analyzer {
+            Analyzer::Spectrum(rta) => rta.lock().unwrap().get_fft(),
+            Analyzer::TransferFunction(_) => vec![],
+        }
+    }
+
+    // None when showing the spectrum, or until the inputs can be lined up
+    pub fn get_transfer_function(&self) -> Option<TransferFunction> {
+        match &self.analyzer {
+            Analyzer::Spectrum(_) => None,
+            // copied out, so the input callback isn't held up by the analysis
+            Analyzer::TransferFunction(rta) => {
+                let rta = rta.lock().unwrap();
+                let (reference, measured) = (rta.reference.get_ordered(), rta.measured.get_ordered());
+                let (sample_rate, fft_len, window_function) = (rta.sample_rate, rta.fft_len, rta.window_function);
+                drop(rta);
+                transfer_function(&reference, &measured, sample_rate, fft_len, window_function, None).ok()
+            }
+        }
     }
 
     pub fn start(&mut self) {
@@ -0,0 +1,170 @@
+use std::f64::consts::{PI, TAU};
+
+use crate::audio::{FreqData, WindowFunction};
+use crate::fft::{Complex, Fft};
+use crate::file_io::WavError;
+
+// fades at both ends of the sweep, so it doesn't start or stop with a click
+const FADE_SECS: f64 = 0.01;
+// responses start this far before their impulse, which rings on both sides and isn't always on a sample
+const PRE_ROLL_SECS: f64 = 0.001;
+
+// An exponential (log) sine sweep and its inverse filter, for measuring impulse responses with Farina's method.
+// The frequency goes up by the same ratio every second, so every harmonic a system adds to it
+// shows up as its own impulse response ahead of the linear one
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExponentialSweep {
+    pub sample_rate: u32,
+    pub start_freq: f32,
+    pub end_freq: f32,
+    pub duration: f32, // seconds
+    pub level: f32, // peak amplitude, 1 for full scale
+    rate: f64, // seconds for the frequency to go up by a factor of e
+    signal: Vec<f32>,
+    inverse: Vec<f32>,
+}
+
+impl ExponentialSweep {
+    pub fn new(sample_rate: u32, start_freq: f32, end_freq: f32, duration: f32, level: f32) -> Result<Self, WavError> {
+        if !(start_freq > 0. && start_freq < end_freq && end_freq <= sample_rate as f32 / 2.) {
+            return Err(WavError::InvalidInput("Sweep must go up from above 0 Hz to at most half the sample rate!"));
+        }
+        if !(duration > 0. && level > 0. && level <= 1.) {
+            return Err(WavError::InvalidInput("Sweep must have a length, and a level of at most full scale!"));
+        }
+        let len = (duration as f64 * sample_rate as f64).round() as usize;
+        if len < 2 {
+            return Err(WavError::InvalidInput("Sweep must be at least 2 samples long!"));
+        }
+
+        let rate = duration as f64 / (end_freq as f64 / start_freq as f64).ln();
+        let fade_len = ((FADE_SECS * sample_rate as f64) as usize).min(len / 2);
+        let signal: Vec<f32> = (0..len).map(|i| {
+            let t = i as f64 / sample_rate as f64;
+            let phase = TAU * start_freq as f64 * rate * ((t / rate).exp() - 1.);
+            let distance_to_end = usize::min(i, len - 1 - i);
+            let fade = if distance_to_end < fade_len { 0.5 - 0.5 * (PI * distance_to_end as f64 / fade_len as f64).cos() } else { 1. };
+            (level as f64 * fade * phase.sin()) as f32
+        }).collect();
+
+        // backwards, and 6dB/octave quieter towards the low end, which the sweep spends more time in
+        let mut inverse: Vec<f32> = signal.iter().rev().enumerate().map(|(i, &x)| {
+            x * (-(i as f64) / sample_rate as f64 / rate).exp() as f32
+        }).collect();
+
+        // the sweep through its inverse is 1 in the middle of the band, and about the same everywhere else in it
+        let middle = TAU * (start_freq as f64 * end_freq as f64).sqrt() / sample_rate as f64;
+        let gain = dft_bin(&signal, middle) * dft_bin(&inverse, middle);
+        let scale = 1. / gain.magnitude();
+        inverse.iter_mut().for_each(|x| *x *= scale);
+
+        Ok(Self { sample_rate, start_freq, end_freq, duration, level, rate, signal, inverse })
+    }
+
+    pub fn signal(&self) -> &[f32] {
+        &self.signal
+    }
+
+    pub fn inverse_filter(&self) -> &[f32] {
+        &self.inverse
+    }
+
+    // how far ahead of the linear response the response of a harmonic shows up, in seconds
+    pub fn harmonic_offset(&self, order: usize) -> f64 {
+        self.rate * (order.max(1) as f64).ln()
+    }
+
+    // The recording of the sweep through a system, convolved with the inverse filter
+    pub fn deconvolve(&self, recording: &[f32]) -> ImpulseResponse {
+        let len = recording.len() + self.inverse.len() - 1;
+        let n = len.next_power_of_two();
+        let fft = Fft::new(self.sample_rate, n, WindowFunction::Square);
+        let padded = |samples: &[f32]| {
+            let mut buffer = samples.to_vec();
+            buffer.resize(n, 0.);
+            fft.forward_complex(&buffer)
+        };
+        let spectrum: Vec<Complex> = padded(recording).iter().zip(padded(&self.inverse)).map(|(r, i)| *r * i).collect();
+        let samples: Vec<f32> = fft.inverse(&spectrum)[..len].iter().map(|x| x.r).collect();
+
+        // the sweep through nothing at all lines up with the end of the inverse filter.
+        // Anything between the input and output moves the whole response later
+        let linear_start = self.inverse.len() - 1;
+        let latency = samples[linear_start..]
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.abs().total_cmp(&b.abs()))
+            .map_or(0, |(i, _)| i);
+
+        ImpulseResponse {
+            sample_rate: self.sample_rate,
+            samples,
+            linear_start,
+            latency,
+            rate: self.rate,
+        }
+    }
+}
+
+// the DFT of `samples` at one frequency, in radians per sample
+fn dft_bin(samples: &[f32], omega: f64) -> Complex {
+    let (mut r, mut i) = (0f64, 0f64);
+    for (n, &x) in samples.iter().enumerate() {
+        let (sin, cos) = (omega * n as f64).sin_cos();
+        r += x as f64 * cos;
+        i -= x as f64 * sin;
+    }
+    Complex::new(r as f32, i as f32)
+}
+
+// The deconvolved recording of a sweep: the linear impulse response, and the harmonics before it
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImpulseResponse {
+    pub sample_rate: u32,
+    pub samples: Vec<f32>,
+    pub linear_start: usize, // where the impulse of a system without any delay would be
+    pub latency: usize, // samples from linear_start to the loudest part of the linear response
+    rate: f64,
+}
+
+impl ImpulseResponse {
+    // everything from where the impulse of a system without delay would be, the part to keep as the impulse response
+    pub fn linear(&self) -> &[f32] {
+        &self.samples[self.linear_start - self.pre_roll().min(self.linear_start)..]
+    }
+
+    fn pre_roll(&self) -> usize {
+        (PRE_ROLL_SECS * self.sample_rate as f64).round() as usize
+    }
+
+    // The response of one harmonic order, 1 being the linear response. It runs up to where the
+    // next lower order starts, so it gets shorter the higher the order. Empty once the orders
+    // are so close together they run into each other, or start before the recording
+    pub fn harmonic(&self, order: usize) -> &[f32] {
+        let order = order.max(1);
+        let pre_roll = self.pre_roll() as f64;
+        let peak = (self.linear_start + self.latency) as f64;
+        let start_of = |order: usize| peak - self.rate * (order as f64).ln() * self.sample_rate as f64 - pre_roll;
+
+        let start = start_of(order);
+        let end = if order == 1 { self.samples.len() as f64 } else { start_of(order - 1) };
+        if start < 0. || end - start < 2. * pre_roll {
+            return &[];
+        }
+        &self.samples[start.round() as usize..end.round() as usize]
+    }
+}
+
+// The gain and phase of an impulse response at every bin of an FFT as long as the response
+pub fn frequency_response(ir: &[f32], sample_rate: u32) -> Vec<FreqData> {
+    if ir.is_empty() {
+        return vec![];
+    }
+    let fft = Fft::new(sample_rate, ir.len(), WindowFunction::Square);
+    let mut scratch = fft.scratch();
+    fft.forward_real(ir, &mut scratch)
+        .iter()
+        .enumerate()
+        .map(|(k, bin)| FreqData::new(k as f32 * fft.frequency_step, bin.magnitude(), bin.phase()))
+        .collect()
+}
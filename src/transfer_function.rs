@@ -0,0 +1,125 @@
+use crate::audio::WindowFunction;
+use crate::fft::{Complex, Fft};
+use crate::file_io::WavError;
+
+// How the measured signal relates to the reference at one frequency
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TransferBin {
+    pub frequency: f32,
+    pub magnitude: f32, // gain, 1 where the measurement matches the reference
+    pub phase: f32, // radians, from -PI to PI, positive where the measurement leads
+    pub coherence: f32, // from 0 to 1, how much of the measurement the reference explains
+}
+
+impl TransferBin {
+    pub fn magnitude_db(&self) -> f32 {
+        20. * self.magnitude.log10()
+    }
+
+    // the gain and phase as one value
+    pub fn response(&self) -> Complex {
+        Complex::from_polar(self.magnitude, self.phase)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransferFunction {
+    pub sample_rate: u32,
+    pub frequency_step: f32,
+    pub delay: isize, // samples the measurement was moved back by to line it up with the reference
+    pub num_segments: usize,
+    pub bins: Vec<TransferBin>, // from 0 Hz up to and including nyquist
+}
+
+// The lag, in samples, at which `measured` looks the most like `reference`, positive when it comes later.
+// Only lags up to max_delay either way are considered
+pub fn find_delay(reference: &[f32], measured: &[f32], max_delay: usize) -> isize {
+    if reference.is_empty() || measured.is_empty() {
+        return 0;
+    }
+    // long enough that the correlation doesn't wrap around
+    let n = (reference.len() + measured.len() - 1).next_power_of_two();
+    let fft = Fft::new(0, n, WindowFunction::Square);
+    let padded = |samples: &[f32]| {
+        let mut buffer = samples.to_vec();
+        buffer.resize(n, 0.);
+        fft.forward_complex(&buffer)
+    };
+    let cross: Vec<Complex> = padded(measured).iter().zip(padded(reference)).map(|(m, r)| *m * r.conj()).collect();
+    let correlation = fft.inverse(&cross);
+
+    // lags past the end of the buffer are negative
+    let max_delay = max_delay as isize;
+    let lag = |i: usize| if i < measured.len() { i as isize } else { i as isize - n as isize };
+    correlation.iter()
+        .enumerate()
+        .filter(|&(i, _)| (-max_delay..=max_delay).contains(&lag(i)))
+        .max_by(|(_, a), (_, b)| a.r.abs().total_cmp(&b.r.abs()))
+        .map_or(0, |(i, _)| lag(i))
+}
+
+// The transfer function from `reference` to `measured`, averaged over windowed segments of fft_len
+// samples that overlap by half. The measurement is lined up with the reference first, by `delay`
+// samples if given, or else by the delay find_delay gives anywhere within a segment
+pub fn transfer_function(
+    reference: &[f32],
+    measured: &[f32],
+    sample_rate: u32,
+    fft_len: usize,
+    window_func: WindowFunction,
+    delay: Option<isize>,
+) -> Result<TransferFunction, WavError> {
+    if fft_len == 0 {
+        return Err(WavError::InvalidInput("FFT length must be at least 1 sample!"));
+    }
+    let delay = delay.unwrap_or_else(|| find_delay(reference, measured, fft_len));
+    let (reference, measured) = if delay >= 0 {
+        (reference, measured.get(delay as usize..).unwrap_or_default())
+    } else {
+        (reference.get(delay.unsigned_abs()..).unwrap_or_default(), measured)
+    };
+    let len = usize::min(reference.len(), measured.len());
+    if len < fft_len {
+        return Err(WavError::InvalidInput("Signals are shorter than one FFT once lined up!"));
+    }
+
+    let fft = Fft::new(sample_rate, fft_len, window_func);
+    let mut reference_scratch = fft.scratch();
+    let mut measured_scratch = fft.scratch();
+    let num_bins = fft_len / 2 + 1;
+    // auto and cross spectra, summed over the segments
+    let mut gxx = vec![0f64; num_bins];
+    let mut gyy = vec![0f64; num_bins];
+    let mut gxy = vec![Complex::zero(); num_bins];
+
+    let step_size = (fft_len / 2).max(1);
+    let num_segments = (len - fft_len) / step_size + 1;
+    for segment in 0..num_segments {
+        let start = segment * step_size;
+        let x = fft.forward_real(&reference[start..start + fft_len], &mut reference_scratch);
+        let y = fft.forward_real(&measured[start..start + fft_len], &mut measured_scratch);
+        for k in 0..num_bins {
+            gxx[k] += (x[k].r * x[k].r + x[k].i * x[k].i) as f64;
+            gyy[k] += (y[k].r * y[k].r + y[k].i * y[k].i) as f64;
+            gxy[k] += x[k].conj() * y[k];
+        }
+    }
+
+    let bins = (0..num_bins).map(|k| {
+        let frequency = k as f32 * fft.frequency_step;
+        // nothing to compare at frequencies the reference doesn't have
+        if gxx[k] <= f64::MIN_POSITIVE || gyy[k] <= f64::MIN_POSITIVE {
+            return TransferBin { frequency, ..Default::default() };
+        }
+        let response = gxy[k] / gxx[k] as f32;
+        let cross_power = gxy[k].magnitude() as f64;
+        TransferBin {
+            frequency,
+            magnitude: response.magnitude(),
+            phase: response.phase(),
+            coherence: (cross_power * cross_power / (gxx[k] * gyy[k])).min(1.) as f32,
+        }
+    }).collect();
+
+    Ok(TransferFunction { sample_rate, frequency_step: fft.frequency_step, delay, num_segments, bins })
+}
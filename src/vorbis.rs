@@ -0,0 +1,1046 @@
+use std::f32::consts::PI;
+
+use crate::file_io::WavError;
+use crate::mdct::Imdct;
+
+// header packets start with their type and "vorbis", audio packets with a 0 bit
+pub const IDENTIFICATION_HEADER: u8 = 1;
+pub const COMMENT_HEADER: u8 = 3;
+pub const SETUP_HEADER: u8 = 5;
+// codewords up to this long are decoded with a single table lookup
+const FAST_BITS: u32 = 10;
+const LEAF: u32 = 1 << 31;
+
+// floor 1 amplitudes, in steps of about 0.55 dB
+#[allow(clippy::excessive_precision)]
+const FLOOR1_INVERSE_DB: [f32; 256] = [
+    1.0649863e-07, 1.1341951e-07, 1.2079015e-07, 1.2863978e-07, 1.3699951e-07, 1.4590251e-07,
+    1.5538408e-07, 1.6548181e-07, 1.7623575e-07, 1.8768855e-07, 1.9988561e-07, 2.1287530e-07,
+    2.2670913e-07, 2.4144197e-07, 2.5713223e-07, 2.7384213e-07, 2.9163793e-07, 3.1059021e-07,
+    3.3077411e-07, 3.5226968e-07, 3.7516214e-07, 3.9954229e-07, 4.2550680e-07, 4.5315863e-07,
+    4.8260743e-07, 5.1396998e-07, 5.4737065e-07, 5.8294187e-07, 6.2082472e-07, 6.6116941e-07,
+    7.0413592e-07, 7.4989464e-07, 7.9862701e-07, 8.5052630e-07, 9.0579828e-07, 9.6466216e-07,
+    1.0273513e-06, 1.0941144e-06, 1.1652161e-06, 1.2409384e-06, 1.3215816e-06, 1.4074654e-06,
+    1.4989305e-06, 1.5963394e-06, 1.7000785e-06, 1.8105592e-06, 1.9282195e-06, 2.0535261e-06,
+    2.1869758e-06, 2.3290978e-06, 2.4804557e-06, 2.6416497e-06, 2.8133190e-06, 2.9961443e-06,
+    3.1908506e-06, 3.3982101e-06, 3.6190449e-06, 3.8542308e-06, 4.1047004e-06, 4.3714470e-06,
+    4.6555282e-06, 4.9580707e-06, 5.2802740e-06, 5.6234160e-06, 5.9888572e-06, 6.3780469e-06,
+    6.7925283e-06, 7.2339451e-06, 7.7040476e-06, 8.2047000e-06, 8.7378876e-06, 9.3057248e-06,
+    9.9104632e-06, 1.0554501e-05, 1.1240392e-05, 1.1970856e-05, 1.2748789e-05, 1.3577278e-05,
+    1.4459606e-05, 1.5399272e-05, 1.6400004e-05, 1.7465768e-05, 1.8600792e-05, 1.9809576e-05,
+    2.1096914e-05, 2.2467911e-05, 2.3928002e-05, 2.5482978e-05, 2.7139006e-05, 2.8902651e-05,
+    3.0780908e-05, 3.2781225e-05, 3.4911534e-05, 3.7180282e-05, 3.9596466e-05, 4.2169667e-05,
+    4.4910090e-05, 4.7828601e-05, 5.0936773e-05, 5.4246931e-05, 5.7772202e-05, 6.1526565e-05,
+    6.5524908e-05, 6.9783085e-05, 7.4317983e-05, 7.9147585e-05, 8.4291040e-05, 8.9768747e-05,
+    9.5602426e-05, 0.00010181521, 0.00010843174, 0.00011547824, 0.00012298267, 0.00013097477,
+    0.00013948625, 0.00014855085, 0.00015820453, 0.00016848555, 0.00017943469, 0.00019109536,
+    0.00020351382, 0.00021673929, 0.00023082423, 0.00024582449, 0.00026179955, 0.00027881276,
+    0.00029693158, 0.00031622787, 0.00033677814, 0.00035866388, 0.00038197188, 0.00040679456,
+    0.00043323036, 0.00046138411, 0.00049136745, 0.00052329927, 0.00055730621, 0.00059352311,
+    0.00063209358, 0.00067317058, 0.00071691700, 0.00076350630, 0.00081312324, 0.00086596457,
+    0.00092223983, 0.00098217216, 0.0010459992, 0.0011139742, 0.0011863665, 0.0012634633,
+    0.0013455702, 0.0014330129, 0.0015261382, 0.0016253153, 0.0017309374, 0.0018434235,
+    0.0019632195, 0.0020908006, 0.0022266726, 0.0023713743, 0.0025254795, 0.0026895994,
+    0.0028643847, 0.0030505286, 0.0032487691, 0.0034598925, 0.0036847358, 0.0039241906,
+    0.0041792066, 0.0044507950, 0.0047400328, 0.0050480668, 0.0053761186, 0.0057254891,
+    0.0060975636, 0.0064938176, 0.0069158225, 0.0073652516, 0.0078438871, 0.0083536271,
+    0.0088964928, 0.009474637, 0.010090352, 0.010746080, 0.011444421, 0.012188144,
+    0.012980198, 0.013823725, 0.014722068, 0.015678791, 0.016697687, 0.017782797,
+    0.018938423, 0.020169149, 0.021479854, 0.022875735, 0.024362330, 0.025945531,
+    0.027631618, 0.029427276, 0.031339626, 0.033376252, 0.035545228, 0.037855157,
+    0.040315199, 0.042935108, 0.045725273, 0.048696758, 0.051861348, 0.055231591,
+    0.058820850, 0.062643361, 0.066714279, 0.071049749, 0.075666962, 0.080584227,
+    0.085821044, 0.091398179, 0.097337747, 0.10366330, 0.11039993, 0.11757434,
+    0.12521498, 0.13335215, 0.14201813, 0.15124727, 0.16107617, 0.17154380,
+    0.18269168, 0.19456402, 0.20720788, 0.22067342, 0.23501402, 0.25028656,
+    0.26655159, 0.28387361, 0.30232132, 0.32196786, 0.34289114, 0.36517414,
+    0.38890521, 0.41417847, 0.44109412, 0.46975890, 0.50028648, 0.53279791,
+    0.56742212, 0.60429640, 0.64356699, 0.68538959, 0.72993007, 0.77736504,
+    0.82788260, 0.88168307, 0.9389798, 1.,
+];
+
+// whether a packet is one of the three headers, which have to come before any audio
+pub fn is_vorbis_header(packet: &[u8], packet_type: u8) -> bool {
+    packet.len() >= 7 && packet[0] == packet_type && &packet[1..7] == b"vorbis"
+}
+
+// the bits of a packet, which are packed starting at the least significant bit of each byte
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() * 8 - self.pos
+    }
+
+    // the next n bits (at most 32), None once the packet has ended
+    fn read(&mut self, n: u32) -> Option<u32> {
+        if n as usize > self.remaining() {
+            self.pos = self.data.len() * 8;
+            return None;
+        }
+        let (value, _) = self.peek(n);
+        self.pos += n as usize;
+        Some(value)
+    }
+
+    fn read_bool(&mut self) -> Option<bool> {
+        self.read(1).map(|bit| bit == 1)
+    }
+
+    // vorbis' own float format: a 21-bit mantissa, a sign bit and a 10-bit exponent
+    fn read_float(&mut self) -> Option<f32> {
+        let bits = self.read(32)?;
+        let mantissa = (bits & 0x1F_FFFF) as f64;
+        let exponent = ((bits >> 21) & 0x3FF) as i32 - 788;
+        let value = mantissa * 2f64.powi(exponent);
+        Some(if bits & 0x8000_0000 != 0 { -value } else { value } as f32)
+    }
+
+    // up to 32 of the next bits, and how many of them are left in the packet
+    fn peek(&self, n: u32) -> (u32, u32) {
+        let available = usize::min(n as usize, self.remaining());
+        let mut value = 0u64;
+        let mut read = 0;
+        while read < available {
+            let pos = self.pos + read;
+            let take = usize::min(8 - pos % 8, available - read);
+            let bits = (self.data[pos / 8] >> (pos % 8)) as u64 & ((1 << take) - 1);
+            value |= bits << read;
+            read += take;
+        }
+        (value as u32, available as u32)
+    }
+}
+
+fn ilog(value: u32) -> u32 {
+    32 - value.leading_zeros()
+}
+
+fn header_error() -> WavError {
+    WavError::InvalidHeader("invalid Vorbis setup header")
+}
+
+struct Codebook {
+    dimensions: usize,
+    single_entry: Option<u32>, // a codebook with one used entry decodes it from any single bit
+    fast: Vec<(u8, u32)>, // (codeword length or 0, entry or tree node) for the next FAST_BITS bits
+    tree: Vec<[u32; 2]>, // children of each node, entries are marked with LEAF
+    values: Vec<f32>, // the vector of every entry, if the codebook has a lookup table
+}
+
+impl Codebook {
+    fn read(bits: &mut BitReader) -> Result<Self, WavError> {
+        let mut read = |n| bits.read(n).ok_or_else(header_error);
+        if read(24)? != 0x564342 {
+            return Err(header_error());
+        }
+        let dimensions = read(16)? as usize;
+        let entries = read(24)? as usize;
+        if dimensions == 0 || entries == 0 {
+            return Err(header_error());
+        }
+
+        let mut lengths = vec![0u8; entries];
+        if read(1)? == 1 {
+            // ordered: runs of entries with increasing lengths
+            let mut entry = 0;
+            let mut length = read(5)? + 1;
+            while entry < entries {
+                let count = read(ilog((entries - entry) as u32))? as usize;
+                if entry + count > entries || length > 32 {
+                    return Err(header_error());
+                }
+                lengths[entry..entry + count].fill(length as u8);
+                entry += count;
+                length += 1;
+            }
+        } else {
+            let sparse = read(1)? == 1;
+            for length in lengths.iter_mut() {
+                if !sparse || read(1)? == 1 {
+                    *length = read(5)? as u8 + 1;
+                }
+            }
+        }
+
+        let lookup_type = read(4)?;
+        let mut values = vec![];
+        if lookup_type == 1 || lookup_type == 2 {
+            let minimum = bits.read_float().ok_or_else(header_error)?;
+            let delta = bits.read_float().ok_or_else(header_error)?;
+            let mut read = |n| bits.read(n).ok_or_else(header_error);
+            let value_bits = read(4)? + 1;
+            let sequence_p = read(1)? == 1;
+            let lookup_values = if lookup_type == 1 { lookup1_values(entries, dimensions) } else { entries * dimensions };
+            let multiplicands = (0..lookup_values).map(|_| read(value_bits)).collect::<Result<Vec<_>, _>>()?;
+
+            values = vec![0.; entries * dimensions];
+            for (entry, vector) in values.chunks_exact_mut(dimensions).enumerate() {
+                let mut last = 0.;
+                let mut divisor = 1;
+                for (i, value) in vector.iter_mut().enumerate() {
+                    let offset = if lookup_type == 1 { entry / divisor % lookup_values } else { entry * dimensions + i };
+                    *value = multiplicands[offset] as f32 * delta + minimum + last;
+                    if sequence_p {
+                        last = *value;
+                    }
+                    divisor *= lookup_values;
+                }
+            }
+        } else if lookup_type != 0 {
+            return Err(header_error());
+        }
+
+        let mut codebook = Self { dimensions, single_entry: None, fast: vec![], tree: vec![[0; 2]], values };
+        codebook.build_tree(&lengths)?;
+        Ok(codebook)
+    }
+
+    // codewords are assigned in entry order, each one the lowest that's free at its length
+    fn build_tree(&mut self, lengths: &[u8]) -> Result<(), WavError> {
+        let used: Vec<usize> = (0..lengths.len()).filter(|&i| lengths[i] > 0).collect();
+        if used.len() == 1 {
+            self.single_entry = Some(used[0] as u32);
+            return Ok(());
+        }
+
+        let mut marker = [0u64; 33];
+        for &entry in &used {
+            let length = lengths[entry] as usize;
+            let mut code = marker[length];
+            if code >> length != 0 {
+                // there's no room left for a codeword this long
+                return Err(header_error());
+            }
+            self.insert(code, length, entry as u32);
+
+            for j in (1..=length).rev() {
+                if marker[j] & 1 != 0 {
+                    marker[j] = if j == 1 { marker[1] + 1 } else { marker[j - 1] << 1 };
+                    break;
+                }
+                marker[j] += 1;
+            }
+            for j in length + 1..33 {
+                if marker[j] >> 1 != code {
+                    break;
+                }
+                code = marker[j];
+                marker[j] = marker[j - 1] << 1;
+            }
+        }
+
+        self.fast = (0..1u32 << FAST_BITS)
+            .map(|bits| {
+                let mut node = 0;
+                for depth in 0..FAST_BITS {
+                    let child = self.tree[node][(bits >> depth) as usize & 1];
+                    if child & LEAF != 0 {
+                        return (depth as u8 + 1, child & !LEAF);
+                    }
+                    if child == 0 {
+                        // not a codeword of an underpopulated tree
+                        return (0, u32::MAX);
+                    }
+                    node = child as usize;
+                }
+                (0, node as u32)
+            })
+            .collect();
+        Ok(())
+    }
+
+    fn insert(&mut self, code: u64, length: usize, entry: u32) {
+        let mut node = 0;
+        for depth in 0..length {
+            let bit = (code >> (length - 1 - depth)) as usize & 1;
+            if depth == length - 1 {
+                self.tree[node][bit] = entry | LEAF;
+            } else {
+                if self.tree[node][bit] == 0 {
+                    self.tree.push([0; 2]);
+                    self.tree[node][bit] = self.tree.len() as u32 - 1;
+                }
+                node = self.tree[node][bit] as usize;
+            }
+        }
+    }
+
+    // the next entry number, None at the end of the packet
+    fn decode(&self, bits: &mut BitReader) -> Option<u32> {
+        if let Some(entry) = self.single_entry {
+            bits.read(1)?;
+            return Some(entry);
+        }
+        let (peeked, available) = bits.peek(FAST_BITS);
+        let mut node = 0;
+        if available == FAST_BITS {
+            let (length, value) = self.fast[peeked as usize];
+            if length > 0 {
+                bits.pos += length as usize;
+                return Some(value);
+            }
+            if value == u32::MAX {
+                return None;
+            }
+            bits.pos += FAST_BITS as usize;
+            node = value as usize;
+        }
+        loop {
+            let child = self.tree[node][bits.read(1)? as usize];
+            if child & LEAF != 0 {
+                return Some(child & !LEAF);
+            }
+            if child == 0 {
+                return None;
+            }
+            node = child as usize;
+        }
+    }
+
+    fn decode_vector(&self, bits: &mut BitReader) -> Option<&[f32]> {
+        let entry = self.decode(bits)? as usize;
+        self.values.get(entry * self.dimensions..(entry + 1) * self.dimensions)
+    }
+}
+
+// the largest number whose `dimensions`th power is at most `entries`
+fn lookup1_values(entries: usize, dimensions: usize) -> usize {
+    let mut values = (entries as f64).powf(1. / dimensions as f64) as usize;
+    while (values + 1).checked_pow(dimensions as u32).is_some_and(|power| power <= entries) {
+        values += 1;
+    }
+    while values > 0 && values.checked_pow(dimensions as u32).is_none_or(|power| power > entries) {
+        values -= 1;
+    }
+    values
+}
+
+enum Floor {
+    Zero(Floor0),
+    One(Floor1),
+}
+
+// a line spectral pair curve
+struct Floor0 {
+    order: usize,
+    amplitude_bits: u32,
+    amplitude_offset: u32,
+    books: Vec<usize>,
+    bark_maps: [Vec<usize>; 2], // for the short and long block size
+    bark_map_size: usize,
+}
+
+// a piecewise linear curve
+struct Floor1 {
+    partition_classes: Vec<usize>,
+    class_dimensions: Vec<usize>,
+    class_subclasses: Vec<u32>,
+    class_masterbooks: Vec<usize>,
+    subclass_books: Vec<Vec<Option<usize>>>,
+    multiplier: u32,
+    x_list: Vec<u32>,
+    sorted: Vec<usize>, // indices into x_list by increasing x
+    neighbors: Vec<(usize, usize)>, // the closest earlier points below and above each point
+}
+
+impl Floor {
+    fn read(bits: &mut BitReader, codebooks: &[Codebook], block_sizes: [usize; 2]) -> Result<Self, WavError> {
+        let mut read = |n| bits.read(n).ok_or_else(header_error);
+        let book = |book: u32| if (book as usize) < codebooks.len() { Ok(book as usize) } else { Err(header_error()) };
+        match read(16)? {
+            0 => {
+                let order = read(8)? as usize;
+                let rate = read(16)?;
+                let bark_map_size = read(16)? as usize;
+                let amplitude_bits = read(6)?;
+                let amplitude_offset = read(8)?;
+                let books = (0..read(4)? + 1).map(|_| book(read(8)?)).collect::<Result<Vec<_>, _>>()?;
+                if order == 0 || rate == 0 || bark_map_size == 0 || books.iter().any(|&b| codebooks[b].values.is_empty()) {
+                    return Err(header_error());
+                }
+                let bark = |x: f32| 13.1 * (0.00074 * x).atan() + 2.24 * (1.85e-8 * x * x).atan() + 1e-4 * x;
+                let bark_maps = block_sizes.map(|block_size| {
+                    let n = block_size / 2;
+                    (0..n)
+                        .map(|i| {
+                            let map = bark(rate as f32 * i as f32 / (2 * n) as f32) * bark_map_size as f32 / bark(0.5 * rate as f32);
+                            usize::min(map as usize, bark_map_size - 1)
+                        })
+                        .collect()
+                });
+                Ok(Floor::Zero(Floor0 { order, amplitude_bits, amplitude_offset, books, bark_maps, bark_map_size }))
+            }
+            1 => {
+                let partitions = read(5)?;
+                let partition_classes = (0..partitions).map(|_| Ok(read(4)? as usize)).collect::<Result<Vec<_>, WavError>>()?;
+                let classes = partition_classes.iter().max().map_or(0, |max| max + 1);
+                let mut class_dimensions = vec![];
+                let mut class_subclasses = vec![];
+                let mut class_masterbooks = vec![];
+                let mut subclass_books = vec![];
+                for _ in 0..classes {
+                    class_dimensions.push(read(3)? as usize + 1);
+                    let subclasses = read(2)?;
+                    class_subclasses.push(subclasses);
+                    class_masterbooks.push(if subclasses > 0 { book(read(8)?)? } else { 0 });
+                    let books = (0..1 << subclasses)
+                        .map(|_| match read(8)? {
+                            0 => Ok(None),
+                            b => book(b - 1).map(Some),
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                    subclass_books.push(books);
+                }
+                let multiplier = read(2)? + 1;
+                let range_bits = read(4)?;
+                let mut x_list = vec![0, 1 << range_bits];
+                for &class in &partition_classes {
+                    for _ in 0..class_dimensions[class] {
+                        x_list.push(read(range_bits)?);
+                    }
+                }
+                if x_list.len() > 65 {
+                    return Err(header_error());
+                }
+                let mut sorted: Vec<usize> = (0..x_list.len()).collect();
+                sorted.sort_by_key(|&i| x_list[i]);
+                if sorted.windows(2).any(|pair| x_list[pair[0]] == x_list[pair[1]]) {
+                    return Err(header_error());
+                }
+                let neighbors = (0..x_list.len())
+                    .map(|i| {
+                        let low = (0..i).filter(|&j| x_list[j] < x_list[i]).max_by_key(|&j| x_list[j]).unwrap_or(0);
+                        let high = (0..i).filter(|&j| x_list[j] > x_list[i]).min_by_key(|&j| x_list[j]).unwrap_or(1);
+                        (low, high)
+                    })
+                    .collect();
+                Ok(Floor::One(Floor1 {
+                    partition_classes,
+                    class_dimensions,
+                    class_subclasses,
+                    class_masterbooks,
+                    subclass_books,
+                    multiplier,
+                    x_list,
+                    sorted,
+                    neighbors,
+                }))
+            }
+            _ => Err(header_error()),
+        }
+    }
+
+    // the floor curve of a channel, None if the channel is unused in this packet
+    fn decode(&self, bits: &mut BitReader, codebooks: &[Codebook], long_block: bool, n: usize) -> Option<Vec<f32>> {
+        match self {
+            Floor::Zero(floor) => floor.decode(bits, codebooks, long_block, n),
+            Floor::One(floor) => floor.decode(bits, codebooks, n),
+        }
+    }
+}
+
+impl Floor0 {
+    fn decode(&self, bits: &mut BitReader, codebooks: &[Codebook], long_block: bool, n: usize) -> Option<Vec<f32>> {
+        // the amplitude can be up to 63 bits long
+        let low = bits.read(u32::min(self.amplitude_bits, 32))? as u64;
+        let high = bits.read(self.amplitude_bits.saturating_sub(32))? as u64;
+        let amplitude = (high << 32 | low) as f32;
+        if amplitude == 0. {
+            return None;
+        }
+        let book = bits.read(ilog(self.books.len() as u32))? as usize;
+        let codebook = &codebooks[*self.books.get(book)?];
+        let mut coefficients = Vec::with_capacity(self.order + codebook.dimensions);
+        let mut last = 0.;
+        while coefficients.len() < self.order {
+            let vector = codebook.decode_vector(bits)?;
+            coefficients.extend(vector.iter().map(|value| value + last));
+            last = *coefficients.last().unwrap();
+        }
+        coefficients.truncate(self.order);
+        let cosines: Vec<f32> = coefficients.iter().map(|c| c.cos()).collect();
+
+        let map = &self.bark_maps[long_block as usize];
+        let max_amplitude = ((1u64 << self.amplitude_bits) - 1) as f32;
+        let mut curve = vec![0.; n];
+        let mut i = 0;
+        while i < n {
+            let omega = PI * map[i] as f32 / self.bark_map_size as f32;
+            let cos_omega = omega.cos();
+            let mut p = 1.;
+            let mut q = 1.;
+            for (j, cosine) in cosines.iter().enumerate() {
+                let factor = 4. * (cosine - cos_omega) * (cosine - cos_omega);
+                if j % 2 == 1 { p *= factor } else { q *= factor }
+            }
+            if self.order % 2 == 1 {
+                p *= 1. - cos_omega * cos_omega;
+                q *= 0.25;
+            } else {
+                p *= (1. - cos_omega) / 2.;
+                q *= (1. + cos_omega) / 2.;
+            }
+            let offset = self.amplitude_offset as f32;
+            let value = (0.11512925 * (amplitude * offset / (max_amplitude * (p + q).sqrt()) - offset)).exp();
+            // every line that maps to the same bark value gets the same level
+            let bark = map[i];
+            while i < n && map[i] == bark {
+                curve[i] = value;
+                i += 1;
+            }
+        }
+        Some(curve)
+    }
+}
+
+impl Floor1 {
+    fn decode(&self, bits: &mut BitReader, codebooks: &[Codebook], n: usize) -> Option<Vec<f32>> {
+        if !bits.read_bool()? {
+            return None;
+        }
+        let range = [256, 128, 86, 64][self.multiplier as usize - 1];
+        let range_bits = ilog(range - 1);
+        let mut y = vec![bits.read(range_bits)? as i32, bits.read(range_bits)? as i32];
+        for &class in &self.partition_classes {
+            let subclass_bits = self.class_subclasses[class];
+            let mut subclass = 0;
+            if subclass_bits > 0 {
+                subclass = codebooks[self.class_masterbooks[class]].decode(bits)?;
+            }
+            for _ in 0..self.class_dimensions[class] {
+                let book = self.subclass_books[class][(subclass & ((1 << subclass_bits) - 1)) as usize];
+                subclass >>= subclass_bits;
+                y.push(match book {
+                    Some(book) => codebooks[book].decode(bits)? as i32,
+                    None => 0,
+                });
+            }
+        }
+
+        // the points are coded as the difference to a prediction from their neighbours
+        let values = self.x_list.len();
+        let mut used = vec![false; values];
+        used[0] = true;
+        used[1] = true;
+        let mut final_y = y.clone();
+        for i in 2..values {
+            let (low, high) = self.neighbors[i];
+            let predicted = render_point(self.x_list[low], final_y[low], self.x_list[high], final_y[high], self.x_list[i]);
+            let high_room = range as i32 - predicted;
+            let low_room = predicted;
+            let room = i32::min(high_room, low_room) * 2;
+            let value = y[i];
+            if value == 0 {
+                final_y[i] = predicted;
+                continue;
+            }
+            used[low] = true;
+            used[high] = true;
+            used[i] = true;
+            final_y[i] = if value >= room {
+                if high_room > low_room { value - low_room + predicted } else { predicted - value + high_room - 1 }
+            } else if value % 2 == 1 {
+                predicted - (value + 1) / 2
+            } else {
+                predicted + value / 2
+            };
+        }
+        // out of range values only come from damaged packets
+        final_y.iter_mut().for_each(|y| *y = (*y).clamp(0, range as i32 - 1));
+
+        let mut curve = vec![0.; n];
+        let multiplier = self.multiplier as i32;
+        let (mut lx, mut ly) = (0, final_y[self.sorted[0]] * multiplier);
+        let (mut hx, mut hy) = (0, 0);
+        for &i in &self.sorted[1..] {
+            if used[i] {
+                hy = final_y[i] * multiplier;
+                hx = self.x_list[i] as usize;
+                render_line(lx, ly, hx, hy, &mut curve);
+                lx = hx;
+                ly = hy;
+            }
+        }
+        if hx < n {
+            render_line(hx, hy, n, hy, &mut curve);
+        }
+        Some(curve)
+    }
+}
+
+fn render_point(x0: u32, y0: i32, x1: u32, y1: i32, x: u32) -> i32 {
+    let dy = y1 - y0;
+    let adx = (x1 - x0) as i32;
+    let offset = dy.abs() * (x - x0) as i32 / adx;
+    if dy < 0 { y0 - offset } else { y0 + offset }
+}
+
+// draws the integer line from (x0, y0) up to x1 into the curve, which ends at its length
+fn render_line(x0: usize, y0: i32, x1: usize, y1: i32, curve: &mut [f32]) {
+    let dy = y1 - y0;
+    let adx = (x1 - x0) as i32;
+    let base = dy / adx;
+    let step = if dy < 0 { base - 1 } else { base + 1 };
+    let ady = dy.abs() - base.abs() * adx;
+    let mut y = y0;
+    let mut err = 0;
+    let db = |y: i32| FLOOR1_INVERSE_DB[y.clamp(0, 255) as usize];
+    if x0 < curve.len() {
+        curve[x0] = db(y);
+    }
+    for x in x0 + 1..usize::min(x1, curve.len()) {
+        err += ady;
+        if err >= adx {
+            err -= adx;
+            y += step;
+        } else {
+            y += base;
+        }
+        curve[x] = db(y);
+    }
+}
+
+struct Residue {
+    kind: u32,
+    begin: usize,
+    end: usize,
+    partition_size: usize,
+    classifications: usize,
+    classbook: usize,
+    books: Vec<[Option<usize>; 8]>, // for every classification and pass
+}
+
+impl Residue {
+    fn read(bits: &mut BitReader, codebooks: &[Codebook]) -> Result<Self, WavError> {
+        let mut read = |n| bits.read(n).ok_or_else(header_error);
+        let kind = read(16)?;
+        if kind > 2 {
+            return Err(header_error());
+        }
+        let begin = read(24)? as usize;
+        let end = read(24)? as usize;
+        let partition_size = read(24)? as usize + 1;
+        let classifications = read(6)? as usize + 1;
+        let classbook = read(8)? as usize;
+        let cascades = (0..classifications)
+            .map(|_| {
+                let low = read(3)?;
+                let high = if read(1)? == 1 { read(5)? } else { 0 };
+                Ok(high << 3 | low)
+            })
+            .collect::<Result<Vec<_>, WavError>>()?;
+        let mut books = vec![[None; 8]; classifications];
+        for (cascade, books) in cascades.iter().zip(books.iter_mut()) {
+            for (pass, book) in books.iter_mut().enumerate() {
+                if cascade & (1 << pass) != 0 {
+                    let b = read(8)? as usize;
+                    if codebooks.get(b).is_none_or(|codebook| codebook.values.is_empty()) {
+                        return Err(header_error());
+                    }
+                    *book = Some(b);
+                }
+            }
+        }
+        if classbook >= codebooks.len() {
+            return Err(header_error());
+        }
+        Ok(Self { kind, begin, end, partition_size, classifications, classbook, books })
+    }
+
+    // decodes the residue vectors of the channels that aren't flagged in `skip`, each n long
+    fn decode(&self, bits: &mut BitReader, codebooks: &[Codebook], skip: &[bool], n: usize) -> Vec<Vec<f32>> {
+        let channels = skip.len();
+        if self.kind == 2 {
+            // all channels are interleaved into one vector
+            let mut vectors = vec![vec![0.; n]; channels];
+            if skip.iter().all(|&skip| skip) {
+                return vectors;
+            }
+            let mut interleaved = vec![vec![0.; n * channels]];
+            self.decode_partitions(bits, codebooks, &[false], n * channels, &mut interleaved);
+            for (i, value) in interleaved[0].iter().enumerate() {
+                vectors[i % channels][i / channels] = *value;
+            }
+            return vectors;
+        }
+        let mut vectors = vec![vec![0.; n]; channels];
+        self.decode_partitions(bits, codebooks, skip, n, &mut vectors);
+        vectors
+    }
+
+    fn decode_partitions(&self, bits: &mut BitReader, codebooks: &[Codebook], skip: &[bool], n: usize, vectors: &mut [Vec<f32>]) {
+        let begin = usize::min(self.begin, n);
+        let end = usize::min(self.end, n);
+        let partitions = end.saturating_sub(begin) / self.partition_size;
+        if partitions == 0 {
+            return;
+        }
+        let classbook = &codebooks[self.classbook];
+        let per_codeword = classbook.dimensions;
+        let mut classes = vec![vec![0; partitions + per_codeword]; vectors.len()];
+
+        for pass in 0..8 {
+            let mut partition = 0;
+            while partition < partitions {
+                if pass == 0 {
+                    for (ch, classes) in classes.iter_mut().enumerate() {
+                        if skip[ch] {
+                            continue;
+                        }
+                        let Some(mut entry) = classbook.decode(bits) else {
+                            return;
+                        };
+                        for i in (0..per_codeword).rev() {
+                            classes[partition + i] = entry as usize % self.classifications;
+                            entry /= self.classifications as u32;
+                        }
+                    }
+                }
+                for _ in 0..per_codeword {
+                    if partition >= partitions {
+                        break;
+                    }
+                    for (ch, vector) in vectors.iter_mut().enumerate() {
+                        if skip[ch] {
+                            continue;
+                        }
+                        let Some(book) = self.books[classes[ch][partition]][pass] else {
+                            continue;
+                        };
+                        let codebook = &codebooks[book];
+                        let offset = begin + partition * self.partition_size;
+                        let decoded = if self.kind == 0 {
+                            decode_interleaved(bits, codebook, &mut vector[offset..offset + self.partition_size])
+                        } else {
+                            decode_sequential(bits, codebook, self.partition_size, &mut vector[offset..])
+                        };
+                        if decoded.is_none() {
+                            return;
+                        }
+                    }
+                    partition += 1;
+                }
+            }
+        }
+    }
+}
+
+// residue 0: the dimensions of each vector are spread across the partition
+fn decode_interleaved(bits: &mut BitReader, codebook: &Codebook, partition: &mut [f32]) -> Option<()> {
+    let step = partition.len() / codebook.dimensions;
+    for i in 0..step {
+        let vector = codebook.decode_vector(bits)?;
+        for (j, value) in vector.iter().enumerate() {
+            partition[i + j * step] += value;
+        }
+    }
+    Some(())
+}
+
+// residue 1 and 2: the vectors follow each other, and the last one may run past the partition
+fn decode_sequential(bits: &mut BitReader, codebook: &Codebook, partition_size: usize, vector: &mut [f32]) -> Option<()> {
+    let mut i = 0;
+    while i < partition_size {
+        let values = codebook.decode_vector(bits)?;
+        let Some(target) = vector.get_mut(i..i + values.len()) else {
+            break;
+        };
+        for (target, value) in target.iter_mut().zip(values) {
+            *target += value;
+        }
+        i += values.len();
+    }
+    Some(())
+}
+
+struct Mapping {
+    coupling: Vec<(usize, usize)>, // (magnitude, angle) channels
+    mux: Vec<usize>, // submap of every channel
+    submaps: Vec<(usize, usize)>, // (floor, residue)
+}
+
+impl Mapping {
+    fn read(bits: &mut BitReader, channels: usize, floors: usize, residues: usize) -> Result<Self, WavError> {
+        let mut read = |n| bits.read(n).ok_or_else(header_error);
+        if read(16)? != 0 {
+            return Err(header_error());
+        }
+        let submap_count = if read(1)? == 1 { read(4)? as usize + 1 } else { 1 };
+        let mut coupling = vec![];
+        if read(1)? == 1 {
+            let channel_bits = ilog(channels as u32 - 1);
+            for _ in 0..read(8)? + 1 {
+                let magnitude = read(channel_bits)? as usize;
+                let angle = read(channel_bits)? as usize;
+                if magnitude == angle || magnitude >= channels || angle >= channels {
+                    return Err(header_error());
+                }
+                coupling.push((magnitude, angle));
+            }
+        }
+        if read(2)? != 0 {
+            return Err(header_error());
+        }
+        let mut mux = vec![0; channels];
+        if submap_count > 1 {
+            for submap in mux.iter_mut() {
+                *submap = read(4)? as usize;
+                if *submap >= submap_count {
+                    return Err(header_error());
+                }
+            }
+        }
+        let submaps = (0..submap_count)
+            .map(|_| {
+                read(8)?;
+                let floor = read(8)? as usize;
+                let residue = read(8)? as usize;
+                if floor >= floors || residue >= residues {
+                    return Err(header_error());
+                }
+                Ok((floor, residue))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { coupling, mux, submaps })
+    }
+}
+
+struct Mode {
+    long_block: bool,
+    mapping: usize,
+}
+
+// Decodes the audio packets of a Vorbis stream into interleaved f32 samples, in Vorbis'
+// channel order. Every packet overlaps with the one before, so the first one only primes
+// the decoder and the output of each later one ends in the middle of its block.
+pub struct VorbisDecoder {
+    channels: usize,
+    sample_rate: u32,
+    nominal_bitrate: u32,
+    block_sizes: [usize; 2],
+    codebooks: Vec<Codebook>,
+    floors: Vec<Floor>,
+    residues: Vec<Residue>,
+    mappings: Vec<Mapping>,
+    modes: Vec<Mode>,
+    imdct: [Imdct; 2],
+    // the second half of the previous block, windowed, and its block size
+    overlap: Vec<Vec<f32>>,
+    previous: Option<usize>,
+}
+
+impl VorbisDecoder {
+    pub fn new(identification: &[u8], setup: &[u8]) -> Result<Self, WavError> {
+        if !is_vorbis_header(identification, IDENTIFICATION_HEADER) || identification.len() < 30 {
+            return Err(WavError::InvalidHeader("invalid Vorbis identification header"));
+        }
+        let id = &identification[7..];
+        let version = u32::from_le_bytes(id[0..4].try_into().unwrap());
+        let channels = id[4] as usize;
+        let sample_rate = u32::from_le_bytes(id[5..9].try_into().unwrap());
+        let nominal_bitrate = i32::from_le_bytes(id[13..17].try_into().unwrap()).max(0) as u32;
+        let block_sizes = [1 << (id[21] & 0xF), 1 << (id[21] >> 4)];
+        if version != 0 || channels == 0 || sample_rate == 0 || id[22] & 1 == 0 {
+            return Err(WavError::InvalidHeader("invalid Vorbis identification header"));
+        }
+        if block_sizes[0] < 64 || block_sizes[1] > 8192 || block_sizes[0] > block_sizes[1] {
+            return Err(WavError::InvalidHeader("invalid Vorbis block sizes"));
+        }
+
+        if !is_vorbis_header(setup, SETUP_HEADER) {
+            return Err(header_error());
+        }
+        let mut bits = BitReader::new(&setup[7..]);
+        let count = |bits: &mut BitReader, n| bits.read(n).map(|count| count as usize + 1).ok_or_else(header_error);
+
+        let codebooks = (0..count(&mut bits, 8)?).map(|_| Codebook::read(&mut bits)).collect::<Result<Vec<_>, _>>()?;
+        // time domain transforms are placeholders that have to be 0
+        for _ in 0..count(&mut bits, 6)? {
+            if bits.read(16) != Some(0) {
+                return Err(header_error());
+            }
+        }
+        let floors = (0..count(&mut bits, 6)?)
+            .map(|_| Floor::read(&mut bits, &codebooks, block_sizes))
+            .collect::<Result<Vec<_>, _>>()?;
+        let residues = (0..count(&mut bits, 6)?).map(|_| Residue::read(&mut bits, &codebooks)).collect::<Result<Vec<_>, _>>()?;
+        let mappings = (0..count(&mut bits, 6)?)
+            .map(|_| Mapping::read(&mut bits, channels, floors.len(), residues.len()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let modes = (0..count(&mut bits, 6)?)
+            .map(|_| {
+                let long_block = bits.read_bool().ok_or_else(header_error)?;
+                let window_type = bits.read(16).ok_or_else(header_error)?;
+                let transform_type = bits.read(16).ok_or_else(header_error)?;
+                let mapping = bits.read(8).ok_or_else(header_error)? as usize;
+                if window_type != 0 || transform_type != 0 || mapping >= mappings.len() {
+                    return Err(header_error());
+                }
+                Ok(Mode { long_block, mapping })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if bits.read(1) != Some(1) {
+            return Err(header_error());
+        }
+
+        Ok(Self {
+            channels,
+            sample_rate,
+            nominal_bitrate,
+            block_sizes,
+            codebooks,
+            floors,
+            residues,
+            mappings,
+            modes,
+            imdct: block_sizes.map(Imdct::new),
+            overlap: vec![vec![]; channels],
+            previous: None,
+        })
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // in bit/s, 0 if the encoder didn't set one
+    pub fn nominal_bitrate(&self) -> u32 {
+        self.nominal_bitrate
+    }
+
+    // forgets the previous packet, for when the next one doesn't follow it in the stream
+    pub fn reset(&mut self) {
+        self.previous = None;
+    }
+
+    // Decodes an audio packet and appends its samples to `out`, returns the number of frames.
+    // Packets that can't be decoded produce no samples and are skipped, like the spec says.
+    pub fn decode_packet(&mut self, packet: &[u8], out: &mut Vec<f32>) -> usize {
+        let mut bits = BitReader::new(packet);
+        if bits.read(1) != Some(0) {
+            return 0;
+        }
+        let Some(mode) = bits.read(ilog(self.modes.len() as u32 - 1)).and_then(|mode| self.modes.get(mode as usize)) else {
+            return 0;
+        };
+        let long_block = mode.long_block;
+        let n = self.block_sizes[long_block as usize];
+        let (mut previous_long, mut next_long) = (false, false);
+        if long_block {
+            let (Some(previous), Some(next)) = (bits.read_bool(), bits.read_bool()) else {
+                return 0;
+            };
+            (previous_long, next_long) = (previous, next);
+        }
+        let mapping = &self.mappings[mode.mapping];
+        let half = n / 2;
+
+        let floors: Vec<Option<Vec<f32>>> = (0..self.channels)
+            .map(|ch| {
+                let (floor, _) = mapping.submaps[mapping.mux[ch]];
+                self.floors[floor].decode(&mut bits, &self.codebooks, long_block, half)
+            })
+            .collect();
+        // coupled channels are both decoded if either of them is used
+        let mut used: Vec<bool> = floors.iter().map(|floor| floor.is_some()).collect();
+        for &(magnitude, angle) in &mapping.coupling {
+            if used[magnitude] || used[angle] {
+                used[magnitude] = true;
+                used[angle] = true;
+            }
+        }
+
+        let mut spectra = vec![vec![0.; half]; self.channels];
+        for (submap, &(_, residue)) in mapping.submaps.iter().enumerate() {
+            let channels: Vec<usize> = (0..self.channels).filter(|&ch| mapping.mux[ch] == submap).collect();
+            let skip: Vec<bool> = channels.iter().map(|&ch| !used[ch]).collect();
+            let vectors = self.residues[residue].decode(&mut bits, &self.codebooks, &skip, half);
+            for (&ch, vector) in channels.iter().zip(vectors) {
+                spectra[ch] = vector;
+            }
+        }
+
+        for &(magnitude, angle) in mapping.coupling.iter().rev() {
+            // the setup header guarantees the two channels differ
+            let mut magnitudes = std::mem::take(&mut spectra[magnitude]);
+            for (m, a) in magnitudes.iter_mut().zip(spectra[angle].iter_mut()) {
+                (*m, *a) = match (*m > 0., *a > 0.) {
+                    (true, true) => (*m, *m - *a),
+                    (true, false) => (*m + *a, *m),
+                    (false, true) => (*m, *m + *a),
+                    (false, false) => (*m - *a, *m),
+                };
+            }
+            spectra[magnitude] = magnitudes;
+        }
+
+        let window = self.window(long_block, previous_long, next_long);
+        let frames = self.previous.map_or(0, |previous| previous / 4 + n / 4);
+        let start = out.len();
+        out.resize(start + frames * self.channels, 0.);
+        let mut block = vec![0.; n];
+        for (ch, spectrum) in spectra.iter_mut().enumerate() {
+            match &floors[ch] {
+                Some(floor) => spectrum.iter_mut().zip(floor).for_each(|(value, floor)| *value *= floor),
+                None => spectrum.fill(0.),
+            }
+            self.imdct[long_block as usize].process(spectrum, &mut block);
+            block.iter_mut().zip(&window).for_each(|(sample, window)| *sample *= window);
+
+            // the output runs from the middle of the previous block to the middle of this one
+            if let Some(previous) = self.previous {
+                let overlap = &self.overlap[ch];
+                for i in 0..frames {
+                    let mut sample = overlap.get(i).copied().unwrap_or(0.);
+                    if let Some(j) = (i + n / 4).checked_sub(previous / 4) {
+                        sample += block[j];
+                    }
+                    out[start + i * self.channels + ch] = sample;
+                }
+            }
+            self.overlap[ch].clear();
+            self.overlap[ch].extend_from_slice(&block[half..]);
+        }
+        self.previous = Some(n);
+        frames
+    }
+
+    // the window of a block, whose slopes depend on the size of the blocks it overlaps with
+    fn window(&self, long_block: bool, previous_long: bool, next_long: bool) -> Vec<f32> {
+        let n = self.block_sizes[long_block as usize];
+        let short = self.block_sizes[0];
+        let (left_start, left_n) = if long_block && !previous_long { (n / 4 - short / 4, short / 2) } else { (0, n / 2) };
+        let (right_start, right_n) = if long_block && !next_long { (n * 3 / 4 - short / 4, short / 2) } else { (n / 2, n / 2) };
+        let slope = |i: usize, len: usize| {
+            let x = (i as f32 + 0.5) / len as f32 * PI / 2.;
+            (PI / 2. * x.sin() * x.sin()).sin()
+        };
+        (0..n)
+            .map(|i| {
+                if i < left_start {
+                    0.
+                } else if i < left_start + left_n {
+                    slope(i - left_start, left_n)
+                } else if i < right_start {
+                    1.
+                } else if i < right_start + right_n {
+                    slope(right_start + right_n - 1 - i, right_n)
+                } else {
+                    0.
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,534 @@
+use crate::file_io::WavError;
+
+// Broadcast Wave Format extension chunk (EBU Tech 3285)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BextChunk {
+    pub description: String,
+    pub originator: String,
+    pub originator_reference: String,
+    pub origination_date: String, // yyyy:mm:dd
+    pub origination_time: String, // hh:mm:ss
+    pub time_reference: u64, // first sample of the file, counted in samples since midnight
+    pub version: u16,
+    pub umid: Vec<u8>, // SMPTE UMID, 64 bytes (all zero if unused)
+    // loudness fields only exist from version 2 onwards, None if unset
+    pub loudness_value: Option<f32>, // LUFS
+    pub loudness_range: Option<f32>, // LU
+    pub max_true_peak_level: Option<f32>, // dBTP
+    pub max_momentary_loudness: Option<f32>, // LUFS
+    pub max_short_term_loudness: Option<f32>, // LUFS
+    pub coding_history: String,
+}
+
+// size of everything in the bext chunk before the coding history
+const BEXT_FIXED_SIZE: usize = 602;
+// value of a loudness field that isn't being used
+const BEXT_LOUDNESS_UNSET: i16 = 0x7FFF;
+
+impl BextChunk {
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, WavError> {
+        if buf.len() < BEXT_FIXED_SIZE {
+            return Err(WavError::TruncatedChunk("bext".to_string()));
+        }
+
+        let version = u16::from_le_bytes([buf[346], buf[347]]);
+        let read_loudness = |pos: usize| -> Option<f32> {
+            let val = i16::from_le_bytes([buf[pos], buf[pos + 1]]);
+            if version < 2 || val == BEXT_LOUDNESS_UNSET {
+                None
+            } else {
+                Some(val as f32 / 100.)
+            }
+        };
+
+        Ok(Self {
+            description: read_fixed_str(&buf[0..256]),
+            originator: read_fixed_str(&buf[256..288]),
+            originator_reference: read_fixed_str(&buf[288..320]),
+            origination_date: read_fixed_str(&buf[320..330]),
+            origination_time: read_fixed_str(&buf[330..338]),
+            time_reference: u64::from_le_bytes(buf[338..346].try_into().unwrap()),
+            version,
+            umid: buf[348..412].to_vec(),
+            loudness_value: read_loudness(412),
+            loudness_range: read_loudness(414),
+            max_true_peak_level: read_loudness(416),
+            max_momentary_loudness: read_loudness(418),
+            max_short_term_loudness: read_loudness(420),
+            // line breaks are part of the coding history, so this isn't trimmed
+            coding_history: String::from_utf8_lossy(&buf[BEXT_FIXED_SIZE..]).trim_end_matches('\0').to_string(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(BEXT_FIXED_SIZE + self.coding_history.len());
+        write_fixed_str(&mut out, &self.description, 256);
+        write_fixed_str(&mut out, &self.originator, 32);
+        write_fixed_str(&mut out, &self.originator_reference, 32);
+        write_fixed_str(&mut out, &self.origination_date, 10);
+        write_fixed_str(&mut out, &self.origination_time, 8);
+        out.extend_from_slice(&self.time_reference.to_le_bytes());
+        // the loudness fields are always written, so this is at least a version 2 chunk
+        out.extend_from_slice(&u16::max(self.version, 2).to_le_bytes());
+
+        let mut umid = self.umid.clone();
+        umid.resize(64, 0);
+        out.extend_from_slice(&umid);
+
+        for loudness in [
+            self.loudness_value,
+            self.loudness_range,
+            self.max_true_peak_level,
+            self.max_momentary_loudness,
+            self.max_short_term_loudness,
+        ] {
+            let val = match loudness {
+                Some(l) => (l * 100.).round().clamp(i16::MIN as f32, (BEXT_LOUDNESS_UNSET - 1) as f32) as i16,
+                None => BEXT_LOUDNESS_UNSET,
+            };
+            out.extend_from_slice(&val.to_le_bytes());
+        }
+        // reserved
+        out.resize(BEXT_FIXED_SIZE, 0);
+
+        out.extend_from_slice(self.coding_history.as_bytes());
+        out
+    }
+}
+
+// iXML production metadata chunk (only the commonly used fields are kept)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IxmlChunk {
+    pub project: String,
+    pub scene: String,
+    pub take: String,
+    pub tape: String,
+    pub note: String,
+    pub tracks: Vec<IxmlTrack>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IxmlTrack {
+    pub channel_index: u32, // 1-based
+    pub interleave_index: u32, // 1-based position of the track in this file
+    pub name: String,
+}
+
+impl IxmlChunk {
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, WavError> {
+        // writers often pad the chunk with nulls or spaces after the document
+        let text = String::from_utf8_lossy(buf);
+        let root = XmlElement::parse(text.trim_end_matches(['\0', ' ', '\n', '\r', '\t']))
+            .ok_or(WavError::InvalidHeader("malformed iXML chunk"))?;
+        if root.name != "BWFXML" {
+            return Err(WavError::InvalidHeader("iXML chunk has no BWFXML root"));
+        }
+
+        let mut tracks = vec![];
+        if let Some(track_list) = root.child("TRACK_LIST") {
+            for track in track_list.children.iter().filter(|e| e.name == "TRACK") {
+                tracks.push(IxmlTrack {
+                    channel_index: track.child_text("CHANNEL_INDEX").trim().parse().unwrap_or(0),
+                    interleave_index: track.child_text("INTERLEAVE_INDEX").trim().parse().unwrap_or(0),
+                    name: track.child_text("NAME"),
+                });
+            }
+        }
+
+        Ok(Self {
+            project: root.child_text("PROJECT"),
+            scene: root.child_text("SCENE"),
+            take: root.child_text("TAKE"),
+            tape: root.child_text("TAPE"),
+            note: root.child_text("NOTE"),
+            tracks,
+        })
+    }
+
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<BWFXML>\n");
+        xml += "\t<IXML_VERSION>1.61</IXML_VERSION>\n";
+        for (tag, val) in [
+            ("PROJECT", &self.project),
+            ("SCENE", &self.scene),
+            ("TAKE", &self.take),
+            ("TAPE", &self.tape),
+            ("NOTE", &self.note),
+        ] {
+            if !val.is_empty() {
+                xml += &format!("\t<{}>{}</{}>\n", tag, xml_escape(val), tag);
+            }
+        }
+        if !self.tracks.is_empty() {
+            xml += "\t<TRACK_LIST>\n";
+            xml += &format!("\t\t<TRACK_COUNT>{}</TRACK_COUNT>\n", self.tracks.len());
+            for track in &self.tracks {
+                xml += "\t\t<TRACK>\n";
+                xml += &format!("\t\t\t<CHANNEL_INDEX>{}</CHANNEL_INDEX>\n", track.channel_index);
+                xml += &format!("\t\t\t<INTERLEAVE_INDEX>{}</INTERLEAVE_INDEX>\n", track.interleave_index);
+                xml += &format!("\t\t\t<NAME>{}</NAME>\n", xml_escape(&track.name));
+                xml += "\t\t</TRACK>\n";
+            }
+            xml += "\t</TRACK_LIST>\n";
+        }
+        xml += "</BWFXML>\n";
+        xml
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_xml().into_bytes()
+    }
+}
+
+// fixed size text fields are null (or sometimes space) padded
+fn read_fixed_str(buf: &[u8]) -> String {
+    let end = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..end]).trim_end().to_string()
+}
+
+fn write_fixed_str(out: &mut Vec<u8>, val: &str, len: usize) {
+    let bytes = val.as_bytes();
+    let written = usize::min(bytes.len(), len);
+    out.extend_from_slice(&bytes[..written]);
+    out.resize(out.len() + len - written, 0);
+}
+
+// iXML nests 3 or 4 levels deep, anything far past that is refused before it can run out of stack
+const MAX_XML_DEPTH: usize = 32;
+
+// Just enough of an xml parser for iXML: elements, text and entities.
+// Attributes, comments, processing instructions and doctypes are skipped.
+struct XmlElement {
+    name: String,
+    text: String,
+    children: Vec<XmlElement>,
+}
+
+impl XmlElement {
+    fn parse(xml: &str) -> Option<Self> {
+        let mut pos = 0;
+        let bytes = xml.as_bytes();
+        // skip everything in front of the root element
+        loop {
+            pos += xml[pos..].find('<')?;
+            match bytes.get(pos + 1) {
+                Some(b'?') => pos += xml[pos..].find("?>")? + 2,
+                Some(b'!') => pos = skip_markup(xml, pos)?,
+                _ => break,
+            }
+        }
+        Self::parse_element(xml, &mut pos, 0)
+    }
+
+    // pos has to point at the '<' of the opening tag, and is left after the closing tag.
+    // None past MAX_XML_DEPTH nested elements
+    fn parse_element(xml: &str, pos: &mut usize, depth: usize) -> Option<Self> {
+        if depth > MAX_XML_DEPTH {
+            return None;
+        }
+        let tag_end = *pos + xml[*pos..].find('>')?;
+        let tag = &xml[*pos + 1..tag_end];
+        let self_closing = tag.ends_with('/');
+        let name = tag.trim_end_matches('/').split_whitespace().next()?.to_string();
+        *pos = tag_end + 1;
+
+        let mut elem = Self { name, text: String::new(), children: vec![] };
+        if self_closing {
+            return Some(elem);
+        }
+
+        loop {
+            let next = *pos + xml[*pos..].find('<')?;
+            elem.text += &xml_unescape(&xml[*pos..next]);
+            *pos = next;
+
+            if xml[*pos..].starts_with("<![CDATA[") {
+                let end = *pos + xml[*pos..].find("]]>")?;
+                elem.text += &xml[*pos + 9..end];
+                *pos = end + 3;
+            } else if xml[*pos..].starts_with("<!") {
+                *pos = skip_markup(xml, *pos)?;
+            } else if xml[*pos..].starts_with("<?") {
+                *pos += xml[*pos..].find("?>")? + 2;
+            } else if xml[*pos..].starts_with("</") {
+                *pos += xml[*pos..].find('>')? + 1;
+                elem.text = elem.text.trim().to_string();
+                return Some(elem);
+            } else {
+                elem.children.push(Self::parse_element(xml, pos, depth + 1)?);
+            }
+        }
+    }
+
+    fn child(&self, name: &str) -> Option<&XmlElement> {
+        self.children.iter().find(|e| e.name == name)
+    }
+
+    fn child_text(&self, name: &str) -> String {
+        self.child(name).map(|e| e.text.clone()).unwrap_or_default()
+    }
+}
+
+// skips comments and doctypes starting at pos
+fn skip_markup(xml: &str, pos: usize) -> Option<usize> {
+    if xml[pos..].starts_with("<!--") {
+        Some(pos + xml[pos..].find("-->")? + 3)
+    } else {
+        Some(pos + xml[pos..].find('>')? + 1)
+    }
+}
+
+fn xml_escape(val: &str) -> String {
+    val.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn xml_unescape(val: &str) -> String {
+    val.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+// text tags from a LIST/INFO chunk
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WavTags {
+    pub title: String, // INAM
+    pub artist: String, // IART
+    pub comment: String, // ICMT
+    pub software: String, // ISFT
+}
+
+impl WavTags {
+    // buf is the body of the LIST chunk, starting with the "INFO" list type
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, WavError> {
+        if buf.len() < 4 || &buf[0..4] != b"INFO" {
+            return Err(WavError::InvalidHeader("LIST chunk is not an INFO list"));
+        }
+
+        let mut tags = Self::default();
+        for (id, data) in sub_chunks(&buf[4..]) {
+            let text = read_fixed_str(data);
+            match &id {
+                b"INAM" => tags.title = text,
+                b"IART" => tags.artist = text,
+                b"ICMT" => tags.comment = text,
+                b"ISFT" => tags.software = text,
+                _ => {}
+            }
+        }
+        Ok(tags)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = b"INFO".to_vec();
+        for (id, val) in [
+            (b"INAM", &self.title),
+            (b"IART", &self.artist),
+            (b"ICMT", &self.comment),
+            (b"ISFT", &self.software),
+        ] {
+            if !val.is_empty() {
+                // text is null terminated
+                let mut text = val.as_bytes().to_vec();
+                text.push(0);
+                write_sub_chunk(&mut out, id, &text);
+            }
+        }
+        out
+    }
+
+    // Vorbis comments (used by FLAC) are a vendor string followed by "FIELD=value" strings,
+    // each with a little endian 32-bit length in front
+    pub fn from_vorbis_comment(buf: &[u8]) -> Result<Self, WavError> {
+        let mut pos = 0;
+        let _vendor = read_vorbis_field(buf, &mut pos, None)?;
+        let count = read_vorbis_field(buf, &mut pos, Some(4))?;
+        let count = u32::from_le_bytes(count.try_into().unwrap());
+
+        let mut tags = Self::default();
+        for _ in 0..count {
+            let comment = String::from_utf8_lossy(read_vorbis_field(buf, &mut pos, None)?).to_string();
+            let Some((field, value)) = comment.split_once('=') else {
+                continue;
+            };
+            // field names are case insensitive
+            match field.to_ascii_uppercase().as_str() {
+                "TITLE" => tags.title = value.to_string(),
+                "ARTIST" => tags.artist = value.to_string(),
+                "COMMENT" | "DESCRIPTION" => tags.comment = value.to_string(),
+                "ENCODER" => tags.software = value.to_string(),
+                _ => {}
+            }
+        }
+        Ok(tags)
+    }
+
+    pub fn to_vorbis_comment(&self, vendor: &str) -> Vec<u8> {
+        let comments: Vec<String> = [
+            ("TITLE", &self.title),
+            ("ARTIST", &self.artist),
+            ("COMMENT", &self.comment),
+            ("ENCODER", &self.software),
+        ]
+        .into_iter()
+        .filter(|(_, val)| !val.is_empty())
+        .map(|(field, val)| format!("{}={}", field, val))
+        .collect();
+
+        let mut out = vec![];
+        out.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        out.extend_from_slice(vendor.as_bytes());
+        out.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+        for comment in comments {
+            out.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            out.extend_from_slice(comment.as_bytes());
+        }
+        out
+    }
+}
+
+// a marker (length of 0) or region from the cue chunk, named by the LIST/adtl chunk
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CuePoint {
+    pub id: u32,
+    pub position: u32, // in sample frames from the start of the audio data
+    pub length: u32, // in sample frames, 0 for a plain marker
+    pub label: String,
+    pub note: String,
+}
+
+impl CuePoint {
+    // adtl is the body of the LIST chunk starting with the "adtl" list type, if the file has one
+    pub fn parse_all(cue: &[u8], adtl: Option<&[u8]>) -> Result<Vec<Self>, WavError> {
+        if cue.len() < 4 {
+            return Err(WavError::TruncatedChunk("cue ".to_string()));
+        }
+        let num_cues = u32::from_le_bytes(cue[0..4].try_into().unwrap()) as usize;
+        if cue.len() < 4 + num_cues * 24 {
+            return Err(WavError::TruncatedChunk("cue ".to_string()));
+        }
+
+        let mut cue_points = Vec::with_capacity(num_cues);
+        for c in cue[4..4 + num_cues * 24].chunks_exact(24) {
+            cue_points.push(Self {
+                id: u32::from_le_bytes(c[0..4].try_into().unwrap()),
+                // c[4..20] is the play order, the "data" chunk id and offsets that only
+                // matter for wavl files, the sample offset is the actual position
+                position: u32::from_le_bytes(c[20..24].try_into().unwrap()),
+                ..Default::default()
+            });
+        }
+
+        let Some(adtl) = adtl else {
+            return Ok(cue_points);
+        };
+        if adtl.len() < 4 || &adtl[0..4] != b"adtl" {
+            return Err(WavError::InvalidHeader("LIST chunk is not an adtl list"));
+        }
+        for (id, data) in sub_chunks(&adtl[4..]) {
+            if data.len() < 4 {
+                continue;
+            }
+            let cue_id = u32::from_le_bytes(data[0..4].try_into().unwrap());
+            let Some(cue_point) = cue_points.iter_mut().find(|c| c.id == cue_id) else {
+                continue;
+            };
+            match &id {
+                b"labl" => cue_point.label = read_fixed_str(&data[4..]),
+                b"note" => cue_point.note = read_fixed_str(&data[4..]),
+                b"ltxt" if data.len() >= 20 => {
+                    cue_point.length = u32::from_le_bytes(data[4..8].try_into().unwrap());
+                    // some editors only name regions through the ltxt text
+                    if cue_point.label.is_empty() {
+                        cue_point.label = read_fixed_str(&data[20..]);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(cue_points)
+    }
+
+    pub fn cue_chunk_bytes(cue_points: &[Self]) -> Vec<u8> {
+        let mut out = (cue_points.len() as u32).to_le_bytes().to_vec();
+        for (i, c) in cue_points.iter().enumerate() {
+            out.extend_from_slice(&c.id.to_le_bytes());
+            out.extend_from_slice(&(i as u32).to_le_bytes()); // play order
+            out.extend_from_slice(b"data");
+            out.extend_from_slice(&0u32.to_le_bytes()); // chunk start
+            out.extend_from_slice(&0u32.to_le_bytes()); // block start
+            out.extend_from_slice(&c.position.to_le_bytes());
+        }
+        out
+    }
+
+    // the body of the matching LIST/adtl chunk (labels, notes and region lengths)
+    pub fn adtl_chunk_bytes(cue_points: &[Self]) -> Vec<u8> {
+        let mut out = b"adtl".to_vec();
+        for c in cue_points {
+            for (id, val) in [(b"labl", &c.label), (b"note", &c.note)] {
+                if !val.is_empty() {
+                    let mut data = c.id.to_le_bytes().to_vec();
+                    data.extend_from_slice(val.as_bytes());
+                    data.push(0);
+                    write_sub_chunk(&mut out, id, &data);
+                }
+            }
+            if c.length > 0 {
+                let mut data = c.id.to_le_bytes().to_vec();
+                data.extend_from_slice(&c.length.to_le_bytes());
+                data.extend_from_slice(b"rgn ");
+                // country, language, dialect and code page
+                data.extend_from_slice(&[0; 8]);
+                write_sub_chunk(&mut out, b"ltxt", &data);
+            }
+        }
+        out
+    }
+}
+
+// splits the body of a LIST chunk into its (word aligned) sub chunks,
+// stopping at the first one that doesn't fit
+fn sub_chunks(mut buf: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut out = vec![];
+    while buf.len() >= 8 {
+        let id: [u8; 4] = buf[0..4].try_into().unwrap();
+        let size = u32::from_le_bytes(buf[4..8].try_into().unwrap()) as usize;
+        if 8 + size > buf.len() {
+            break;
+        }
+        out.push((id, &buf[8..8 + size]));
+        buf = &buf[usize::min(8 + size + (size & 1), buf.len())..];
+    }
+    out
+}
+
+fn write_sub_chunk(out: &mut Vec<u8>, id: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(id);
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+    if data.len() & 1 == 1 {
+        out.push(0);
+    }
+}
+
+// reads `len` bytes, or a length prefixed string if no length is given
+fn read_vorbis_field<'a>(buf: &'a [u8], pos: &mut usize, len: Option<usize>) -> Result<&'a [u8], WavError> {
+    let truncated = || WavError::TruncatedChunk("VORBIS_COMMENT".to_string());
+    let len = match len {
+        Some(len) => len,
+        None => {
+            let len_bytes = buf.get(*pos..*pos + 4).ok_or_else(truncated)?;
+            *pos += 4;
+            u32::from_le_bytes(len_bytes.try_into().unwrap()) as usize
+        }
+    };
+    let field = buf.get(*pos..pos.saturating_add(len)).ok_or_else(truncated)?;
+    *pos += len;
+    Ok(field)
+}
@@ -0,0 +1,82 @@
+mod common;
+
+use std::fs::{self, File};
+use std::io::{BufReader, Cursor};
+
+use octave::aiff::write_aiff_file;
+use octave::file_io::{read_data, read_wav_meta, WavError, WavReader, WavWriteInfo};
+
+use common::{fresh_audio_path, stereo_format, test_signal};
+
+fn round_trip(name: &str, format: &WavWriteInfo, samples: &[Vec<f32>], tolerance: f32) {
+    let path = fresh_audio_path(name);
+
+    write_aiff_file(name.to_string(), format, samples).unwrap();
+
+    let mut reader = BufReader::new(File::open(&path).unwrap());
+    let meta = read_wav_meta(&mut reader).unwrap();
+    assert_eq!(meta.sample_type, format.sample_type);
+    assert_eq!(meta.bit_depth, format.bit_depth as u32);
+    assert_eq!(meta.channels, format.channels);
+    assert_eq!(meta.sample_rate, format.sample_rate);
+
+    let planar = read_data(&mut reader, &meta, 0., meta.audio_duration + 1.).unwrap();
+    let streamed = WavReader::new(BufReader::new(File::open(&path).unwrap()), 1 << 16)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    fs::remove_file(&path).unwrap();
+
+    for c in 0..samples.len() {
+        assert_eq!(planar[c].len(), samples[c].len());
+        for i in 0..samples[c].len() {
+            assert!((planar[c][i] - samples[c][i]).abs() <= tolerance);
+            assert_eq!(planar[c][i], streamed[c][i]);
+        }
+    }
+}
+
+#[test]
+fn aiff_pcm_round_trip() {
+    let samples = test_signal(1001);
+    round_trip("octave_test_aiff_8bit.aif", &stereo_format(1, 8), &samples, 1. / 127.);
+    round_trip("octave_test_aiff_16bit.aif", &stereo_format(1, 16), &samples, 1. / 0x7FFF as f32);
+    round_trip("octave_test_aiff_24bit.aif", &stereo_format(1, 24), &samples, 1. / 0x7FFFFF as f32);
+}
+
+#[test]
+fn aifc_float_and_g711_round_trip() {
+    let samples = test_signal(1000);
+    round_trip("octave_test_aifc_fl32.aifc", &stereo_format(3, 32), &samples, 0.);
+    round_trip("octave_test_aifc_fl64.aifc", &stereo_format(3, 64), &samples, 0.);
+    // µ-law keeps about 8 bits of precision near full scale
+    round_trip("octave_test_aifc_ulaw.aifc", &stereo_format(7, 8), &samples, 0.02);
+}
+
+#[test]
+fn aifc_sowt_little_endian() {
+    // mono AIFF-C at 48kHz with 3 little endian 16-bit samples and an odd sized COMM chunk
+    let mut file = b"FORM\0\0\0\x46AIFCFVER\0\0\0\x04\xA2\x80\x51\x40COMM\0\0\0\x17".to_vec();
+    file.extend_from_slice(&[0, 1, 0, 0, 0, 3, 0, 16]);
+    // 48000 as an 80-bit extended float
+    file.extend_from_slice(&[0x40, 0x0E, 0xBB, 0x80, 0, 0, 0, 0, 0, 0]);
+    file.extend_from_slice(b"sowt\x00\0");
+    file.extend_from_slice(b"SSND\0\0\0\x0E\0\0\0\0\0\0\0\0");
+    file.extend_from_slice(&[0x00, 0x40, 0x00, 0xC0, 0xFF, 0x7F]);
+
+    let mut reader = Cursor::new(file);
+    let meta = read_wav_meta(&mut reader).unwrap();
+    assert_eq!(meta.sample_rate, 48000);
+    assert_eq!(meta.channels, 1);
+    assert!(!meta.big_endian);
+
+    let samples = read_data(&mut reader, &meta, 0., 1.).unwrap();
+    assert_eq!(samples[0], vec![0x4000 as f32 / 0x7FFF as f32, -0.5, 1.]);
+
+    // "raw " is 8-bit offset binary, there is no 16-bit version of it
+    let mut file = reader.into_inner();
+    let compression = file.windows(4).position(|w| w == b"sowt").unwrap();
+    file[compression..compression + 4].copy_from_slice(b"raw ");
+    assert!(matches!(read_wav_meta(&mut Cursor::new(file)), Err(WavError::UnsupportedBitDepth { bit_depth: 16, .. })));
+}
@@ -0,0 +1,59 @@
+use std::fs;
+
+use octave::aiff::write_aiff_file;
+use octave::audio_source::{audio_path, open_audio, AudioFormat, AUDIO_DIR};
+use octave::file_io::{write_wav_file, SpeakerPos, WavError, WavWriteInfo};
+use octave::flac::write_flac_file;
+
+#[test]
+fn detects_formats_by_magic_bytes_first() {
+    assert_eq!(AudioFormat::detect(b"RIFF\0\0\0\0WAVEfmt ", None), Some(AudioFormat::Wav));
+    assert_eq!(AudioFormat::detect(b"RF64\xFF\xFF\xFF\xFFWAVE", Some("aif")), Some(AudioFormat::Wav));
+    assert_eq!(AudioFormat::detect(b"FORM\0\0\0\0AIFC", Some("wav")), Some(AudioFormat::Aiff));
+    assert_eq!(AudioFormat::detect(b"fLaC\0\0\0\x22", None), Some(AudioFormat::Flac));
+    // unknown contents fall back to the extension
+    assert_eq!(AudioFormat::detect(b"", Some("FLAC")), Some(AudioFormat::Flac));
+    assert_eq!(AudioFormat::detect(b"RIFF\0\0\0\0AVI ", Some("txt")), None);
+}
+
+#[test]
+fn open_audio_ignores_file_names() {
+    fs::create_dir_all(AUDIO_DIR).unwrap();
+    let samples = vec![(0..3000).map(|i| (i as f32 * 0.02).sin() * 0.8).collect::<Vec<f32>>()];
+    let format = WavWriteInfo {
+        sample_type: 1,
+        channels: 1,
+        sample_rate: 48000,
+        bit_depth: 16,
+        channel_mapping: vec![(0, SpeakerPos::FrontLeft)],
+        ..Default::default()
+    };
+
+    // every file gets an extension that doesn't match its format
+    let files = ["octave_test_source_wav.aif", "octave_test_source_aiff.flac", "octave_test_source_flac.wav"];
+    for file in files {
+        let _ = fs::remove_file(audio_path(file));
+    }
+    write_wav_file(files[0].to_string(), &format, &samples).unwrap();
+    write_aiff_file(files[1].to_string(), &format, &samples).unwrap();
+    write_flac_file(files[2].to_string(), &format, &samples).unwrap();
+
+    for file in files {
+        let mut source = open_audio(audio_path(file)).unwrap();
+        assert_eq!(source.info().sample_rate, 48000);
+        assert_eq!(source.num_frames(), 3000);
+
+        source.set_block_size(1 << 16).unwrap();
+        let mut block = vec![];
+        assert_eq!(source.read_block(&mut block).unwrap(), 3000);
+        for (decoded, original) in block[0].iter().zip(&samples[0]) {
+            assert!((decoded - original).abs() <= 1. / 0x7FFF as f32);
+        }
+        fs::remove_file(audio_path(file)).unwrap();
+    }
+
+    let not_audio = audio_path("octave_test_source_text.txt");
+    fs::write(&not_audio, "not audio").unwrap();
+    assert!(matches!(open_audio(&not_audio), Err(WavError::UnrecognizedFormat)));
+    fs::remove_file(&not_audio).unwrap();
+}
@@ -0,0 +1,89 @@
+// helpers shared by the integration tests, not every test file uses all of them
+#![allow(dead_code)]
+
+use std::fs;
+
+use octave::audio_source::{audio_path, AUDIO_DIR};
+use octave::file_io::{SpeakerPos, WavWriteInfo};
+
+// a sine on the left and a slower, quieter cosine on the right
+pub fn test_signal(samples_per_channel: usize) -> Vec<Vec<f32>> {
+    let left = (0..samples_per_channel)
+        .map(|i| (i as f32 * 0.05).sin() * 0.9)
+        .collect();
+    let right = (0..samples_per_channel)
+        .map(|i| (i as f32 * 0.013).cos() * 0.5)
+        .collect();
+    vec![left, right]
+}
+
+// xorshift, uniform between -1 and 1 so the power is 1/3
+pub fn noise(len: usize, seed: u32) -> Vec<f32> {
+    let mut state = seed;
+    (0..len).map(|_| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        (state >> 8) as f32 / (1 << 23) as f32 - 1.
+    }).collect()
+}
+
+pub fn stereo_format(sample_type: u8, bit_depth: u16) -> WavWriteInfo {
+    WavWriteInfo {
+        sample_type,
+        channels: 2,
+        sample_rate: 48000,
+        bit_depth,
+        channel_mapping: vec![(0, SpeakerPos::FrontLeft), (1, SpeakerPos::FrontRight)],
+        ..Default::default()
+    }
+}
+
+// the writers always write into ./res/audio, this makes sure it exists and nothing is left from an earlier run
+pub fn fresh_audio_path(name: &str) -> String {
+    fs::create_dir_all(AUDIO_DIR).unwrap();
+    let path = audio_path(name);
+    let _ = fs::remove_file(&path);
+    path
+}
+
+pub fn assert_close(samples: &[Vec<f32>], planar: &[Vec<f32>], interleaved: &[f32], tolerance: f32) {
+    assert_eq!(planar.len(), samples.len());
+    for c in 0..samples.len() {
+        assert_eq!(planar[c].len(), samples[c].len());
+        for i in 0..samples[c].len() {
+            assert!((planar[c][i] - samples[c][i]).abs() <= tolerance);
+            assert!((interleaved[i * samples.len() + c] - samples[c][i]).abs() <= tolerance);
+        }
+    }
+}
+
+// packs fields into bytes, MPEG frames fill every byte from the most significant bit down,
+// Vorbis packets from the least significant bit up
+pub struct BitWriter {
+    pub bytes: Vec<u8>,
+    pub bits: usize,
+    lsb_first: bool,
+}
+
+impl BitWriter {
+    pub fn msb_first() -> Self {
+        Self { bytes: vec![], bits: 0, lsb_first: false }
+    }
+
+    pub fn lsb_first() -> Self {
+        Self { bytes: vec![], bits: 0, lsb_first: true }
+    }
+
+    // the lowest n bits of value, in the order the format reads them
+    pub fn write(&mut self, value: u32, n: usize) {
+        for i in 0..n {
+            if self.bits.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            let (bit, shift) = if self.lsb_first { (i, self.bits % 8) } else { (n - 1 - i, 7 - self.bits % 8) };
+            self.bytes[self.bits / 8] |= ((value >> bit) as u8 & 1) << shift;
+            self.bits += 1;
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use std::f64::consts::TAU;
+
+use octave::audio::WindowFunction;
+use octave::fft::{Complex, Fft};
+
+// the amplitudes and phases Fft::process should give, from a direct DFT in f64
+fn reference_dft(signal: &[f32]) -> Vec<(f32, f32)> {
+    let n = signal.len();
+    (0..n.div_ceil(2)).map(|k| {
+        let (mut r, mut i) = (0f64, 0f64);
+        for (t, &x) in signal.iter().enumerate() {
+            let angle = -TAU * ((k * t) % n) as f64 / n as f64;
+            r += x as f64 * angle.cos();
+            i += x as f64 * angle.sin();
+        }
+        ((r.hypot(i) / n as f64 * 2.) as f32, i.atan2(r) as f32)
+    }).collect()
+}
+
+#[test]
+fn matches_dft_for_any_length() {
+    // powers of two, mixed radix, and primes that need Bluestein
+    let lengths = (1..=50).chain([64, 100, 126, 210, 243, 343, 1000, 1009, 2310, 4096, 6000]);
+    for n in lengths {
+        let signal: Vec<f32> = (0..n).map(|i| ((i * 7919) % 113) as f32 / 56. - 1.).collect();
+        let fft = Fft::new(48000, n, WindowFunction::Square);
+        let out = fft.process(&signal);
+        let expected = reference_dft(&signal);
+        assert_eq!(out.len(), expected.len(), "n = {}", n);
+        for (k, (bin, &(amplitude, phase))) in out.iter().zip(&expected).enumerate() {
+            assert!((bin.amplitude - amplitude).abs() < 1e-5, "n = {}, bin {}: {} vs {}", n, k, bin.amplitude, amplitude);
+            // the phase of an empty bin is just noise
+            if amplitude > 1e-3 {
+                let diff = (bin.phase - phase).abs();
+                assert!(diff.min(std::f32::consts::TAU - diff) < 1e-3, "n = {}, bin {}", n, k);
+            }
+        }
+    }
+}
+
+#[test]
+fn bins_keep_the_requested_resolution() {
+    // a 6000 sample buffer at 48 kHz has 8 Hz bins, so 1 kHz lands exactly in bin 125
+    let fft = Fft::new(48000, 6000, WindowFunction::Hann);
+    assert_eq!(fft.buffer_size, 6000);
+    assert_eq!(fft.frequency_step, 8.);
+    let signal: Vec<f32> = (0..6000).map(|i| (TAU * 1000. * i as f64 / 48000.).sin() as f32).collect();
+    let out = fft.process(&signal);
+    assert_eq!(out.len(), 3000);
+    let peak = out.iter().enumerate().max_by(|a, b| a.1.amplitude.total_cmp(&b.1.amplitude)).unwrap().0;
+    assert_eq!((peak, out[peak].frequency), (125, 1000.));
+    // the hann window's gain is undone, and a sine that fits the buffer exactly leaks only into the next bins
+    assert!((out[125].amplitude - 1.).abs() < 1e-4);
+    assert!(out[127].amplitude < 1e-4);
+}
+
+#[test]
+fn inverse_undoes_forward() {
+    for n in [1, 12, 97, 1024, 2310] {
+        let signal: Vec<f32> = (0..n).map(|i| ((i * 31) % 17) as f32 / 8. - 1.).collect();
+        let fft = Fft::new(48000, n, WindowFunction::Square);
+        let spectrum = fft.forward_complex(&signal);
+        assert_eq!(spectrum.len(), n);
+        // a real signal has a conjugate symmetric spectrum
+        if n > 1 {
+            assert!((spectrum[1] - spectrum[n - 1].conj()).magnitude() < 1e-3);
+        }
+        for (out, &x) in fft.inverse(&spectrum).iter().zip(&signal) {
+            assert!((out.r - x).abs() < 1e-5 && out.i.abs() < 1e-5, "n = {}", n);
+        }
+    }
+}
+
+#[test]
+fn fast_convolution_matches_direct_convolution() {
+    let signal: Vec<f32> = (0..300).map(|i| (i as f32 * 0.37).sin()).collect();
+    let kernel = [0.5, -0.25, 0.125, 1., -1.];
+    // long enough that the circular convolution doesn't wrap around
+    let n = signal.len() + kernel.len() - 1;
+    let fft = Fft::new(48000, n, WindowFunction::Square);
+    let padded = |x: &[f32]| -> Vec<f32> { x.iter().copied().chain(std::iter::repeat(0.)).take(n).collect() };
+    let product: Vec<Complex> = fft.forward_complex(&padded(&signal)).into_iter()
+        .zip(fft.forward_complex(&padded(&kernel)))
+        .map(|(a, b)| a * b)
+        .collect();
+    let convolved = fft.inverse(&product);
+
+    for (i, out) in convolved.iter().enumerate() {
+        let expected: f32 = kernel.iter().enumerate()
+            .filter(|&(k, _)| k <= i && i - k < signal.len())
+            .map(|(k, &h)| h * signal[i - k])
+            .sum();
+        assert!((out.r - expected).abs() < 1e-4, "sample {}: {} vs {}", i, out.r, expected);
+    }
+    assert!((Complex::from_polar(2., 0.5).phase() - 0.5).abs() < 1e-6);
+}
+
+#[test]
+fn real_transform_matches_complex_transform() {
+    for n in [1, 2, 7, 30, 97, 256, 2310, 6000] {
+        let signal: Vec<f32> = (0..n).map(|i| ((i * 4099) % 61) as f32 / 30. - 1.).collect();
+        let fft = Fft::new(48000, n, WindowFunction::Hann);
+        let full = fft.forward_complex(&signal);
+        let mut scratch = fft.scratch();
+        // the scratch space is reused from one call to the next
+        for _ in 0..2 {
+            let half = fft.forward_real(&signal, &mut scratch);
+            assert_eq!(half.len(), n / 2 + 1, "n = {}", n);
+            for (k, (a, b)) in half.iter().zip(&full).enumerate() {
+                assert!((*a - *b).magnitude() < 1e-3 * (1. + b.magnitude()), "n = {}, bin {}: {:?} vs {:?}", n, k, a, b);
+            }
+        }
+    }
+}
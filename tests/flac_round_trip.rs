@@ -0,0 +1,107 @@
+mod common;
+
+use std::fs;
+use std::io::Cursor;
+
+use octave::audio_source::AudioSource;
+use octave::file_io::{read_data, read_wav_meta, WavError, WavWriteInfo};
+use octave::flac::{write_flac_file, FlacReader};
+use octave::wav_metadata::WavTags;
+
+fn test_signal(samples_per_channel: usize) -> Vec<Vec<f32>> {
+    let mut signal = common::test_signal(samples_per_channel);
+    // a bit of noise so the predictors can't be perfect
+    for (i, x) in signal[1].iter_mut().enumerate() {
+        *x += (i * 7919 % 1000) as f32 / 10000.;
+    }
+    signal
+}
+
+fn stereo_format(bit_depth: u16) -> WavWriteInfo {
+    common::stereo_format(1, bit_depth)
+}
+
+// writes the samples as FLAC and returns the bytes of the file
+fn write_flac(name: &str, format: &WavWriteInfo, samples: &[Vec<f32>]) -> Vec<u8> {
+    let path = common::fresh_audio_path(name);
+    write_flac_file(name.to_string(), format, samples).unwrap();
+    let bytes = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    bytes
+}
+
+#[test]
+fn flac_pcm_round_trip() {
+    let samples = test_signal(10001);
+    for (bit_depth, tolerance) in [(8, 1. / 127.), (16, 1. / 0x7FFF as f32), (24, 1. / 0x7FFFFF as f32)] {
+        let mut format = stereo_format(bit_depth);
+        format.tags = Some(WavTags { title: "Flac Test".to_string(), artist: "Octave".to_string(), ..Default::default() });
+        let file = write_flac(&format!("octave_test_flac_{}bit.flac", bit_depth), &format, &samples);
+        // the encoder should actually compress
+        assert!(file.len() < samples[0].len() * 2 * bit_depth as usize / 8);
+
+        let mut reader = Cursor::new(file.clone());
+        let meta = read_wav_meta(&mut reader).unwrap();
+        assert_eq!(meta.sample_type, 1);
+        assert_eq!(meta.bit_depth, bit_depth as u32);
+        assert_eq!(meta.channels, 2);
+        assert_eq!(meta.sample_rate, 48000);
+        assert_eq!(meta.tags, format.tags);
+
+        let planar = read_data(&mut reader, &meta, 0., meta.audio_duration + 1.).unwrap();
+        let mut streamed = FlacReader::new(Cursor::new(file), 4096).unwrap();
+        assert_eq!(streamed.stream_info().total_samples, samples[0].len() as u64);
+        let mut blocks = vec![];
+        let mut block = vec![];
+        while streamed.read_block(&mut block).unwrap() > 0 {
+            blocks.push(block.clone());
+        }
+        for c in 0..samples.len() {
+            let streamed: Vec<f32> = blocks.iter().flat_map(|block| block[c].clone()).collect();
+            assert_eq!(planar[c].len(), samples[c].len());
+            assert_eq!(planar[c], streamed);
+            for i in 0..samples[c].len() {
+                assert!((planar[c][i] - samples[c][i]).abs() <= tolerance);
+            }
+        }
+    }
+}
+
+#[test]
+fn flac_seek_matches_sequential_decode() {
+    // long enough that seeking has to search for frames instead of decoding from the start
+    let samples = test_signal(44100 * 3);
+    let file = write_flac("octave_test_flac_seek.flac", &stereo_format(16), &samples);
+
+    let mut reader = FlacReader::new(Cursor::new(file.clone()), 1000).unwrap();
+    let full = read_data(&mut Cursor::new(file), reader.info(), 0., 10.).unwrap();
+    for frame in [100_000, 4096, 130_000, 0, 77_777] {
+        reader.seek_frame(frame).unwrap();
+        let mut block = vec![];
+        let frames = reader.read_block(&mut block).unwrap();
+        assert_eq!(reader.position(), frame + frames as u64);
+        for c in 0..2 {
+            assert_eq!(block[c], full[c][frame as usize..frame as usize + frames]);
+        }
+    }
+}
+
+#[test]
+fn flac_detects_corruption() {
+    let samples = test_signal(5000);
+    let file = write_flac("octave_test_flac_corrupt.flac", &stereo_format(16), &samples);
+
+    // the MD5 is the last field of STREAMINFO, which starts after "fLaC" and the block header
+    let mut wrong_md5 = file.clone();
+    wrong_md5[8 + 33] ^= 0xFF;
+    let mut reader = Cursor::new(wrong_md5);
+    let meta = read_wav_meta(&mut reader).unwrap();
+    assert!(matches!(read_data(&mut reader, &meta, 0., 1.), Err(WavError::ChecksumMismatch)));
+
+    // a flipped bit in the audio makes the frame CRC fail
+    let mut wrong_data = file.clone();
+    let len = wrong_data.len();
+    wrong_data[len - 100] ^= 0x10;
+    let mut reader = Cursor::new(wrong_data);
+    assert!(matches!(read_data(&mut reader, &meta, 0., 1.), Err(WavError::InvalidData(_))));
+}
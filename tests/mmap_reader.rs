@@ -0,0 +1,88 @@
+use std::fs;
+use std::io::Cursor;
+
+use octave::audio_source::{audio_path, AudioSource, AUDIO_DIR};
+use octave::file_io::{read_data, read_wav_meta, write_wav_file, SpeakerPos, WavError, WavWriteInfo};
+use octave::mmap::{MmapReader, Samples};
+
+fn write_test_file(name: &str, sample_type: u8, bit_depth: u16) -> Vec<u8> {
+    fs::create_dir_all(AUDIO_DIR).unwrap();
+    let samples: Vec<Vec<f32>> = vec![
+        (0..3000).map(|i| (i as f32 * 0.07).sin() * 0.8).collect(),
+        (0..3000).map(|i| (i as f32 * 0.011).cos() - 0.1).collect(),
+    ];
+    let format = WavWriteInfo {
+        sample_type,
+        channels: 2,
+        sample_rate: 48000,
+        bit_depth,
+        channel_mapping: vec![(0, SpeakerPos::FrontLeft), (1, SpeakerPos::FrontRight)],
+        ..Default::default()
+    };
+    write_wav_file(name.to_string(), &format, &samples).unwrap();
+    fs::read(audio_path(name)).unwrap()
+}
+
+#[test]
+fn mapped_samples_match_read_data() {
+    for (sample_type, bit_depth) in [(1, 8), (1, 16), (1, 24), (1, 32), (3, 32), (3, 64)] {
+        let name = format!("octave_test_mmap_{}_{}.wav", sample_type, bit_depth);
+        let file = write_test_file(&name, sample_type, bit_depth);
+        let meta = read_wav_meta(&mut Cursor::new(&file)).unwrap();
+        let expected = read_data(&mut Cursor::new(&file), &meta, 0., 1.).unwrap();
+
+        let mut reader = MmapReader::open(audio_path(&name), 1000).unwrap();
+        fs::remove_file(audio_path(&name)).unwrap();
+        let view = reader.samples();
+        assert_eq!((view.channels(), view.num_frames()), (2, 3000));
+        assert_eq!(view.get(1234, 1), expected[1][1234], "{} bit", bit_depth);
+        if bit_depth == 16 {
+            assert!(matches!(view.samples(), Samples::Pcm16(s) if s.len() == 6000));
+        }
+
+        let mut block = vec![];
+        let mut read: Vec<Vec<f32>> = vec![vec![]; 2];
+        while reader.read_block(&mut block).unwrap() > 0 {
+            for (read, block) in read.iter_mut().zip(&block) {
+                read.extend(block);
+            }
+        }
+        assert_eq!(read, expected, "type {} at {} bit", sample_type, bit_depth);
+    }
+}
+
+#[test]
+fn mapped_reader_seeks_anywhere() {
+    let name = "octave_test_mmap_seek.wav";
+    let file = write_test_file(name, 1, 24);
+    let meta = read_wav_meta(&mut Cursor::new(&file)).unwrap();
+    let expected = read_data(&mut Cursor::new(&file), &meta, 0., 1.).unwrap();
+
+    let mut reader = MmapReader::open(audio_path(name), 1 << 16).unwrap();
+    fs::remove_file(audio_path(name)).unwrap();
+    assert_eq!(reader.num_frames(), 3000);
+
+    let mut out = [0.; 20];
+    for frame in [2990, 17, 1500] {
+        reader.seek_frame(frame).unwrap();
+        let frames = reader.read_interleaved(&mut out).unwrap();
+        assert_eq!(frames, usize::min(10, 3000 - frame as usize));
+        for i in 0..frames {
+            assert_eq!([out[2 * i], out[2 * i + 1]], [expected[0][frame as usize + i], expected[1][frame as usize + i]]);
+        }
+        assert_eq!(reader.position(), frame + frames as u64);
+    }
+    assert!(matches!(reader.seek_frame(3001), Err(WavError::InvalidInput(_))));
+}
+
+#[test]
+fn compressed_files_are_not_mapped() {
+    fs::create_dir_all(AUDIO_DIR).unwrap();
+    let path = audio_path("octave_test_mmap_compressed.ogg");
+    let mut page = b"OggS\0\x02".to_vec();
+    page.extend([0; 40]);
+    fs::write(&path, &page).unwrap();
+    assert!(MmapReader::open(&path, 1024).is_err());
+    fs::remove_file(&path).unwrap();
+    assert!(matches!(MmapReader::open(&path, 1024), Err(WavError::Io(_))));
+}
@@ -0,0 +1,162 @@
+mod common;
+
+use std::fs;
+use std::io::Cursor;
+
+use octave::audio_source::{audio_path, open_audio, read_sample_rate, AudioFormat, AudioSource, AUDIO_DIR};
+use octave::file_io::{read_data, read_wav_meta, WavError};
+use octave::mp3::{Mp3Reader, MpegVersion};
+
+use common::BitWriter;
+
+// MPEG-1 Layer III, 128 kbit/s, 44.1 kHz, mono, no CRC: 417 byte frames with 17 bytes of side info
+const HEADER: [u8; 4] = [0xFF, 0xFB, 0x90, 0xC0];
+const FRAME_LEN: usize = 417;
+const FRAMES: usize = 20;
+const DELAY: u32 = 576;
+const PADDING: u32 = 1000;
+// the only non-zero spectral line, which decodes to a tone at (LINE + 0.5) * 44100 / 1152 Hz
+const LINE: usize = 100;
+
+// a frame whose two granules hold a single line of value 1, coded with Huffman table 1
+fn audio_frame() -> Vec<u8> {
+    let mut main_data = BitWriter::msb_first();
+    for _ in 0..LINE / 2 {
+        main_data.write(0b1, 1); // (0, 0)
+    }
+    if LINE.is_multiple_of(2) {
+        main_data.write(0b01, 2); // (1, 0)
+    } else {
+        main_data.write(0b001, 3); // (0, 1)
+    }
+    main_data.write(0, 1); // positive
+    let granule_bits = main_data.bits;
+    for i in 0..granule_bits {
+        let bit = main_data.bytes[i / 8] >> (7 - i % 8) & 1;
+        main_data.write(bit as u32, 1);
+    }
+
+    let mut side_info = BitWriter::msb_first();
+    side_info.write(0, 9 + 5 + 4); // main_data_begin, private bits, scfsi
+    for _ in 0..2 {
+        side_info.write(granule_bits as u32, 12);
+        side_info.write(LINE as u32 / 2 + 1, 9); // big_values
+        side_info.write(190, 8); // global_gain
+        side_info.write(0, 4 + 1); // scalefac_compress, no window switching
+        for _ in 0..3 {
+            side_info.write(1, 5);
+        }
+        side_info.write(15, 4);
+        side_info.write(7, 3);
+        side_info.write(0, 3); // preflag, scalefac_scale, count1table
+    }
+
+    let mut frame = HEADER.to_vec();
+    frame.extend(side_info.bytes);
+    frame.extend(main_data.bytes);
+    frame.resize(FRAME_LEN, 0);
+    frame
+}
+
+// an ID3v2 tag, a silent frame with an Info tag that has LAME's gapless info, then the audio frames
+fn mp3_file() -> Vec<u8> {
+    let mut file = b"ID3\x04\x00\x00\x00\x00\x01\x00".to_vec();
+    file.extend([0xAA; 128]);
+
+    let mut info = HEADER.to_vec();
+    info.extend([0; 17]);
+    info.extend(b"Info\0\0\0\0");
+    let mut lame = b"LAME3.100".to_vec();
+    lame.resize(21, 0);
+    lame.extend([(DELAY >> 4) as u8, ((DELAY & 0xF) << 4 | PADDING >> 8) as u8, PADDING as u8]);
+    info.extend(lame);
+    info.resize(FRAME_LEN, 0);
+    file.extend(info);
+
+    for _ in 0..FRAMES {
+        file.extend(audio_frame());
+    }
+    file
+}
+
+#[test]
+fn mp3_gapless_decode() {
+    fs::create_dir_all(AUDIO_DIR).unwrap();
+    // the extension doesn't matter, the ID3 tag gives the format away
+    let path = audio_path("octave_test_mp3_gapless.bin");
+    fs::write(&path, mp3_file()).unwrap();
+    let mut source = open_audio(&path).unwrap();
+    // only the first frame header is read for this
+    assert_eq!(read_sample_rate(&path).unwrap(), 44100);
+    fs::remove_file(&path).unwrap();
+
+    let info = source.info().clone();
+    assert_eq!(info.sample_rate, 44100);
+    assert_eq!(info.channels, 1);
+    assert_eq!(info.sample_type_str, "MPEG-1 Layer III");
+    assert_eq!(info.format, AudioFormat::Mp3);
+    let mp3 = Mp3Reader::new(Cursor::new(mp3_file()), 4096).unwrap().stream_info().clone();
+    assert_eq!((mp3.version, mp3.frames, mp3.bitrate), (MpegVersion::Mpeg1, FRAMES as u64, 128));
+    assert_eq!((mp3.encoder_delay, mp3.encoder_padding), (DELAY, PADDING));
+    let total = (FRAMES * 1152) as u64 - (DELAY + PADDING) as u64;
+    assert_eq!(source.num_frames(), total);
+
+    source.set_block_size(1 << 16).unwrap();
+    let mut block = vec![];
+    assert_eq!(source.read_block(&mut block).unwrap() as u64, total);
+
+    // the single line comes out as a steady tone, count its zero crossings in the middle
+    let middle = &block[0][5000..15000];
+    assert!(middle.iter().any(|x| x.abs() > 0.01));
+    let crossings = middle.windows(2).filter(|pair| (pair[0] < 0.) != (pair[1] < 0.)).count();
+    let frequency = crossings as f32 / 2. * 44100. / middle.len() as f32;
+    let expected = (LINE as f32 + 0.5) * 44100. / 1152.;
+    assert!((frequency - expected).abs() < expected * 0.02, "{} Hz instead of {} Hz", frequency, expected);
+}
+
+#[test]
+fn mp3_seek_matches_sequential_decode() {
+    let file = mp3_file();
+    let mut reader = Mp3Reader::new(Cursor::new(file.clone()), 1000).unwrap();
+    let meta = read_wav_meta(&mut Cursor::new(file.clone())).unwrap();
+    let full = read_data(&mut Cursor::new(file), &meta, 0., 10.).unwrap();
+    assert_eq!(full[0].len() as u64, reader.num_frames());
+
+    for frame in [10_000, 1, 20_000, 0, 5_555] {
+        reader.seek_frame(frame).unwrap();
+        let mut block = vec![];
+        let frames = reader.read_block(&mut block).unwrap();
+        assert_eq!(reader.position(), frame + frames as u64);
+        assert_eq!(block[0], full[0][frame as usize..frame as usize + frames]);
+    }
+}
+
+#[test]
+fn detects_mp3_files() {
+    assert_eq!(AudioFormat::detect(b"ID3\x04\0\0\0\0\0\0", None), Some(AudioFormat::Mp3));
+    assert_eq!(AudioFormat::detect(&HEADER, Some("wav")), Some(AudioFormat::Mp3));
+    // Layer II frames aren't supported
+    assert_eq!(AudioFormat::detect(&[0xFF, 0xFD, 0x90, 0xC0], None), None);
+    assert_eq!(AudioFormat::detect(b"", Some("MP3")), Some(AudioFormat::Mp3));
+}
+
+#[test]
+fn mp3_with_junk_at_the_start_opens_by_extension() {
+    fs::create_dir_all(AUDIO_DIR).unwrap();
+    // nothing at the start says mp3, only the extension does
+    let mut file = vec![0x11; 300];
+    file.extend(mp3_file());
+
+    let path = audio_path("octave_test_mp3_junk.mp3");
+    fs::write(&path, &file).unwrap();
+    let source = open_audio(&path);
+    fs::remove_file(&path).unwrap();
+    let source = source.unwrap();
+    assert_eq!(source.info().format, AudioFormat::Mp3);
+    assert_eq!(source.num_frames(), (FRAMES * 1152) as u64 - (DELAY + PADDING) as u64);
+
+    let path = audio_path("octave_test_mp3_junk.bin");
+    fs::write(&path, &file).unwrap();
+    assert!(matches!(open_audio(&path), Err(WavError::UnrecognizedFormat)));
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,229 @@
+mod common;
+
+use std::io::Cursor;
+
+use octave::audio_source::{AudioFormat, AudioSource};
+use octave::file_io::{read_data, read_wav_meta};
+use octave::ogg::{OggCodec, OggReader};
+
+use common::BitWriter;
+
+const SERIAL: u32 = 0x0C7A_7E00;
+// the last granule position ends the stream part way through the decoded samples
+const VORBIS_PACKETS: usize = 100;
+const VORBIS_END: u64 = 12000;
+const OPUS_PACKETS: usize = 50;
+const OPUS_PRE_SKIP: u64 = 312;
+
+fn crc32(data: &[u8]) -> u32 {
+    data.iter().fold(0u32, |mut crc, &byte| {
+        crc ^= (byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 { crc << 1 ^ 0x04C1_1DB7 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
+// a page holding whole packets, all of them shorter than 255 bytes
+fn ogg_page(sequence: u32, flags: u8, granule: u64, packets: &[Vec<u8>]) -> Vec<u8> {
+    let mut page = b"OggS\0".to_vec();
+    page.push(flags);
+    page.extend(granule.to_le_bytes());
+    page.extend(SERIAL.to_le_bytes());
+    page.extend(sequence.to_le_bytes());
+    page.extend([0; 4]);
+    page.push(packets.len() as u8);
+    page.extend(packets.iter().map(|packet| packet.len() as u8));
+    for packet in packets {
+        page.extend(packet);
+    }
+    let crc = crc32(&page);
+    page[22..26].copy_from_slice(&crc.to_le_bytes());
+    page
+}
+
+// a mono 44.1 kHz stream with 256 sample blocks, one codebook and a floor that every packet marks as unused
+fn vorbis_file() -> Vec<u8> {
+    let mut identification = b"\x01vorbis".to_vec();
+    identification.extend(0u32.to_le_bytes());
+    identification.push(1);
+    identification.extend(44100u32.to_le_bytes());
+    identification.extend([0, 0, 0, 0, 0x80, 0x38, 0x01, 0x00, 0, 0, 0, 0]); // 80 kbit/s nominal
+    identification.extend([0x88, 0x01]);
+
+    let mut comment = b"\x03vorbis".to_vec();
+    comment.extend([0; 8]);
+    comment.push(1);
+
+    let mut setup = BitWriter::lsb_first();
+    for &byte in b"\x05vorbis" {
+        setup.write(byte as u32, 8);
+    }
+    setup.write(0, 8); // one codebook
+    setup.write(0x564342, 24);
+    setup.write(1, 16); // dimensions
+    setup.write(2, 24); // entries
+    setup.write(0, 2); // not ordered, not sparse
+    setup.write(0, 5);
+    setup.write(0, 5); // both entries one bit long
+    setup.write(0, 4); // no lookup
+    setup.write(0, 6 + 16); // one placeholder time domain transform
+    setup.write(0, 6);
+    setup.write(1, 16); // floor 1
+    setup.write(0, 5); // no partitions
+    setup.write(0, 2); // multiplier 1
+    setup.write(8, 4); // range bits
+    setup.write(0, 6);
+    setup.write(0, 16); // residue 0
+    setup.write(0, 24); // begin
+    setup.write(0, 24); // end
+    setup.write(0, 24); // partition size
+    setup.write(0, 6); // one classification
+    setup.write(0, 8); // classbook
+    setup.write(0, 3 + 1); // no cascade
+    setup.write(0, 6);
+    setup.write(0, 16); // mapping 0
+    setup.write(0, 1 + 1 + 2); // one submap, no coupling
+    setup.write(0, 8 + 8 + 8); // submap floor and residue
+    setup.write(0, 6); // one mode
+    setup.write(0, 1); // short blocks
+    setup.write(0, 16 + 16); // window and transform type
+    setup.write(0, 8); // mapping 0
+    setup.write(1, 1);
+
+    let mut file = ogg_page(0, 0x2, 0, &[identification]);
+    file.extend(ogg_page(1, 0, 0, &[comment, setup.bytes]));
+    for (i, chunk) in (0..VORBIS_PACKETS).collect::<Vec<_>>().chunks(25).enumerate() {
+        let last = (i + 1) * 25 == VORBIS_PACKETS;
+        let granule = if last { VORBIS_END } else { (chunk[chunk.len() - 1] * 128) as u64 };
+        let packets = vec![vec![0]; chunk.len()];
+        file.extend(ogg_page(2 + i as u32, if last { 0x4 } else { 0 }, granule, &packets));
+    }
+    file
+}
+
+// a stereo stream of 20 ms CELT frames whose silence flag is set
+fn opus_file() -> Vec<u8> {
+    let mut head = b"OpusHead\x01\x02".to_vec();
+    head.extend((OPUS_PRE_SKIP as u16).to_le_bytes());
+    head.extend(44100u32.to_le_bytes());
+    head.extend([0, 0, 0]); // no output gain, mapping family 0
+    let mut tags = b"OpusTags".to_vec();
+    tags.extend([0; 8]);
+
+    let mut file = ogg_page(0, 0x2, 0, &[head]);
+    file.extend(ogg_page(1, 0, 0, &[tags]));
+    let packets = vec![vec![31 << 3 | 0x4, 0xFF, 0xFF, 0xFF]; OPUS_PACKETS];
+    file.extend(ogg_page(2, 0x4, OPUS_PACKETS as u64 * 960, &packets));
+    file
+}
+
+#[test]
+fn vorbis_decode() {
+    let file = vorbis_file();
+    let mut reader = OggReader::new(Cursor::new(file.clone()), 1 << 16).unwrap();
+    let info = reader.info().clone();
+    assert_eq!((info.sample_rate, info.channels), (44100, 1));
+    let ogg = reader.stream_info().clone();
+    assert_eq!(ogg.codec, OggCodec::Vorbis);
+    assert_eq!(ogg.total_samples, VORBIS_END);
+    assert_eq!(reader.num_frames(), VORBIS_END);
+
+    let mut block = vec![];
+    assert_eq!(reader.read_block(&mut block).unwrap() as u64, VORBIS_END);
+    assert!(block[0].iter().all(|&x| x == 0.));
+
+    let meta = read_wav_meta(&mut Cursor::new(file.clone())).unwrap();
+    let full = read_data(&mut Cursor::new(file), &meta, 0., 10.).unwrap();
+    assert_eq!(full[0].len() as u64, VORBIS_END);
+}
+
+#[test]
+fn opus_decode_trims_pre_skip() {
+    let mut reader = OggReader::new(Cursor::new(opus_file()), 1 << 16).unwrap();
+    let info = reader.info().clone();
+    // Opus is always decoded at 48 kHz, whatever the input rate was
+    assert_eq!((info.sample_rate, info.channels), (48000, 2));
+    let ogg = reader.stream_info().clone();
+    assert_eq!((ogg.codec, ogg.input_sample_rate, ogg.pre_skip), (OggCodec::Opus, 44100, OPUS_PRE_SKIP as u32));
+    let total = OPUS_PACKETS as u64 * 960 - OPUS_PRE_SKIP;
+    assert_eq!(reader.num_frames(), total);
+
+    let mut block = vec![];
+    assert_eq!(reader.read_block(&mut block).unwrap() as u64, total);
+    assert_eq!(block.len(), 2);
+    // CELT keeps a tiny offset in its de-emphasis filter, so silent frames aren't exactly 0
+    assert!(block.iter().flatten().all(|x| x.abs() < 1e-6));
+
+    reader.seek_frame(30_000).unwrap();
+    assert_eq!(reader.read_block(&mut block).unwrap() as u64, total - 30_000);
+}
+
+// every sample of a file, through the same reader open_audio uses for it
+fn decode(file: &[u8]) -> (u32, Vec<Vec<f32>>) {
+    let mut reader = OggReader::new(Cursor::new(file.to_vec()), 1 << 20).unwrap();
+    let mut block = vec![];
+    assert_eq!(reader.read_block(&mut block).unwrap() as u64, reader.num_frames());
+    (reader.info().sample_rate, block)
+}
+
+// frequency from the zero crossings and the rms, both skipping the first 0.1 s where the decoders settle
+fn tone(samples: &[f32], sample_rate: u32) -> (f32, f32) {
+    let samples = &samples[sample_rate as usize / 10..];
+    let crossings = samples.windows(2).filter(|w| (w[0] < 0.) != (w[1] < 0.)).count();
+    let rms = (samples.iter().map(|x| x * x).sum::<f32>() / samples.len() as f32).sqrt();
+    (crossings as f32 / 2. * sample_rate as f32 / samples.len() as f32, rms)
+}
+
+fn assert_tone(samples: &[f32], sample_rate: u32, freq: f32, amplitude: f32) {
+    let (found_freq, rms) = tone(samples, sample_rate);
+    assert!((found_freq - freq).abs() < freq * 0.01, "{} Hz instead of {} Hz", found_freq, freq);
+    assert!((rms - amplitude / 2f32.sqrt()).abs() < amplitude * 0.05, "rms {} for amplitude {}", rms, amplitude);
+}
+
+// the reference decoders' output is stored as 16 bit, so it is only that exact
+fn assert_matches_reference(decoded: &[Vec<f32>], reference: &[u8], tolerance: f32) {
+    let meta = read_wav_meta(&mut Cursor::new(reference)).unwrap();
+    assert_eq!(meta.sample_rate, 48000);
+    let reference = read_data(&mut Cursor::new(reference), &meta, 0., meta.audio_duration + 1.).unwrap();
+    assert_eq!(decoded.len(), reference.len());
+    for (decoded, reference) in decoded.iter().zip(&reference) {
+        assert_eq!(decoded.len(), reference.len());
+        let max_diff = decoded.iter().zip(reference).fold(0f32, |max, (a, b)| max.max((a - b).abs()));
+        assert!(max_diff <= tolerance, "differs from the reference by up to {}", max_diff);
+    }
+}
+
+// see tests/fixtures/README.md for how the tone files and their references were made
+#[test]
+fn vorbis_tone_fixture() {
+    let (sample_rate, decoded) = decode(include_bytes!("fixtures/vorbis_tone.ogg"));
+    assert_eq!((sample_rate, decoded.len(), decoded[0].len()), (48000, 2, 24000));
+    assert_tone(&decoded[0], sample_rate, 440., 0.5);
+    assert_tone(&decoded[1], sample_rate, 660., 0.25);
+    assert_matches_reference(&decoded, include_bytes!("fixtures/vorbis_tone_ref.wav"), 1e-4);
+}
+
+#[test]
+fn opus_silk_tone_fixture() {
+    let (sample_rate, decoded) = decode(include_bytes!("fixtures/opus_silk_tone.opus"));
+    assert_eq!((sample_rate, decoded.len(), decoded[0].len()), (48000, 1, 14088));
+    assert_tone(&decoded[0], sample_rate, 440., 0.25);
+    assert_matches_reference(&decoded, include_bytes!("fixtures/opus_silk_tone_ref.wav"), 1e-4);
+}
+
+#[test]
+fn opus_celt_tone_fixture() {
+    let (sample_rate, decoded) = decode(include_bytes!("fixtures/opus_celt_tone.opus"));
+    assert_eq!((sample_rate, decoded.len(), decoded[0].len()), (48000, 1, 14088));
+    assert_tone(&decoded[0], sample_rate, 1000., 0.25);
+    assert_matches_reference(&decoded, include_bytes!("fixtures/opus_celt_tone_ref.wav"), 1e-4);
+}
+
+#[test]
+fn detects_ogg_files() {
+    assert_eq!(AudioFormat::detect(b"OggS\0\x02", Some("wav")), Some(AudioFormat::Ogg));
+    assert_eq!(AudioFormat::detect(b"", Some("opus")), Some(AudioFormat::Ogg));
+    assert_eq!(AudioFormat::detect(b"", Some("OGG")), Some(AudioFormat::Ogg));
+}
@@ -0,0 +1,89 @@
+use std::f32::consts::TAU;
+use std::fs;
+
+use octave::audio::{do_inverse_short_time_fourier_transform, do_short_time_fourier_transform, StftOutput, WindowFunction};
+use octave::audio_source::{audio_path, open_audio, AUDIO_DIR};
+use octave::fft::Fft;
+use octave::file_io::{write_wav_file, SpeakerPos, WavError, WavWriteInfo};
+use octave::phase_vocoder::{pitch_shift, time_stretch, time_stretch_file};
+
+const SAMPLE_RATE: u32 = 16000;
+
+fn sine(frequency: f32, len: usize) -> Vec<f32> {
+    (0..len).map(|i| (TAU * frequency * i as f32 / SAMPLE_RATE as f32).sin() * 0.5).collect()
+}
+
+// the frequency of the loudest bin in the middle of the signal
+fn peak_frequency(samples: &[f32]) -> f32 {
+    let start = samples.len() / 2 - 2000;
+    let fft = Fft::new(SAMPLE_RATE, 4000, WindowFunction::Hann);
+    let bins = fft.process(&samples[start..start + 4000]);
+    bins.iter().max_by(|a, b| a.amplitude.total_cmp(&b.amplitude)).unwrap().frequency
+}
+
+#[test]
+fn inverse_stft_gives_back_the_input() {
+    let signal: Vec<f32> = (0..9000).map(|i| ((i * 7919) % 211) as f32 / 105. - 1.).collect();
+    // window length, overlap, window
+    for (window_size, overlap, window) in [(0.032, 0.75, WindowFunction::Hann), (0.01, 0., WindowFunction::Square), (0.0201875, 0.5, WindowFunction::Hamming)] {
+        let stdft = do_short_time_fourier_transform(&signal, SAMPLE_RATE, window_size, overlap, window, StftOutput::Complex).unwrap();
+        let out = do_inverse_short_time_fourier_transform(&stdft).unwrap();
+        assert_eq!(out.len(), (stdft.num_dfts as usize - 1) * stdft.step_size + stdft.window_len);
+        // skip the ends, where only one window covers the samples
+        for i in stdft.window_len..out.len() - stdft.window_len {
+            assert!((out[i] - signal[i]).abs() < 1e-4, "{:?} sample {}: {} vs {}", window, i, out[i], signal[i]);
+        }
+    }
+    let magnitude = do_short_time_fourier_transform(&signal, SAMPLE_RATE, 0.032, 0.5, WindowFunction::Hann, StftOutput::Magnitude).unwrap();
+    assert!(do_inverse_short_time_fourier_transform(&magnitude).is_err());
+}
+
+#[test]
+fn stretches_time_and_shifts_pitch() {
+    let signal = sine(440., 32000);
+    let stretched = time_stretch(&signal, 1.5).unwrap();
+    assert_eq!(stretched.len(), 48000);
+    assert!((peak_frequency(&stretched) - 440.).abs() <= 4.);
+    // the level holds up away from the ends
+    let rms = (stretched[10000..38000].iter().map(|x| x * x).sum::<f32>() / 28000.).sqrt();
+    assert!((rms - 0.5 / 2f32.sqrt()).abs() < 0.02, "rms {}", rms);
+
+    let shifted = pitch_shift(&signal, 12.).unwrap();
+    assert_eq!(shifted.len(), signal.len());
+    assert!((peak_frequency(&shifted) - 880.).abs() <= 4.);
+    let lowered = pitch_shift(&signal, -7.).unwrap();
+    assert!((peak_frequency(&lowered) - 440. * 2f32.powf(-7. / 12.)).abs() <= 4.);
+
+    assert!(time_stretch(&signal, 5.).is_err());
+    for semitones in [24.5, -37.] {
+        assert!(matches!(pitch_shift(&signal, semitones), Err(WavError::InvalidInput(msg)) if msg.contains("semitones")));
+    }
+}
+
+#[test]
+fn stretches_files() {
+    fs::create_dir_all(AUDIO_DIR).unwrap();
+    // left over from an earlier failed run
+    let _ = fs::remove_file(audio_path("octave_test_vocoder_in.wav"));
+    let _ = fs::remove_file(audio_path("octave_test_vocoder_out.wav"));
+    let format = WavWriteInfo {
+        sample_type: 1,
+        channels: 2,
+        sample_rate: SAMPLE_RATE,
+        bit_depth: 16,
+        channel_mapping: vec![(0, SpeakerPos::FrontLeft), (1, SpeakerPos::FrontRight)],
+        ..Default::default()
+    };
+    write_wav_file("octave_test_vocoder_in.wav".to_string(), &format, &[sine(300., 16000), sine(500., 16000)]).unwrap();
+    time_stretch_file(audio_path("octave_test_vocoder_in.wav"), "octave_test_vocoder_out.wav".to_string(), 0.5).unwrap();
+
+    let mut reader = open_audio(audio_path("octave_test_vocoder_out.wav")).unwrap();
+    let info = reader.info().clone();
+    assert_eq!((info.channels, info.sample_rate, info.sample_type, info.bit_depth), (2, SAMPLE_RATE, 3, 32));
+    assert_eq!(reader.num_frames(), 8000);
+    let mut block = vec![];
+    reader.read_block(&mut block).unwrap();
+    assert!(block[1][2000..4000].iter().any(|&x| x > 0.4));
+    fs::remove_file(audio_path("octave_test_vocoder_in.wav")).unwrap();
+    fs::remove_file(audio_path("octave_test_vocoder_out.wav")).unwrap();
+}
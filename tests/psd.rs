@@ -0,0 +1,93 @@
+mod common;
+
+use std::f32::consts::TAU;
+use std::fs;
+
+use octave::audio::WindowFunction;
+use octave::audio_source::{audio_path, AUDIO_DIR};
+use octave::file_analyzer::analyze_file;
+use octave::file_io::{write_wav_file, SpeakerPos, WavError, WavWriteInfo};
+use octave::psd::{welch_psd, Averaging, Welch, MAX_MEDIAN_VALUES};
+
+use common::noise;
+
+const SAMPLE_RATE: u32 = 48000;
+
+#[test]
+fn density_is_power_per_hz() {
+    let signal = noise(1 << 18, 1);
+    let flat = 1. / 3. / (SAMPLE_RATE as f32 / 2.);
+    for window in [WindowFunction::Square, WindowFunction::Hann, WindowFunction::BlackmanHarris] {
+        for averaging in [Averaging::Mean, Averaging::Median] {
+            let psd = welch_psd(&signal, SAMPLE_RATE, 1024, 0.5, window, averaging).unwrap();
+            assert_eq!((psd.density.len(), psd.num_segments, psd.frequency_step), (513, 511, 46.875));
+            assert!((psd.total_power() - 1. / 3.).abs() < 0.01, "{:?} {:?}: {}", window, averaging, psd.total_power());
+            // any band of white noise, relative to its width
+            let band = psd.band_power(1000., 5000.) / (psd.frequency(107) - psd.frequency(22));
+            assert!((band / flat - 1.).abs() < 0.05, "{:?} {:?}: {}", window, averaging, band / flat);
+        }
+        let max = welch_psd(&signal, SAMPLE_RATE, 1024, 0.5, window, Averaging::MaxHold).unwrap();
+        let mean = welch_psd(&signal, SAMPLE_RATE, 1024, 0.5, window, Averaging::Mean).unwrap();
+        assert!(max.density.iter().zip(&mean.density).all(|(max, mean)| max >= mean));
+        assert!(max.total_power() > 3. * mean.total_power());
+    }
+}
+
+#[test]
+fn sine_power_and_streaming() {
+    // not on a bin, so its power is spread over several
+    let signal: Vec<f32> = (0..100_000).map(|i| 0.5 * (TAU * 1000. * i as f32 / SAMPLE_RATE as f32).sin()).collect();
+    let psd = welch_psd(&signal, SAMPLE_RATE, 2048, 0.75, WindowFunction::Hann, Averaging::Mean).unwrap();
+    assert!((psd.band_power(900., 1100.) - 0.125).abs() < 0.002, "{}", psd.band_power(900., 1100.));
+    assert!(psd.band_power(2000., 20000.) < 1e-6);
+
+    let mut welch = Welch::new(SAMPLE_RATE, 2048, 0.75, WindowFunction::Hann, Averaging::Mean).unwrap();
+    for block in signal.chunks(1000) {
+        welch.push(block);
+    }
+    assert_eq!(welch.finish().unwrap(), psd);
+
+    assert!(matches!(welch_psd(&signal[..2000], SAMPLE_RATE, 2048, 0.5, WindowFunction::Hann, Averaging::Mean), Err(WavError::InvalidInput(_))));
+    assert!(Welch::new(SAMPLE_RATE, 2048, 1., WindowFunction::Hann, Averaging::Mean).is_err());
+}
+
+#[test]
+fn median_rejects_long_signals() {
+    // every segment has 8193 bins, one more segment than fits
+    let segments = MAX_MEDIAN_VALUES / 8193 + 1;
+    let mut welch = Welch::new(SAMPLE_RATE, 1 << 14, 0., WindowFunction::Hann, Averaging::Median).unwrap();
+    let block = vec![0.; 1 << 14];
+    for _ in 0..segments {
+        welch.push(&block);
+    }
+    assert!(matches!(welch.finish(), Err(WavError::InvalidInput(_))));
+}
+
+#[test]
+fn file_analyzer_has_long_term_spectrum() {
+    fs::create_dir_all(AUDIO_DIR).unwrap();
+    let format = WavWriteInfo {
+        sample_type: 3,
+        channels: 2,
+        sample_rate: SAMPLE_RATE,
+        bit_depth: 32,
+        channel_mapping: vec![(0, SpeakerPos::FrontLeft), (1, SpeakerPos::FrontRight)],
+        ..Default::default()
+    };
+    let sine = |frequency: f32, len: usize| (0..len).map(|i| 0.5 * (TAU * frequency * i as f32 / SAMPLE_RATE as f32).sin()).collect::<Vec<f32>>();
+    for (name, len) in [("octave_test_ltas.wav", 200_000), ("octave_test_ltas_short.wav", 5000)] {
+        let _ = fs::remove_file(audio_path(name));
+        write_wav_file(name.to_string(), &format, &[sine(500., len), sine(3000., len)]).unwrap();
+        let results = analyze_file(audio_path(name));
+        fs::remove_file(audio_path(name)).unwrap();
+
+        let Some(ltas) = results.unwrap().ltas else {
+            assert_eq!(len, 5000);
+            continue;
+        };
+        // each channel's sine, averaged with nothing from the other channel
+        assert!((ltas.band_power(450., 550.) - 0.0625).abs() < 0.002);
+        assert!((ltas.band_power(2900., 3100.) - 0.0625).abs() < 0.002);
+        assert!((ltas.total_power() - 0.125).abs() < 0.002);
+    }
+}
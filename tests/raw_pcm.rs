@@ -0,0 +1,106 @@
+use std::fs;
+use std::io::Cursor;
+
+use octave::audio_source::{audio_path, open_raw_pcm, AUDIO_DIR};
+use octave::file_io::{read_data, read_wav_meta, write_wav_file, SpeakerPos, WavError, WavWriteInfo};
+use octave::lookup_tables::ALAW_TO_PCM;
+use octave::raw_pcm::{read_raw_pcm, Endianness, RawPcmSpec, RawSampleType};
+
+fn spec(sample_type: RawSampleType, endianness: Endianness, bit_depth: u32, channels: u8) -> RawPcmSpec {
+    RawPcmSpec { sample_type, endianness, bit_depth, channels, ..Default::default() }
+}
+
+#[test]
+fn raw_dump_of_a_wav_matches_read_data() {
+    fs::create_dir_all(AUDIO_DIR).unwrap();
+    let name = "octave_test_raw_source.wav";
+    let samples: Vec<Vec<f32>> = vec![
+        (0..2000).map(|i| (i as f32 * 0.05).sin() * 0.9).collect(),
+        (0..2000).map(|i| (i as f32 * 0.013).cos() * 0.5).collect(),
+    ];
+    let format = WavWriteInfo {
+        sample_type: 1,
+        channels: 2,
+        sample_rate: 48000,
+        bit_depth: 24,
+        channel_mapping: vec![(0, SpeakerPos::FrontLeft), (1, SpeakerPos::FrontRight)],
+        ..Default::default()
+    };
+    write_wav_file(name.to_string(), &format, &samples).unwrap();
+    let file = fs::read(audio_path(name)).unwrap();
+    fs::remove_file(audio_path(name)).unwrap();
+
+    let meta = read_wav_meta(&mut Cursor::new(&file)).unwrap();
+    let expected = read_data(&mut Cursor::new(&file), &meta, 0.01, 0.02).unwrap();
+
+    // the wav header becomes the byte offset, with a stray byte after the data as a partial frame
+    let (data_start, data_size) = meta.chunks["data"];
+    let mut raw = file[..(data_start + data_size) as usize].to_vec();
+    raw.push(0x55);
+    let raw_spec = RawPcmSpec { byte_offset: data_start, ..spec(RawSampleType::SignedPcm, Endianness::Little, 24, 2) };
+    assert_eq!(read_raw_pcm(&mut Cursor::new(&raw), &raw_spec, 0.01, 0.02).unwrap(), expected);
+
+    let info = raw_spec.wav_info(raw.len() as u64).unwrap();
+    assert_eq!((info.channels, info.sample_rate, info.bit_depth), (2, 48000, 24));
+    assert_eq!(info.chunks["data"], (data_start, data_size));
+    assert_eq!(info.sample_type_str, "Raw Signed PCM (little endian)");
+
+    // the same file byte swapped reads the same as big endian
+    let mut swapped = raw[..(data_start + data_size) as usize].to_vec();
+    for sample in swapped[data_start as usize..].chunks_exact_mut(3) {
+        sample.reverse();
+    }
+    let swapped_spec = RawPcmSpec { endianness: Endianness::Big, ..raw_spec };
+    assert_eq!(read_raw_pcm(&mut Cursor::new(&swapped), &swapped_spec, 0.01, 0.02).unwrap(), expected);
+}
+
+#[test]
+fn converts_every_sample_type() {
+    let cases: [(RawPcmSpec, &[u8], [f32; 2]); 7] = [
+        (spec(RawSampleType::SignedPcm, Endianness::Little, 8, 1), &[0x80, 0x7F], [-1., 1.]),
+        (spec(RawSampleType::UnsignedPcm, Endianness::Little, 8, 1), &[0x00, 0x80], [-1., 0.]),
+        (spec(RawSampleType::SignedPcm, Endianness::Big, 16, 1), &[0xC0, 0x00, 0x40, 0x00], [-0.5, 0x4000 as f32 / 0x7FFF as f32]),
+        (spec(RawSampleType::UnsignedPcm, Endianness::Big, 16, 1), &[0x00, 0x00, 0xFF, 0xFF], [-1., 1.]),
+        (spec(RawSampleType::SignedPcm, Endianness::Little, 32, 1), &[0, 0, 0, 0x80, 0, 0, 0, 0], [-1., 0.]),
+        (spec(RawSampleType::IeeeFloat, Endianness::Big, 32, 1), &[0x3F, 0x00, 0, 0, 0xBE, 0x80, 0, 0], [0.5, -0.25]),
+        (spec(RawSampleType::ALaw, Endianness::Big, 8, 1), &[0x00, 0xD5], [ALAW_TO_PCM[0x00], ALAW_TO_PCM[0xD5]]),
+    ];
+    for (raw_spec, bytes, expected) in cases {
+        let mut out = [0.; 2];
+        raw_spec.decode(bytes, &mut out);
+        assert_eq!(out, expected, "{:?}", raw_spec);
+    }
+    assert_eq!("IEEE Float".parse::<RawSampleType>().unwrap(), RawSampleType::IeeeFloat);
+    assert_eq!(RawSampleType::MuLaw.to_string().parse::<RawSampleType>().unwrap(), RawSampleType::MuLaw);
+}
+
+#[test]
+fn raw_reader_streams_and_seeks() {
+    fs::create_dir_all(AUDIO_DIR).unwrap();
+    let path = audio_path("octave_test_raw_reader.pcm");
+    // 3 channels of 16-bit samples counting up, after an 8 byte device header
+    let mut file = vec![0xEE; 8];
+    for i in 0..3000u16 {
+        file.extend(i.to_le_bytes());
+    }
+    fs::write(&path, &file).unwrap();
+
+    let raw_spec = RawPcmSpec { byte_offset: 8, ..spec(RawSampleType::SignedPcm, Endianness::Little, 16, 3) };
+    let mut source = open_raw_pcm(&path, raw_spec).unwrap();
+    assert_eq!(source.num_frames(), 1000);
+    source.set_block_size(400).unwrap();
+    let mut block = vec![];
+    assert_eq!(source.read_block(&mut block).unwrap(), 400);
+    assert_eq!(block[2][10], 32. / 0x7FFF as f32);
+
+    source.seek_frame(900).unwrap();
+    assert_eq!(source.read_block(&mut block).unwrap(), 100);
+    assert_eq!(block[1][0], 2701. / 0x7FFF as f32);
+    assert_eq!(source.read_block(&mut block).unwrap(), 0);
+
+    let past_end = RawPcmSpec { byte_offset: 10_000, ..raw_spec };
+    assert!(matches!(open_raw_pcm(&path, past_end), Err(WavError::InvalidInput(_))));
+    let float_24 = spec(RawSampleType::IeeeFloat, Endianness::Little, 24, 1);
+    assert!(matches!(open_raw_pcm(&path, float_24), Err(WavError::UnsupportedBitDepth { sample_type: 3, bit_depth: 24 })));
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use std::fs;
+
+use octave::audio_source::{audio_path, AUDIO_DIR};
+use octave::file_analyzer::analyze_impulse_response;
+use octave::file_io::{write_wav_file, SpeakerPos, WavError, WavWriteInfo};
+use octave::room_acoustics::{decay_slope, room_acoustics, schroeder_curve};
+
+use common::noise;
+
+const SAMPLE_RATE: u32 = 48000;
+
+// silence, a direct sound, then noise dying away 60 dB every rt60 seconds over a constant noise floor
+fn room_ir(rt60: f32, len: usize, floor: f32) -> Vec<f32> {
+    let decay = 6.9078 / rt60 / SAMPLE_RATE as f32;
+    let noise = noise(2 * len, 3);
+    let (reverb, background) = noise.split_at(len);
+    let mut ir = vec![0.; 100];
+    ir.push(1.);
+    ir.extend(reverb.iter().zip(background).enumerate().map(|(t, (r, n))| 0.5 * r * (-decay * t as f32).exp() + floor * n));
+    ir
+}
+
+#[test]
+fn exponential_decay() {
+    let rt60 = 0.8;
+    let ir = room_ir(rt60, 2 * SAMPLE_RATE as usize, 0.);
+    let acoustics = room_acoustics(&ir, SAMPLE_RATE).unwrap();
+    assert_eq!((acoustics.onset, acoustics.bands.len()), (100, 8));
+
+    let broadband = acoustics.broadband;
+    for time in [broadband.edt, broadband.t20, broadband.t30] {
+        assert!((time.unwrap() - rt60).abs() < 0.02, "{:?}", broadband);
+    }
+    // the energy decays by e^(-2kt), so the early part over the rest is e^(2kt) - 1
+    let k = 6.9078 / rt60;
+    let clarity = |secs: f32| 10. * ((2. * k * secs).exp() - 1.).log10();
+    assert!((broadband.c50 - clarity(0.05)).abs() < 0.3, "{:?}", broadband);
+    assert!((broadband.c80 - clarity(0.08)).abs() < 0.3, "{:?}", broadband);
+    assert!((broadband.d50 - (1. - (-2. * k * 0.05).exp())).abs() < 0.02, "{:?}", broadband);
+
+    // white noise decays the same in every band
+    for (center, band) in &acoustics.bands[2..] {
+        assert!((band.t30.unwrap() - rt60).abs() < 0.05, "{} {:?}", center, band);
+    }
+
+    assert!(matches!(room_acoustics(&[0.; 1000], SAMPLE_RATE), Err(WavError::InvalidInput(_))));
+}
+
+#[test]
+fn stops_at_the_noise_floor() {
+    let rt60 = 0.5;
+    // 40 dB under the start of the decay, too close for T30
+    let ir = room_ir(rt60, 3 * SAMPLE_RATE as usize, 0.005);
+    let broadband = room_acoustics(&ir, SAMPLE_RATE).unwrap().broadband;
+    assert!((broadband.edt.unwrap() - rt60).abs() < 0.03, "{:?}", broadband);
+    assert!((broadband.t20.unwrap() - rt60).abs() < 0.05, "{:?}", broadband);
+    assert_eq!(broadband.t30, None);
+
+    // every sample has half the energy of the one before
+    let curve = schroeder_curve(&[1., 0.5f32.sqrt(), 0.5, 0.5f32.powf(1.5), 0.25]);
+    assert_eq!(curve[0], 0.);
+    assert!((curve[1] - 10. * (15. / 31f32).log10()).abs() < 1e-4);
+    let line: Vec<f32> = (0..1000).map(|i| -0.06 * i as f32).collect();
+    assert!((decay_slope(&line, -5., -35., 1000).unwrap() + 60.).abs() < 0.01);
+    assert_eq!(decay_slope(&line, -5., -80., 1000), None);
+}
+
+#[test]
+fn file_analyzed_as_impulse_response() {
+    fs::create_dir_all(AUDIO_DIR).unwrap();
+    let format = WavWriteInfo {
+        sample_type: 3,
+        channels: 1,
+        sample_rate: SAMPLE_RATE,
+        bit_depth: 32,
+        channel_mapping: vec![(0, SpeakerPos::FrontLeft)],
+        ..Default::default()
+    };
+    let name = "octave_test_room_ir.wav";
+    let ir = room_ir(1.2, 2 * SAMPLE_RATE as usize, 0.);
+    let _ = fs::remove_file(audio_path(name));
+    write_wav_file(name.to_string(), &format, std::slice::from_ref(&ir)).unwrap();
+    let acoustics = analyze_impulse_response(audio_path(name));
+    fs::remove_file(audio_path(name)).unwrap();
+
+    let acoustics = acoustics.unwrap();
+    assert_eq!(acoustics, room_acoustics(&ir, SAMPLE_RATE).unwrap());
+    assert!((acoustics.broadband.t30.unwrap() - 1.2).abs() < 0.03);
+
+    // a file much longer than any room takes to decay
+    let long = vec![0.5; 31 * SAMPLE_RATE as usize];
+    write_wav_file(name.to_string(), &format, std::slice::from_ref(&long)).unwrap();
+    let acoustics = analyze_impulse_response(audio_path(name));
+    fs::remove_file(audio_path(name)).unwrap();
+    assert!(matches!(acoustics, Err(WavError::InvalidInput(_))));
+}
@@ -0,0 +1,106 @@
+use std::io::Cursor;
+
+use octave::audio::{do_short_time_fourier_transform, do_short_time_fourier_transform_streaming, Stft, StftFrames, StftOutput, WindowFunction};
+use octave::fft::Fft;
+use octave::raw_pcm::{RawPcmReader, RawPcmSpec, RawSampleType, Endianness};
+
+const SAMPLE_RATE: u32 = 8000;
+
+fn chirp(len: usize) -> Vec<f32> {
+    (0..len).map(|i| {
+        let t = i as f32 / SAMPLE_RATE as f32;
+        (std::f32::consts::TAU * (100. + 600. * t) * t).sin() * 0.7
+    }).collect()
+}
+
+#[test]
+fn matches_one_fft_per_window() {
+    let signal = chirp(40_000);
+    // 25 ms windows (200 samples) with 30% overlap, so 140 sample steps
+    let stdft = do_short_time_fourier_transform(&signal, SAMPLE_RATE, 0.025, 0.3, WindowFunction::Hann, StftOutput::Magnitude).unwrap();
+    assert_eq!((stdft.num_dfts, stdft.num_freq), ((40_000 - 200) / 140 + 1, 100));
+    assert_eq!(stdft.frequency_step, 40.);
+    assert_eq!(stdft.data_size, stdft.num_dfts as usize * 100 * 4 + 16);
+
+    let fft = Fft::new(SAMPLE_RATE, 200, WindowFunction::Hann);
+    for dft in [0, 1, 57, stdft.num_dfts as usize - 1] {
+        let expected: Vec<f32> = fft.process(&signal[dft * 140..dft * 140 + 200]).iter().map(|bin| bin.amplitude).collect();
+        assert_eq!(stdft.dft(dft), expected, "window {}", dft);
+    }
+
+    // pushing the samples a few at a time gives the same frames
+    let mut stft = Stft::new(SAMPLE_RATE, 0.025, 0.3, WindowFunction::Hann, StftOutput::Magnitude).unwrap();
+    let mut windows = 0;
+    for chunk in signal.chunks(333) {
+        windows += stft.push(chunk);
+        assert_eq!(stft.num_dfts(), windows);
+    }
+    assert_eq!(stft.finish().frames, stdft.frames);
+}
+
+#[test]
+fn take_frames_leaves_the_rest_for_finish() {
+    let signal = chirp(40_000);
+    let stdft = do_short_time_fourier_transform(&signal, SAMPLE_RATE, 0.025, 0.3, WindowFunction::Hann, StftOutput::Magnitude).unwrap();
+
+    // short pushes only add windows once there are enough for a batch
+    let mut stft = Stft::new(SAMPLE_RATE, 0.025, 0.3, WindowFunction::Hann, StftOutput::Magnitude).unwrap();
+    assert_eq!(stft.push(&signal[..1000]), 0);
+    assert_eq!(stft.flush(), (1000 - 200) / 140 + 1);
+
+    let StftFrames::Magnitude(mut frames) = stft.take_frames() else { panic!("expected magnitude frames") };
+    assert_eq!(stft.num_dfts(), 0);
+    stft.push(&signal[1000..]);
+    let rest = stft.finish();
+    assert_eq!(rest.num_dfts as usize, stdft.num_dfts as usize - frames.len() / 100);
+    assert_eq!(rest.dft(rest.num_dfts as usize - 1), stdft.dft(stdft.num_dfts as usize - 1));
+
+    let StftFrames::Magnitude(rest) = rest.frames else { panic!("expected magnitude frames") };
+    frames.extend(rest);
+    assert_eq!(StftFrames::Magnitude(frames), stdft.frames);
+}
+
+#[test]
+fn complex_frames_keep_the_phase() {
+    let signal = chirp(20_000);
+    let magnitude = do_short_time_fourier_transform(&signal, SAMPLE_RATE, 0.032, 0.5, WindowFunction::BlackmanHarris, StftOutput::Magnitude).unwrap();
+    let complex = do_short_time_fourier_transform(&signal, SAMPLE_RATE, 0.032, 0.5, WindowFunction::BlackmanHarris, StftOutput::Complex).unwrap();
+    // complex frames keep the nyquist bin as well, so they can be turned back into samples
+    assert_eq!((complex.num_dfts, complex.num_freq), (magnitude.num_dfts, magnitude.num_freq + 1));
+    // about twice the size of magnitudes alone, and a third of what a FreqData per bin took
+    assert_eq!(complex.data_size - 16, complex.num_dfts as usize * 129 * 8);
+    assert_eq!(magnitude.data_size - 16, magnitude.num_dfts as usize * 128 * 4);
+
+    let fft = Fft::new(SAMPLE_RATE, 256, WindowFunction::BlackmanHarris);
+    let StftFrames::Complex(frames) = &complex.frames else { panic!("expected complex frames") };
+    let expected = fft.forward_complex(&signal[128 * 30..128 * 30 + 256]);
+    for (bin, (a, b)) in frames[30 * 129..31 * 129].iter().zip(&expected).enumerate() {
+        assert!((*a - *b).magnitude() < 1e-4 * (1. + b.magnitude()), "bin {}", bin);
+        if bin < 128 {
+            assert!((complex.amplitude(30, bin) - magnitude.amplitude(30, bin)).abs() < 1e-5);
+        }
+    }
+}
+
+#[test]
+fn streams_from_a_source() {
+    let signal = chirp(12_345);
+    let bytes: Vec<u8> = signal.iter().flat_map(|&x| [x, -x]).flat_map(f32::to_le_bytes).collect();
+    let spec = RawPcmSpec {
+        sample_type: RawSampleType::IeeeFloat,
+        endianness: Endianness::Little,
+        bit_depth: 32,
+        channels: 2,
+        sample_rate: SAMPLE_RATE,
+        byte_offset: 0,
+    };
+    let mut reader = RawPcmReader::new(Cursor::new(bytes), spec, 1000).unwrap();
+    let streamed = do_short_time_fourier_transform_streaming(&mut reader, 1, 0.05, 0.5, WindowFunction::Hann, StftOutput::Magnitude).unwrap();
+    let inverted: Vec<f32> = signal.iter().map(|x| -x).collect();
+    let in_memory = do_short_time_fourier_transform(&inverted, SAMPLE_RATE, 0.05, 0.5, WindowFunction::Hann, StftOutput::Magnitude).unwrap();
+    assert_eq!(streamed.frames, in_memory.frames);
+    assert_eq!(streamed.num_dfts, (12_345 - 400) / 200 + 1);
+
+    assert!(do_short_time_fourier_transform_streaming(&mut reader, 2, 0.05, 0.5, WindowFunction::Hann, StftOutput::Magnitude).is_err());
+    assert!(Stft::new(SAMPLE_RATE, 0.05, 1., WindowFunction::Hann, StftOutput::Magnitude).is_err());
+}
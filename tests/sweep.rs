@@ -0,0 +1,60 @@
+use octave::audio::FreqData;
+use octave::sweep::{frequency_response, ExponentialSweep};
+
+const SAMPLE_RATE: u32 = 48000;
+
+// the amplitude of the bin closest to `frequency`
+fn amplitude_at(response: &[FreqData], frequency: f32) -> f32 {
+    response.iter().min_by(|a, b| (a.frequency - frequency).abs().total_cmp(&(b.frequency - frequency).abs())).unwrap().amplitude
+}
+
+#[test]
+fn sweep_through_nothing_is_an_impulse() {
+    let sweep = ExponentialSweep::new(SAMPLE_RATE, 20., 20000., 2., 0.5).unwrap();
+    assert_eq!((sweep.signal().len(), sweep.inverse_filter().len()), (96000, 96000));
+    assert!(sweep.signal().iter().all(|x| x.abs() <= 0.5));
+    assert!((sweep.harmonic_offset(2) - 2. * 2f64.ln() / 1000f64.ln()).abs() < 1e-9);
+
+    let mut recording = sweep.signal().to_vec();
+    recording.resize(recording.len() + SAMPLE_RATE as usize / 2, 0.);
+    let ir = sweep.deconvolve(&recording);
+    assert_eq!((ir.linear_start, ir.latency, ir.linear().len()), (95999, 0, 120048));
+
+    let response = frequency_response(&ir.linear()[..4800], SAMPLE_RATE);
+    for frequency in [100., 1000., 5000., 10000.] {
+        let db = 20. * amplitude_at(&response, frequency).log10();
+        assert!(db.abs() < 0.5, "{} Hz: {} dB", frequency, db);
+    }
+
+    assert!(ExponentialSweep::new(SAMPLE_RATE, 1000., 100., 2., 0.5).is_err());
+    assert!(ExponentialSweep::new(SAMPLE_RATE, 20., 30000., 2., 0.5).is_err());
+    assert!(ExponentialSweep::new(SAMPLE_RATE, 20., 20000., 0., 0.5).is_err());
+}
+
+#[test]
+fn separates_harmonics() {
+    let sweep = ExponentialSweep::new(SAMPLE_RATE, 50., 20000., 4., 0.5).unwrap();
+    // delayed, half the level, and squared and cubed a little
+    let mut recording = vec![0.; 300];
+    recording.extend(sweep.signal().iter().map(|&x| 0.5 * x + 0.2 * x * x + 0.4 * x * x * x));
+    recording.resize(recording.len() + SAMPLE_RATE as usize, 0.);
+
+    let ir = sweep.deconvolve(&recording);
+    assert_eq!(ir.latency, 300);
+    let fft_len = 4800;
+    let linear = frequency_response(&ir.harmonic(1)[..fft_len], SAMPLE_RATE);
+    let second = frequency_response(&ir.harmonic(2)[..fft_len], SAMPLE_RATE);
+    let third = frequency_response(&ir.harmonic(3)[..fft_len], SAMPLE_RATE);
+    // against the level of the sweep: the cube adds 3/4 of its amplitude at the fundamental,
+    // and 1/4 at the third harmonic. The square adds half at the second
+    let amplitude = 0.5f32;
+    for frequency in [1000., 3000.] {
+        let expected = [0.5 + 0.4 * 0.75 * amplitude.powi(2), 0.2 * 0.5 * amplitude, 0.4 * 0.25 * amplitude.powi(2)];
+        let measured = [amplitude_at(&linear, frequency), amplitude_at(&second, 2. * frequency), amplitude_at(&third, 3. * frequency)];
+        for (expected, measured) in expected.iter().zip(measured) {
+            assert!((20. * (measured / expected).log10()).abs() < 0.3, "{} Hz: {} {}", frequency, measured, expected);
+        }
+    }
+    // further ahead than the sweep is long
+    assert!(ir.harmonic(5000).is_empty());
+}
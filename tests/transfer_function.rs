@@ -0,0 +1,62 @@
+mod common;
+
+use std::f32::consts::TAU;
+
+use octave::audio::WindowFunction;
+use octave::fft::Complex;
+use octave::transfer_function::{find_delay, transfer_function};
+
+use common::noise;
+
+const SAMPLE_RATE: u32 = 48000;
+
+#[test]
+fn finds_delay_and_gain() {
+    let reference = noise(40000, 1);
+    let mut measured = vec![0.; 37];
+    measured.extend(reference.iter().map(|x| x * 0.5));
+
+    assert_eq!(find_delay(&reference, &measured, 1000), 37);
+    assert_eq!(find_delay(&measured, &reference, 1000), -37);
+    // out of range, so the best is somewhere else
+    assert_ne!(find_delay(&reference, &measured, 20), 37);
+
+    let tf = transfer_function(&reference, &measured, SAMPLE_RATE, 4096, WindowFunction::Hann, None).unwrap();
+    assert_eq!((tf.delay, tf.num_segments, tf.bins.len(), tf.frequency_step), (37, 18, 2049, 11.71875));
+    for bin in &tf.bins[1..] {
+        assert!((bin.magnitude - 0.5).abs() < 1e-3 && bin.phase.abs() < 1e-3 && bin.coherence > 0.999, "{:?}", bin);
+    }
+    assert!((tf.bins[100].magnitude_db() + 6.0206).abs() < 0.01);
+
+    // lined up by hand instead
+    let tf = transfer_function(&measured, &reference, SAMPLE_RATE, 4096, WindowFunction::Hann, Some(-37)).unwrap();
+    assert!((tf.bins[500].magnitude - 2.).abs() < 1e-3);
+    assert!(transfer_function(&reference[..5000], &measured[..5000], SAMPLE_RATE, 8192, WindowFunction::Hann, None).is_err());
+}
+
+#[test]
+fn filter_response_and_coherence() {
+    // two halves of one sequence, different seeds give related sequences
+    let noise = noise(1 << 19, 2);
+    let (reference, extra) = noise.split_at(1 << 18);
+    let filtered: Vec<f32> = (0..reference.len()).map(|t| 0.7 * reference[t] + 0.3 * if t > 0 { reference[t - 1] } else { 0. }).collect();
+    let noisy: Vec<f32> = filtered.iter().zip(extra).map(|(y, n)| y + n).collect();
+
+    let tf = transfer_function(reference, &filtered, SAMPLE_RATE, 1024, WindowFunction::Hann, None).unwrap();
+    let noisy_tf = transfer_function(reference, &noisy, SAMPLE_RATE, 1024, WindowFunction::Hann, None).unwrap();
+    assert_eq!((tf.delay, noisy_tf.delay), (0, 0));
+
+    for k in (1..512).step_by(7) {
+        let omega = TAU * k as f32 / 1024.;
+        let expected = Complex::new(0.7, 0.) + Complex::from_polar(0.3, -omega);
+        let bin = tf.bins[k];
+        assert!((bin.response() - expected).magnitude() < 1e-3, "{} {:?} {:?}", k, bin, expected);
+        assert!(bin.coherence > 0.999);
+
+        // the noise adds as much power as the filtered reference has at dc
+        let bin = noisy_tf.bins[k];
+        let coherence = expected.magnitude().powi(2) / (expected.magnitude().powi(2) + 1.);
+        assert!((bin.response() - expected).magnitude() < 0.1, "{} {:?}", k, bin);
+        assert!((bin.coherence - coherence).abs() < 0.05, "{} {} {}", k, bin.coherence, coherence);
+    }
+}
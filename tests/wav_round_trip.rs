@@ -0,0 +1,321 @@
+mod common;
+
+use std::fs::{self, File};
+use std::io::{BufReader, Cursor, Seek, SeekFrom};
+
+use octave::file_io::{
+    read_data, read_data_interleaved_unchecked, read_wav_meta, write_wav_file, SpeakerPos, SubFormat, WavError,
+    WavReader, WavWriteInfo, WavWriter,
+};
+use octave::lookup_tables::{ALAW_TO_PCM, ULAW_TO_PCM};
+use octave::wav_metadata::{BextChunk, CuePoint, IxmlChunk, IxmlTrack, WavTags};
+
+use common::{assert_close, fresh_audio_path, stereo_format, test_signal};
+
+// writes the samples, reads them back with both read paths, then removes the file
+fn round_trip(name: &str, format: &WavWriteInfo, samples: &[Vec<f32>]) -> (Vec<Vec<f32>>, Vec<f32>) {
+    let path = fresh_audio_path(name);
+
+    write_wav_file(name.to_string(), format, samples).unwrap();
+
+    let mut reader = BufReader::new(File::open(&path).unwrap());
+    let meta = read_wav_meta(&mut reader).unwrap();
+    assert_eq!(meta.sample_type, format.sample_type);
+    assert_eq!(meta.bit_depth, format.bit_depth as u32);
+    assert_eq!(meta.channels, format.channels);
+    assert_eq!(meta.sample_rate, format.sample_rate);
+
+    let planar = read_data(&mut reader, &meta, 0., meta.audio_duration + 1.).unwrap();
+
+    reader.seek(SeekFrom::Start(meta.chunks.get("data").unwrap().0)).unwrap();
+    let interleaved =
+        read_data_interleaved_unchecked(&mut reader, &meta, samples[0].len() * samples.len()).unwrap();
+
+    fs::remove_file(&path).unwrap();
+    (planar, interleaved)
+}
+
+#[test]
+fn pcm_8bit_round_trip() {
+    let samples = test_signal(1000);
+    let (planar, interleaved) = round_trip("octave_test_pcm_8bit.wav", &stereo_format(1, 8), &samples);
+    // one step of 8 bit offset binary
+    assert_close(&samples, &planar, &interleaved, 1. / 127.);
+}
+
+#[test]
+fn pcm_8bit_silence_is_offset() {
+    let samples = vec![vec![0.; 16]; 2];
+    let (planar, interleaved) = round_trip("octave_test_pcm_8bit_silence.wav", &stereo_format(1, 8), &samples);
+    assert_close(&samples, &planar, &interleaved, 0.);
+}
+
+#[test]
+fn float_64bit_round_trip() {
+    let samples = test_signal(1000);
+    let (planar, interleaved) = round_trip("octave_test_float_64bit.wav", &stereo_format(3, 64), &samples);
+    // f32 -> f64 -> f32 is lossless
+    assert_close(&samples, &planar, &interleaved, 0.);
+}
+
+#[test]
+fn bext_and_ixml_round_trip() {
+    let bext = BextChunk {
+        description: "Scene 4 boom".to_string(),
+        originator: "Octave".to_string(),
+        originator_reference: "OCT0001".to_string(),
+        origination_date: "2025:01:31".to_string(),
+        origination_time: "12:30:00".to_string(),
+        time_reference: 48000 * 3600,
+        version: 2,
+        umid: vec![0; 64],
+        loudness_value: Some(-23.),
+        loudness_range: Some(7.5),
+        max_true_peak_level: Some(-1.),
+        max_momentary_loudness: None,
+        max_short_term_loudness: None,
+        coding_history: "A=PCM,F=48000,W=24,M=stereo\r\n".to_string(),
+    };
+    let ixml = IxmlChunk {
+        project: "Octave & Co".to_string(),
+        scene: "4".to_string(),
+        take: "2".to_string(),
+        tracks: vec![
+            IxmlTrack { channel_index: 1, interleave_index: 1, name: "Boom".to_string() },
+            IxmlTrack { channel_index: 2, interleave_index: 2, name: "Lav <1>".to_string() },
+        ],
+        ..Default::default()
+    };
+
+    let name = "octave_test_bext_ixml.wav";
+    let format = WavWriteInfo { bext: Some(bext.clone()), ixml: Some(ixml.clone()), ..stereo_format(1, 16) };
+    let path = fresh_audio_path(name);
+    write_wav_file(name.to_string(), &format, &test_signal(100)).unwrap();
+
+    let mut reader = BufReader::new(File::open(&path).unwrap());
+    let meta = read_wav_meta(&mut reader).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(meta.bext, Some(bext));
+    assert_eq!(meta.ixml, Some(ixml));
+}
+
+#[test]
+fn deeply_nested_ixml_is_rejected() {
+    let depth = 200_000;
+    let xml = format!("<BWFXML>{}{}</BWFXML>", "<a>".repeat(depth), "</a>".repeat(depth));
+    assert!(matches!(IxmlChunk::from_bytes(xml.as_bytes()), Err(WavError::InvalidHeader(_))));
+}
+
+#[test]
+fn tags_and_cue_points_round_trip() {
+    let tags = WavTags {
+        title: "Room tone".to_string(),
+        artist: "Octave".to_string(),
+        comment: "odd".to_string(),
+        software: String::new(),
+    };
+    let cue_points = vec![
+        CuePoint { id: 1, position: 10, label: "Start".to_string(), ..Default::default() },
+        CuePoint { id: 2, position: 40, length: 25, label: "Chorus".to_string(), note: "take 3".to_string() },
+        CuePoint { id: 3, position: 90, ..Default::default() },
+    ];
+
+    let name = "octave_test_tags_cue.wav";
+    let format = WavWriteInfo { tags: Some(tags.clone()), cue_points: cue_points.clone(), ..stereo_format(1, 16) };
+    let path = fresh_audio_path(name);
+    write_wav_file(name.to_string(), &format, &test_signal(100)).unwrap();
+
+    let mut reader = BufReader::new(File::open(&path).unwrap());
+    let meta = read_wav_meta(&mut reader).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(meta.tags, Some(tags));
+    assert_eq!(meta.cue_points, cue_points);
+    assert!(meta.chunks.contains_key("LIST/INFO"));
+    assert!(meta.chunks.contains_key("LIST/adtl"));
+}
+
+#[test]
+fn sub_format_guids() {
+    let pcm = SubFormat::Pcm.guid();
+    assert_eq!(pcm.to_string(), "{00000001-0000-0010-8000-00AA00389B71}");
+    assert_eq!(SubFormat::from_guid(pcm), SubFormat::Pcm);
+
+    let b_format = SubFormat::AmbisonicBFormatFloat.guid();
+    assert_eq!(b_format.to_string(), "{00000003-0721-11D3-8644-C8C1CA000000}");
+    assert_eq!(SubFormat::from_guid(b_format).sample_type(), Some(3));
+
+    // Dolby AC-3 over S/PDIF
+    let mut ac3 = pcm;
+    ac3.0[0] = 0x92;
+    assert_eq!(SubFormat::from_guid(ac3), SubFormat::Unknown(ac3));
+    assert_eq!(SubFormat::from_guid(ac3).sample_type(), None);
+}
+
+#[test]
+fn extensible_pcm_reports_sub_format() {
+    // anything with more than 2 channels is written as WAVE_FORMAT_EXTENSIBLE
+    let format = WavWriteInfo {
+        channels: 3,
+        channel_mapping: vec![(0, SpeakerPos::FrontLeft), (1, SpeakerPos::FrontRight), (2, SpeakerPos::BackCenter)],
+        ..stereo_format(1, 24)
+    };
+    let mut samples = test_signal(100);
+    samples.push(vec![0.; 100]);
+
+    let name = "octave_test_extensible.wav";
+    let path = fresh_audio_path(name);
+    write_wav_file(name.to_string(), &format, &samples).unwrap();
+
+    let mut reader = BufReader::new(File::open(&path).unwrap());
+    let meta = read_wav_meta(&mut reader).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(meta.sub_format, Some(SubFormat::Pcm));
+    assert_eq!(meta.valid_bits_per_sample, 24);
+}
+
+#[test]
+fn extensible_valid_bits_below_container_size() {
+    // mono 20-bit audio in 24-bit containers, with the samples left aligned
+    let mut file = b"RIFF\x42\0\0\0WAVEfmt \x28\0\0\0".to_vec();
+    file.extend_from_slice(&0xFFFEu16.to_le_bytes());
+    file.extend_from_slice(&1u16.to_le_bytes());
+    file.extend_from_slice(&48000u32.to_le_bytes());
+    file.extend_from_slice(&144000u32.to_le_bytes());
+    file.extend_from_slice(&[3, 0, 24, 0]);
+    file.extend_from_slice(&22u16.to_le_bytes());
+    file.extend_from_slice(&20u16.to_le_bytes()); // valid bits
+    file.extend_from_slice(&4u32.to_le_bytes()); // front center
+    file.extend_from_slice(&SubFormat::Pcm.guid().0);
+    file.extend_from_slice(b"data\x06\0\0\0");
+    file.extend_from_slice(&[0x00, 0x00, 0x40, 0xF0, 0xFF, 0xBF]);
+
+    let mut reader = Cursor::new(file);
+    let meta = read_wav_meta(&mut reader).unwrap();
+    assert_eq!((meta.bit_depth, meta.valid_bits_per_sample), (24, 20));
+    assert_eq!(meta.sub_format, Some(SubFormat::Pcm));
+
+    let samples = read_data(&mut reader, &meta, 0., 1.).unwrap();
+    assert_eq!(samples[0], vec![0x40_0000 as f32 / 0x7F_FFFF as f32, -0x40_0010 as f32 / 0x80_0000 as f32]);
+}
+
+#[test]
+fn wav_reader_blocks_and_seek() {
+    let samples = test_signal(1000);
+    let name = "octave_test_wav_reader.wav";
+    let path = fresh_audio_path(name);
+    write_wav_file(name.to_string(), &stereo_format(3, 32), &samples).unwrap();
+
+    let mut reader = WavReader::new(BufReader::new(File::open(&path).unwrap()), 300).unwrap();
+    assert_eq!(reader.num_frames(), 1000);
+
+    // 3 full blocks and a short one
+    let blocks = reader.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(blocks.iter().map(|b| b[0].len()).collect::<Vec<_>>(), vec![300, 300, 300, 100]);
+    for c in 0..2 {
+        let joined: Vec<f32> = blocks.iter().flat_map(|b| b[c].iter().copied()).collect();
+        assert_eq!(joined, samples[c]);
+    }
+
+    reader.seek_frame(777).unwrap();
+    let mut block = vec![];
+    assert_eq!(reader.read_block(&mut block).unwrap(), 223);
+    assert_eq!(block[0][0], samples[0][777]);
+    assert_eq!(block[1][222], samples[1][999]);
+    assert!(reader.seek_frame(1001).is_err());
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn g711_round_trip() {
+    // every code decodes to a value that encodes back to the same code
+    for (sample_type, table) in [(6, &ALAW_TO_PCM), (7, &ULAW_TO_PCM)] {
+        let samples = vec![table.to_vec(), table.to_vec()];
+        let (planar, interleaved) = round_trip(&format!("octave_test_g711_{}.wav", sample_type), &stereo_format(sample_type, 8), &samples);
+        assert_close(&samples, &planar, &interleaved, 0.);
+    }
+}
+
+#[test]
+fn wav_writer_streams_blocks() {
+    let samples = test_signal(1001);
+    let name = "octave_test_wav_writer.wav";
+    let path = fresh_audio_path(name);
+
+    {
+        let mut writer = WavWriter::create(name.to_string(), &stereo_format(1, 24)).unwrap();
+        // planar first half, interleaved second half
+        writer.write_planar(&[samples[0][..500].to_vec(), samples[1][..500].to_vec()]).unwrap();
+        let rest: Vec<f32> = (500..1001).flat_map(|i| [samples[0][i], samples[1][i]]).collect();
+        writer.write_interleaved(&rest).unwrap();
+        assert!(writer.write_interleaved(&[0.]).is_err());
+        assert_eq!(writer.frames_written(), 1001);
+        // dropping the writer patches the header
+    }
+
+    let mut reader = WavReader::new(BufReader::new(File::open(&path).unwrap()), 4096).unwrap();
+    assert_eq!(reader.num_frames(), 1001);
+    let block = reader.next().unwrap().unwrap();
+    fs::remove_file(&path).unwrap();
+
+    for c in 0..2 {
+        for i in 0..1001 {
+            assert!((block[c][i] - samples[c][i]).abs() <= 1. / 0x7FFFFF as f32);
+        }
+    }
+}
+
+// a mono 16-bit file with the real sizes in a ds64 chunk and -1 in the 32-bit size fields
+fn ds64_file(riff_tag: &[u8; 4], samples: &[i16]) -> Vec<u8> {
+    let data_size = samples.len() as u64 * 2;
+    let riff_size = 4 + (8 + 28) + (8 + 16) + 8 + data_size;
+
+    let mut file = riff_tag.to_vec();
+    file.extend_from_slice(&u32::MAX.to_le_bytes());
+    file.extend_from_slice(b"WAVEds64");
+    file.extend_from_slice(&28u32.to_le_bytes());
+    file.extend_from_slice(&riff_size.to_le_bytes());
+    file.extend_from_slice(&data_size.to_le_bytes());
+    file.extend_from_slice(&(samples.len() as u64).to_le_bytes());
+    file.extend_from_slice(&0u32.to_le_bytes());
+
+    file.extend_from_slice(b"fmt ");
+    file.extend_from_slice(&16u32.to_le_bytes());
+    file.extend_from_slice(&[1, 0, 1, 0]);
+    file.extend_from_slice(&8000u32.to_le_bytes());
+    file.extend_from_slice(&16000u32.to_le_bytes());
+    file.extend_from_slice(&[2, 0, 16, 0]);
+
+    file.extend_from_slice(b"data");
+    file.extend_from_slice(&u32::MAX.to_le_bytes());
+    for s in samples {
+        file.extend_from_slice(&s.to_le_bytes());
+    }
+    file
+}
+
+#[test]
+fn rf64_and_bw64_sizes_come_from_ds64() {
+    let samples = [0, 0x4000, -0x4000, 0x7FFF, -0x8000];
+    for riff_tag in [b"RF64", b"BW64"] {
+        let file = ds64_file(riff_tag, &samples);
+        let file_len = file.len() as u64;
+        let mut reader = Cursor::new(file);
+        let meta = read_wav_meta(&mut reader).unwrap();
+
+        assert_eq!(meta.file_size, file_len - 8);
+        assert_eq!(meta.chunks["data"], (file_len - 10, 10));
+        assert_eq!(meta.channels, 1);
+        assert_eq!(meta.sample_rate, 8000);
+
+        let planar = read_data(&mut reader, &meta, 0., 1.).unwrap();
+        assert_eq!(planar[0], vec![0., 0x4000 as f32 / 0x7FFF as f32, -0.5, 1., -1.]);
+    }
+    // a data size that runs past the end of what a u64 can address
+    let mut file = ds64_file(b"RF64", &samples);
+    file[28..36].copy_from_slice(&u64::MAX.to_le_bytes());
+    assert!(matches!(read_wav_meta(&mut Cursor::new(file)), Err(WavError::TruncatedChunk(title)) if title == "data"));
+}
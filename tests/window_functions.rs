@@ -0,0 +1,54 @@
+use std::f64::consts::TAU;
+
+use octave::audio::WindowFunction;
+use octave::fft::Fft;
+
+#[test]
+fn gains_match_the_textbook_values() {
+    let n = 4096;
+    let cases = [
+        (WindowFunction::Square, 1., 1.),
+        (WindowFunction::Hann, 0.5, 1.5),
+        (WindowFunction::Hamming, 0.54, 1.3628),
+        (WindowFunction::BlackmanHarris, 0.35875, 2.0044),
+        (WindowFunction::FlatTop, 0.21558, 3.7702),
+        // with no taper the kaiser window is just a square one
+        (WindowFunction::Kaiser { beta: 0. }, 1., 1.),
+    ];
+    for (window, coherent_gain, enbw) in cases {
+        assert!((window.coherent_gain(n) - coherent_gain).abs() < 1e-4, "{:?}: {}", window, window.coherent_gain(n));
+        assert!((window.enbw(n) - enbw).abs() < 1e-3, "{:?}: {}", window, window.enbw(n));
+        let fft = Fft::new(48000, n, window);
+        assert_eq!((fft.coherent_gain, fft.enbw), (window.coherent_gain(n), window.enbw(n)));
+    }
+
+    let kaiser = WindowFunction::Kaiser { beta: 8.6 }.coefficients(n);
+    let gaussian = WindowFunction::Gaussian { sigma: 0.4 }.coefficients(n);
+    for window in [&kaiser, &gaussian] {
+        // symmetric around the middle, peaking at 1 there
+        assert_eq!(window[n / 2], 1.);
+        assert!((window[100] - window[n - 100]).abs() < 1e-6);
+    }
+    assert!(kaiser[0] < 2e-3 && (gaussian[0] - (-0.5f32 / 0.16).exp()).abs() < 1e-6);
+    assert_eq!(WindowFunction::from_str("Flat Top"), Some(WindowFunction::FlatTop));
+    assert_eq!(WindowFunction::from_str("blackman-harris"), Some(WindowFunction::BlackmanHarris));
+}
+
+#[test]
+fn flat_top_reads_tone_amplitudes_between_bins() {
+    // 1 kHz plus half a bin, the worst case for scalloping
+    let (n, sample_rate) = (4800, 48000);
+    let frequency = 1005.;
+    let signal: Vec<f32> = (0..n).map(|i| (0.5 * (TAU * frequency * i as f64 / sample_rate as f64).sin()) as f32).collect();
+    let peak = |window| -> f32 {
+        let fft = Fft::new(sample_rate, n, window);
+        fft.process(&signal).iter().map(|bin| bin.amplitude).fold(0., f32::max)
+    };
+
+    let flat_top = peak(WindowFunction::FlatTop);
+    // within 0.02 dB
+    assert!((flat_top - 0.5).abs() < 0.5 * 0.0025, "flat top read {}", flat_top);
+    // hann loses about 1.4 dB there
+    let hann = peak(WindowFunction::Hann);
+    assert!(hann < 0.45 && hann > 0.4, "hann read {}", hann);
+}